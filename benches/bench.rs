@@ -0,0 +1,196 @@
+//! Benchmarks of every stage a program goes through: parsing, optimizing,
+//! interpreting and generating code for each backend, on real programs and
+//! on synthetic ones that stress one thing each. Every benchmark is sampled
+//! for about a second and reported by its fastest and median time; a saved
+//! baseline makes regressions stand out: each median is shown with its change
+//! from the baseline's.
+//!
+//!     make bench
+//!     benches/bench [filter] [--save-baseline <file>] [--baseline <file>]
+//!
+//! Only benchmarks whose name contains `filter` run.
+
+extern crate rustfuck;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::process;
+use std::time::Duration;
+use std::time::Instant;
+
+use rustfuck::Config;
+use rustfuck::ProgramState;
+use rustfuck::Stmt;
+
+/* Time spent sampling each benchmark, and the least number of samples */
+const BUDGET      : Duration = Duration::from_secs(1);
+const MIN_SAMPLES : usize = 3;
+
+/* Shortest sample; faster routines run several times per sample */
+const MIN_SAMPLE_TIME : Duration = Duration::from_millis(1);
+
+/* Change from the baseline that is reported as a regression or improvement */
+const THRESHOLD : f64 = 0.10;
+
+/* Programs from the test directory */
+const PROGRAMS : &[(&str, &[u8])] = &[
+    ("mandelbrot", include_bytes!("../test/mandelbrot.b")),
+    ("hanoi", include_bytes!("../test/hanoi.b"))
+];
+
+/* Synthetic programs, each stressing one thing */
+fn synthetic() -> Vec<(&'static str, Vec<u8>)>
+{
+    return vec![
+        /* deep nesting, for the parser, the optimizer and lowering */
+        ("nested", format!("+{}-{}", "[".repeat(10_000), "]".repeat(10_000)).into_bytes()),
+        /* long runs of moves and adds to fold, each dipping one cell left of where it starts */
+        ("runs", format!(">{}", "+++>--<<+>".repeat(50_000)).into_bytes()),
+        /* about 17 million iterations of an inner loop the optimizer can't replace, for dispatch */
+        ("dispatch", b"-[>-[>-[>+>[-]+<<-]<-]<-]".to_vec())
+    ];
+}
+
+/* The fastest and median time of one run of a routine */
+struct Timing {
+    min     : Duration,
+    median  : Duration,
+    samples : usize
+}
+
+/*
+ * Times `routine` on inputs made by `setup`, which isn't timed. Each sample
+ * runs it enough times to take at least `MIN_SAMPLE_TIME`.
+ */
+fn measure<T>(setup : &dyn Fn() -> T, routine : &dyn Fn(T)) -> Timing
+{
+    let start = Instant::now();
+    routine(setup());
+    let once = start.elapsed();
+    let batch = (MIN_SAMPLE_TIME.as_nanos() / once.as_nanos().max(1)).max(1) as usize;
+
+    let mut times = Vec::new();
+    let start = Instant::now();
+    while times.len() < MIN_SAMPLES || start.elapsed() < BUDGET {
+        let inputs : Vec<T> = (0..batch).map(|_| setup()).collect();
+        let sample = Instant::now();
+        for input in inputs {
+            routine(input);
+        }
+        times.push(sample.elapsed() / batch as u32);
+    }
+    times.sort();
+    return Timing { min: times[0], median: times[times.len() / 2], samples: times.len() };
+}
+
+fn parse(src : &[u8]) -> Vec<Stmt>
+{
+    return rustfuck::parse(src).expect("benchmark program doesn't parse");
+}
+
+fn optimized(src : &[u8]) -> Vec<Stmt>
+{
+    return rustfuck::optimizer::optimize_for(parse(src), &Config::default());
+}
+
+/* Runs the benchmarks of every stage for one program, adding their names and timings to `results` */
+fn bench_program(name : &str, src : &[u8], filter : &str, baseline : &HashMap<String, Duration>,
+        results : &mut Vec<(String, Timing)>)
+{
+    let config = Config::default();
+    let code = optimized(src);
+    let mut run = |stage : &str, timing : &dyn Fn() -> Timing| {
+        let name = format!("{}/{}", stage, name);
+        if name.contains(filter) {
+            let timing = timing();
+            report(&name, &timing, baseline.get(&name).cloned());
+            results.push((name, timing));
+        }
+    };
+    run("parse", &|| measure(&|| (), &|_| { black_box(parse(src)); }));
+    run("optimize", &|| measure(&|| parse(src), &|code| { black_box(rustfuck::optimizer::optimize_for(code, &Config::default())); }));
+    run("interpret", &|| measure(&|| ProgramState::with_config(&config), &|mut state| {
+        rustfuck::execute(&code, &mut state, &mut io::empty(), &mut io::sink()).unwrap();
+    }));
+    run("interpret-threaded", &|| measure(&|| ProgramState::with_config(&config), &|mut state| {
+        rustfuck::interpreter::execute_threaded(&code, &mut state, &mut io::empty(), &mut io::sink()).unwrap();
+    }));
+    run("codegen-llvm", &|| measure(&|| (), &|_| { black_box(rustfuck::llvm_ir_generator::code_gen(&code, &config)); }));
+    run("codegen-c", &|| measure(&|| (), &|_| { black_box(rustfuck::c_generator::code_gen(&code, &config)); }));
+    run("codegen-native", &|| measure(&|| (), &|_| { black_box(rustfuck::object_generator::code_gen(&code, &config)); }));
+}
+
+fn report(name : &str, timing : &Timing, baseline : Option<Duration>)
+{
+    let change = match baseline {
+        Some(before) => {
+            let change = timing.median.as_secs_f64() / before.as_secs_f64() - 1.0;
+            let verdict = if change > THRESHOLD {
+                "  regressed"
+            } else if change < -THRESHOLD {
+                "  improved"
+            } else {
+                ""
+            };
+            format!("  {:+6.1}%{}", change * 100.0, verdict)
+        },
+        None => String::new()
+    };
+    println!("{:<28} min {:>12?}  median {:>12?}  ({} samples){}", name, timing.min, timing.median, timing.samples, change);
+}
+
+/* Reads a baseline saved by `--save-baseline`: a benchmark name and median in nanoseconds per line */
+fn read_baseline(path : &str) -> io::Result<HashMap<String, Duration>>
+{
+    let mut baseline = HashMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let mut fields = line.split_whitespace();
+        if let (Some(name), Some(Ok(nanos))) = (fields.next(), fields.next().map(|n| n.parse::<u64>())) {
+            baseline.insert(name.to_owned(), Duration::from_nanos(nanos));
+        }
+    }
+    return Ok(baseline);
+}
+
+fn main()
+{
+    let args : Vec<String> = env::args().skip(1).collect();
+    let mut filter = String::new();
+    let mut save_to = None;
+    let mut compare_to = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--save-baseline" => save_to = iter.next().cloned(),
+            "--baseline"      => compare_to = iter.next().cloned(),
+            _                 => filter = arg.clone()
+        }
+    }
+
+    let baseline = match compare_to {
+        Some(path) => read_baseline(&path).unwrap_or_else(|e| {
+            eprintln!("Error: {}: {}", path, e);
+            process::exit(2);
+        }),
+        None => HashMap::new()
+    };
+
+    let mut results = Vec::new();
+    for &(name, src) in PROGRAMS {
+        bench_program(name, src, &filter, &baseline, &mut results);
+    }
+    for (name, src) in synthetic() {
+        bench_program(name, &src, &filter, &baseline, &mut results);
+    }
+    if let Some(path) = save_to {
+        let lines : String = results.iter().map(|(name, timing)| format!("{} {}\n", name, timing.median.as_nanos())).collect();
+        if let Err(e) = fs::write(&path, lines) {
+            eprintln!("Error: {}: {}", path, e);
+            process::exit(2);
+        }
+        println!("\nsaved the medians as a baseline to {}", path);
+    }
+}
@@ -0,0 +1,233 @@
+//! Fuzzes the parser, the optimizer, the JIT, the printer and the minifier
+//! with random inputs. Each input is parsed as source, which must not panic,
+//! and decoded into a program with `Arbitrary`, which must behave the same
+//! unoptimized in the interpreter, optimized in the interpreter, optimized in
+//! the JIT, optimized and printed by `to_brainfuck()` then parsed again, and
+//! minified: same output, same tape and pointer, and failing in all or none
+//! of them, though only programs that don't fail are minified. Programs that
+//! don't finish within a step limit are skipped. Minified source must print
+//! back unchanged after parsing it.
+//!
+//!     make fuzz
+//!     fuzz/fuzz [iterations] [seed]   run random inputs
+//!     fuzz/fuzz <file>...             rerun inputs saved from failures
+//!
+//! Inputs that fail are saved to `crash-<n>` files in the current directory.
+
+extern crate rustfuck;
+
+use std::env;
+use std::fs;
+use std::panic;
+use std::process;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use rustfuck::Config;
+use rustfuck::ProgramState;
+use rustfuck::RustfuckError;
+use rustfuck::Stmt;
+use rustfuck::arbitrary::Arbitrary;
+use rustfuck::arbitrary::LONG_MOVE;
+use rustfuck::arbitrary::Unstructured;
+use rustfuck::config::Bounds;
+use rustfuck::config::CellWidth;
+use rustfuck::config::Eof;
+use rustfuck::config::Tape;
+
+/* Instructions the unoptimized program may run; the optimized one gets more in case it is slower */
+const MAX_STEPS : u64 = 100_000;
+
+/* Longest random input */
+const MAX_INPUT : usize = 256;
+
+/*
+ * How a run ended: its output, and if it didn't fail, the cells that aren't
+ * zero by where the program sees them, since a dynamic tape may grow more in
+ * one run than in another, and the pointer, or if it ran out of steps, why
+ */
+struct Run {
+    output  : Vec<u8>,
+    state   : Option<(Vec<(i64, i32)>, i64)>,
+    limited : Option<String>
+}
+
+fn run(code : &[Stmt], config : &Config, input : &[u8], jit : bool) -> Run
+{
+    let mut state = ProgramState::with_config(config);
+    let mut output = Vec::new();
+    let result = if jit {
+        rustfuck::jit::execute(&rustfuck::bytecode::lower(code), &mut state, &mut &input[..], &mut output)
+    } else {
+        rustfuck::execute(code, &mut state, &mut &input[..], &mut output)
+    };
+    match result {
+        Ok(())                                    => {
+            let cells = state.tape.values().into_iter().enumerate()
+                    .filter(|&(_, value)| value != 0)
+                    .map(|(i, value)| (i as i64 - state.origin as i64, value))
+                    .collect();
+            Run { output, state: Some((cells, state.position())), limited: None }
+        },
+        Err(e @ RustfuckError::LimitExceeded(..)) => Run { output, state: None, limited: Some(e.to_string()) },
+        Err(_)                                    => Run { output, state: None, limited: None }
+    }
+}
+
+/*
+ * Whether `actual` ran as `expected`. If `stops` the tape stops the program
+ * at its ends, and where the unoptimized program failed at one the other may
+ * get further, as the optimizer drops moves off the tape and back that touch
+ * nothing, even on to never finish, so it only has to print the same first.
+ */
+fn compare(expected : &Run, actual : &Run, what : &str, stops : bool) -> Result<(), String>
+{
+    if stops && expected.state.is_none() && actual.output.starts_with(&expected.output) {
+        return Ok(());
+    }
+    if let Some(ref e) = actual.limited {
+        return Err(format!("{}: {}", what, e));
+    }
+    if expected.output != actual.output {
+        return Err(format!("{} printed {:?} instead of {:?}", what,
+                String::from_utf8_lossy(&actual.output), String::from_utf8_lossy(&expected.output)));
+    }
+    match (&expected.state, &actual.state) {
+        (Some(_), None) => Err(format!("{} failed but the unoptimized program didn't", what)),
+        (None, Some(_)) => Err(format!("{} didn't fail but the unoptimized program did", what)),
+        (Some((tape, ptr)), Some((actual_tape, actual_ptr))) if (tape, ptr) != (actual_tape, actual_ptr) => {
+            let cell = tape.iter().zip(actual_tape.iter()).find(|(a, b)| a != b).map(|(&(i, _), &(j, _))| i.min(j));
+            Err(format!("{} ended with the pointer at {} instead of {}, first differing cell {:?}",
+                    what, actual_ptr, ptr, cell))
+        },
+        _ => Ok(())
+    }
+}
+
+/* Runs every check on one input */
+fn check(data : &[u8]) -> Result<(), String>
+{
+    if let Ok(code) = rustfuck::parse(data) {
+        rustfuck::optimizer::optimize(code, &Config::default());
+    }
+
+    /*
+     * a large tape that stops the program at its ends, a tiny one that wraps around where the long moves
+     * come round to where they started, wider cells, and a tiny tape that grows
+     */
+    let configs = [
+        Config::default(),
+        Config { tape_size: LONG_MOVE as usize, bounds: Bounds::Wrap, eof: Eof::Zero, ..Config::default() },
+        Config { cell_width: CellWidth::Bits16, ..Config::default() },
+        Config { tape_size: LONG_MOVE as usize, bounds: Bounds::Wrap, cell_width: CellWidth::Bits32, eof: Eof::Zero, ..Config::default() },
+        Config { tape_size: 4, tape: Tape::Dynamic, ..Config::default() }
+    ];
+    for config in &configs {
+        check_on(data, config).map_err(|e| format!("{}\n    config: {:?}", e, config))?;
+    }
+    Ok(())
+}
+
+/* Runs the checks of a decoded program on a tape configured as `config` */
+fn check_on(data : &[u8], config : &Config) -> Result<(), String>
+{
+    /* `Stmt` isn't `Clone`, so the program is decoded once for each pipeline */
+    let code = Vec::<Stmt>::arbitrary(&mut Unstructured::new(data));
+    let optimized = rustfuck::optimizer::optimize_for(Vec::<Stmt>::arbitrary(&mut Unstructured::new(data)), config);
+
+    let mut limited = config.clone();
+    limited.max_steps = Some(MAX_STEPS);
+    let expected = run(&code, &limited, data, false);
+    if expected.limited.is_some() {
+        return Ok(());
+    }
+    limited.max_steps = Some(MAX_STEPS * 10);
+    let stops = config.tape == Tape::Fixed && config.bounds != Bounds::Wrap;
+    let interpreted = run(&optimized, &limited, data, false);
+    compare(&expected, &interpreted, "the optimized program", stops)?;
+    /* a `Set` prints as `[-]`, which may take a cell's worth of steps where its loop took one, so that is optimized again */
+    let printed = rustfuck::to_brainfuck(&optimized);
+    let reparsed = rustfuck::parse(printed.as_bytes()).map_err(|e| format!("the printed program: {}", e))?;
+    let reparsed = rustfuck::optimizer::optimize(reparsed, config);
+    let actual = run(&reparsed, &limited, data, false);
+    compare(&expected, &actual, "the printed program", stops)?;
+    /* minifying drops moves off the tape and back that touch nothing, which fail unminified */
+    if expected.state.is_some() {
+        let minified = rustfuck::minify::minify(Vec::<Stmt>::arbitrary(&mut Unstructured::new(data)));
+        let reparsed = rustfuck::parse(minified.as_bytes()).map_err(|e| format!("the minified program: {}", e))?;
+        if rustfuck::to_brainfuck(&reparsed) != minified {
+            return Err(format!("the minified program {:?} printed differently after parsing it", minified));
+        }
+        let reparsed = rustfuck::optimizer::optimize(reparsed, config);
+        let actual = run(&reparsed, &limited, data, false);
+        compare(&expected, &actual, "the minified program", stops)?;
+    }
+    /* the JIT doesn't grow the tape, nor count steps, so it only runs what finished interpreted */
+    if cfg!(all(target_arch = "x86_64", unix)) && config.tape == Tape::Fixed && interpreted.limited.is_none() {
+        let jitted = run(&optimized, config, data, true);
+        compare(&expected, &jitted, "the JIT", stops)?;
+    }
+    Ok(())
+}
+
+/* Checks `data`, saving it and reporting why if it fails; returns whether it passed */
+fn fuzz_one(data : &[u8], crashes : &mut usize) -> bool
+{
+    let result = panic::catch_unwind(|| check(data)).unwrap_or_else(|_| Err("panicked".to_owned()));
+    let msg = match result {
+        Ok(())   => return true,
+        Err(msg) => msg
+    };
+    let file = format!("crash-{}", *crashes);
+    *crashes += 1;
+    let _ = fs::write(&file, data);
+    eprintln!("FAILED: {}", msg);
+    eprintln!("    program: {:?}", Vec::<Stmt>::arbitrary(&mut Unstructured::new(data)));
+    eprintln!("    input saved to {}", file);
+    return false;
+}
+
+/* xorshift64*, good enough to make up inputs */
+fn next(state : &mut u64) -> u64
+{
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    return state.wrapping_mul(0x2545F4914F6CDD1D);
+}
+
+fn main()
+{
+    let args : Vec<String> = env::args().skip(1).collect();
+    let mut crashes = 0;
+    let mut failed = false;
+
+    if !args.is_empty() && args[0].parse::<u64>().is_err() {
+        for path in &args {
+            let data = fs::read(path).unwrap_or_else(|e| {
+                eprintln!("Error: {}: {}", path, e);
+                process::exit(2);
+            });
+            failed |= !fuzz_one(&data, &mut crashes);
+        }
+        process::exit(if failed { 1 } else { 0 });
+    }
+
+    let iterations = args.first().map_or(10_000, |n| n.parse().unwrap());
+    let seed = match args.get(1) {
+        Some(seed) => seed.parse().unwrap_or_else(|_| {
+            eprintln!("Error: invalid seed '{}'", seed);
+            process::exit(2);
+        }),
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1)
+    };
+    println!("fuzzing {} inputs with seed {}", iterations, seed);
+    let mut rng = seed | 1;
+    for _ in 0..iterations {
+        let len = next(&mut rng) as usize % (MAX_INPUT + 1);
+        let data : Vec<u8> = (0..len).map(|_| next(&mut rng) as u8).collect();
+        failed |= !fuzz_one(&data, &mut crashes);
+    }
+    println!("{} of {} inputs failed", crashes, iterations);
+    process::exit(if failed { 1 } else { 0 });
+}
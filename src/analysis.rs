@@ -0,0 +1,215 @@
+/*
+ * Facts about what loops do to the pointer and to the cell they test, for
+ * the optimizer and for `--dump-analysis`.
+ */
+
+use config::Bounds;
+use config::Tape;
+use diagnostic::LineIndex;
+use Config;
+use Span;
+use Stmt;
+
+/* Loops nested deeper are indented no further, so the report can't grow with the square of the depth */
+const MAX_DEPTH : usize = 32;
+
+/// How the cells at different offsets from the pointer are laid out on the
+/// tape a program runs on, which decides whether two offsets are the same
+/// cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// A dynamic tape, on which different offsets are different cells.
+    Unbounded,
+    /// A fixed tape of that many cells, where the program stops at either
+    /// end.
+    Bounded(i32),
+    /// A fixed tape of that many cells, where offsets a multiple of it apart
+    /// are the same cell.
+    Wrapping(i32)
+}
+
+impl Layout {
+    /// The layout of the tape `config` sets up.
+    pub fn of(config : &Config) -> Layout
+    {
+        let size = config.tape_size.clamp(1, i32::MAX as usize) as i32;
+        match (config.tape, config.bounds) {
+            (Tape::Dynamic, _)          => Layout::Unbounded,
+            (Tape::Fixed, Bounds::Wrap) => Layout::Wrapping(size),
+            (Tape::Fixed, _)            => Layout::Bounded(size)
+        }
+    }
+
+    /// The same offset for every offset that is the same cell as `offset`:
+    /// on a wrapping tape the one from 0 up to its size, otherwise `offset`
+    /// itself.
+    pub fn key(self, offset : i32) -> i32
+    {
+        match self {
+            Layout::Wrapping(size) => offset.rem_euclid(size),
+            _                      => offset
+        }
+    }
+}
+
+/// What one run through a loop's body does to the pointer and to the cell
+/// the loop tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopFacts {
+    /// Whether the body, and every loop in it, leaves the pointer where it
+    /// started, so the loop tests the same cell every time round.
+    pub balanced     : bool,
+    /// What the body adds to the cell the loop tests, if it is balanced and
+    /// doesn't otherwise write that cell, e.g. -1 for `[->+<]`.
+    pub counter_step : Option<i32>
+}
+
+/// What a loop does each time round, worked out from its body and what was
+/// worked out before for the loops in it, see `summarize()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopSummary {
+    pub facts  : LoopFacts,
+    /// The cells the body may write to, by their `Layout::key()` relative to
+    /// where the loop starts, if it is balanced.
+    pub writes : Option<Vec<i32>>
+}
+
+/* What is known of a loop that moves the pointer somewhere else, or that is too far off to tell */
+const UNKNOWN : LoopSummary = LoopSummary { facts: LoopFacts { balanced: false, counter_step: None }, writes: None };
+
+/// What a loop with the given body does each time round on a tape laid out
+/// as `layout`.
+pub fn facts(body : &[Stmt], layout : Layout) -> LoopFacts
+{
+    let (_, inner) = summaries(body, layout);
+    return summarize(body, layout, &inner).facts;
+}
+
+/// What a loop with the given body does each time round on a tape laid out
+/// as `layout`, given the summaries of the loops in the body, not counting
+/// those in its procedures, in order. Only the statements of the body itself
+/// are looked at, so summarizing every loop of a program innermost first
+/// takes time in proportion to its size. An offset too far off for an i32
+/// leaves nothing known about the loop.
+pub fn summarize(body : &[Stmt], layout : Layout, inner : &[LoopSummary]) -> LoopSummary
+{
+    let mut inner = inner.iter();
+    let mut offset : i32 = 0;
+    let mut step : Option<i32> = Some(0);
+    let mut written = Vec::new();
+    for stmt in body {
+        let (target, n) = match *stmt {
+            Stmt::Move(n, _)      => match offset.checked_add(n) {
+                Some(moved) => {
+                    offset = moved;
+                    continue;
+                },
+                None        => return UNKNOWN
+            },
+            Stmt::Add(n, _)       => (Some(offset), Some(n)),
+            Stmt::AddAt(o, n, _)  => (offset.checked_add(o), Some(n)),
+            Stmt::MulAdd(o, _, _) => (offset.checked_add(o), None),
+            Stmt::Set(..) | Stmt::Input(_) | Stmt::Random(_) => (Some(offset), None),
+            Stmt::Output(_) | Stmt::Debug(_) | Stmt::Proc(..) => continue,
+            Stmt::Scan(..) | Stmt::Call(_) | Stmt::Fork(_)    => return UNKNOWN,
+            Stmt::Loop(..)        => {
+                let writes = match inner.next().expect("no summary for a loop").writes {
+                    Some(ref writes) => writes,
+                    None             => return UNKNOWN
+                };
+                /* the loop may write its cells any number of times */
+                for &o in writes {
+                    let target = match offset.checked_add(o) {
+                        Some(target) => layout.key(target),
+                        None         => return UNKNOWN
+                    };
+                    if target == 0 {
+                        step = None;
+                    }
+                    written.push(target);
+                }
+                continue;
+            }
+        };
+        let target = match target {
+            Some(target) => layout.key(target),
+            None         => return UNKNOWN
+        };
+        if target == 0 {
+            step = match n {
+                Some(n) => step.and_then(|step| step.checked_add(n)),
+                None    => None
+            };
+        }
+        written.push(target);
+    }
+    if layout.key(offset) != 0 {
+        return UNKNOWN;
+    }
+    written.sort_unstable();
+    written.dedup();
+    return LoopSummary { facts: LoopFacts { balanced: true, counter_step: step }, writes: Some(written) };
+}
+
+/*
+ * The facts about every loop in `code` on a tape laid out as `layout`, in
+ * source order, with its span and how many loops and procedures it is nested
+ * in, and the summaries of the loops in `code` itself. Each loop is
+ * summarized once, innermost first, from the summaries of the loops in it.
+ */
+fn summaries(code : &[Stmt], layout : Layout) -> (Vec<(Span, usize, LoopFacts)>, Vec<LoopSummary>)
+{
+    let mut loops = Vec::new();
+    /* the blocks being walked: the statements, those left, the summaries of its loops so far, and where its own goes in `loops` */
+    let mut blocks = vec![(code, code.iter(), Vec::new(), None)];
+    loop {
+        let next = blocks.last_mut().unwrap().1.next();
+        match next {
+            Some(&Stmt::Loop(ref body, span)) => {
+                loops.push((span, blocks.len() - 1, UNKNOWN.facts));
+                blocks.push((body, body.iter(), Vec::new(), Some(loops.len() - 1)));
+            },
+            Some(&Stmt::Proc(ref body, _)) => blocks.push((body, body.iter(), Vec::new(), None)),
+            Some(_)                        => {},
+            None                           => {
+                let (body, _, inner, slot) = blocks.pop().unwrap();
+                let outer = match blocks.last_mut() {
+                    Some(outer) => outer,
+                    None        => return (loops, inner)
+                };
+                if let Some(i) = slot {
+                    let summary = summarize(body, layout, &inner);
+                    loops[i].2 = summary.facts;
+                    outer.2.push(summary);
+                }
+            }
+        }
+    }
+}
+
+/// The facts about every loop in `code` on a tape laid out as `layout`, in
+/// source order, with its span and how many loops it is nested in.
+pub fn analyze(code : &[Stmt], layout : Layout) -> Vec<(Span, usize, LoopFacts)>
+{
+    return summaries(code, layout).0;
+}
+
+/// A line for every loop in `code`, parsed from `src`, with where it starts
+/// and what `facts()` found on a tape laid out as `layout`, indented by how
+/// deeply it is nested, up to a point, e.g. `3:5  balanced, counter -1`.
+pub fn report(code : &[Stmt], src : &[u8], layout : Layout) -> String
+{
+    let lines = LineIndex::new(src);
+    let mut out = String::new();
+    for (span, depth, facts) in analyze(code, layout) {
+        let (line, col) = lines.locate(span.start);
+        let counter = match facts.counter_step {
+            Some(0)    => "counter unchanged".to_owned(),
+            Some(step) => format!("counter {:+}", step),
+            None       => "counter varies".to_owned()
+        };
+        out.push_str(&format!("{:width$}{}:{}  {}, {}\n", "", line, col,
+                if facts.balanced { "balanced" } else { "unbalanced" }, counter, width = depth.min(MAX_DEPTH) * 2));
+    }
+    return out;
+}
@@ -0,0 +1,136 @@
+/*
+ * Programs for fuzzing, decoded from the raw bytes a fuzzer generates. Every
+ * byte string decodes to some valid program and similar strings decode to
+ * similar programs, which is what lets a fuzzer find its way around. This is
+ * the `arbitrary` crate's idea, without depending on it.
+ */
+
+use Span;
+use Stmt;
+
+/* Deepest loop nesting of a decoded program */
+const MAX_DEPTH : usize = 8;
+
+/* Most statements in a loop body */
+const MAX_BODY : u8 = 8;
+
+/// How far, give or take one, the long moves of a decoded program go. A
+/// fuzzer that makes a tape this size has them come round to where they
+/// started, or next to it.
+pub const LONG_MOVE : i32 = 16;
+
+/// Fuzzer input being decoded. Reading past its end yields zeros, so
+/// decoding always finishes, with the remaining input deciding nothing.
+pub struct Unstructured<'a> {
+    data  : &'a [u8],
+    /* loops around the statement being decoded */
+    depth : usize
+}
+
+impl<'a> Unstructured<'a> {
+    pub fn new(data : &'a [u8]) -> Unstructured<'a>
+    {
+        return Unstructured { data, depth: 0 };
+    }
+
+    /// The next byte of input, or 0 if it has run out.
+    pub fn byte(&mut self) -> u8
+    {
+        match self.data.split_first() {
+            Some((&b, rest)) => {
+                self.data = rest;
+                b
+            },
+            None => 0
+        }
+    }
+
+    /// Whether all input has been read.
+    pub fn is_empty(&self) -> bool
+    {
+        return self.data.is_empty();
+    }
+}
+
+/// Types that can be decoded from fuzzer input.
+pub trait Arbitrary : Sized {
+    fn arbitrary(u : &mut Unstructured) -> Self;
+}
+
+/// Decodes only the statements `parse()` produces, so that the result can
+/// go through the optimizer. Moves go one to three cells, or about
+/// `LONG_MOVE` cells either way, and half the adds add one or two, like
+/// counters do. Like counting loops, half the loops count the cell they
+/// test down by one and half move back to where they started. Loops are
+/// nested at most `MAX_DEPTH` deep, and with no source every statement spans
+/// nothing. Like `parse()`, runs of moves or adds in a loop body are folded
+/// into one.
+impl Arbitrary for Stmt {
+    fn arbitrary(u : &mut Unstructured) -> Stmt
+    {
+        let kind = u.byte();
+        let span = Span::default();
+        match kind % 8 {
+            0 | 1 => Stmt::Move(match u.byte() % 8 {
+                n @ 0..=2 => n as i32 + 1,
+                n @ 3..=5 => 2 - n as i32,
+                6         => LONG_MOVE - 1 + (u.byte() % 3) as i32,
+                _         => 1 - LONG_MOVE - (u.byte() % 3) as i32
+            }, span),
+            2 => Stmt::Add(match u.byte() % 4 {
+                0 => -1,
+                1 => 1,
+                2 => -2,
+                _ => 2
+            }, span),
+            3 => Stmt::Add(match u.byte() as i8 as i32 {
+                0 => 1,
+                n => n
+            }, span),
+            4 => Stmt::Input(span),
+            5 => Stmt::Output(span),
+            _ if u.depth >= MAX_DEPTH => Stmt::Add(-1, span),
+            _ => {
+                let b = u.byte();
+                u.depth += 1;
+                /* half the loops count the cell they test down by one, and half move back to where they started */
+                let mut body = Vec::new();
+                if b & 0x40 != 0 {
+                    body.push(Stmt::Add(-1, span));
+                }
+                for _ in 0..b % MAX_BODY {
+                    push(&mut body, Stmt::arbitrary(u));
+                }
+                u.depth -= 1;
+                let moved : i32 = body.iter().map(|stmt| if let Stmt::Move(n, _) = *stmt { n } else { 0 }).sum();
+                if b >= 0x80 && moved != 0 {
+                    push(&mut body, Stmt::Move(-moved, span));
+                }
+                Stmt::Loop(body, span)
+            }
+        }
+    }
+}
+
+/// A whole program: statements are decoded until the input runs out.
+impl Arbitrary for Vec<Stmt> {
+    fn arbitrary(u : &mut Unstructured) -> Vec<Stmt>
+    {
+        let mut code = Vec::new();
+        while !u.is_empty() {
+            push(&mut code, Stmt::arbitrary(u));
+        }
+        return code;
+    }
+}
+
+/* Appends `stmt` to `code`, folding it into a move or add before it as `parse()` does */
+fn push(code : &mut Vec<Stmt>, stmt : Stmt)
+{
+    let folded = match (code.last(), &stmt) {
+        (Some(&Stmt::Move(a, span)), &Stmt::Move(b, _)) => Stmt::Move(a + b, span),
+        (Some(&Stmt::Add(a, span)), &Stmt::Add(b, _))   => Stmt::Add(a + b, span),
+        _                                               => return code.push(stmt)
+    };
+    *code.last_mut().unwrap() = folded;
+}
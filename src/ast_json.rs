@@ -0,0 +1,433 @@
+/*
+ * The program representation as JSON, for tools that generate or transform
+ * programs without going through brainfuck source. A program is written as
+ *
+ *     {
+ *       "format": "rustfuck-ast",
+ *       "version": 1,
+ *       "program": [
+ *         {"op": "add", "n": 2, "span": [0, 2]},
+ *         {"op": "loop", "span": [2, 8], "body": [
+ *           {"op": "mul_add", "offset": 1, "factor": 3, "span": [2, 8]}
+ *         ]}
+ *       ]
+ *     }
+ *
+ * with each statement an object with an "op", one of
+ *
+ *     move, add, set          with "n", see `Stmt::Move`, `Stmt::Add` and `Stmt::Set`
+ *     add_at                  with "offset" and "n", see `Stmt::AddAt`
+ *     mul_add                 with "offset" and "factor", see `Stmt::MulAdd`
+ *     scan                    with "stride", which isn't 0, see `Stmt::Scan`
+ *     loop, proc              with a "body" of statements
+ *     input, output, debug, call, fork, random
+ *
+ * numbers that fit 32 bits, and the "span" of source it came from, as its
+ * start and end offset. A span may be left out, when there is no source;
+ * fields and members this version doesn't know are skipped, so that a
+ * version 1 file can grow them without breaking readers.
+ */
+
+use std::mem;
+use std::slice;
+use Diagnostic;
+use RustfuckError;
+use Span;
+use Stmt;
+
+/// What the "format" of a file says it is.
+pub const FORMAT : &str = "rustfuck-ast";
+/// The version of the schema written, and the only one read.
+pub const VERSION : u32 = 1;
+
+/* Blocks nested deeper are indented no further, so the output can't grow with the square of the depth */
+const MAX_INDENT : usize = 32;
+
+/// Writes `code` as JSON, a statement a line, with loop and procedure
+/// bodies indented.
+pub fn to_json(code : &[Stmt]) -> String
+{
+    let mut out = format!("{{\n  \"format\": \"{}\",\n  \"version\": {},\n  \"program\": [", FORMAT, VERSION);
+    /* iterators over the blocks the next statement is in, outermost first, and whether any statement of theirs was written */
+    let mut blocks : Vec<(slice::Iter<Stmt>, bool)> = vec![(code.iter(), false)];
+    loop {
+        let depth = blocks.len();
+        let (block, written) = match blocks.last_mut() {
+            Some(block) => block,
+            None        => break
+        };
+        let stmt = match block.next() {
+            Some(stmt) => stmt,
+            None       => {
+                if *written {
+                    out.push('\n');
+                    out.push_str(&"  ".repeat(depth.min(MAX_INDENT)));
+                }
+                out.push(']');
+                blocks.pop();
+                if !blocks.is_empty() {
+                    out.push('}');
+                }
+                continue;
+            }
+        };
+        out.push_str(if *written { ",\n" } else { "\n" });
+        *written = true;
+        out.push_str(&"  ".repeat((depth + 1).min(MAX_INDENT + 1)));
+
+        let (op, fields, span) = match *stmt {
+            Stmt::Move(n, span)                => ("move", format!(", \"n\": {}", n), span),
+            Stmt::Add(n, span)                 => ("add", format!(", \"n\": {}", n), span),
+            Stmt::Set(n, span)                 => ("set", format!(", \"n\": {}", n), span),
+            Stmt::AddAt(offset, n, span)       => ("add_at", format!(", \"offset\": {}, \"n\": {}", offset, n), span),
+            Stmt::MulAdd(offset, factor, span) => ("mul_add", format!(", \"offset\": {}, \"factor\": {}", offset, factor), span),
+            Stmt::Scan(stride, span)           => ("scan", format!(", \"stride\": {}", stride), span),
+            Stmt::Input(span)                  => ("input", String::new(), span),
+            Stmt::Output(span)                 => ("output", String::new(), span),
+            Stmt::Debug(span)                  => ("debug", String::new(), span),
+            Stmt::Call(span)                   => ("call", String::new(), span),
+            Stmt::Fork(span)                   => ("fork", String::new(), span),
+            Stmt::Random(span)                 => ("random", String::new(), span),
+            Stmt::Loop(ref body, span) | Stmt::Proc(ref body, span) => {
+                let op = if let Stmt::Loop(..) = *stmt { "loop" } else { "proc" };
+                out.push_str(&format!("{{\"op\": \"{}\", \"span\": [{}, {}], \"body\": [", op, span.start, span.end));
+                blocks.push((body.iter(), false));
+                continue;
+            }
+        };
+        out.push_str(&format!("{{\"op\": \"{}\"{}, \"span\": [{}, {}]}}", op, fields, span.start, span.end));
+    }
+    out.push_str("\n}\n");
+    return out;
+}
+
+/// Reads a program written as JSON, see `to_json()`. What isn't valid JSON
+/// or a valid program is a `ParseError` pointing into `text`.
+pub fn from_json(text : &[u8]) -> Result<Vec<Stmt>, RustfuckError>
+{
+    let mut lexer = Lexer { text, pos: 0 };
+    lexer.expect(Token::Open, "expected '{'")?;
+    let mut version = None;
+    let mut program = None;
+    let mut first = true;
+    while let Some((at, key)) = lexer.member(first)? {
+        first = false;
+        match key.as_str() {
+            "format"  => if lexer.string()? != FORMAT {
+                return Err(lexer.error(at, &format!("not a program, the format isn't \"{}\"", FORMAT)));
+            },
+            "version" => version = Some((at, lexer.int()?)),
+            "program" => program = Some(parse_program(&mut lexer)?),
+            _         => lexer.skip_value()?
+        }
+    }
+    lexer.end()?;
+    match version {
+        Some((_, v)) if v == VERSION as i32 => (),
+        Some((at, v))                       => return Err(lexer.error(at, &format!("unsupported version {}, expected {}", v, VERSION))),
+        None                                => return Err(lexer.error(0, "missing \"version\""))
+    }
+    return program.ok_or_else(|| lexer.error(0, "missing \"program\""));
+}
+
+/*
+ * Parses an array of statements, keeping the blocks they are nested in on a
+ * worklist rather than the stack, like `parse()` does, so however deep they
+ * are nested they can't overflow it
+ */
+fn parse_program(lexer : &mut Lexer) -> Result<Vec<Stmt>, RustfuckError>
+{
+    lexer.expect(Token::OpenArray, "expected '[' of statements")?;
+    /* statements of the enclosing blocks, with the statement whose body the next block is */
+    let mut open : Vec<(Vec<Stmt>, Fields)> = Vec::new();
+    let mut code = Vec::new();
+    loop {
+        /* the next statement, or what is left of the one whose body just ended */
+        let mut fields = match lexer.next()? {
+            (_, Token::CloseArray) => match open.pop() {
+                Some((outer, mut fields)) => {
+                    fields.body = Some(mem::replace(&mut code, outer));
+                    fields
+                },
+                None                      => return Ok(code)
+            },
+            (at, Token::Open) if code.is_empty() => Fields::new(at),
+            (_, Token::Comma) if !code.is_empty() => Fields::new(lexer.expect(Token::Open, "expected a statement")?),
+            (at, _)                               => return Err(lexer.error(at, "expected a statement or ']'"))
+        };
+        if fields.members(lexer)? {
+            open.push((mem::take(&mut code), fields));
+            continue;
+        }
+        code.push(fields.into_stmt(lexer)?);
+    }
+}
+
+/* The members of a statement's object read so far */
+struct Fields {
+    at     : usize,
+    first  : bool,
+    op     : Option<(usize, String)>,
+    n      : Option<i32>,
+    offset : Option<i32>,
+    factor : Option<i32>,
+    stride : Option<i32>,
+    span   : Option<Span>,
+    body   : Option<Vec<Stmt>>
+}
+
+impl Fields {
+    fn new(at : usize) -> Fields
+    {
+        return Fields { at, first: true, op: None, n: None, offset: None, factor: None, stride: None, span: None, body: None };
+    }
+
+    /* Reads members up to the end of the object, or returns true when a body starts, to be continued after it */
+    fn members(&mut self, lexer : &mut Lexer) -> Result<bool, RustfuckError>
+    {
+        while let Some((at, key)) = lexer.member(self.first)? {
+            self.first = false;
+            match key.as_str() {
+                "op"     => self.op = Some((at, lexer.string()?)),
+                "n"      => self.n = Some(lexer.int()?),
+                "offset" => self.offset = Some(lexer.int()?),
+                "factor" => self.factor = Some(lexer.int()?),
+                "stride" => self.stride = Some(lexer.int()?),
+                "span"   => self.span = Some(lexer.span()?),
+                "body"   => {
+                    lexer.expect(Token::OpenArray, "expected '[' of statements")?;
+                    return Ok(true);
+                },
+                _        => lexer.skip_value()?
+            }
+        }
+        return Ok(false);
+    }
+
+    /* The statement the members make */
+    fn into_stmt(self, lexer : &Lexer) -> Result<Stmt, RustfuckError>
+    {
+        let object = self.at;
+        let (at, op) = self.op.ok_or_else(|| lexer.error(object, "missing \"op\""))?;
+        let span = self.span.unwrap_or_default();
+        let need = |field : Option<i32>, name : &str| field.ok_or_else(|| lexer.error(object, &format!("missing \"{}\" of \"{}\"", name, op)));
+        let stmt = match op.as_str() {
+            "move"    => Stmt::Move(need(self.n, "n")?, span),
+            "add"     => Stmt::Add(need(self.n, "n")?, span),
+            "set"     => Stmt::Set(need(self.n, "n")?, span),
+            "add_at"  => Stmt::AddAt(need(self.offset, "offset")?, need(self.n, "n")?, span),
+            "mul_add" => Stmt::MulAdd(need(self.offset, "offset")?, need(self.factor, "factor")?, span),
+            "scan"    => match need(self.stride, "stride")? {
+                0      => return Err(lexer.error(object, "a scan's \"stride\" can't be 0")),
+                stride => Stmt::Scan(stride, span)
+            },
+            "input"   => Stmt::Input(span),
+            "output"  => Stmt::Output(span),
+            "debug"   => Stmt::Debug(span),
+            "call"    => Stmt::Call(span),
+            "fork"    => Stmt::Fork(span),
+            "random"  => Stmt::Random(span),
+            "loop" | "proc" => {
+                let body = self.body.ok_or_else(|| lexer.error(object, &format!("missing \"body\" of \"{}\"", op)))?;
+                if op == "loop" { Stmt::Loop(body, span) } else { Stmt::Proc(body, span) }
+            },
+            _         => return Err(lexer.error(at, &format!("unknown op \"{}\"", op)))
+        };
+        return Ok(stmt);
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+enum Token {
+    Open,
+    Close,
+    OpenArray,
+    CloseArray,
+    Colon,
+    Comma,
+    Str(String),
+    Number(String),
+    /* true, false or null */
+    Literal
+}
+
+/* Reads the tokens of JSON text, and the values the schema has */
+struct Lexer<'a> {
+    text : &'a [u8],
+    pos  : usize
+}
+
+impl<'a> Lexer<'a> {
+    fn error(&self, at : usize, msg : &str) -> RustfuckError
+    {
+        return RustfuckError::ParseError(Diagnostic::new(self.text, at, msg));
+    }
+
+    /* The next token, with the offset it starts at */
+    fn next(&mut self) -> Result<(usize, Token), RustfuckError>
+    {
+        let text = self.text;
+        while self.pos < text.len() && matches!(text[self.pos], b' ' | b'\t' | b'\n' | b'\r') {
+            self.pos += 1;
+        }
+        let at = self.pos;
+        let b = match text.get(at) {
+            Some(&b) => b,
+            None     => return Err(self.error(at, "unexpected end of file"))
+        };
+        self.pos += 1;
+        let token = match b {
+            b'{' => Token::Open,
+            b'}' => Token::Close,
+            b'[' => Token::OpenArray,
+            b']' => Token::CloseArray,
+            b':' => Token::Colon,
+            b',' => Token::Comma,
+            b'"' => Token::Str(self.rest_of_string(at)?),
+            b'-' | b'0'..=b'9' => {
+                while self.pos < text.len() && matches!(text[self.pos], b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') {
+                    self.pos += 1;
+                }
+                Token::Number(String::from_utf8_lossy(&text[at..self.pos]).into_owned())
+            },
+            _ => match [&b"true"[..], b"false", b"null"].iter().find(|word| text[at..].starts_with(word)) {
+                Some(word) => {
+                    self.pos = at + word.len();
+                    Token::Literal
+                },
+                None       => return Err(self.error(at, "unexpected character"))
+            }
+        };
+        return Ok((at, token));
+    }
+
+    /* A string after its opening quote, at `at` */
+    fn rest_of_string(&mut self, at : usize) -> Result<String, RustfuckError>
+    {
+        let text = self.text;
+        let mut s = Vec::new();
+        loop {
+            let b = match text.get(self.pos) {
+                Some(&b) => b,
+                None     => return Err(self.error(at, "unterminated string"))
+            };
+            self.pos += 1;
+            match b {
+                b'"'  => return Ok(String::from_utf8_lossy(&s).into_owned()),
+                b'\\' => {
+                    let escaped = match text.get(self.pos) {
+                        Some(b'n')              => '\n',
+                        Some(b't')              => '\t',
+                        Some(b'r')              => '\r',
+                        Some(b'b')              => '\u{8}',
+                        Some(b'f')              => '\u{c}',
+                        Some(&c @ (b'"' | b'\\' | b'/')) => c as char,
+                        Some(b'u')              => {
+                            let code = text.get(self.pos + 1..self.pos + 5)
+                                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                    .ok_or_else(|| self.error(self.pos - 1, "bad \\u escape"))?;
+                            self.pos += 4;
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        },
+                        _                       => return Err(self.error(self.pos - 1, "unknown escape"))
+                    };
+                    self.pos += 1;
+                    s.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                _     => s.push(b)
+            }
+        }
+    }
+
+    /* Reads `token`, returning where it is, or fails with `msg` */
+    fn expect(&mut self, token : Token, msg : &str) -> Result<usize, RustfuckError>
+    {
+        match self.next()? {
+            (at, next) if next == token => Ok(at),
+            (at, _)                     => Err(self.error(at, msg))
+        }
+    }
+
+    /* The key of the next member of an object, past its colon, or `None` at the end of the object */
+    fn member(&mut self, first : bool) -> Result<Option<(usize, String)>, RustfuckError>
+    {
+        let (at, token) = match self.next()? {
+            (_, Token::Comma) if !first => self.next()?,
+            (_, Token::Close)           => return Ok(None),
+            (at, Token::Str(_)) if !first => return Err(self.error(at, "expected ',' or '}'")),
+            next                        => next
+        };
+        match token {
+            Token::Str(key) => {
+                self.expect(Token::Colon, "expected ':'")?;
+                Ok(Some((at, key)))
+            },
+            _               => Err(self.error(at, "expected a string key"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, RustfuckError>
+    {
+        match self.next()? {
+            (_, Token::Str(s)) => Ok(s),
+            (at, _)            => Err(self.error(at, "expected a string"))
+        }
+    }
+
+    fn number<T : std::str::FromStr>(&mut self, what : &str) -> Result<T, RustfuckError>
+    {
+        match self.next()? {
+            (at, Token::Number(n)) => n.parse().map_err(|_| self.error(at, &format!("expected {}", what))),
+            (at, _)                => Err(self.error(at, &format!("expected {}", what)))
+        }
+    }
+
+    fn int(&mut self) -> Result<i32, RustfuckError>
+    {
+        return self.number("a whole number that fits 32 bits");
+    }
+
+    /* A span, as `[start, end]` */
+    fn span(&mut self) -> Result<Span, RustfuckError>
+    {
+        let at = self.expect(Token::OpenArray, "expected a span, [start, end]")?;
+        let start = self.number("an offset")?;
+        self.expect(Token::Comma, "expected ','")?;
+        let end = self.number("an offset")?;
+        self.expect(Token::CloseArray, "expected ']'")?;
+        if start > end {
+            return Err(self.error(at, "the span ends before it starts"));
+        }
+        return Ok(Span::new(start, end));
+    }
+
+    /* Skips a value of any kind, counting brackets rather than recursing into them */
+    fn skip_value(&mut self) -> Result<(), RustfuckError>
+    {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                (_, Token::Open) | (_, Token::OpenArray)              => depth += 1,
+                (_, Token::Close) | (_, Token::CloseArray) if depth > 0 => depth -= 1,
+                (at, Token::Close) | (at, Token::CloseArray) | (at, Token::Colon) | (at, Token::Comma) if depth == 0 => {
+                    return Err(self.error(at, "expected a value"));
+                },
+                _ => ()
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /* Fails if anything but whitespace is left */
+    fn end(&mut self) -> Result<(), RustfuckError>
+    {
+        if self.text[self.pos..].iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(());
+        }
+        let at = self.pos + self.text[self.pos..].iter().take_while(|b| b.is_ascii_whitespace()).count();
+        return Err(self.error(at, "unexpected text after the program"));
+    }
+}
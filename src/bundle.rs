@@ -0,0 +1,152 @@
+/*
+ * Bundled executables, which run a program without any toolchain having
+ * built them: a copy of the running rustfuck executable with the program's
+ * bytecode and the config it was built with appended to it,
+ *
+ *     <config> <bytecode> <their length, 8 bytes little-endian> RFBUNDLE
+ *
+ * When rustfuck starts, `load()` looks for that at the end of its own
+ * executable, and if it is there, rustfuck runs the program instead of
+ * reading its arguments. A program linking this crate bundles itself, so
+ * only one that does the same can build bundles that work.
+ */
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use bytecode;
+use bytecode::Op;
+use config::Bounds;
+use config::CellWidth;
+use config::Eof;
+use config::Flush;
+use config::Io;
+use config::Tape;
+use Config;
+use RustfuckError;
+use Stmt;
+
+const TRAILER : &[u8; 8] = b"RFBUNDLE";
+
+/* The values of each setting, numbered by their index */
+const TAPES       : [Tape; 2] = [Tape::Fixed, Tape::Dynamic];
+const BOUNDS      : [Bounds; 3] = [Bounds::Error, Bounds::Wrap, Bounds::Unchecked];
+const CELL_WIDTHS : [CellWidth; 3] = [CellWidth::Bits8, CellWidth::Bits16, CellWidth::Bits32];
+const IOS         : [Io; 2] = [Io::Bytes, Io::Numeric];
+const EOFS        : [Eof; 3] = [Eof::Zero, Eof::MinusOne, Eof::Unchanged];
+const FLUSHES     : [Flush; 3] = [Flush::Line, Flush::Full, Flush::EveryByte];
+
+/* Bytes of the config: the tape size, the index of each setting, and whether there is a seed and what it is */
+const CONFIG_LEN : usize = 8 + 6 + 1 + 8;
+
+/// Writes an executable to `executable_file` which runs `program` with the
+/// semantics in `config`, its seed included, and no limits, by bundling it
+/// with the running executable, see the module documentation.
+pub fn write(program : &[Stmt], config : &Config, executable_file : &str) -> Result<(), RustfuckError>
+{
+    let mut payload = encode_config(config);
+    payload.extend(bytecode::encode(&bytecode::lower(program)));
+    let mut bundle = fs::read(env::current_exe()?)?;
+    bundle.extend_from_slice(&payload);
+    bundle.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bundle.extend_from_slice(TRAILER);
+    fs::write(executable_file, bundle)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(executable_file, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// The program bundled with the running executable, lowered and fused,
+/// see `bytecode::fuse()`, and the config to run it with, or `None` if it
+/// isn't a bundle. Fails if it is one, but a broken one.
+pub fn load() -> Result<Option<(Vec<Op>, Config)>, RustfuckError>
+{
+    /* an executable that can't be read back can't be a bundle either */
+    let mut exe = match env::current_exe().and_then(File::open) {
+        Ok(exe) => exe,
+        Err(_)  => return Ok(None)
+    };
+    let len = exe.metadata()?.len();
+    if len < 16 {
+        return Ok(None);
+    }
+    let mut tail = [0; 16];
+    exe.seek(SeekFrom::End(-16))?;
+    exe.read_exact(&mut tail)?;
+    if tail[8..] != TRAILER[..] {
+        return Ok(None);
+    }
+    let mut payload_len = [0; 8];
+    payload_len.copy_from_slice(&tail[..8]);
+    let payload_len = u64::from_le_bytes(payload_len);
+    if payload_len < CONFIG_LEN as u64 || payload_len > len - 16 {
+        return Err(invalid("the program doesn't fit in it"));
+    }
+    let mut payload = vec![0; payload_len as usize];
+    exe.seek(SeekFrom::End(-16 - payload_len as i64))?;
+    exe.read_exact(&mut payload)?;
+    let config = decode_config(&payload[..CONFIG_LEN])?;
+    let mut ops = bytecode::decode(&payload[CONFIG_LEN..])?;
+    /* a bundle has no step limit, so only threads, which take turns at ops, keep it from being fused */
+    if !ops.contains(&Op::Fork) {
+        bytecode::fuse(&mut ops);
+    }
+    return Ok(Some((ops, config)));
+}
+
+/* The settings of `config` a bundle keeps, `CONFIG_LEN` bytes of them */
+fn encode_config(config : &Config) -> Vec<u8>
+{
+    let mut out = (config.tape_size as u64).to_le_bytes().to_vec();
+    out.push(index(&TAPES, config.tape));
+    out.push(index(&BOUNDS, config.bounds));
+    out.push(index(&CELL_WIDTHS, config.cell_width));
+    out.push(index(&IOS, config.io));
+    out.push(index(&EOFS, config.eof));
+    out.push(index(&FLUSHES, config.flush));
+    out.push(config.seed.is_some() as u8);
+    out.extend_from_slice(&config.seed.unwrap_or(0).to_le_bytes());
+    return out;
+}
+
+/* The config `encode_config()` wrote into `bytes` */
+fn decode_config(bytes : &[u8]) -> Result<Config, RustfuckError>
+{
+    let mut tape_size = [0; 8];
+    tape_size.copy_from_slice(&bytes[..8]);
+    let mut seed = [0; 8];
+    seed.copy_from_slice(&bytes[15..23]);
+    return Ok(Config {
+        tape_size:  u64::from_le_bytes(tape_size) as usize,
+        tape:       pick(&TAPES, bytes[8])?,
+        bounds:     pick(&BOUNDS, bytes[9])?,
+        cell_width: pick(&CELL_WIDTHS, bytes[10])?,
+        io:         pick(&IOS, bytes[11])?,
+        eof:        pick(&EOFS, bytes[12])?,
+        flush:      pick(&FLUSHES, bytes[13])?,
+        seed:       if bytes[14] != 0 { Some(u64::from_le_bytes(seed)) } else { None },
+        ..Config::default()
+    });
+}
+
+fn index<T : PartialEq>(values : &[T], value : T) -> u8
+{
+    return values.iter().position(|v| *v == value).unwrap() as u8;
+}
+
+fn pick<T : Copy>(values : &[T], i : u8) -> Result<T, RustfuckError>
+{
+    return values.get(i as usize).cloned().ok_or_else(|| invalid("its config is broken"));
+}
+
+fn invalid(msg : &str) -> RustfuckError
+{
+    return RustfuckError::IO(io::Error::new(io::ErrorKind::InvalidData, format!("invalid bundle: {}", msg)));
+}
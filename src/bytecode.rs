@@ -0,0 +1,393 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::mem;
+use RustfuckError;
+use Span;
+use Stmt;
+
+/// What a bytecode file, see `encode()`, starts with.
+pub const MAGIC : &[u8; 4] = b"RFBC";
+/// The version of the bytecode format `encode()` writes, and the only one
+/// `decode()` reads.
+pub const FORMAT_VERSION : u8 = 1;
+/// The extension of bytecode files.
+pub const EXTENSION : &str = "bfc";
+
+/// A flattened brainfuck instruction. Loops are lowered into a pair of
+/// conditional jumps whose offsets are relative to the jump itself.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Op
+{
+    Move(i32),
+    Add(i32),
+    Input,
+    Output,
+    Set(i32),
+    MulAdd(i32, i32),
+    AddAt(i32, i32),
+    Scan(i32),
+    /// `[`: skip forward by the offset (past the matching `]`) if the
+    /// current cell is zero.
+    JumpIfZero(usize),
+    /// `]`: jump back by the offset (to just after the matching `[`) if the
+    /// current cell is non-zero.
+    JumpIfNonZero(usize),
+    /// `#`: a breakpoint at the given source offset, see `Stmt::Debug`.
+    Debug(usize),
+    /// `(`: define the procedure starting at the next op under the number in
+    /// the current cell, then skip forward by the offset, past its `)`.
+    Proc(usize),
+    /// `)`: return to the op after the `:` that called the procedure.
+    Return,
+    /// `:`: call the procedure numbered by the current cell.
+    Call,
+    /// `Y`: fork a Brainfork thread, see `Stmt::Fork`.
+    Fork,
+    /// `?`: store a random byte in the current cell.
+    Random,
+    /// `AddMove(n, m)`: an `Add(n)` fused with the `Move(m)` after it, see
+    /// `fuse()`.
+    AddMove(i32, i32),
+    /// `SetAdd(n, m)`: a `Set(n)` fused with the `Add(m)` after it.
+    SetAdd(i32, i32),
+    /// `MoveJumpIfZero(n, offset)`: a `Move(n)` fused with the
+    /// `JumpIfZero(offset)` after it, whose offset is still relative to
+    /// that jump.
+    MoveJumpIfZero(i32, usize),
+    /// `MoveJumpIfNonZero(n, offset)`: a `Move(n)` fused with the
+    /// `JumpIfNonZero(offset)` after it, whose offset is still relative to
+    /// that jump.
+    MoveJumpIfNonZero(i32, usize)
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            Op::Move(n)                      => write!(f, "move {}", n),
+            Op::Add(n)                       => write!(f, "add {}", n),
+            Op::Input                        => write!(f, "input"),
+            Op::Output                       => write!(f, "output"),
+            Op::Set(n)                       => write!(f, "set {}", n),
+            Op::MulAdd(offset, n)            => write!(f, "mul-add {} {}", offset, n),
+            Op::AddAt(offset, n)             => write!(f, "add-at {} {}", offset, n),
+            Op::Scan(stride)                 => write!(f, "scan {}", stride),
+            Op::JumpIfZero(offset)           => write!(f, "jump-if-zero +{}", offset),
+            Op::JumpIfNonZero(offset)        => write!(f, "jump-if-non-zero -{}", offset),
+            Op::Debug(_)                     => write!(f, "debug"),
+            Op::Proc(offset)                 => write!(f, "proc +{}", offset),
+            Op::Return                       => write!(f, "return"),
+            Op::Call                         => write!(f, "call"),
+            Op::Fork                         => write!(f, "fork"),
+            Op::Random                       => write!(f, "random"),
+            Op::AddMove(n, m)                => write!(f, "add-move {} {}", n, m),
+            Op::SetAdd(n, m)                 => write!(f, "set-add {} {}", n, m),
+            Op::MoveJumpIfZero(n, offset)    => write!(f, "move-jump-if-zero {} +{}", n, offset + 1),
+            Op::MoveJumpIfNonZero(n, offset) => write!(f, "move-jump-if-non-zero {} -{}", n, offset - 1)
+        }
+    }
+}
+
+/// Lowers a program into a linear instruction stream with precomputed jump
+/// offsets.
+pub fn lower(code : &[Stmt]) -> Vec<Op>
+{
+    return lower_with_sources(code).0;
+}
+
+/// Like `lower()`, but also returns the span of the source each op came
+/// from. The jumps of a loop span its `[` and its `]`, and so do the `Proc`
+/// and `Return` of a procedure its `(` and `)`.
+pub fn lower_with_sources(code : &[Stmt]) -> (Vec<Op>, Vec<Span>)
+{
+    let mut ops : Vec<Op> = Vec::new();
+    let mut sources : Vec<Span> = Vec::new();
+
+    /* each frame is a block, the index of its next statement and the index of its `[` or `(` op */
+    let mut frames : Vec<(&[Stmt], usize, Option<usize>)> = vec![(code, 0, None)];
+    while let Some(&mut (block, ref mut idx, open_idx)) = frames.last_mut() {
+        if *idx >= block.len() {
+            frames.pop();
+            if let Some(open_idx) = open_idx {
+                let close_idx = ops.len();
+                if let Op::Proc(_) = ops[open_idx] {
+                    ops[open_idx] = Op::Proc(close_idx - open_idx + 1);
+                    ops.push(Op::Return);
+                } else {
+                    ops[open_idx] = Op::JumpIfZero(close_idx - open_idx + 1);
+                    ops.push(Op::JumpIfNonZero(close_idx - open_idx - 1));
+                }
+                /* the loop or procedure is the statement the enclosing block just moved past */
+                let (outer, outer_idx, _) = frames[frames.len() - 1];
+                let span = outer[outer_idx - 1].span();
+                sources.push(Span::new(span.end.saturating_sub(1), span.end));
+            }
+            continue;
+        }
+
+        let stmt = &block[*idx];
+        *idx += 1;
+        match stmt {
+            Stmt::Move(n, _)      => ops.push(Op::Move(*n)),
+            Stmt::Add(n, _)       => ops.push(Op::Add(*n)),
+            Stmt::Input(_)        => ops.push(Op::Input),
+            Stmt::Output(_)       => ops.push(Op::Output),
+            Stmt::Set(n, _)       => ops.push(Op::Set(*n)),
+            Stmt::MulAdd(o, f, _) => ops.push(Op::MulAdd(*o, *f)),
+            Stmt::AddAt(o, n, _)  => ops.push(Op::AddAt(*o, *n)),
+            Stmt::Scan(n, _)      => ops.push(Op::Scan(*n)),
+            Stmt::Debug(span)     => ops.push(Op::Debug(span.start)),
+            Stmt::Call(_)         => ops.push(Op::Call),
+            Stmt::Fork(_)         => ops.push(Op::Fork),
+            Stmt::Random(_)       => ops.push(Op::Random),
+            Stmt::Loop(loop_code, span) => {
+                /* patched with the real offset once the body is lowered */
+                frames.push((loop_code, 0, Some(ops.len())));
+                ops.push(Op::JumpIfZero(0));
+                sources.push(Span::new(span.start, span.start + 1));
+                continue;
+            },
+            Stmt::Proc(proc_code, span) => {
+                frames.push((proc_code, 0, Some(ops.len())));
+                ops.push(Op::Proc(0));
+                sources.push(Span::new(span.start, span.start + 1));
+                continue;
+            }
+        }
+        sources.push(stmt.span());
+    }
+
+    return (ops, sources);
+}
+
+/// Fuses common pairs of ops in `ops`, as `lower()` makes them, into one
+/// op that does both, so the interpreter dispatches once for the pair: an
+/// `Add` and the `Move` after it, a `Set` and the `Add` after it, and a
+/// `Move` and the jump of a loop after it. The second op of a pair is kept
+/// where it was, and the fused op skips it, so no op moves and the jumps,
+/// spans and pcs of the program stay the same. Nothing jumps to the second
+/// op of a pair either: ops are only jumped or returned to right after a
+/// jump, `Proc`, `Return`, `Call` or `Fork`, which no pair starts with. A pc
+/// in the middle of a pair, saved by a run without fused ops, still runs
+/// the second op. Everything but the interpreter can take a fused op for
+/// its first op alone, and run the second op after it.
+pub fn fuse(ops : &mut [Op])
+{
+    let mut i = 0;
+    while i + 1 < ops.len() {
+        let fused = match (ops[i], ops[i + 1]) {
+            (Op::Add(n), Op::Move(m))                => Op::AddMove(n, m),
+            (Op::Set(n), Op::Add(m))                 => Op::SetAdd(n, m),
+            (Op::Move(n), Op::JumpIfZero(offset))    => Op::MoveJumpIfZero(n, offset),
+            (Op::Move(n), Op::JumpIfNonZero(offset)) => Op::MoveJumpIfNonZero(n, offset),
+            _                                        => {
+                i += 1;
+                continue;
+            }
+        };
+        ops[i] = fused;
+        i += 2;
+    }
+}
+
+/// Serializes `ops` into a bytecode file: `MAGIC`, `FORMAT_VERSION`, the
+/// number of ops, and each op as a byte saying which it is followed by its
+/// operands, as zigzag encoded LEB128 varints. Spans are left out, except
+/// for the source offsets of breakpoints. Fused ops, see `fuse()`, are
+/// written as their first op.
+pub fn encode(ops : &[Op]) -> Vec<u8>
+{
+    let mut out = MAGIC.to_vec();
+    out.push(FORMAT_VERSION);
+    push_unsigned(&mut out, ops.len() as u64);
+    for op in ops {
+        let (tag, operands) : (u8, [Option<i64>; 2]) = match *op {
+            Op::Move(n) | Op::MoveJumpIfZero(n, _) | Op::MoveJumpIfNonZero(n, _) => (0, [Some(n as i64), None]),
+            Op::Add(n) | Op::AddMove(n, _) => (1, [Some(n as i64), None]),
+            Op::Input                 => (2, [None, None]),
+            Op::Output                => (3, [None, None]),
+            Op::Set(n) | Op::SetAdd(n, _)  => (4, [Some(n as i64), None]),
+            Op::MulAdd(offset, n)     => (5, [Some(offset as i64), Some(n as i64)]),
+            Op::AddAt(offset, n)      => (6, [Some(offset as i64), Some(n as i64)]),
+            Op::Scan(stride)          => (7, [Some(stride as i64), None]),
+            Op::JumpIfZero(offset)    => (8, [Some(offset as i64), None]),
+            Op::JumpIfNonZero(offset) => (9, [Some(offset as i64), None]),
+            Op::Debug(offset)         => (10, [Some(offset as i64), None]),
+            Op::Proc(offset)          => (11, [Some(offset as i64), None]),
+            Op::Return                => (12, [None, None]),
+            Op::Call                  => (13, [None, None]),
+            Op::Fork                  => (14, [None, None]),
+            Op::Random                => (15, [None, None])
+        };
+        out.push(tag);
+        for n in operands.iter().flatten() {
+            push_unsigned(&mut out, ((n << 1) ^ (n >> 63)) as u64);
+        }
+    }
+    return out;
+}
+
+/// Reads a bytecode file written by `encode()`. Fails on anything else,
+/// including ops whose jumps don't pair up the way `lower()` pairs them,
+/// so the result can be run like a lowered program.
+pub fn decode(bytes : &[u8]) -> Result<Vec<Op>, RustfuckError>
+{
+    if !bytes.starts_with(MAGIC) {
+        return Err(invalid("not a bytecode file"));
+    }
+    if bytes.get(MAGIC.len()) != Some(&FORMAT_VERSION) {
+        return Err(invalid("unsupported version"));
+    }
+    let mut reader = Reader { bytes, pos: MAGIC.len() + 1 };
+    let count = reader.unsigned()?;
+    /* each op takes a byte at least, which keeps a bad count from allocating much */
+    let mut ops = Vec::with_capacity((count as usize).min(bytes.len()));
+    /* the `JumpIfZero` and `Proc` ops whose partners haven't been read yet */
+    let mut open : Vec<usize> = Vec::new();
+    for _ in 0..count {
+        let tag = reader.byte()?;
+        let op = match tag {
+            0  => Op::Move(reader.int()?),
+            1  => Op::Add(reader.int()?),
+            2  => Op::Input,
+            3  => Op::Output,
+            4  => Op::Set(reader.int()?),
+            5  => Op::MulAdd(reader.int()?, reader.int()?),
+            6  => Op::AddAt(reader.int()?, reader.int()?),
+            7  => Op::Scan(reader.int()?),
+            8  => Op::JumpIfZero(reader.offset()?),
+            9  => Op::JumpIfNonZero(reader.offset()?),
+            10 => Op::Debug(reader.offset()?),
+            11 => Op::Proc(reader.offset()?),
+            12 => Op::Return,
+            13 => Op::Call,
+            14 => Op::Fork,
+            15 => Op::Random,
+            _  => return Err(invalid(&format!("unknown op {}", tag)))
+        };
+        let close = ops.len();
+        let pairs = match (op, open.last().map(|&open| (open, ops[open]))) {
+            (Op::JumpIfZero(_), _) | (Op::Proc(_), _) => {
+                open.push(close);
+                true
+            },
+            (Op::JumpIfNonZero(back), Some((start, Op::JumpIfZero(forward)))) => {
+                open.pop();
+                forward == close - start + 1 && back == close - start - 1
+            },
+            (Op::Return, Some((start, Op::Proc(forward)))) => {
+                open.pop();
+                forward == close - start + 1
+            },
+            (Op::JumpIfNonZero(_), _) | (Op::Return, _) => false,
+            _                                           => true
+        };
+        if !pairs {
+            return Err(invalid(&format!("op {} doesn't close the loop or procedure it is in", close)));
+        }
+        ops.push(op);
+    }
+    if !open.is_empty() {
+        return Err(invalid("a loop or procedure isn't closed"));
+    }
+    if reader.pos != bytes.len() {
+        return Err(invalid("unexpected bytes after the last op"));
+    }
+    return Ok(ops);
+}
+
+/// Turns `ops`, as `lower()` or `decode()` makes them, back into a program
+/// that lowers to the same ops, up to fused ones, which are raised like
+/// their first op. The program has no spans, except for the
+/// source offsets of breakpoints.
+pub fn raise(ops : &[Op]) -> Vec<Stmt>
+{
+    /* blocks of the enclosing loops and procedures */
+    let mut open : Vec<Vec<Stmt>> = Vec::new();
+    let mut code : Vec<Stmt> = Vec::new();
+    let span = Span::default();
+    for op in ops {
+        let stmt = match *op {
+            Op::Move(n) | Op::MoveJumpIfZero(n, _) | Op::MoveJumpIfNonZero(n, _) => Stmt::Move(n, span),
+            Op::Add(n) | Op::AddMove(n, _) => Stmt::Add(n, span),
+            Op::Input            => Stmt::Input(span),
+            Op::Output           => Stmt::Output(span),
+            Op::Set(n) | Op::SetAdd(n, _)  => Stmt::Set(n, span),
+            Op::MulAdd(offset, n) => Stmt::MulAdd(offset, n, span),
+            Op::AddAt(offset, n) => Stmt::AddAt(offset, n, span),
+            Op::Scan(stride)     => Stmt::Scan(stride, span),
+            Op::Debug(offset)    => Stmt::Debug(Span::new(offset, offset + 1)),
+            Op::Call             => Stmt::Call(span),
+            Op::Fork             => Stmt::Fork(span),
+            Op::Random           => Stmt::Random(span),
+            Op::JumpIfZero(_) | Op::Proc(_) => {
+                open.push(mem::take(&mut code));
+                continue;
+            },
+            Op::JumpIfNonZero(_) => Stmt::Loop(mem::replace(&mut code, open.pop().unwrap()), span),
+            Op::Return           => Stmt::Proc(mem::replace(&mut code, open.pop().unwrap()), span)
+        };
+        code.push(stmt);
+    }
+    return code;
+}
+
+/* Appends `n` as an unsigned LEB128 varint */
+fn push_unsigned(out : &mut Vec<u8>, mut n : u64)
+{
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7F) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn invalid(msg : &str) -> RustfuckError
+{
+    return RustfuckError::IO(io::Error::new(io::ErrorKind::InvalidData, format!("invalid bytecode: {}", msg)));
+}
+
+/* Reads the values of a bytecode file in order */
+struct Reader<'a> {
+    bytes : &'a [u8],
+    pos   : usize
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, RustfuckError>
+    {
+        let b = *self.bytes.get(self.pos).ok_or_else(|| invalid("it ends in the middle of an op"))?;
+        self.pos += 1;
+        return Ok(b);
+    }
+
+    fn unsigned(&mut self) -> Result<u64, RustfuckError>
+    {
+        let mut n : u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        return Err(invalid("a number is too long"));
+    }
+
+    fn signed(&mut self) -> Result<i64, RustfuckError>
+    {
+        let n = self.unsigned()?;
+        return Ok((n >> 1) as i64 ^ -((n & 1) as i64));
+    }
+
+    fn int(&mut self) -> Result<i32, RustfuckError>
+    {
+        return i32::try_from(self.signed()?).map_err(|_| invalid("a number doesn't fit 32 bits"));
+    }
+
+    fn offset(&mut self) -> Result<usize, RustfuckError>
+    {
+        return usize::try_from(self.signed()?).map_err(|_| invalid("an offset is negative"));
+    }
+}
@@ -0,0 +1,138 @@
+use Config;
+use config::Bounds;
+use config::CellWidth;
+use config::Eof;
+use config::Io;
+use Stmt;
+use std::fmt::Write;
+
+/* Loops nested deeper are indented no further, or the source would grow with the square of the depth */
+const MAX_INDENT : usize = 32;
+
+fn write_header(c : &mut String, config : &Config)
+{
+    write!(c, "#include <stdint.h>\n").unwrap();
+    write!(c, "#include <stdio.h>\n").unwrap();
+    write!(c, "#include <stdlib.h>\n").unwrap();
+    write!(c, "#include <string.h>\n\n").unwrap();
+    write!(c, "#define TAPE_SIZE {}\n\n", config.tape_size).unwrap();
+    write!(c, "typedef uint{}_t cell;\n\n", config.cell_width.bits()).unwrap();
+    write!(c, "static cell memory[TAPE_SIZE];\n\n").unwrap();
+    write_out_of_tape(c);
+    write_moved(c, config.bounds);
+    write!(c, "int main(void)\n").unwrap();
+    write!(c, "{{\n").unwrap();
+    write!(c, "    cell *p = memory;\n\n").unwrap();
+}
+
+/*
+ * Defines `out_of_tape(message)`, which reports that the pointer left the
+ * tape the way the interpreter does, after what was printed so far, and
+ * exits with status 1
+ */
+fn write_out_of_tape(c : &mut String)
+{
+    write!(c, "static inline void out_of_tape(const char *message)\n").unwrap();
+    write!(c, "{{\n").unwrap();
+    write!(c, "    fflush(stdout);\n").unwrap();
+    write!(c, "    fprintf(stderr, \"Error: runtime error: pointer moved outside of tape%s\\n\", message);\n").unwrap();
+    write!(c, "    exit(1);\n").unwrap();
+    write!(c, "}}\n\n").unwrap();
+}
+
+/*
+ * Defines `moved(p, n)`, the cell `n` cells from `p` as `bounds` says: off
+ * the tape it fails or wraps around, or isn't checked at all
+ */
+fn write_moved(c : &mut String, bounds : Bounds)
+{
+    write!(c, "static inline cell *moved(cell *p, long n)\n").unwrap();
+    write!(c, "{{\n").unwrap();
+    write!(c, "    long target = (long)(p - memory) + n;\n").unwrap();
+    match bounds {
+        Bounds::Error     => {
+            write!(c, "    if (target < 0 || target >= TAPE_SIZE) {{\n").unwrap();
+            write!(c, "        char message[32];\n").unwrap();
+            write!(c, "        sprintf(message, \" (cell %ld)\", target);\n").unwrap();
+            write!(c, "        out_of_tape(message);\n").unwrap();
+            write!(c, "    }}\n").unwrap();
+        },
+        Bounds::Wrap      => write!(c, "    target = (target % TAPE_SIZE + TAPE_SIZE) % TAPE_SIZE;\n").unwrap(),
+        Bounds::Unchecked => ()
+    }
+    write!(c, "    return memory + target;\n").unwrap();
+    write!(c, "}}\n\n").unwrap();
+}
+
+fn write_footer(c : &mut String)
+{
+    write!(c, "\n    return 0;\n").unwrap();
+    write!(c, "}}\n").unwrap();
+}
+
+fn write_line(c : &mut String, depth : usize, line : &str)
+{
+    write!(c, "{}{}\n", "    ".repeat(depth.min(MAX_INDENT) + 1), line).unwrap();
+}
+
+fn write_code(c : &mut String, code : &[Stmt], config : &Config, depth : usize)
+{
+    let width = config.cell_width;
+    /* constants are written as unsigned, so arithmetic on promoted cells can't overflow */
+    let cell = |n : i32| { width.wrap(n) as u32 };
+    for stmt in code {
+        match stmt {
+            Stmt::Move(n, _)      => write_line(c, depth, &format!("p = moved(p, {});", n)),
+            Stmt::Add(n, _)       => write_line(c, depth, &format!("*p += {}u;", cell(*n))),
+            Stmt::Set(n, _)       => write_line(c, depth, &format!("*p = {}u;", cell(*n))),
+            Stmt::AddAt(o, n, _)  => write_line(c, depth, &format!("*moved(p, {}) += {}u;", o, cell(*n))),
+            Stmt::MulAdd(o, f, _) => write_line(c, depth, &format!("if (*p) *moved(p, {}) += *p * {}u;", o, cell(*f))),
+            /* memchr can't scan round a wrapping tape, and finds no zero cell before its end */
+            Stmt::Scan(1, _) if width == CellWidth::Bits8 && config.bounds != Bounds::Wrap => {
+                write_line(c, depth, "p = memchr(p, 0, memory + TAPE_SIZE - p);");
+                write_line(c, depth, "if (!p) out_of_tape(\" while scanning for a zero cell\");");
+            },
+            Stmt::Scan(n, _)      => write_line(c, depth, &format!("while (*p) p = moved(p, {});", n)),
+            Stmt::Input(_)        => write_line(c, depth, match (config.io, config.eof) {
+                (Io::Bytes, Eof::MinusOne)    => "*p = (cell)getchar();",
+                (Io::Bytes, Eof::Zero)        => "{ int c = getchar(); *p = c == EOF ? 0 : (cell)c; }",
+                (Io::Bytes, Eof::Unchanged)   => "{ int c = getchar(); if (c != EOF) *p = (cell)c; }",
+                (Io::Numeric, Eof::MinusOne)  => "{ int n; *p = scanf(\"%d\", &n) == 1 ? (cell)n : (cell)-1; }",
+                (Io::Numeric, Eof::Zero)      => "{ int n; *p = scanf(\"%d\", &n) == 1 ? (cell)n : 0; }",
+                (Io::Numeric, Eof::Unchanged) => "{ int n; if (scanf(\"%d\", &n) == 1) *p = (cell)n; }"
+            }),
+            Stmt::Output(_)       => write_line(c, depth, match config.io {
+                Io::Bytes   => "putchar(*p);",
+                Io::Numeric => "printf(\"%u\\n\", (unsigned)*p);"
+            }),
+            Stmt::Loop(loop_code, _) => {
+                write_line(c, depth, "while (*p) {");
+                write_code(c, loop_code, config, depth + 1);
+                write_line(c, depth, "}");
+            },
+            /* breakpoints are only shown by the interpreter */
+            Stmt::Debug(_)        => (),
+            /* only the interpreter and the llvm backend run them, see `uses_procedures()` */
+            Stmt::Proc(..) | Stmt::Call(_) => panic!("C has no pbrain procedures"),
+            /* only the interpreter runs them, see `uses_forks()` */
+            Stmt::Fork(_)         => panic!("C has no Brainfork threads"),
+            /* only the interpreter and the llvm backend run it, see `uses_random()` */
+            Stmt::Random(_)       => panic!("C has no random bytes")
+        }
+    }
+}
+
+/// Generates a portable C99 translation unit whose `main` runs `code`, with
+/// the configured tape size and wrapping cells of the configured width.
+/// Moving off the tape fails with the interpreter's error or wraps around as
+/// `config.bounds` says, or with `Bounds::Unchecked` isn't checked.
+pub fn code_gen(code : &[Stmt], config : &Config) -> String
+{
+    let mut c : String = String::new();
+
+    write_header(&mut c, config);
+    write_code(&mut c, code, config, 0);
+    write_footer(&mut c);
+
+    return c;
+}
@@ -0,0 +1,239 @@
+use config::CellWidth;
+
+/// The cells of a tape, each stored as wide as the configured cell width so
+/// that arithmetic on them wraps by itself. Values go in and out as `i32`,
+/// already wrapped to the width, so 32-bit cells above `i32::MAX` come out
+/// negative.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Cells {
+    Bits8(Vec<u8>),
+    Bits16(Vec<u16>),
+    Bits32(Vec<u32>)
+}
+
+/* A cell as stored, converted to and from the `i32` it holds */
+trait Cell : Copy + Default + PartialEq {
+    /* `value` wrapped to the cell's width */
+    fn from_value(value : i32) -> Self;
+    fn value(self) -> i32;
+    /* the cell plus `n`, wrapping around */
+    fn add(self, n : i32) -> Self;
+}
+
+impl Cell for u8 {
+    #[inline(always)]
+    fn from_value(value : i32) -> u8
+    {
+        return value as u8;
+    }
+
+    #[inline(always)]
+    fn value(self) -> i32
+    {
+        return self as i32;
+    }
+
+    #[inline(always)]
+    fn add(self, n : i32) -> u8
+    {
+        return self.wrapping_add(n as u8);
+    }
+}
+
+impl Cell for u16 {
+    #[inline(always)]
+    fn from_value(value : i32) -> u16
+    {
+        return value as u16;
+    }
+
+    #[inline(always)]
+    fn value(self) -> i32
+    {
+        return self as i32;
+    }
+
+    #[inline(always)]
+    fn add(self, n : i32) -> u16
+    {
+        return self.wrapping_add(n as u16);
+    }
+}
+
+impl Cell for u32 {
+    #[inline(always)]
+    fn from_value(value : i32) -> u32
+    {
+        return value as u32;
+    }
+
+    #[inline(always)]
+    fn value(self) -> i32
+    {
+        return self as i32;
+    }
+
+    #[inline(always)]
+    fn add(self, n : i32) -> u32
+    {
+        return self.wrapping_add(n as u32);
+    }
+}
+
+impl Cells {
+    /// `len` zeroed cells of the given width.
+    pub fn new(width : CellWidth, len : usize) -> Cells
+    {
+        match width {
+            CellWidth::Bits8  => Cells::Bits8(vec![0; len]),
+            CellWidth::Bits16 => Cells::Bits16(vec![0; len]),
+            CellWidth::Bits32 => Cells::Bits32(vec![0; len])
+        }
+    }
+
+    /// Cells of the given width holding `values`, each wrapped to it.
+    pub fn from_values(width : CellWidth, values : &[i32]) -> Cells
+    {
+        match width {
+            CellWidth::Bits8  => Cells::Bits8(values.iter().map(|&v| u8::from_value(v)).collect()),
+            CellWidth::Bits16 => Cells::Bits16(values.iter().map(|&v| u16::from_value(v)).collect()),
+            CellWidth::Bits32 => Cells::Bits32(values.iter().map(|&v| u32::from_value(v)).collect())
+        }
+    }
+
+    pub fn len(&self) -> usize
+    {
+        match *self {
+            Cells::Bits8(ref cells)  => cells.len(),
+            Cells::Bits16(ref cells) => cells.len(),
+            Cells::Bits32(ref cells) => cells.len()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        return self.len() == 0;
+    }
+
+    /// The value of cell `i`.
+    #[inline(always)]
+    pub fn get(&self, i : usize) -> i32
+    {
+        match *self {
+            Cells::Bits8(ref cells)  => cells[i].value(),
+            Cells::Bits16(ref cells) => cells[i].value(),
+            Cells::Bits32(ref cells) => cells[i].value()
+        }
+    }
+
+    /// Stores `value`, wrapped to the width, in cell `i`.
+    #[inline(always)]
+    pub fn set(&mut self, i : usize, value : i32)
+    {
+        match *self {
+            Cells::Bits8(ref mut cells)  => cells[i] = u8::from_value(value),
+            Cells::Bits16(ref mut cells) => cells[i] = u16::from_value(value),
+            Cells::Bits32(ref mut cells) => cells[i] = u32::from_value(value)
+        }
+    }
+
+    /// Adds `n` to cell `i`, wrapping around.
+    #[inline(always)]
+    pub fn add(&mut self, i : usize, n : i32)
+    {
+        match *self {
+            Cells::Bits8(ref mut cells)  => cells[i] = cells[i].add(n),
+            Cells::Bits16(ref mut cells) => cells[i] = cells[i].add(n),
+            Cells::Bits32(ref mut cells) => cells[i] = cells[i].add(n)
+        }
+    }
+
+    /// Whether cell `i` is zero.
+    #[inline(always)]
+    pub fn is_zero(&self, i : usize) -> bool
+    {
+        match *self {
+            Cells::Bits8(ref cells)  => cells[i] == 0,
+            Cells::Bits16(ref cells) => cells[i] == 0,
+            Cells::Bits32(ref cells) => cells[i] == 0
+        }
+    }
+
+    /// The values of every cell, first to last.
+    pub fn values(&self) -> Vec<i32>
+    {
+        return (0..self.len()).map(|i| self.get(i)).collect();
+    }
+
+    /// The first zero cell from `from` onwards in steps of `stride`, if
+    /// there is one before either end.
+    pub fn find_zero(&self, from : usize, stride : i32) -> Option<usize>
+    {
+        match *self {
+            Cells::Bits8(ref cells)  => find_zero(cells, from, stride),
+            Cells::Bits16(ref cells) => find_zero(cells, from, stride),
+            Cells::Bits32(ref cells) => find_zero(cells, from, stride)
+        }
+    }
+
+    /// Adds `n` zeroed cells before the first, so cell `i` becomes `i + n`.
+    pub fn prepend(&mut self, n : usize)
+    {
+        match *self {
+            Cells::Bits8(ref mut cells)  => { cells.splice(0..0, vec![0; n]); },
+            Cells::Bits16(ref mut cells) => { cells.splice(0..0, vec![0; n]); },
+            Cells::Bits32(ref mut cells) => { cells.splice(0..0, vec![0; n]); }
+        }
+    }
+
+    /// Adds zeroed cells after the last up to `len` cells.
+    pub fn extend_to(&mut self, len : usize)
+    {
+        match *self {
+            Cells::Bits8(ref mut cells)  => cells.resize(len, 0),
+            Cells::Bits16(ref mut cells) => cells.resize(len, 0),
+            Cells::Bits32(ref mut cells) => cells.resize(len, 0)
+        }
+    }
+
+    /// The cells as little-endian bytes, each as wide as the cell width.
+    pub fn to_le_bytes(&self) -> Vec<u8>
+    {
+        match *self {
+            Cells::Bits8(ref cells)  => cells.clone(),
+            Cells::Bits16(ref cells) => cells.iter().flat_map(|c| c.to_le_bytes()).collect(),
+            Cells::Bits32(ref cells) => cells.iter().flat_map(|c| c.to_le_bytes()).collect()
+        }
+    }
+
+    /// Overwrites the cells with little-endian bytes as written by
+    /// `to_le_bytes()`.
+    pub fn copy_from_le_bytes(&mut self, bytes : &[u8])
+    {
+        match *self {
+            Cells::Bits8(ref mut cells)  => cells.copy_from_slice(bytes),
+            Cells::Bits16(ref mut cells) => cells.iter_mut().zip(bytes.chunks(2))
+                    .for_each(|(cell, le)| *cell = u16::from_le_bytes([le[0], le[1]])),
+            Cells::Bits32(ref mut cells) => cells.iter_mut().zip(bytes.chunks(4))
+                    .for_each(|(cell, le)| *cell = u32::from_le_bytes([le[0], le[1], le[2], le[3]]))
+        }
+    }
+}
+
+/* `Cells::find_zero()` for cells of one width */
+fn find_zero<C : Cell>(cells : &[C], from : usize, stride : i32) -> Option<usize>
+{
+    let zero = C::default();
+    match stride {
+        1  => cells[from..].iter().position(|&c| c == zero).map(|i| from + i),
+        -1 => cells[..=from].iter().rposition(|&c| c == zero),
+        _  => {
+            let len = cells.len() as i64;
+            let mut i = from as i64;
+            while i >= 0 && i < len && cells[i as usize] != zero {
+                i += stride as i64;
+            }
+            if i >= 0 && i < len { Some(i as usize) } else { None }
+        }
+    }
+}
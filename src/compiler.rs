@@ -0,0 +1,857 @@
+use Config;
+use config::CellWidth;
+use config::Io;
+use config::Tape;
+use Stmt;
+use bundle;
+use bytecode;
+use uses_breakpoints;
+use uses_forks;
+use uses_random;
+use uses_procedures;
+use RustfuckError;
+use llvm_ir_generator;
+use llvm_ir_generator::DebugSource;
+use object_generator;
+#[cfg(llvm_in_process)]
+use llvm_in_process;
+use std::io::Write;
+use std::io::BufWriter;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::process::Command;
+use std::process::Output;
+#[cfg(not(llvm_in_process))]
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const COLOR_GREEN  : &str = "\x1b[92m";
+const COLOR_PURPLE : &str = "\x1b[95m";
+const COLOR_NONE   : &str = "\x1b[0m";
+
+/* Runs an external toolchain command, failing with its diagnostics if it doesn't succeed */
+fn run_tool(cmd : &mut Command) -> Result<(), RustfuckError>
+{
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let output = cmd.output().map_err(|e| RustfuckError::ToolchainError(
+            format!("failed to run `{}`: {}", program, e)))?;
+    return check_tool(&program, &output);
+}
+
+/*
+ * Runs an external toolchain command like `run_tool()`, with `input` on its
+ * stdin, returning what it wrote to its stdout
+ */
+#[cfg(not(llvm_in_process))]
+fn pipe_tool(cmd : &mut Command, input : &[u8]) -> Result<Vec<u8>, RustfuckError>
+{
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let failed = |e : std::io::Error| RustfuckError::ToolchainError(format!("failed to run `{}`: {}", program, e));
+    let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+            .spawn().map_err(failed)?;
+    let mut stdin = child.stdin.take().unwrap();
+    /* written as the tool reads it, so it can't block writing its output while we block writing its input */
+    let output = thread::scope(|scope| {
+        /* a tool that stops reading early fails, and says why */
+        scope.spawn(move || { let _ = stdin.write_all(input); });
+        child.wait_with_output()
+    }).map_err(failed)?;
+    check_tool(&program, &output)?;
+    return Ok(output.stdout);
+}
+
+/* Fails with the diagnostics of `program`, which ran with `output`, unless it succeeded */
+fn check_tool(program : &str, output : &Output) -> Result<(), RustfuckError>
+{
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let status = match output.status.code() {
+        Some(code) => format!("exit code {}", code),
+        None       => "killed by a signal".to_owned()
+    };
+    return Err(RustfuckError::ToolchainError(
+            format!("`{}` failed ({}):\n{}", program, status, stderr.trim_end())));
+}
+
+/* Whether `tool` is on the PATH, or exists if given as a path */
+fn installed(tool : &str) -> bool
+{
+    if tool.contains('/') {
+        return Path::new(tool).is_file();
+    }
+    let paths : Vec<PathBuf> = env::var_os("PATH")
+            .map(|path| env::split_paths(&path).collect())
+            .unwrap_or_default();
+    return paths.iter().any(|dir| dir.join(tool).is_file());
+}
+
+/* Checks that every tool a build needs is on the PATH, before any step runs */
+#[cfg(not(llvm_in_process))]
+fn require_tools(tools : &[&str]) -> Result<(), RustfuckError>
+{
+    let missing : Vec<&str> = tools.iter()
+            .filter(|tool| !installed(tool))
+            .cloned()
+            .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    return Err(RustfuckError::ToolchainError(
+            format!("could not find {} on the PATH", missing.join(", "))));
+}
+
+/// How `compile()` produces the object file that gets linked.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Backend {
+    /// Emit an x86-64 ELF object directly; only a linker is required.
+    Native,
+    /// Generate LLVM IR and optimize it with `opt` and `llc`, or with the
+    /// linked LLVM libraries when built with `make LLVM=1`.
+    Llvm,
+    /// Bundle the program's bytecode with the running executable, which
+    /// interprets it, see `bundle`; no toolchain is required.
+    Bundle
+}
+
+impl Backend {
+    /// The native backend where it is supported and can build programs with
+    /// `config`, the LLVM pipeline otherwise.
+    pub fn default_for(config : &Config) -> Backend
+    {
+        if cfg!(all(target_arch = "x86_64", target_os = "linux")) && config.tape == Tape::Fixed {
+            return Backend::Native;
+        }
+        return Backend::Llvm;
+    }
+}
+
+/// How much the llvm backend optimizes, trading build time for speed.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    /// Like `O2`, but favoring smaller code.
+    Os,
+    /// Like `Os`, but smaller still, at any cost in speed. The llvm
+    /// backend also generates less code for each statement then.
+    Oz
+}
+
+#[cfg(not(llvm_in_process))]
+impl OptLevel {
+    /* The flag `opt` takes for the level */
+    fn flag(self) -> &'static str
+    {
+        match self {
+            OptLevel::O0 => "-O0",
+            OptLevel::O1 => "-O1",
+            OptLevel::O2 => "-O2",
+            OptLevel::O3 => "-O3",
+            OptLevel::Os => "-Os",
+            OptLevel::Oz => "-Oz"
+        }
+    }
+
+    /* The flag `llc` takes for it, which has no size level */
+    fn codegen_flag(self) -> &'static str
+    {
+        match self {
+            OptLevel::Os | OptLevel::Oz => "-O2",
+            _            => self.flag()
+        }
+    }
+}
+
+/// How `compile()` builds the program, beyond what it does when run.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BuildOptions {
+    /// How much the llvm backend optimizes; `O3` by default.
+    pub opt_level    : OptLevel,
+    /// Whether the llvm backend may use every instruction the CPU building
+    /// the program has, which other CPUs may not; on by default, unless
+    /// building for another `target`.
+    pub native_cpu   : bool,
+    /// The LLVM target triple to build for with the llvm backend, like
+    /// `aarch64-unknown-linux-gnu`, or `None` for the host.
+    pub target       : Option<String>,
+    /// Whether to link libc into the executable, so it runs without one
+    /// installed; musl targets make the smallest of these.
+    pub static_link  : bool,
+    /// Whether the llvm backend makes system calls itself instead of using
+    /// libc, for tiny static executables. Only Linux on x86-64 and AArch64,
+    /// with a fixed tape and bytes for I/O.
+    pub freestanding : bool,
+    /// Whether the llvm backend checks every access of a fixed tape, even
+    /// with unchecked bounds, and reports the source offset of one that
+    /// falls off it on stderr, like the interpreter, before exiting.
+    pub safe         : bool,
+    /// Whether the LLVM IR has a comment with the brainfuck each statement
+    /// was generated from, and loop labels named after how the loops nest,
+    /// for reading the `.ll` file.
+    pub ir_comments  : bool,
+    /// Whether the executable has its symbols stripped, making it smaller
+    /// but harder to debug.
+    pub strip        : bool,
+    /// The compiler driver to link with, like `clang` or a path to one, or
+    /// `None` for the first of the usual ones for the target that is
+    /// installed.
+    pub linker       : Option<String>
+}
+
+impl BuildOptions {
+    /// Whether the target is WebAssembly, which has no position independent
+    /// code; everything else is built as such, for gcc's default PIE.
+    pub fn targets_wasm(&self) -> bool
+    {
+        return self.target.as_ref().is_some_and(|triple| triple.starts_with("wasm"));
+    }
+
+    /// Whether the target is WebAssembly run with WASI, like `wasm32-wasi`,
+    /// whose functions a freestanding program imports instead of libc.
+    pub fn targets_wasi(&self) -> bool
+    {
+        return self.targets_wasm() && self.target.as_ref().is_some_and(|triple| triple.contains("-wasi"));
+    }
+
+    /// The architecture built for, like `x86_64`, from the target triple or
+    /// the host.
+    pub fn arch(&self) -> &str
+    {
+        match self.target {
+            Some(ref triple) => triple.split('-').next().unwrap_or(""),
+            None             => env::consts::ARCH
+        }
+    }
+}
+
+impl Default for BuildOptions {
+    fn default() -> BuildOptions
+    {
+        return BuildOptions { opt_level: OptLevel::O3, native_cpu: true, target: None,
+                              static_link: false, freestanding: false, safe: false, ir_comments: false, strip: false,
+                              linker: None };
+    }
+}
+
+/*
+ * A compiler driver that links executables, with the arguments it needs
+ * before the files, or WebAssembly's linker itself, which links no libc
+ */
+struct Linker {
+    program : String,
+    args    : Vec<String>,
+    driver  : bool
+}
+
+impl Linker {
+    /* `program`, told the target if it is a clang, which can link for any */
+    fn new(program : &str, target : Option<&str>) -> Linker
+    {
+        let name = Path::new(program).file_name().map_or(program.into(), |name| name.to_string_lossy());
+        let args = match target {
+            Some(triple) if name.starts_with("clang") => vec![format!("--target={}", triple)],
+            _                                         => Vec::new()
+        };
+        return Linker { program: program.to_owned(), args, driver: !name.starts_with("wasm-ld") };
+    }
+}
+
+/*
+ * The linkers that can link executables for `target`, best first: for the
+ * host gcc, cc or clang, for musl on the host's architecture musl-gcc, for
+ * WebAssembly clang, whose WASI sysroot it knows, or wasm-ld for a
+ * freestanding program that needs none, and otherwise the GNU
+ * cross compiler, named after the triple without its vendor, like
+ * `aarch64-linux-gnu-gcc`, or clang. Cross compilers for musl are named
+ * like the GNU ones.
+ */
+fn linker_candidates(target : Option<&str>, freestanding : bool) -> Vec<Linker>
+{
+    let triple = match target {
+        Some(triple) => triple,
+        None         => return ["gcc", "cc", "clang"].iter().map(|program| Linker::new(program, None)).collect()
+    };
+    let mut parts : Vec<&str> = triple.split('-').collect();
+    if parts[0].starts_with("wasm") {
+        let mut candidates = vec![Linker::new("clang", target)];
+        if freestanding {
+            candidates.push(Linker::new("wasm-ld", target));
+        }
+        return candidates;
+    }
+    let mut candidates = Vec::new();
+    if parts[0] == env::consts::ARCH && triple.ends_with("-musl") {
+        candidates.push(Linker::new("musl-gcc", target));
+    }
+    if parts.len() == 4 {
+        parts.remove(1);
+    }
+    candidates.push(Linker::new(&format!("{}-gcc", parts.join("-")), target));
+    if !triple.ends_with("-musl") {
+        candidates.push(Linker::new("clang", target));
+    }
+    return candidates;
+}
+
+/* The linker `build` asks for, or else the first of the candidates for its target that is installed */
+fn find_linker(build : &BuildOptions) -> Result<Linker, RustfuckError>
+{
+    let target = build.target.as_deref();
+    if let Some(ref program) = build.linker {
+        if !installed(program) {
+            return Err(RustfuckError::ToolchainError(format!("could not find the linker `{}`", program)));
+        }
+        return Ok(Linker::new(program, target));
+    }
+    let candidates = linker_candidates(target, build.freestanding);
+    let tried : Vec<String> = candidates.iter().map(|linker| linker.program.clone()).collect();
+    if let Some(linker) = candidates.into_iter().find(|linker| installed(&linker.program)) {
+        return Ok(linker);
+    }
+    return Err(RustfuckError::ToolchainError(format!(
+            "no linker found for {}, tried {}; install one or choose one with --linker",
+            target.unwrap_or("the host"), tried.join(", "))));
+}
+
+/* Links the object file in `dir` into its executable, or shared `library`, with `linker`, as `build` says */
+fn run_linker(dir : &BuildDir, build : &BuildOptions, linker : &Linker, library : bool) -> Result<(), RustfuckError>
+{
+    let mut args = linker.args.clone();
+    if library {
+        args.push("-shared".to_owned());
+    }
+    /* WebAssembly modules are always linked statically */
+    if (build.static_link || build.freestanding) && !build.targets_wasm() {
+        args.push("-static".to_owned());
+    }
+    if build.freestanding && linker.driver {
+        args.push("-nostdlib".to_owned());
+    }
+    if build.strip {
+        args.push("-s".to_owned());
+    }
+    let output = if library { Artifact::SharedLibrary } else { Artifact::Executable };
+    run_tool(Command::new(&linker.program).args(args).arg(dir.file(Artifact::Object)).
+            arg("-o").arg(dir.file(output)))
+}
+
+/* Checks that a freestanding program can be built with `backend`, without libc */
+fn check_freestanding(config : &Config, backend : Backend, build : &BuildOptions) -> Result<(), RustfuckError>
+{
+    let unsupported = if backend == Backend::Native {
+        "the native backend"
+    } else if !llvm_ir_generator::supports_freestanding(build.arch())
+            || !build.target.as_ref().map_or(cfg!(target_os = "linux"), |triple| triple.contains("-linux") || build.targets_wasi()) {
+        "this target, only Linux on x86_64 and aarch64, and WASI on wasm32, are"
+    } else if config.tape == Tape::Dynamic {
+        "a dynamic tape, which needs libc to allocate it"
+    } else if config.io == Io::Numeric {
+        "numeric I/O, which needs libc to read and write numbers"
+    } else {
+        return Ok(());
+    };
+    return Err(RustfuckError::ToolchainError(
+            format!("freestanding programs are not supported with {}", unsupported)));
+}
+
+/* Checks that a shared library can be built, which reads and writes bytes in memory, and only runs in a process with libc */
+fn check_library(config : &Config, emit : &[Artifact], build : &BuildOptions) -> Result<(), RustfuckError>
+{
+    let unsupported = if emit.contains(&Artifact::Executable) {
+        "an executable, which has to be built on its own"
+    } else if config.tape == Tape::Dynamic {
+        "a dynamic tape"
+    } else if config.io == Io::Numeric {
+        "numeric I/O"
+    } else if build.freestanding || build.static_link || build.targets_wasm() {
+        "a program that does without a dynamically linked libc"
+    } else if build.safe {
+        "--safe, which exits with the report"
+    } else {
+        return Ok(());
+    };
+    return Err(RustfuckError::ToolchainError(
+            format!("shared libraries are not supported with {}", unsupported)));
+}
+
+/// Files `compile()` can produce. Each is copied to the path from
+/// `artifact_file()`: the executable path itself for the only one, otherwise
+/// named after it with the artifact's extension.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Artifact {
+    /// Unoptimized textual LLVM IR (`.ll`).
+    LlvmIr,
+    /// Optimized LLVM bitcode (`.bc`).
+    Bitcode,
+    /// Assembly for the host (`.s`).
+    Assembly,
+    /// The relocatable object file that gets linked (`.o`).
+    Object,
+    /// The linked executable.
+    Executable,
+    /// A shared library (`.so`, or `.dylib` on macOS) exporting `bf_run()`
+    /// instead of an executable, see `llvm_ir_generator::code_gen_library()`.
+    SharedLibrary,
+    /// The lowered program as bytecode (`.bfc`), see `bytecode::encode()`,
+    /// which rustfuck runs without parsing or optimizing it. It is written
+    /// without a backend.
+    Bytecode
+}
+
+impl Artifact {
+    fn description(self) -> &'static str
+    {
+        match self {
+            Artifact::LlvmIr     => "LLVM IR",
+            Artifact::Bitcode    => "LLVM bitcode",
+            Artifact::Assembly   => "assembly",
+            Artifact::Object     => "object file",
+            Artifact::Executable => "executable",
+            Artifact::SharedLibrary => "shared library",
+            Artifact::Bytecode   => "bytecode"
+        }
+    }
+
+    /// What the file of the artifact is named after the executable with,
+    /// e.g. `.o`, or nothing for the executable.
+    pub fn extension(self) -> &'static str
+    {
+        match self {
+            Artifact::LlvmIr     => ".ll",
+            Artifact::Bitcode    => ".bc",
+            Artifact::Assembly   => ".s",
+            Artifact::Object     => ".o",
+            Artifact::Executable => "",
+            Artifact::SharedLibrary => if cfg!(target_os = "macos") { ".dylib" } else { ".so" },
+            Artifact::Bytecode   => ".bfc"
+        }
+    }
+}
+
+/// The path `compile()` writes `artifact` to when building `executable_file`
+/// with the artifacts `all`: `executable_file` itself if it is the only one,
+/// so `-o foo.ll` doesn't become `foo.ll.ll`, otherwise with its extension.
+pub fn artifact_file(executable_file : &str, artifact : Artifact, all : &[Artifact]) -> String
+{
+    if all == [artifact] {
+        return executable_file.to_owned();
+    }
+    return format!("{}{}", executable_file, artifact.extension());
+}
+
+/* Numbers the build steps as they run, unless `quiet` */
+struct Progress {
+    step  : usize,
+    steps : usize,
+    quiet : bool
+}
+
+impl Progress {
+    fn new(quiet : bool) -> Progress
+    {
+        Progress { step: 0, steps: 0, quiet }
+    }
+
+    fn next(&mut self, msg : &str)
+    {
+        self.step += 1;
+        if self.quiet {
+            return;
+        }
+        println!("[{}{}/{}{}] {}", COLOR_PURPLE, self.step, self.steps, COLOR_NONE, msg);
+    }
+}
+
+/* A per-build directory for intermediate files, removed with its contents when dropped */
+struct BuildDir {
+    path : PathBuf
+}
+
+impl BuildDir {
+    fn new() -> Result<BuildDir, RustfuckError>
+    {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let path = env::temp_dir().join(format!("rustfuck-{}-{}", process::id(), nanos));
+        fs::create_dir(&path)?;
+        Ok(BuildDir { path })
+    }
+
+    /* a directory of its own inside this one, for one of several builds sharing it */
+    fn within(&self, name : &str) -> Result<BuildDir, RustfuckError>
+    {
+        let path = self.path.join(name);
+        fs::create_dir(&path)?;
+        Ok(BuildDir { path })
+    }
+
+    /* where `artifact` is built inside the directory */
+    fn file(&self, artifact : Artifact) -> String
+    {
+        let file = format!("program{}", artifact.extension());
+        return self.path.join(file).to_string_lossy().into_owned();
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/* Copies the artifacts which were asked for out of the build directory, but the bytecode, which was written already */
+fn copy_artifacts(progress : &mut Progress, dir : &BuildDir, executable_file : &str, emit : &[Artifact]) -> Result<(), RustfuckError>
+{
+    progress.next("Copying build artifacts...");
+    for &artifact in emit.iter().filter(|&&artifact| artifact != Artifact::Bytecode) {
+        fs::copy(dir.file(artifact), artifact_file(executable_file, artifact, emit))?;
+    }
+    Ok(())
+}
+
+/// Compiles `program`, with the semantics in `config`, into a native
+/// executable at the path `executable_file` using the given backend, linking
+/// the result with the linker in `build`, or the first one installed of gcc,
+/// cc and clang, or the cross compilers for another target. `emit`
+/// selects the
+/// artifacts that are kept (see `Artifact`), the only one written to
+/// `executable_file` itself; the build stops after the last
+/// step needed to produce them. Every step runs in a temporary directory, and
+/// only the kept artifacts are copied out of it. Given the `debug_source`
+/// the program was parsed from, the llvm backend adds DWARF line info
+/// pointing into it. The llvm backend optimizes, and builds for the target,
+/// that `build` says; the native backend only builds for the host.
+/// Bytecode is written without either, and only needs the program.
+pub fn compile(program : &[Stmt], config : &Config, executable_file : &str, backend : Backend, emit : &[Artifact],
+               debug_source : Option<&DebugSource>, build : &BuildOptions) -> Result<(), RustfuckError>
+{
+    if write_bytecode(program, executable_file, emit)? {
+        check_build(config, backend, emit, debug_source.is_some(), build)?;
+        check_extensions(program, config, backend, emit)?;
+        let dir = BuildDir::new()?;
+        let mut progress = Progress::new(false);
+        match backend {
+            Backend::Native => compile_native(program, config, executable_file, emit, build, &dir, &mut progress)?,
+            Backend::Llvm   => compile_llvm(program, config, executable_file, emit, debug_source, build, &dir, &mut progress)?,
+            Backend::Bundle => compile_bundle(program, config, executable_file, emit, &mut progress)?
+        }
+    }
+
+    println!();
+    for &artifact in emit {
+        println!("\t{}Successfully built {}{}: {}", COLOR_GREEN, artifact.description(), COLOR_NONE,
+                 artifact_file(executable_file, artifact, emit));
+    }
+
+    Ok(())
+}
+
+/// A program for `compile_batch()` to build, with the arguments `compile()`
+/// takes for it alone.
+pub struct BatchProgram<'a> {
+    pub program         : &'a [Stmt],
+    pub executable_file : &'a str,
+    pub debug_source    : Option<DebugSource<'a>>
+}
+
+/// Builds each of `programs` like `compile()`, with the same semantics,
+/// backend, artifacts and build options, but in one temporary directory and
+/// several at a time, on up to as many threads as there are CPUs. Fails
+/// without building any if the options can't build them; otherwise returns
+/// how each build went, in order. Nothing is printed, the steps of parallel
+/// builds would only be interleaved.
+pub fn compile_batch(programs : &[BatchProgram], config : &Config, backend : Backend, emit : &[Artifact],
+                     build : &BuildOptions) -> Result<Vec<Result<(), RustfuckError>>, RustfuckError>
+{
+    let debug = programs.iter().any(|p| p.debug_source.is_some());
+    check_build(config, backend, emit, debug, build)?;
+    let dir = BuildDir::new()?;
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(programs.iter().map(|_| None).collect::<Vec<_>>());
+    let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(programs.len());
+
+    /* each worker takes the next program nobody is building yet */
+    let build_next = || loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let p = match programs.get(i) {
+            Some(p) => p,
+            None    => break
+        };
+        let mut progress = Progress::new(true);
+        let result = write_bytecode(p.program, p.executable_file, emit).and_then(|more| {
+            if !more {
+                return Ok(());
+            }
+            check_extensions(p.program, config, backend, emit)
+                    .and_then(|_| dir.within(&i.to_string()))
+                    .and_then(|dir| match backend {
+                        Backend::Native => compile_native(p.program, config, p.executable_file, emit, build, &dir, &mut progress),
+                        Backend::Llvm   => compile_llvm(p.program, config, p.executable_file, emit, p.debug_source.as_ref(),
+                                                        build, &dir, &mut progress),
+                        Backend::Bundle => compile_bundle(p.program, config, p.executable_file, emit, &mut progress)
+                    })
+        });
+        results.lock().unwrap()[i] = Some(result);
+    };
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(&build_next);
+        }
+    });
+
+    Ok(results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect())
+}
+
+/* Writes the bytecode of `program` for `executable_file` if `emit` has it, returning whether there are other artifacts left to build */
+fn write_bytecode(program : &[Stmt], executable_file : &str, emit : &[Artifact]) -> Result<bool, RustfuckError>
+{
+    if emit.contains(&Artifact::Bytecode) {
+        fs::write(artifact_file(executable_file, Artifact::Bytecode, emit), bytecode::encode(&bytecode::lower(program)))?;
+    }
+    return Ok(emit.iter().any(|&artifact| artifact != Artifact::Bytecode));
+}
+
+/* Fails if `backend` can't build with these options, before any step runs */
+fn check_build(config : &Config, backend : Backend, emit : &[Artifact], debug : bool, build : &BuildOptions) -> Result<(), RustfuckError>
+{
+    if backend == Backend::Native && debug {
+        return Err(RustfuckError::ToolchainError(
+                "the native backend cannot emit debug info, use the llvm backend".to_owned()));
+    }
+    if backend == Backend::Native && build.target.is_some() {
+        return Err(RustfuckError::ToolchainError(
+                "the native backend only builds for the host, use the llvm backend".to_owned()));
+    }
+    if backend == Backend::Bundle && (debug || build.target.is_some() || build.freestanding) {
+        return Err(RustfuckError::ToolchainError(
+                "the bundle backend only bundles the interpreter for the host, use the llvm backend".to_owned()));
+    }
+    if backend == Backend::Native && build.safe {
+        return Err(RustfuckError::ToolchainError(
+                "the native backend cannot report where the pointer moved off the tape, use the llvm backend".to_owned()));
+    }
+    if build.freestanding {
+        check_freestanding(config, backend, build)?;
+    }
+    if emit.contains(&Artifact::SharedLibrary) && backend == Backend::Llvm {
+        check_library(config, emit, build)?;
+    }
+    Ok(())
+}
+
+/* Fails if `program` has Brainfork threads, random bytes, breakpoints or pbrain procedures `backend` can't build */
+fn check_extensions(program : &[Stmt], config : &Config, backend : Backend, emit : &[Artifact]) -> Result<(), RustfuckError>
+{
+    /* the interpreter runs them all */
+    if backend == Backend::Bundle {
+        return Ok(());
+    }
+    if uses_forks(program) {
+        return Err(RustfuckError::ToolchainError("Brainfork threads can only be run by the interpreter".to_owned()));
+    }
+    if backend == Backend::Native && uses_random(program) {
+        return Err(RustfuckError::ToolchainError(
+                "the native backend does not support random bytes, use the llvm backend".to_owned()));
+    }
+    if backend == Backend::Native && uses_breakpoints(program) {
+        return Err(RustfuckError::ToolchainError(
+                "the native backend does not support breakpoints, use the llvm backend".to_owned()));
+    }
+    if !uses_procedures(program) {
+        return Ok(());
+    }
+    let unsupported = if backend == Backend::Native {
+        "the native backend does not support pbrain procedures, use the llvm backend"
+    } else if config.cell_width == CellWidth::Bits32 {
+        "pbrain procedures are not supported with 32-bit cells, which would number too many"
+    } else if emit.contains(&Artifact::SharedLibrary) {
+        "pbrain procedures are not supported in shared libraries"
+    } else {
+        return Ok(());
+    };
+    return Err(RustfuckError::ToolchainError(unsupported.to_owned()));
+}
+
+/* The LLVM IR of the program, or of a shared `library` running it */
+fn generate_ir(program : &[Stmt], config : &Config, build : &BuildOptions, debug_source : Option<&DebugSource>, library : bool) -> String
+{
+    if library {
+        return llvm_ir_generator::code_gen_library(program, config, build, debug_source);
+    }
+    return llvm_ir_generator::code_gen_for(program, config, build, debug_source);
+}
+
+/// The unoptimized LLVM IR the llvm backend would build `program` from,
+/// like `compile()` with only `Artifact::LlvmIr` emitted, but returned
+/// instead of written to a file, without running any tools.
+pub fn llvm_ir(program : &[Stmt], config : &Config, debug_source : Option<&DebugSource>, build : &BuildOptions) -> Result<String, RustfuckError>
+{
+    if build.freestanding {
+        check_freestanding(config, Backend::Llvm, build)?;
+    }
+    check_extensions(program, config, Backend::Llvm, &[Artifact::LlvmIr])?;
+    return Ok(llvm_ir_generator::code_gen_for(program, config, build, debug_source));
+}
+
+fn compile_native(program : &[Stmt], config : &Config, executable_file : &str, emit : &[Artifact], build : &BuildOptions,
+                  dir : &BuildDir, progress : &mut Progress) -> Result<(), RustfuckError>
+{
+    if let Some(artifact) = emit.iter().find(|a| !matches!(a, Artifact::Object | Artifact::Executable | Artifact::Bytecode)) {
+        return Err(RustfuckError::ToolchainError(
+                format!("the native backend cannot emit {}, use the llvm backend", artifact.description())));
+    }
+    if config.tape == Tape::Dynamic {
+        return Err(RustfuckError::ToolchainError(
+                "the native backend does not support a dynamic tape, use the llvm backend".to_owned()));
+    }
+    let link = emit.contains(&Artifact::Executable);
+    let linker = if link { Some(find_linker(build)?) } else { None };
+    let o_file = dir.file(Artifact::Object);
+    progress.steps = if link { 3 } else { 2 };
+
+    /* generate object file */
+    progress.next("Generating object file...");
+    let f = File::create(&o_file)?;
+    BufWriter::new(f).write_all(&object_generator::code_gen(program, config))?;
+
+    if let Some(ref linker) = linker {
+        /* Run linker */
+        progress.next("Running linker...");
+        run_linker(dir, build, linker, false)?;
+    }
+
+    copy_artifacts(progress, dir, executable_file, emit)
+}
+
+fn compile_bundle(program : &[Stmt], config : &Config, executable_file : &str, emit : &[Artifact], progress : &mut Progress)
+                  -> Result<(), RustfuckError>
+{
+    if let Some(artifact) = emit.iter().find(|&&a| a != Artifact::Executable && a != Artifact::Bytecode) {
+        return Err(RustfuckError::ToolchainError(
+                format!("the bundle backend cannot emit {}, use the llvm backend", artifact.description())));
+    }
+    progress.steps = 1;
+    progress.next("Bundling the program with the interpreter...");
+    bundle::write(program, config, executable_file)
+}
+
+#[cfg(llvm_in_process)]
+fn compile_llvm(program : &[Stmt], config : &Config, executable_file : &str, emit : &[Artifact],
+                debug_source : Option<&DebugSource>, build : &BuildOptions, dir : &BuildDir, progress : &mut Progress)
+                -> Result<(), RustfuckError>
+{
+    let wanted = |artifact : Artifact| emit.contains(&artifact);
+    let library = wanted(Artifact::SharedLibrary);
+    let link = wanted(Artifact::Executable) || library;
+    let needs_object = link || wanted(Artifact::Object);
+    let compile = needs_object || wanted(Artifact::Bitcode) || wanted(Artifact::Assembly);
+    let linker = if link { Some(find_linker(build)?) } else { None };
+    progress.steps = if link { 3 } else { 2 };
+
+    /* compile the LLVM IR in-process */
+    progress.next("Generating and compiling LLVM ir... (this step might take some time)");
+    let ir = generate_ir(program, config, build, debug_source, library);
+    if wanted(Artifact::LlvmIr) {
+        let f = File::create(dir.file(Artifact::LlvmIr))?;
+        BufWriter::new(f).write_all(ir.as_bytes())?;
+    }
+    if compile {
+        let bc_file  = Some(dir.file(Artifact::Bitcode)).filter(|_| wanted(Artifact::Bitcode));
+        let asm_file = Some(dir.file(Artifact::Assembly)).filter(|_| wanted(Artifact::Assembly));
+        let o_file   = Some(dir.file(Artifact::Object)).filter(|_| needs_object);
+        llvm_in_process::compile_ir(&ir, build, bc_file.as_deref(), asm_file.as_deref(), o_file.as_deref())?;
+    }
+
+    if let Some(ref linker) = linker {
+        /* Run linker */
+        progress.next("Running linker...");
+        run_linker(dir, build, linker, library)?;
+    }
+
+    copy_artifacts(progress, dir, executable_file, emit)
+}
+
+#[cfg(not(llvm_in_process))]
+fn compile_llvm(program : &[Stmt], config : &Config, executable_file : &str, emit : &[Artifact],
+                debug_source : Option<&DebugSource>, build : &BuildOptions, dir : &BuildDir, progress : &mut Progress)
+                -> Result<(), RustfuckError>
+{
+    let wanted = |artifact : Artifact| emit.contains(&artifact);
+
+    /* work out which steps have to run, each one feeding the next */
+    let library = wanted(Artifact::SharedLibrary);
+    let link = wanted(Artifact::Executable) || library;
+    let assemble = wanted(Artifact::Assembly);
+    let needs_object = link || wanted(Artifact::Object);
+    let optimize = needs_object || assemble || wanted(Artifact::Bitcode);
+    let target = build.target.as_deref();
+    let tools = [("opt", optimize), ("llc", assemble || needs_object)];
+    require_tools(&tools.iter().filter(|t| t.1).map(|t| t.0).collect::<Vec<_>>())?;
+    let linker = if link { Some(find_linker(build)?) } else { None };
+    let steps = [true, optimize, assemble, needs_object, link, true];
+    progress.steps = steps.iter().filter(|&&s| s).count();
+
+    /* otherwise, only what every CPU of the target's architecture has */
+    let cpu = if build.native_cpu && target.is_none() { "-mcpu=native" } else { "-mcpu=generic" };
+    let relocation = if build.targets_wasm() { "-relocation-model=static" } else { "-relocation-model=pic" };
+
+    /* generate LLVM IR, which is only written to a file if it was asked for */
+    progress.next("Generating LLVM ir...");
+    let ir = generate_ir(program, config, build, debug_source, library);
+    if wanted(Artifact::LlvmIr) {
+        let f = File::create(dir.file(Artifact::LlvmIr))?;
+        BufWriter::new(f).write_all(ir.as_bytes())?;
+    }
+
+    /* the optimized bitcode, piped from opt into llc, and only written to a file if it was asked for */
+    let mut bitcode = Vec::new();
+    if optimize {
+        /* Run LLVM optimizer */
+        progress.next("Running LLVM optimizer... (this step might take some time)");
+        bitcode = pipe_tool(Command::new("opt").arg("-")
+                .arg(build.opt_level.flag()).arg(cpu)
+                .arg("-o").arg("-"), ir.as_bytes())?;
+        drop(ir);
+        if wanted(Artifact::Bitcode) {
+            fs::write(dir.file(Artifact::Bitcode), &bitcode)?;
+        }
+    }
+
+    if assemble {
+        /* Run LLVM static compiler, producing assembly */
+        progress.next("Generating assembly...");
+        pipe_tool(Command::new("llc").arg("-").arg("-filetype=asm")
+                .arg(build.opt_level.codegen_flag()).arg(cpu)
+                .arg(relocation).arg("-o").arg(dir.file(Artifact::Assembly)), &bitcode)?;
+    }
+
+    if needs_object {
+        /* Run LLVM static compiler, into the file the linker reads */
+        progress.next("Running LLVM compiler...");
+        pipe_tool(Command::new("llc").arg("-").arg("-filetype=obj")
+                .arg(build.opt_level.codegen_flag()).arg(cpu)
+                .arg(relocation).arg("-o").arg(dir.file(Artifact::Object)), &bitcode)?;
+    }
+
+    if let Some(ref linker) = linker {
+        /* Run LLVM linker */
+        progress.next("Running linker...");
+        run_linker(dir, build, linker, library)?;
+    }
+
+    copy_artifacts(progress, dir, executable_file, emit)
+}
@@ -0,0 +1,171 @@
+use TAPE_SIZE;
+use std::time::Duration;
+
+/// What happens when the pointer moves past either end of the tape.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Tape {
+    /// The tape has exactly `Config::tape_size` cells.
+    Fixed,
+    /// The tape starts out with `Config::tape_size` cells and grows in
+    /// either direction whenever the pointer moves past an end.
+    Dynamic
+}
+
+/// What happens when the pointer moves off a fixed tape.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Bounds {
+    /// Stop the program with a runtime error.
+    Error,
+    /// Wrap around to the other end of the tape.
+    Wrap,
+    /// Don't check at all in compiled code, for speed. The interpreter and
+    /// JIT share memory with rustfuck itself, so they still report an error.
+    Unchecked
+}
+
+/// Width of a tape cell. Cell arithmetic wraps around at 2^bits.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CellWidth {
+    Bits8,
+    Bits16,
+    Bits32
+}
+
+impl CellWidth {
+    /// Number of bits in a cell.
+    pub fn bits(self) -> u32
+    {
+        match self {
+            CellWidth::Bits8  => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32
+        }
+    }
+
+    /// Number of bytes a cell takes up in memory.
+    pub fn bytes(self) -> usize
+    {
+        return self.bits() as usize / 8;
+    }
+
+    /// Reduces `value` to a cell value, as the bit pattern of a `bits` wide
+    /// unsigned integer (so 32-bit cells may come out negative).
+    pub fn wrap(self, value : i32) -> i32
+    {
+        match self {
+            CellWidth::Bits8  => value & 0xFF,
+            CellWidth::Bits16 => value & 0xFFFF,
+            CellWidth::Bits32 => value
+        }
+    }
+}
+
+/// What `,` stores when there is no more input.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Eof {
+    /// Store 0.
+    Zero,
+    /// Store -1, i.e. a cell with all bits set (like C's `EOF`).
+    MinusOne,
+    /// Leave the cell as it is.
+    Unchanged
+}
+
+/// How `,` and `.` exchange cell values with the outside world.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Io {
+    /// One byte per cell.
+    Bytes,
+    /// `.` prints the cell as an unsigned decimal number and a newline, and
+    /// `,` reads a (possibly signed) decimal number, wrapped to the cell
+    /// width. If no number can be read, `,` acts as at the end of input.
+    Numeric
+}
+
+/// When buffered program output is flushed.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Flush {
+    /// After each newline, and before reading input.
+    Line,
+    /// Only when the buffer is full and when the program ends.
+    Full,
+    /// After every byte.
+    EveryByte
+}
+
+impl Flush {
+    /// Whether output should be flushed right after writing `byte`.
+    pub fn after(self, byte : u8) -> bool
+    {
+        match self {
+            Flush::Line      => byte == b'\n',
+            Flush::Full      => false,
+            Flush::EveryByte => true
+        }
+    }
+}
+
+/// Runtime semantics of a program, shared by the interpreter, the JIT and
+/// every code generator so they can't drift apart.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Config {
+    /// Number of cells on the tape, at most `i32::MAX`.
+    pub tape_size  : usize,
+    /// Whether the tape is fixed or grows on demand.
+    pub tape       : Tape,
+    /// How moving off a fixed tape is handled.
+    pub bounds     : Bounds,
+    /// Width of each cell.
+    pub cell_width : CellWidth,
+    /// How cells are read and written.
+    pub io         : Io,
+    /// What reading past the end of input does.
+    pub eof        : Eof,
+    /// When output is flushed by the interpreter, the JIT and programs
+    /// built with the llvm backend.
+    pub flush      : Flush,
+    /// Number of instructions the interpreter may execute before it stops
+    /// the program, if limited.
+    pub max_steps  : Option<u64>,
+    /// How long the interpreter may run a program before it stops it, if
+    /// limited.
+    pub timeout    : Option<Duration>,
+    /// What the random bytes `?` stores start from, so they come out the
+    /// same in every run, interpreted or compiled. Different in each run if
+    /// not given.
+    pub seed       : Option<u64>
+}
+
+impl Default for Config {
+    fn default() -> Config
+    {
+        return Config {
+            tape_size:  TAPE_SIZE,
+            tape:       Tape::Fixed,
+            bounds:     Bounds::Error,
+            cell_width: CellWidth::Bits8,
+            io:         Io::Bytes,
+            eof:        Eof::MinusOne,
+            flush:      Flush::Line,
+            max_steps:  None,
+            timeout:    None,
+            seed:       None
+        };
+    }
+}
+
+impl Config {
+    /// This configuration with the conventions brainfuck self-interpreters
+    /// like dbfi.b are written for: 8-bit cells, a tape that grows as far
+    /// as the programs they run need, and 0 for the end of input. The tape
+    /// size, I/O, flushing, limits and seed are kept.
+    pub fn for_self_interpreters(&self) -> Config
+    {
+        return Config {
+            tape:       Tape::Dynamic,
+            cell_width: CellWidth::Bits8,
+            eof:        Eof::Zero,
+            ..self.clone()
+        };
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * An interactive debugger in the terminal. The screen is drawn with plain
+ * ANSI escape codes: the source around the current instruction, the tape
+ * around the pointer and the program's latest output, redrawn after every
+ * command. Keys are read from stdin in raw mode, so the program itself reads
+ * its input from elsewhere.
+ */
+
+use std::collections::HashSet;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use Config;
+use Stmt;
+use RustfuckError;
+use diagnostic::Diagnostic;
+use interpreter::ProgramState;
+use interpreter::Stepper;
+use bytecode::Op;
+use terminal;
+use terminal::RawMode;
+
+/* Instructions run between checks for a keypress that interrupts `continue` */
+const POLL_INTERVAL : u64 = 1 << 14;
+
+/* Lines of the screen taken by everything but the source */
+const CHROME_LINES : usize = 13;
+
+/* Lines of program output shown, from at most the last this many bytes of it */
+const OUTPUT_LINES : usize = 3;
+const OUTPUT_TAIL  : usize = 4096;
+
+/* Width of a cell in the tape view */
+const CELL_WIDTH : usize = 6;
+
+const HELP : &str = "s/space step  c continue  b toggle breakpoint  h/l scroll tape  p find pointer  q quit";
+
+struct Session<'a> {
+    src         : &'a [u8],
+    name        : &'a str,
+    stepper     : Stepper,
+    state       : ProgramState,
+    input       : &'a mut dyn Read,
+    output      : Vec<u8>,
+    /* instructions the user put a breakpoint on, besides the `#`s */
+    breakpoints : HashSet<usize>,
+    steps       : u64,
+    /* first cell shown in the tape view, and whether it should follow the pointer */
+    tape_start  : i64,
+    follow      : bool,
+    status      : String,
+    failed      : bool
+}
+
+/// Runs `code`, parsed from `src` in the file `name`, in the debugger until
+/// the user quits. The program reads `,` from `input`; its output is shown
+/// on screen. Breakpoints are the `#`s in the program, if it was parsed with
+/// them, and any the user sets.
+pub fn run(code : &[Stmt], src : &[u8], name : &str, config : &Config, input : &mut dyn Read)
+        -> Result<(), RustfuckError>
+{
+    let _raw_mode = RawMode::enable_without_signals()?;
+    let mut session = Session {
+        src,
+        name,
+        stepper:     Stepper::new(code),
+        state:       ProgramState::with_config(config),
+        input,
+        output:      Vec::new(),
+        breakpoints: HashSet::new(),
+        steps:       0,
+        tape_start:  0,
+        follow:      true,
+        status:      "stopped at the start of the program".to_owned(),
+        failed:      false
+    };
+
+    let stdout = io::stdout();
+    let mut screen = stdout.lock();
+    write!(screen, "\x1b[?1049h\x1b[?25l")?; // switch to the alternate screen and hide the cursor
+    let result = session.interact(&mut screen);
+    write!(screen, "\x1b[?25h\x1b[?1049l")?;
+    screen.flush()?;
+    return result;
+}
+
+impl<'a> Session<'a> {
+    fn interact(&mut self, screen : &mut dyn Write) -> Result<(), RustfuckError>
+    {
+        let mut key = [0u8; 1];
+        loop {
+            self.draw(screen)?;
+            if io::stdin().read(&mut key)? == 0 {
+                return Ok(());
+            }
+            match key[0] {
+                b's' | b' ' => self.step(),
+                b'c'        => self.resume(),
+                b'b'        => self.toggle_breakpoint(),
+                b'h'        => self.scroll(-1),
+                b'l'        => self.scroll(1),
+                b'p'        => self.follow = true,
+                b'q' | 3    => return Ok(()), // Ctrl-C doesn't send a signal in this mode
+                _           => ()
+            }
+        }
+    }
+
+    fn scroll(&mut self, cells : i64)
+    {
+        self.tape_start += cells;
+        self.follow = false;
+    }
+
+    /* Runs one instruction, and reports whether the program can go on */
+    fn step_once(&mut self) -> bool
+    {
+        if self.failed {
+            return false;
+        }
+        if self.stepper.is_finished() {
+            self.status = "the program has finished".to_owned();
+            return false;
+        }
+        self.follow = true;
+        if let Err(e) = self.stepper.step(&mut self.state, &mut *self.input, &mut self.output) {
+            self.status = e.to_string();
+            self.failed = true;
+            return false;
+        }
+        self.steps += 1;
+        if self.stepper.is_finished() {
+            self.status = "the program has finished".to_owned();
+            return false;
+        }
+        return true;
+    }
+
+    fn step(&mut self)
+    {
+        if self.step_once() {
+            self.status = "stepped".to_owned();
+        }
+    }
+
+    /* Runs until a breakpoint, the end of the program or a keypress */
+    fn resume(&mut self)
+    {
+        let mut n : u64 = 0;
+        while self.step_once() {
+            let pc = self.stepper.pc();
+            if let Some(Op::Debug(_)) = self.stepper.next_op() {
+                self.status = "stopped at a # breakpoint".to_owned();
+                return;
+            }
+            if self.breakpoints.contains(&pc) {
+                self.status = "stopped at a breakpoint".to_owned();
+                return;
+            }
+            n += 1;
+            if n.is_multiple_of(POLL_INTERVAL) && terminal::input_pending() {
+                let mut key = [0u8; 1];
+                let _ = io::stdin().read(&mut key);
+                self.status = "interrupted".to_owned();
+                return;
+            }
+        }
+    }
+
+    fn toggle_breakpoint(&mut self)
+    {
+        let pc = self.stepper.pc();
+        if self.stepper.is_finished() {
+            return;
+        }
+        if self.breakpoints.remove(&pc) {
+            self.status = format!("removed the breakpoint on instruction {}", pc);
+        } else {
+            self.breakpoints.insert(pc);
+            self.status = format!("set a breakpoint on instruction {}", pc);
+        }
+    }
+
+    fn draw(&mut self, screen : &mut dyn Write) -> io::Result<()>
+    {
+        let (rows, cols) = terminal::size();
+        let mut frame = String::new();
+        frame.push_str("\x1b[H\x1b[2J");
+
+        /* header with the next instruction */
+        let next = match self.stepper.next_op() {
+            Some(op) => format!("{:>6}: {}", self.stepper.pc(), op),
+            None     => "   end".to_owned()
+        };
+        let mark = if self.breakpoints.contains(&self.stepper.pc()) { " (breakpoint)" } else { "" };
+        push_line(&mut frame, &format!("{}  step {}  next {}{}", self.name, self.steps, next, mark), cols);
+        push_line(&mut frame, &self.status, cols);
+        push_line(&mut frame, "", cols);
+
+        self.draw_source(&mut frame, rows.saturating_sub(CHROME_LINES).max(3), cols);
+        push_line(&mut frame, "", cols);
+        self.draw_tape(&mut frame, cols);
+        push_line(&mut frame, "", cols);
+        self.draw_output(&mut frame, cols);
+        frame.push_str(&format!("\x1b[7m{}\x1b[0m", truncate(HELP, cols)));
+
+        screen.write_all(frame.as_bytes())?;
+        return screen.flush();
+    }
+
+    /*
+     * The source lines around the current instruction, with the characters
+     * it came from highlighted, as far as they are on its first line.
+     */
+    fn draw_source(&self, frame : &mut String, height : usize, cols : usize)
+    {
+        let span = self.stepper.span();
+        let location = span.map(|span| Diagnostic::new(self.src, span.start, ""));
+        let len = span.map_or(1, |span| span.end.saturating_sub(span.start).max(1));
+        let lines : Vec<&[u8]> = self.src.split(|&b| b == b'\n').collect();
+        let current = location.as_ref().map_or(1, |diag| diag.line);
+        let first = current.saturating_sub(height / 2).max(1).min(lines.len().saturating_sub(height) + 1);
+        for number in first..first + height {
+            let line = match lines.get(number - 1) {
+                Some(line) => String::from_utf8_lossy(line).replace('\t', " "),
+                None       => {
+                    push_line(frame, "", cols);
+                    continue;
+                }
+            };
+            let gutter = format!("{:>5} | ", number);
+            let width = cols.saturating_sub(gutter.len());
+            let text = match location {
+                Some(ref diag) if diag.line == number => highlight(&line, diag.col - 1, len, width),
+                _                                     => truncate(&line, width)
+            };
+            frame.push_str(&format!("{}{}\r\n", gutter, text));
+        }
+    }
+
+    /* Cell numbers and values around the pointer, with the pointer marked */
+    fn draw_tape(&mut self, frame : &mut String, cols : usize)
+    {
+        let count = (cols.saturating_sub(7) / CELL_WIDTH).max(1) as i64;
+        let ptr = self.state.position();
+        if self.follow && (ptr < self.tape_start || ptr >= self.tape_start + count) {
+            self.tape_start = ptr - count / 2;
+        }
+        let (mut numbers, mut values, mut marker) = ("cell  ".to_owned(), "value ".to_owned(), "      ".to_owned());
+        for cell in self.tape_start..self.tape_start + count {
+            let idx = cell + self.state.origin as i64;
+            let value = if idx >= 0 && (idx as usize) < self.state.tape.len() {
+                (self.state.tape.get(idx as usize) as u32).to_string()
+            } else {
+                "".to_owned()
+            };
+            numbers.push_str(&format!("{:>w$}", cell, w = CELL_WIDTH));
+            values.push_str(&format!("{:>w$}", value, w = CELL_WIDTH));
+            marker.push_str(&format!("{:>w$}", if cell == ptr { "^" } else { "" }, w = CELL_WIDTH));
+        }
+        push_line(frame, &numbers, cols);
+        push_line(frame, &values, cols);
+        push_line(frame, &marker, cols);
+    }
+
+    /* The last lines the program printed, with unprintable bytes escaped */
+    fn draw_output(&self, frame : &mut String, cols : usize)
+    {
+        let tail = &self.output[self.output.len().saturating_sub(OUTPUT_TAIL)..];
+        let text : String = tail.iter().flat_map(|&b| match b {
+            b'\n'       => vec!['\n'],
+            0x20..=0x7E => vec![b as char],
+            _           => format!("\\x{:02x}", b).chars().collect()
+        }).collect();
+        let lines : Vec<&str> = text.split('\n').collect();
+        push_line(frame, "output:", cols);
+        for i in 0..OUTPUT_LINES {
+            let idx = (lines.len() + i).checked_sub(OUTPUT_LINES);
+            let line = idx.map_or("", |idx| lines[idx]);
+            push_line(frame, &format!("  {}", line), cols);
+        }
+    }
+}
+
+/* Appends `line` cut off at the width of the screen, plus a line break */
+fn push_line(frame : &mut String, line : &str, cols : usize)
+{
+    frame.push_str(&truncate(line, cols));
+    frame.push_str("\r\n");
+}
+
+fn truncate(text : &str, width : usize) -> String
+{
+    return text.chars().take(width).collect();
+}
+
+/* `text` cut off at `width`, with the `len` characters from `col` in reverse video */
+fn highlight(text : &str, col : usize, len : usize, width : usize) -> String
+{
+    let chars : Vec<char> = text.chars().take(width).collect();
+    if col >= chars.len() {
+        return chars.into_iter().collect();
+    }
+    let end = (col + len).min(chars.len());
+    let before : String = chars[..col].iter().collect();
+    let marked : String = chars[col..end].iter().collect();
+    let after : String = chars[end..].iter().collect();
+    return format!("{}\x1b[7m{}\x1b[0m{}", before, marked, after);
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+
+/// A message pointing at a byte offset in brainfuck source, rendered with
+/// the line and column of the offset and a caret-style snippet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message : String,
+    pub offset  : usize,
+    pub line    : usize,
+    pub col     : usize,
+    pub snippet : String
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for `offset` in `src`. Lines and columns are
+    /// 1-based; columns count characters.
+    pub fn new(src : &[u8], offset : usize, message : &str) -> Diagnostic
+    {
+        let offset     = offset.min(src.len());
+        let line_start = src[..offset].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let line_end   = src[offset..].iter().position(|&b| b == b'\n').map_or(src.len(), |i| offset + i);
+        let line       = src[..line_start].iter().filter(|&&b| b == b'\n').count() + 1;
+
+        return Diagnostic {
+            message: message.to_owned(),
+            offset,
+            line,
+            col: String::from_utf8_lossy(&src[line_start..offset]).chars().count() + 1,
+            snippet: String::from_utf8_lossy(&src[line_start..line_end]).trim_end().to_owned()
+        };
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} at line {}, col {}\n", self.message, self.line, self.col)?;
+        write!(f, "    {}\n", self.snippet)?;
+        let pad : String = self.snippet.chars()
+                .take(self.col - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+        write!(f, "    {}^", pad)
+    }
+}
+
+/// The line and column of byte offsets in brainfuck source, for looking up
+/// many of them without rescanning the source each time.
+pub struct LineIndex<'a> {
+    src    : &'a [u8],
+    /* the offset each line starts at */
+    starts : Vec<usize>,
+    /* whether each line is all ASCII, so its columns are its bytes */
+    ascii  : Vec<bool>
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(src : &'a [u8]) -> LineIndex<'a>
+    {
+        let starts : Vec<usize> = Some(0).into_iter()
+                .chain(src.iter().enumerate().filter(|&(_, &b)| b == b'\n').map(|(i, _)| i + 1))
+                .collect();
+        let ascii = starts.iter().enumerate()
+                .map(|(line, &start)| src[start..starts.get(line + 1).map_or(src.len(), |&end| end)].is_ascii())
+                .collect();
+        return LineIndex { src, starts, ascii };
+    }
+
+    /// The line and column of `offset`, counted like in a `Diagnostic`.
+    pub fn locate(&self, offset : usize) -> (usize, usize)
+    {
+        let offset = offset.min(self.src.len());
+        let line = self.starts.partition_point(|&start| start <= offset);
+        let start = self.starts[line - 1];
+        let col = if self.ascii[line - 1] {
+            offset - start + 1
+        } else {
+            String::from_utf8_lossy(&self.src[start..offset]).chars().count() + 1
+        };
+        return (line, col);
+    }
+}
@@ -0,0 +1,150 @@
+/*
+ * Bounded equivalence checking: running two programs on the same inputs and
+ * comparing what they print, to check that minifying, optimizing or
+ * rewriting a program by hand didn't change what it does. That only shows
+ * they agree on those inputs, and only within the step bound: when one of
+ * them runs out of steps, they differ if they already printed differently,
+ * or if the other finished and it printed more than all the other did, and
+ * nothing is known about that input otherwise.
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use interpreter;
+use interpreter::ProgramState;
+use verify;
+use verify::Outcome;
+use Config;
+use RustfuckError;
+use Stmt;
+
+/// Steps each program may run on an input unless the config says otherwise.
+pub const DEFAULT_STEPS : u64 = 10_000_000;
+
+const NAMES : [&str; 2] = ["first program", "second program"];
+
+/// How two programs compared on an input.
+pub enum Verdict {
+    Same,
+    /// They printed differently, or only one of them failed, as described.
+    Different(String),
+    /// One of them ran out of steps before they printed differently, as
+    /// described.
+    Unknown(String)
+}
+
+/// How many inputs the programs agreed, disagreed and may or may not agree
+/// on.
+pub struct Summary {
+    pub same      : usize,
+    pub different : usize,
+    pub unknown   : usize
+}
+
+/// The files in `dir`, sorted by name, each with its path and contents.
+pub fn read_inputs(dir : &Path) -> io::Result<Vec<(String, Vec<u8>)>>
+{
+    let mut paths = fs::read_dir(dir)?.map(|entry| entry.map(|e| e.path())).collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+    return paths.into_iter().map(|path| Ok((path.display().to_string(), fs::read(&path)?))).collect();
+}
+
+/// Runs `first` and `second` on each of `inputs`, named by their paths, with
+/// `config` and at most `config.max_steps` steps, or `DEFAULT_STEPS`.
+/// Prints a line per input and why they didn't agree on it.
+pub fn check(first : &[Stmt], second : &[Stmt], config : &Config, inputs : &[(String, Vec<u8>)]) -> Summary
+{
+    let config = Config { max_steps: Some(config.max_steps.unwrap_or(DEFAULT_STEPS)), ..config.clone() };
+    let mut summary = Summary { same: 0, different: 0, unknown: 0 };
+    for &(ref name, ref input) in inputs {
+        let report = match compare_on(first, second, &config, input) {
+            Verdict::Same => {
+                println!("input {} ... same", name);
+                summary.same += 1;
+                continue;
+            },
+            Verdict::Different(report) => {
+                println!("input {} ... DIFFERENT", name);
+                summary.different += 1;
+                report
+            },
+            Verdict::Unknown(report) => {
+                println!("input {} ... unknown", name);
+                summary.unknown += 1;
+                report
+            }
+        };
+        for line in report.lines() {
+            println!("    {}", line);
+        }
+    }
+    println!("\n{} same, {} different, {} unknown within {} steps",
+            summary.same, summary.different, summary.unknown, config.max_steps.unwrap());
+    return summary;
+}
+
+/// Runs `first` and `second` on `input` with `config`, its step limit
+/// included, and compares what they print.
+pub fn compare_on(first : &[Stmt], second : &[Stmt], config : &Config, input : &[u8]) -> Verdict
+{
+    let (a, a_limited) = run(first, config, input);
+    let (b, b_limited) = run(second, config, input);
+    if !a_limited && !b_limited {
+        return match verify::compare_named(&a, NAMES[0], &b, NAMES[1]) {
+            Some(report) => Verdict::Different(report),
+            None         => Verdict::Same
+        };
+    }
+
+    /*
+     * a program that ran out of steps could still print anything after what it printed so far, while one that
+     * finished printed all it does, so the other printing past the end of that differs too
+     */
+    let (a_seen, b_seen) = match (a_limited, b_limited) {
+        (true, true)  => {
+            let shared = a.output.len().min(b.output.len());
+            (shared, shared)
+        },
+        (true, false) => {
+            let a_seen = a.output.len().min(b.output.len() + 1);
+            (a_seen, b.output.len().min(a_seen))
+        },
+        _             => {
+            let b_seen = b.output.len().min(a.output.len() + 1);
+            (a.output.len().min(b_seen), b_seen)
+        }
+    };
+    let a_so_far = Outcome { output: a.output[..a_seen].to_vec(), error: None };
+    let b_so_far = Outcome { output: b.output[..b_seen].to_vec(), error: None };
+    if let Some(report) = verify::compare_named(&a_so_far, NAMES[0], &b_so_far, NAMES[1]) {
+        return Verdict::Different(report);
+    }
+    let limit = config.max_steps.unwrap_or(0);
+    return Verdict::Unknown(match (a_limited, b_limited) {
+        (true, true) => format!("both programs reached the step limit of {}, after printing {} and {}",
+                limit, bytes(a.output.len()), bytes(b.output.len())),
+        (true, _)    => format!("the {} reached the step limit of {} after printing {}", NAMES[0], limit, bytes(a.output.len())),
+        _            => format!("the {} reached the step limit of {} after printing {}", NAMES[1], limit, bytes(b.output.len()))
+    });
+}
+
+/* How many bytes `n` is, e.g. `1 byte` */
+fn bytes(n : usize) -> String
+{
+    return match n {
+        1 => "1 byte".to_owned(),
+        _ => format!("{} bytes", n)
+    };
+}
+
+/* Runs `code` on `input`, and whether it ran out of steps */
+fn run(code : &[Stmt], config : &Config, input : &[u8]) -> (Outcome, bool)
+{
+    let mut state = ProgramState::with_config(config);
+    let mut output = Vec::new();
+    let result = interpreter::execute(code, &mut state, &mut &input[..], &mut output);
+    let limited = matches!(result, Err(RustfuckError::LimitExceeded(..)));
+    return (Outcome { output, error: result.err().map(|e| e.to_string()) }, limited);
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::io;
+use std::error::Error;
+use diagnostic::Diagnostic;
+
+/// Everything that can go wrong while parsing, running or compiling a
+/// brainfuck program.
+#[derive(Debug)]
+pub enum RustfuckError
+{
+    /// Reading or writing a file or stream failed.
+    IO(io::Error),
+    /// The brainfuck source is malformed.
+    ParseError(Diagnostic),
+    /// An external tool (`opt`, `llc`, `gcc`) could not be run or failed.
+    ToolchainError(String),
+    /// The program did something illegal while being run, or couldn't be
+    /// run. Holds what went wrong and the source offset of the instruction
+    /// it went wrong at, if known.
+    RuntimeError(String, Option<usize>),
+    /// The interpreter stopped the program at its step limit or timeout.
+    /// Holds which limit it was and the source offset of the instruction
+    /// it stopped at, if known.
+    LimitExceeded(String, Option<usize>),
+    /// The interpreter stopped the program because Ctrl-C was pressed.
+    /// Holds the source offset of where it was, if known.
+    Interrupted(Option<usize>)
+}
+
+impl fmt::Display for RustfuckError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            RustfuckError::IO(e)               => write!(f, "I/O error: {}", e),
+            RustfuckError::ParseError(diag)    => write!(f, "parse error: {}", diag),
+            RustfuckError::ToolchainError(msg) => write!(f, "toolchain error: {}", msg),
+            RustfuckError::RuntimeError(msg, Some(offset)) =>
+                write!(f, "runtime error: {} at offset {}", msg, offset),
+            RustfuckError::RuntimeError(msg, None) =>
+                write!(f, "runtime error: {}", msg),
+            RustfuckError::LimitExceeded(msg, Some(offset)) =>
+                write!(f, "execution limit exceeded: {} at offset {}", msg, offset),
+            RustfuckError::LimitExceeded(msg, None) =>
+                write!(f, "execution limit exceeded: {}", msg),
+            RustfuckError::Interrupted(Some(offset)) => write!(f, "interrupted at offset {}", offset),
+            RustfuckError::Interrupted(None)         => write!(f, "interrupted")
+        }
+    }
+}
+
+impl Error for RustfuckError {
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self {
+            RustfuckError::IO(e) => Some(e),
+            _                    => None
+        }
+    }
+}
+
+impl From<io::Error> for RustfuckError {
+    fn from(e : io::Error) -> RustfuckError
+    {
+        return RustfuckError::IO(e);
+    }
+}
@@ -0,0 +1,124 @@
+/*
+ * Explaining an optimized program as C-like pseudocode, with `p` for the
+ * pointer and `mem` for the tape:
+ *
+ *     mem[p] += 5;
+ *     // multiply mem[p] into mem[p + 1]
+ *     mem[p + 1] += mem[p] * 3;
+ *     mem[p] = 0;
+ *
+ * The idioms the optimizer recognized are named in comments: a clear, a scan
+ * for a zero cell, and the loops that add the current cell to others, which
+ * move it to one cell, copy it to several, or multiply it.
+ */
+
+use Stmt;
+
+/* Spaces per level of loop nesting */
+const INDENT : usize = 4;
+
+/* Blocks nested deeper are indented no further, so the output can't grow with the square of the depth */
+const MAX_DEPTH : usize = 32;
+
+/// The pseudocode of `code`, a statement a line, with loop and procedure
+/// bodies indented.
+pub fn explain(code : &[Stmt]) -> String
+{
+    let mut out = String::new();
+    /* the blocks the next statement is in, outermost first, with the index of that statement */
+    let mut blocks : Vec<(&[Stmt], usize)> = vec![(code, 0)];
+    while let Some(&(block, i)) = blocks.last() {
+        let depth = blocks.len() - 1;
+        let indent = " ".repeat(depth.min(MAX_DEPTH) * INDENT);
+        let stmt = match block.get(i) {
+            Some(stmt) => stmt,
+            None       => {
+                blocks.pop();
+                if !blocks.is_empty() {
+                    out.push_str(&" ".repeat((depth - 1).min(MAX_DEPTH) * INDENT));
+                    out.push_str("}\n");
+                }
+                continue;
+            }
+        };
+
+        /* adds of the current cell to others, and the clear after them, are one idiom */
+        let adds = block[i..].iter().take_while(|stmt| matches!(stmt, Stmt::MulAdd(..))).count();
+        if adds > 0 && matches!(block.get(i + adds), Some(Stmt::Set(0, _))) {
+            let targets : Vec<(i32, i32)> = block[i..i + adds].iter().map(|stmt| match *stmt {
+                Stmt::MulAdd(offset, factor, _) => (offset, factor),
+                _                               => unreachable!()
+            }).collect();
+            let cells : Vec<String> = targets.iter().map(|&(offset, _)| cell(offset)).collect();
+            let idiom = match (targets.iter().all(|&(_, factor)| factor == 1), targets.len()) {
+                (true, 1) => "move",
+                (true, _) => "copy",
+                _         => "multiply"
+            };
+            out.push_str(&format!("{}// {} mem[p] into {}\n", indent, idiom, cells.join(", ")));
+            for (&(_, factor), cell) in targets.iter().zip(&cells) {
+                let term = match factor {
+                    1  => "mem[p]".to_owned(),
+                    -1 => "-mem[p]".to_owned(),
+                    _  => format!("mem[p] * {}", factor)
+                };
+                out.push_str(&format!("{}{} += {};\n", indent, cell, term));
+            }
+            out.push_str(&format!("{}mem[p] = 0;\n", indent));
+            blocks[depth].1 = i + adds + 1;
+            continue;
+        }
+        blocks[depth].1 = i + 1;
+
+        let line = match *stmt {
+            Stmt::Move(n, _) if n < 0       => format!("p -= {};", -(n as i64)),
+            Stmt::Move(n, _)                => format!("p += {};", n),
+            Stmt::Add(n, _)                 => add(&cell(0), n),
+            Stmt::AddAt(offset, n, _)       => add(&cell(offset), n),
+            Stmt::Set(0, _)                 => "mem[p] = 0;  // clear".to_owned(),
+            Stmt::Set(n, _)                 => format!("mem[p] = {};", n),
+            Stmt::MulAdd(offset, factor, _) => format!("{} += mem[p] * {};", cell(offset), factor),
+            Stmt::Scan(stride, _)           => {
+                let (direction, step) = if stride < 0 { ("left", format!("p -= {}", -(stride as i64))) } else { ("right", format!("p += {}", stride)) };
+                format!("while (mem[p]) {};  // scan {} for a zero cell", step, direction)
+            },
+            Stmt::Input(_)                  => "mem[p] = getchar();".to_owned(),
+            Stmt::Output(_)                 => "putchar(mem[p]);".to_owned(),
+            Stmt::Debug(_)                  => "breakpoint();".to_owned(),
+            Stmt::Call(_)                   => "call(mem[p]);".to_owned(),
+            Stmt::Fork(_)                   => "fork();".to_owned(),
+            Stmt::Random(_)                 => "mem[p] = random();".to_owned(),
+            Stmt::Loop(ref body, _)         => {
+                blocks.push((body, 0));
+                "while (mem[p]) {".to_owned()
+            },
+            Stmt::Proc(ref body, _)         => {
+                blocks.push((body, 0));
+                "procedure mem[p] {".to_owned()
+            }
+        };
+        out.push_str(&indent);
+        out.push_str(&line);
+        out.push('\n');
+    }
+    return out;
+}
+
+/* The cell at `offset` from the pointer */
+fn cell(offset : i32) -> String
+{
+    match offset {
+        0          => "mem[p]".to_owned(),
+        o if o < 0 => format!("mem[p - {}]", -(o as i64)),
+        o          => format!("mem[p + {}]", o)
+    }
+}
+
+/* Adding `n` to `cell` */
+fn add(cell : &str, n : i32) -> String
+{
+    if n < 0 {
+        return format!("{} -= {};", cell, -(n as i64));
+    }
+    return format!("{} += {};", cell, n);
+}
@@ -0,0 +1,218 @@
+/*
+ * Formatting brainfuck source for people to read: loop bodies are indented
+ * one level deeper than their brackets, the commands between them reflowed
+ * to fit a line width, and comments kept, on lines of their own or after the
+ * code they followed on the same line. Loops without loops or comments in
+ * them stay on one line when they fit, like `[-]` or `[->+<]`.
+ */
+
+use RustfuckError;
+
+/// Line width `format()` is usually given.
+pub const WIDTH : usize = 80;
+
+/* Spaces per level of loop nesting */
+const INDENT : usize = 4;
+
+/* Loops nested deeper are indented no further, so the output grows only with the size of the source */
+const MAX_DEPTH : usize = 32;
+
+enum Token {
+    Command(u8),
+    Open,
+    Close,
+    Comment(String)
+}
+
+/// Formats `src` to lines of at most `width` columns, where the comments and
+/// nesting allow it. Fails on unbalanced brackets, like `parse()`.
+pub fn format(src : &[u8], width : usize) -> Result<String, RustfuckError>
+{
+    ::parse(src)?;
+    let tokens = tokenize(src);
+    let mut f = Formatter { out: String::new(), line: String::new(), depth: 0, width };
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Command(c) => {
+                let run = tokens[i..].iter().take_while(|t| matches!(t, Token::Command(d) if *d == c)).count();
+                f.code(&(c as char).to_string().repeat(run), true);
+                i += run;
+                continue;
+            },
+            Token::Open => {
+                if let Some(len) = flat_loop(&tokens[i..]) {
+                    if f.indent() + len <= width {
+                        let text : String = tokens[i..i + len].iter().map(|t| match *t {
+                            Token::Command(c) => c as char,
+                            Token::Open       => '[',
+                            _                 => ']'
+                        }).collect();
+                        f.code(&text, false);
+                        i += len;
+                        continue;
+                    }
+                }
+                f.push_line("[");
+                f.depth += 1;
+            },
+            Token::Close => {
+                f.end_line();
+                /* no blank line before a `]` */
+                if f.out.ends_with("\n\n") {
+                    f.out.pop();
+                }
+                f.depth -= 1;
+                f.push_line("]");
+            },
+            Token::Comment(ref text) => f.comment(text)
+        }
+        i += 1;
+    }
+    f.end_line();
+    while f.out.ends_with("\n\n") {
+        f.out.pop();
+    }
+    return Ok(f.out);
+}
+
+fn tokenize(src : &[u8]) -> Vec<Token>
+{
+    let mut tokens = Vec::new();
+    let mut comment = Vec::new();
+    let mut i = 0;
+    while i < src.len() {
+        /* a #! line and preprocessor lines are kept as they are, commands in paths and macros and all */
+        let line = src[i..].trim_ascii_start();
+        let shebang = i == 0 && src.starts_with(b"#!");
+        if shebang || ((i == 0 || src[i - 1] == b'\n') && (line.starts_with(b"#include") || line.starts_with(b"@"))) {
+            let end = src[i..].iter().position(|&b| b == b'\n').map_or(src.len(), |n| i + n);
+            comment.extend_from_slice(&src[i..end]);
+            i = end;
+            continue;
+        }
+        let b = src[i];
+        i += 1;
+        let token = match b {
+            b'+' | b'-' | b'<' | b'>' | b',' | b'.' => Token::Command(b),
+            b'['                                    => Token::Open,
+            b']'                                    => Token::Close,
+            _                                       => {
+                comment.push(b);
+                continue;
+            }
+        };
+        push_comment(&mut tokens, &mut comment);
+        tokens.push(token);
+    }
+    push_comment(&mut tokens, &mut comment);
+    return tokens;
+}
+
+/* Adds the comment text read so far, unless it is only spaces and line breaks without a blank line */
+fn push_comment(tokens : &mut Vec<Token>, comment : &mut Vec<u8>)
+{
+    let text = String::from_utf8_lossy(comment).into_owned();
+    comment.clear();
+    if !text.trim().is_empty() || text.matches('\n').count() > 1 {
+        tokens.push(Token::Comment(text));
+    }
+}
+
+/* The length of the loop `tokens` starts with, if it holds only commands */
+fn flat_loop(tokens : &[Token]) -> Option<usize>
+{
+    for (i, token) in tokens.iter().enumerate().skip(1) {
+        match *token {
+            Token::Command(_) => (),
+            Token::Close      => return Some(i + 1),
+            _                 => return None
+        }
+    }
+    return None;
+}
+
+/* The formatted source so far, and the line being filled */
+struct Formatter {
+    out   : String,
+    /* without its indentation */
+    line  : String,
+    depth : usize,
+    width : usize
+}
+
+impl Formatter {
+    /* Never so deep that a command doesn't fit on the line after it */
+    fn indent(&self) -> usize
+    {
+        return self.depth.min(MAX_DEPTH).min(self.width.saturating_sub(1) / INDENT) * INDENT;
+    }
+
+    /* Adds commands to the line, breaking it where they don't fit if `breakable` */
+    fn code(&mut self, text : &str, breakable : bool)
+    {
+        if !self.line.is_empty() && self.indent() + self.line.len() + text.len() > self.width {
+            self.end_line();
+        }
+        let mut rest = text;
+        while breakable && !rest.is_empty() && self.indent() + self.line.len() + rest.len() > self.width {
+            /* at least one command a line, however deep the indentation */
+            let room = self.width.saturating_sub(self.indent() + self.line.len());
+            let room = if self.line.is_empty() { room.max(1) } else { room };
+            self.line.push_str(&rest[..room.min(rest.len())]);
+            self.end_line();
+            rest = &rest[room.min(rest.len())..];
+        }
+        self.line.push_str(rest);
+    }
+
+    /*
+     * Keeps each line of a comment: the first after the code on the line if
+     * there is any, the others on lines of their own, with blank lines
+     * between them kept but not repeated.
+     */
+    fn comment(&mut self, text : &str)
+    {
+        let lines : Vec<&str> = text.split('\n').collect();
+        for (i, line) in lines.iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                if i > 0 && i + 1 < lines.len() {
+                    self.blank_line();
+                }
+            } else if i == 0 && !self.line.is_empty() {
+                self.line.push(' ');
+                self.line.push_str(line);
+                self.end_line();
+            } else {
+                self.push_line(line);
+            }
+        }
+    }
+
+    fn push_line(&mut self, text : &str)
+    {
+        self.end_line();
+        self.line.push_str(text);
+        self.end_line();
+    }
+
+    fn end_line(&mut self)
+    {
+        if !self.line.is_empty() {
+            self.out.push_str(&" ".repeat(self.indent()));
+            self.out.push_str(&self.line);
+            self.out.push('\n');
+            self.line.clear();
+        }
+    }
+
+    /* Ends the line with a blank one after it, unless at the start of the file or a loop */
+    fn blank_line(&mut self)
+    {
+        self.end_line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !self.out.ends_with("[\n") {
+            self.out.push('\n');
+        }
+    }
+}
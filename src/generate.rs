@@ -0,0 +1,80 @@
+/*
+ * Generating brainfuck that prints a given text. Each byte is reached from
+ * the one before it in the same cell, by adding the difference, or, when it
+ * is shorter, a multiple of it in a loop on the cell to the right plus the
+ * rest:
+ *
+ *     >+++++++[<++++++++++>-]<++.     adds 7 * 10 + 2 = 72, and prints H
+ *
+ * Cells never wrap around, so the program prints the same with any cell
+ * width.
+ */
+
+use std::io;
+use interpreter;
+use interpreter::ProgramState;
+use optimizer;
+use parse;
+use Config;
+use RustfuckError;
+
+/* Commands of a multiplication loop besides its counter and factor: `>[<` `>-]<` */
+const LOOP_COST : usize = 7;
+
+/// A brainfuck program printing `text`, a line of code for each byte of
+/// it. The program is parsed, optimized and run before it is returned, to
+/// check that it prints exactly `text`.
+pub fn printer(text : &[u8]) -> Result<String, RustfuckError>
+{
+    let mut code = String::new();
+    let mut current = 0;
+    for &b in text {
+        push_delta(&mut code, b as i32 - current);
+        code.push_str(".\n");
+        current = b as i32;
+    }
+
+    let config = Config { max_steps: Some(100 * code.len() as u64 + 1000), ..Config::default() };
+    let program = optimizer::optimize(parse(code.as_bytes())?, &config);
+    let mut output = Vec::new();
+    interpreter::execute(&program, &mut ProgramState::with_config(&config), &mut io::empty(), &mut output)?;
+    if output != text {
+        return Err(RustfuckError::RuntimeError("the generated program doesn't print the text".to_owned(), None));
+    }
+    return Ok(code);
+}
+
+/* Appends the shortest code this finds that adds `delta` to the current cell, leaving the one right of it zero */
+fn push_delta(code : &mut String, delta : i32)
+{
+    let (up, down) = if delta < 0 { ('-', '+') } else { ('+', '-') };
+    let n = delta.unsigned_abs() as usize;
+
+    /* the counter and factor of the loop, and the rest added after it, which may go the other way */
+    let mut best : Option<(usize, usize, i64)> = None;
+    let mut best_cost = n;
+    for counter in 2..=n {
+        let factor = n / counter;
+        for factor in [factor, factor + 1] {
+            let rest = n as i64 - (counter * factor) as i64;
+            let cost = counter + factor + rest.unsigned_abs() as usize + LOOP_COST;
+            if factor > 1 && cost < best_cost {
+                best = Some((counter, factor, rest));
+                best_cost = cost;
+            }
+        }
+    }
+
+    match best {
+        Some((counter, factor, rest)) => {
+            code.push('>');
+            code.push_str(&"+".repeat(counter));
+            code.push_str("[<");
+            code.push_str(&up.to_string().repeat(factor));
+            code.push_str(">-]<");
+            let c = if rest < 0 { down } else { up };
+            code.push_str(&c.to_string().repeat(rest.unsigned_abs() as usize));
+        },
+        None => code.push_str(&up.to_string().repeat(n))
+    }
+}
@@ -0,0 +1,979 @@
+use Span;
+use Stmt;
+use bytecode;
+use bytecode::Op;
+use cells::Cells;
+use Config;
+use config::Bounds;
+use config::Eof;
+use config::Flush;
+use config::Io;
+use config::Tape;
+use random::Random;
+use RustfuckError;
+use terminal;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::io::Read;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The tape and data pointer of a running program, and the configuration
+/// it runs with.
+pub struct ProgramState {
+    pub ptr        : i32,
+    pub tape       : Cells,
+    /// How many cells a dynamic tape has grown to the left, so `tape.get(i)` is
+    /// cell `i - origin` as the program sees it.
+    pub origin     : usize,
+    /// The instruction of the lowered program to run next. A program that is
+    /// stopped by an error leaves it at the instruction it stopped at, where
+    /// running it again resumes; one that finishes resets it to 0.
+    pub pc         : usize,
+    /// The pbrain procedures defined so far, by their number, each with the
+    /// instruction it starts at.
+    pub procedures : HashMap<i32, usize>,
+    /// The instruction each procedure being run returns to, innermost last.
+    pub calls      : Vec<usize>,
+    /// The Brainfork threads waiting for their turn, next first. The one
+    /// running is the pointer, `pc` and `calls` above.
+    pub threads    : VecDeque<Thread>,
+    /// Where `?` gets its random bytes from.
+    pub random     : Random,
+    pub config     : Config
+}
+
+/// A Brainfork thread waiting for its turn, with what it doesn't share with
+/// the others: everything but the tape.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Thread {
+    pub ptr   : i32,
+    pub pc    : usize,
+    pub calls : Vec<usize>
+}
+
+impl ProgramState {
+    /// Creates a zeroed tape of the default size with the pointer at cell 0.
+    pub fn new() -> ProgramState
+    {
+        return ProgramState::with_config(&Config::default());
+    }
+
+    /// Creates a zeroed tape as configured, with the pointer at cell 0.
+    pub fn with_config(config : &Config) -> ProgramState
+    {
+        return ProgramState {
+            ptr:        0,
+            tape:       Cells::new(config.cell_width, config.tape_size),
+            origin:     0,
+            pc:         0,
+            procedures: HashMap::new(),
+            calls:      Vec::new(),
+            threads:    VecDeque::new(),
+            random:     config.seed.map_or_else(Random::from_entropy, Random::new),
+            config:     config.clone()
+        };
+    }
+}
+
+/// Cells shown on either side of the pointer by `ProgramState::tape_window()`.
+pub const WINDOW_CELLS : i32 = 8;
+
+impl ProgramState {
+    /// The pointer as the program sees it, i.e. not counting the cells a
+    /// dynamic tape grew to the left.
+    pub fn position(&self) -> i64
+    {
+        return self.ptr as i64 - self.origin as i64;
+    }
+
+    /// Renders the cells around the pointer as two lines of cell numbers and
+    /// values, with the pointer's cell in brackets.
+    pub fn tape_window(&self) -> String
+    {
+        let first = (self.ptr - WINDOW_CELLS).max(0);
+        let last = (self.ptr + WINDOW_CELLS).min(self.tape.len() as i32 - 1);
+        let mut cells = String::new();
+        let mut values = String::new();
+        for i in first..=last {
+            let value = self.tape.get(i as usize) as u32;
+            let value = if i == self.ptr { format!("[{}]", value) } else { value.to_string() };
+            let width = value.len().max(5);
+            cells.push_str(&format!(" {:>w$}", i as i64 - self.origin as i64, w = width));
+            values.push_str(&format!(" {:>w$}", value, w = width));
+        }
+        return format!("cell: {}\nvalue:{}\n", cells, values);
+    }
+
+    /// Renders the tape from the first to the last non-zero cell as a hex
+    /// dump, a row of cells per line starting with the number of the first,
+    /// with the cells as characters at the end. Rows of only zeros are
+    /// collapsed into a `*`.
+    pub fn hexdump(&self) -> String
+    {
+        let width = self.config.cell_width;
+        let per_row = (16 / width.bytes()).max(4);
+        let nonzero = |&i : &usize| !self.tape.is_zero(i);
+        let (first, last) = match ((0..self.tape.len()).find(nonzero), (0..self.tape.len()).rev().find(nonzero)) {
+            (Some(first), Some(last)) => (first as i64 - self.origin as i64, last as i64 - self.origin as i64),
+            _                         => return "all cells are zero\n".to_owned()
+        };
+        let mut dump = String::new();
+        let mut skipped = false;
+        let mut row = first.div_euclid(per_row as i64) * per_row as i64;
+        while row <= last {
+            let values : Vec<Option<u32>> = (row..row + per_row as i64).map(|cell| {
+                let idx = cell + self.origin as i64;
+                if idx < 0 || idx >= self.tape.len() as i64 {
+                    return None;
+                }
+                Some(self.tape.get(idx as usize) as u32)
+            }).collect();
+            row += per_row as i64;
+            if values.iter().all(|v| v.unwrap_or(0) == 0) {
+                if !skipped {
+                    dump.push_str("*\n");
+                    skipped = true;
+                }
+                continue;
+            }
+            skipped = false;
+            dump.push_str(&format!("{:>8}:", row - per_row as i64));
+            for value in &values {
+                match *value {
+                    Some(value) => dump.push_str(&format!(" {:0w$x}", value, w = width.bytes() * 2)),
+                    None        => dump.push_str(&format!(" {:w$}", "", w = width.bytes() * 2))
+                }
+            }
+            let chars : String = values.iter().map(|value| match *value {
+                Some(value @ 0x20..=0x7E) => value as u8 as char,
+                Some(_)                   => '.',
+                None                      => ' '
+            }).collect();
+            dump.push_str(&format!("  |{}|\n", chars));
+        }
+        return dump;
+    }
+}
+
+impl Default for ProgramState {
+    fn default() -> ProgramState
+    {
+        return ProgramState::new();
+    }
+}
+
+/// Callbacks from the interpreter around every instruction it executes, for
+/// collecting statistics and the like. The interpreter is compiled
+/// separately for each implementation, so the no-op `()` costs nothing.
+pub trait Hooks {
+    /// Called right before the instruction at `pc` is executed.
+    fn before_op(&mut self, _pc : usize, _op : Op, _state : &ProgramState) {}
+    /// Called right after the instruction at `pc` was executed, unless it
+    /// failed.
+    fn after_op(&mut self, _pc : usize, _op : Op, _state : &ProgramState) {}
+    /// Called when a `#` breakpoint at source `offset` is hit, with the
+    /// program's output so far, which may still be buffered.
+    fn breakpoint(&mut self, _offset : usize, _state : &ProgramState, _output : &mut dyn Write) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+impl Hooks for () {}
+
+/// Hooks that show the cells around the pointer on stderr at every
+/// breakpoint. Without them, breakpoints do nothing.
+pub struct ShowBreakpoints;
+
+impl Hooks for ShowBreakpoints {
+    fn breakpoint(&mut self, offset : usize, state : &ProgramState, output : &mut dyn Write) -> io::Result<()>
+    {
+        /* the dump should come after everything the program printed before it */
+        output.flush()?;
+        eprint!("# breakpoint at offset {}, pointer at cell {}\n{}", offset, state.position(), state.tape_window());
+        Ok(())
+    }
+}
+
+impl<H : Hooks> Hooks for Option<H> {
+    fn before_op(&mut self, pc : usize, op : Op, state : &ProgramState)
+    {
+        if let Some(hooks) = self {
+            hooks.before_op(pc, op, state);
+        }
+    }
+
+    fn after_op(&mut self, pc : usize, op : Op, state : &ProgramState)
+    {
+        if let Some(hooks) = self {
+            hooks.after_op(pc, op, state);
+        }
+    }
+
+    fn breakpoint(&mut self, offset : usize, state : &ProgramState, output : &mut dyn Write) -> io::Result<()>
+    {
+        match self {
+            Some(hooks) => hooks.breakpoint(offset, state, output),
+            None        => Ok(())
+        }
+    }
+}
+
+impl<A : Hooks, B : Hooks> Hooks for (A, B) {
+    fn before_op(&mut self, pc : usize, op : Op, state : &ProgramState)
+    {
+        self.0.before_op(pc, op, state);
+        self.1.before_op(pc, op, state);
+    }
+
+    fn after_op(&mut self, pc : usize, op : Op, state : &ProgramState)
+    {
+        self.0.after_op(pc, op, state);
+        self.1.after_op(pc, op, state);
+    }
+
+    fn breakpoint(&mut self, offset : usize, state : &ProgramState, output : &mut dyn Write) -> io::Result<()>
+    {
+        self.0.breakpoint(offset, state, output)?;
+        self.1.breakpoint(offset, state, output)
+    }
+}
+
+/// Runs `code` in the interpreter against `state`, reading `,` from `input`
+/// and writing the low byte of the cell for each `.` to `output`. The
+/// program is lowered to bytecode first, see `execute_ops()`, with its
+/// common pairs of ops fused, see `bytecode::fuse()`, unless it has a step
+/// limit or forks threads. If it is
+/// stopped at a limit, the error points at the loop that was running.
+pub fn execute(code : &[Stmt], state : &mut ProgramState, input : &mut dyn Read, output : &mut dyn Write)
+        -> Result<(), RustfuckError>
+{
+    let (mut ops, sources) = bytecode::lower_with_sources(code);
+    /* a fused pair is one step, and threads would take turns at them */
+    if state.config.max_steps.is_none() && !ops.contains(&Op::Fork) {
+        bytecode::fuse(&mut ops);
+    }
+    return run_buffered(&ops, &sources, state, input, output, &mut ());
+}
+
+/// Like `execute()`, with each instruction resolved to the function that
+/// runs it before the program starts, so the interpreter calls one after
+/// the other instead of matching on every instruction it runs. Breakpoints
+/// do nothing.
+pub fn execute_threaded(code : &[Stmt], state : &mut ProgramState, input : &mut dyn Read, output : &mut dyn Write)
+        -> Result<(), RustfuckError>
+{
+    let (mut ops, sources) = bytecode::lower_with_sources(code);
+    if state.config.max_steps.is_none() && !ops.contains(&Op::Fork) {
+        bytecode::fuse(&mut ops);
+    }
+    let handlers = thread(&ops);
+    let mut output = BufWriter::new(output);
+    let result = run_threaded(&ops, &handlers, &sources, state, input, &mut output);
+    let flushed = output.flush();
+    result?;
+    flushed?;
+    Ok(())
+}
+
+/// Like `execute()`, calling `hooks` around every instruction.
+pub fn execute_with<H : Hooks>(code : &[Stmt], state : &mut ProgramState, input : &mut dyn Read,
+        output : &mut dyn Write, hooks : &mut H) -> Result<(), RustfuckError>
+{
+    let (ops, sources) = bytecode::lower_with_sources(code);
+    return run_buffered(&ops, &sources, state, input, output, hooks);
+}
+
+/// A program being interpreted one instruction at a time, as a debugger
+/// does. It runs against a `ProgramState` like `execute()` does, except that
+/// output isn't buffered, there are no limits and breakpoints do nothing.
+pub struct Stepper {
+    ops     : Vec<Op>,
+    sources : Vec<Span>,
+    pc      : usize
+}
+
+impl Stepper {
+    /// Prepares `code` to be run from its first instruction.
+    pub fn new(code : &[Stmt]) -> Stepper
+    {
+        let (ops, sources) = bytecode::lower_with_sources(code);
+        return Stepper { ops, sources, pc: 0 };
+    }
+
+    /// The lowered program.
+    pub fn ops(&self) -> &[Op]
+    {
+        return &self.ops;
+    }
+
+    /// Index of the next instruction in `ops()`.
+    pub fn pc(&self) -> usize
+    {
+        return self.pc;
+    }
+
+    /// The next instruction, or `None` once the program has finished.
+    pub fn next_op(&self) -> Option<Op>
+    {
+        return self.ops.get(self.pc).cloned();
+    }
+
+    /// The span of the source of the next instruction, or `None` once the
+    /// program has finished.
+    pub fn span(&self) -> Option<Span>
+    {
+        return self.sources.get(self.pc).cloned();
+    }
+
+    /// The source offset of the next instruction, or `None` once the program
+    /// has finished.
+    pub fn location(&self) -> Option<usize>
+    {
+        return location(&self.sources, self.pc);
+    }
+
+    pub fn is_finished(&self) -> bool
+    {
+        return self.pc >= self.ops.len();
+    }
+
+    /// Executes the next instruction, if the program hasn't finished yet.
+    /// After an error the program is stuck on the failed instruction.
+    pub fn step(&mut self, state : &mut ProgramState, input : &mut dyn Read, output : &mut dyn Write)
+            -> Result<(), RustfuckError>
+    {
+        if let Some(op) = self.next_op() {
+            self.pc = run_op(op, self.pc, state, input, output, &mut ())?;
+            if !state.threads.is_empty() {
+                self.pc = switch_thread(state, self.pc, self.ops.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+/* How deeply procedure calls may nest, so runaway recursion fails instead of eating all memory */
+const MAX_CALLS : usize = 1 << 20;
+
+/* How many Brainfork threads may wait for their turn, so a fork bomb fails instead of eating all memory */
+const MAX_THREADS : usize = 1 << 16;
+
+/* How many instructions run between checks of the clock for a timeout and for Ctrl-C */
+const CLOCK_INTERVAL : u64 = 1 << 16;
+
+/*
+ * Returns the tape index of cell `target`, growing a dynamic tape to include
+ * it or wrapping around a fixed one if so configured.
+ */
+fn cell(state : &mut ProgramState, target : i32) -> Result<usize, RustfuckError>
+{
+    if target >= 0 && (target as usize) < state.tape.len() {
+        return Ok(target as usize);
+    }
+    match (state.config.tape, state.config.bounds) {
+        /* every cell of a dynamic tape has to have an index an i32 can hold */
+        (Tape::Dynamic, _) if target < 0 && state.tape.len() + target.unsigned_abs() as usize > i32::MAX as usize
+                                    => Err(outside(target as i64)),
+        (Tape::Dynamic, _)          => Ok(grow(state, target)),
+        (Tape::Fixed, Bounds::Wrap) => Ok(target.rem_euclid(state.tape.len() as i32) as usize),
+        (Tape::Fixed, _)            => Err(outside(target as i64))
+    }
+}
+
+/*
+ * The cell `n` cells from the pointer, which may be off the tape, or an error
+ * if it is past the cells an i32 can number
+ */
+fn beside(state : &ProgramState, n : i32) -> Result<i32, RustfuckError>
+{
+    return state.ptr.checked_add(n).ok_or_else(|| outside(state.ptr as i64 + n as i64));
+}
+
+/* The error for moving the pointer to cell `target`, outside of the tape */
+fn outside(target : i64) -> RustfuckError
+{
+    return RustfuckError::RuntimeError(format!("pointer moved outside of tape (cell {})", target), None);
+}
+
+/*
+ * Grows the tape to include cell `target`, at least doubling it. Growing to
+ * the left shifts every cell, and the pointer with them; the new index of
+ * `target` is returned.
+ */
+fn grow(state : &mut ProgramState, target : i32) -> usize
+{
+    let len = state.tape.len();
+    if target < 0 {
+        let shift = len.min((i32::MAX as usize).saturating_sub(len)).max(target.unsigned_abs() as usize);
+        state.tape.prepend(shift);
+        state.ptr += shift as i32;
+        state.origin += shift;
+        for thread in state.threads.iter_mut() {
+            thread.ptr += shift as i32;
+        }
+        return (target + shift as i32) as usize;
+    }
+    state.tape.extend_to((len * 2).min(i32::MAX as usize + 1).max(target as usize + 1));
+    return target as usize;
+}
+
+/// Runs a lowered program against `state`. Since the instruction stream is
+/// flat, the nesting depth of a program is only limited by available memory.
+/// Output is buffered and flushed as `state.config.flush` says, and always
+/// when the program stops, even on an error. The program is stopped with a
+/// `LimitExceeded` error once it exceeds `state.config.max_steps` or
+/// `state.config.timeout`, and with an `Interrupted` error on Ctrl-C if
+/// `terminal::catch_interrupts()` was called.
+pub fn execute_ops(ops : &[Op], state : &mut ProgramState, input : &mut dyn Read, output : &mut dyn Write)
+        -> Result<(), RustfuckError>
+{
+    return run_buffered(ops, &[], state, input, output, &mut ());
+}
+
+fn run_buffered<H : Hooks>(ops : &[Op], sources : &[Span], state : &mut ProgramState,
+        input : &mut dyn Read, output : &mut dyn Write, hooks : &mut H) -> Result<(), RustfuckError>
+{
+    let mut output = BufWriter::new(output);
+    let result = run_ops(ops, sources, state, input, &mut output, hooks);
+    let flushed = output.flush();
+    result?;
+    flushed?;
+    Ok(())
+}
+
+fn run_ops<H : Hooks>(ops : &[Op], sources : &[Span], state : &mut ProgramState,
+        input : &mut dyn Read, output : &mut dyn Write, hooks : &mut H) -> Result<(), RustfuckError>
+{
+    let mut limits = Limits::new(&state.config);
+    let mut pc = state.pc;
+    while pc < ops.len() {
+        if let Err(e) = limits.step(sources, pc) {
+            state.pc = pc;
+            return Err(e);
+        }
+        hooks.before_op(pc, ops[pc], state);
+        let next = match run_op(ops[pc], pc, state, input, output, hooks) {
+            Ok(next) => next,
+            Err(e)   => return Err(failed(e, ops, sources, pc, state))
+        };
+        hooks.after_op(pc, ops[pc], state);
+        pc = next;
+        if !state.threads.is_empty() {
+            pc = switch_thread(state, pc, ops.len());
+        }
+    }
+
+    state.pc = 0;
+    Ok(())
+}
+
+/* What a handler runs an instruction against */
+struct Machine<'a> {
+    state  : &'a mut ProgramState,
+    input  : &'a mut dyn Read,
+    output : &'a mut dyn Write,
+    /* why the last instruction failed, if it did */
+    error  : Option<RustfuckError>
+}
+
+/*
+ * A function that runs one kind of instruction, `op` at `pc`, and returns the
+ * pc of the next one, or `FAILED` with the error left in the machine. Keeping
+ * the error out of what it returns keeps that in a register.
+ */
+type Handler = fn(Op, usize, &mut Machine) -> usize;
+
+const FAILED : usize = usize::MAX;
+
+/* The handler for each of `ops`: one of its own for those run most, `run_any` for the rest */
+fn thread(ops : &[Op]) -> Vec<Handler>
+{
+    return ops.iter().map(|&op| -> Handler {
+        match op {
+            Op::Move(_)                 => run_move,
+            Op::Add(_)                  => run_add,
+            Op::Set(_)                  => run_set,
+            Op::AddAt(_, _)             => run_add_at,
+            Op::MulAdd(_, _)            => run_mul_add,
+            Op::Scan(_)                 => run_scan,
+            Op::JumpIfZero(_)           => run_jump_if_zero,
+            Op::JumpIfNonZero(_)        => run_jump_if_non_zero,
+            Op::AddMove(_, _)           => run_add_move,
+            Op::MoveJumpIfZero(_, _)    => run_move_jump_if_zero,
+            Op::MoveJumpIfNonZero(_, _) => run_move_jump_if_non_zero,
+            _                           => run_any
+        }
+    }).collect();
+}
+
+/* `run_ops()` without hooks, calling the handler `thread()` resolved for each instruction */
+fn run_threaded(ops : &[Op], handlers : &[Handler], sources : &[Span], state : &mut ProgramState,
+        input : &mut dyn Read, output : &mut dyn Write) -> Result<(), RustfuckError>
+{
+    let mut limits = Limits::new(&state.config);
+    let mut pc = state.pc;
+    let mut machine = Machine { state, input, output, error: None };
+    while pc < ops.len() {
+        if let Err(e) = limits.step(sources, pc) {
+            machine.state.pc = pc;
+            return Err(e);
+        }
+        let next = handlers[pc](ops[pc], pc, &mut machine);
+        if next == FAILED {
+            return Err(failed(machine.error.take().unwrap(), ops, sources, pc, machine.state));
+        }
+        pc = next;
+        if !machine.state.threads.is_empty() {
+            pc = switch_thread(machine.state, pc, ops.len());
+        }
+    }
+
+    machine.state.pc = 0;
+    Ok(())
+}
+
+/*
+ * The handlers. Each runs `run_op()` on the kind of instruction it is for,
+ * which the compiler then strips down to the code for that kind alone.
+ */
+
+/* `run_op()` against `machine`, as a handler returns what it did */
+#[inline(always)]
+fn handle(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match run_op(op, pc, machine.state, machine.input, machine.output, &mut ()) {
+        Ok(next) => next,
+        Err(e)   => {
+            machine.error = Some(e);
+            FAILED
+        }
+    }
+}
+
+fn run_any(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    return handle(op, pc, machine);
+}
+
+fn run_move(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::Move(_) => handle(op, pc, machine),
+        _           => unreachable!()
+    }
+}
+
+fn run_add(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::Add(_) => handle(op, pc, machine),
+        _          => unreachable!()
+    }
+}
+
+fn run_set(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::Set(_) => handle(op, pc, machine),
+        _          => unreachable!()
+    }
+}
+
+fn run_add_at(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::AddAt(_, _) => handle(op, pc, machine),
+        _               => unreachable!()
+    }
+}
+
+fn run_mul_add(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::MulAdd(_, _) => handle(op, pc, machine),
+        _                => unreachable!()
+    }
+}
+
+fn run_scan(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::Scan(_) => handle(op, pc, machine),
+        _           => unreachable!()
+    }
+}
+
+fn run_jump_if_zero(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::JumpIfZero(_) => handle(op, pc, machine),
+        _                 => unreachable!()
+    }
+}
+
+fn run_jump_if_non_zero(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::JumpIfNonZero(_) => handle(op, pc, machine),
+        _                    => unreachable!()
+    }
+}
+
+fn run_add_move(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::AddMove(_, _) => handle(op, pc, machine),
+        _                 => unreachable!()
+    }
+}
+
+fn run_move_jump_if_zero(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::MoveJumpIfZero(_, _) => handle(op, pc, machine),
+        _                        => unreachable!()
+    }
+}
+
+fn run_move_jump_if_non_zero(op : Op, pc : usize, machine : &mut Machine) -> usize
+{
+    match op {
+        Op::MoveJumpIfNonZero(_, _) => handle(op, pc, machine),
+        _                           => unreachable!()
+    }
+}
+
+/* How many instructions a program may run and for how long, as configured, and how many it has */
+struct Limits {
+    max_steps : u64,
+    deadline  : Option<(Duration, Instant)>,
+    steps     : u64
+}
+
+impl Limits {
+    fn new(config : &Config) -> Limits
+    {
+        return Limits {
+            max_steps: config.max_steps.unwrap_or(u64::MAX),
+            deadline:  config.timeout.map(|timeout| (timeout, Instant::now() + timeout)),
+            steps:     0
+        };
+    }
+
+    /* Counts the instruction at `pc` about to run, failing if it is one too many, time is up or Ctrl-C was pressed */
+    #[inline(always)]
+    fn step(&mut self, sources : &[Span], pc : usize) -> Result<(), RustfuckError>
+    {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(limit_exceeded(format!("step limit of {} reached", self.max_steps), sources, pc));
+        }
+        if self.steps.is_multiple_of(CLOCK_INTERVAL) {
+            if terminal::interrupted() {
+                return Err(RustfuckError::Interrupted(location(sources, pc)));
+            }
+            if let Some((timeout, deadline)) = self.deadline {
+                if Instant::now() >= deadline {
+                    return Err(limit_exceeded(format!("timeout of {:?} reached", timeout), sources, pc));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+ * The error `e` the instruction at `pc` failed with, pointing at where in
+ * the source it is. The program is left stopped at the instruction that
+ * failed.
+ */
+fn failed(e : RustfuckError, ops : &[Op], sources : &[Span], pc : usize, state : &mut ProgramState) -> RustfuckError
+{
+    match e {
+        RustfuckError::RuntimeError(msg, None) => {
+            /* only the move of an `AddMove` can fail, which is the op after it */
+            state.pc = if let Op::AddMove(..) = ops[pc] { pc + 1 } else { pc };
+            return RustfuckError::RuntimeError(msg, location(sources, state.pc));
+        },
+        e => {
+            state.pc = pc;
+            return e;
+        }
+    }
+}
+
+/*
+ * Executes the instruction `op` at `pc` and returns the pc of the next one.
+ */
+#[inline(always)]
+fn run_op<H : Hooks>(op : Op, pc : usize, state : &mut ProgramState, input : &mut dyn Read,
+        output : &mut dyn Write, hooks : &mut H) -> Result<usize, RustfuckError>
+{
+    let mut next = pc + 1;
+    match op {
+        Op::Move(n) => {
+            state.ptr = moved(state, n)?;
+        },
+        Op::Add(n)  => {
+            state.tape.add(state.ptr as usize, n);
+        },
+        Op::Set(n)  => {
+            state.tape.set(state.ptr as usize, n);
+        },
+        /* fused ops skip the second op of their pair, which is left after them */
+        Op::AddMove(n, m) => {
+            state.tape.add(state.ptr as usize, n);
+            state.ptr = moved(state, m)?;
+            next = pc + 2;
+        },
+        Op::SetAdd(n, m) => {
+            state.tape.set(state.ptr as usize, n.wrapping_add(m));
+            next = pc + 2;
+        },
+        Op::MoveJumpIfZero(n, offset) => {
+            state.ptr = moved(state, n)?;
+            next = if state.tape.is_zero(state.ptr as usize) { pc + 1 + offset } else { pc + 2 };
+        },
+        Op::MoveJumpIfNonZero(n, offset) => {
+            state.ptr = moved(state, n)?;
+            next = if !state.tape.is_zero(state.ptr as usize) { pc + 1 - offset } else { pc + 2 };
+        },
+        Op::MulAdd(offset, factor) => {
+            /* the original loop never runs on a zero cell, so neither may we touch its target */
+            if !state.tape.is_zero(state.ptr as usize) {
+                let target = beside(state, offset)?;
+                let target = cell(state, target)?;
+                let value = state.tape.get(state.ptr as usize).wrapping_mul(factor);
+                state.tape.add(target, value);
+            }
+        },
+        Op::AddAt(offset, n) => {
+            let target = beside(state, offset)?;
+            let target = cell(state, target)?;
+            state.tape.add(target, n);
+        },
+        Op::Scan(stride) => {
+            match scan(state, stride)? {
+                Some(ptr) => state.ptr = ptr,
+                /* other threads may still write a zero, so the scan goes on a step at a time */
+                None      => {
+                    state.ptr = moved(state, stride)?;
+                    next = pc;
+                }
+            }
+        },
+        Op::Input   => {
+            /* an interactive program's prompt should be visible before it blocks */
+            if state.config.flush == Flush::Line {
+                output.flush()?;
+            }
+            let value = match state.config.io {
+                Io::Bytes   => read_byte(input)?.map(|b| b as i32),
+                Io::Numeric => read_number(input)?.map(|n| n as i32)
+            };
+            match (value, state.config.eof) {
+                (Some(value), _)       => state.tape.set(state.ptr as usize, value),
+                (None, Eof::Zero)      => state.tape.set(state.ptr as usize, 0),
+                (None, Eof::MinusOne)  => state.tape.set(state.ptr as usize, -1),
+                (None, Eof::Unchanged) => ()
+            }
+        },
+        Op::Output  => {
+            let cell = state.tape.get(state.ptr as usize);
+            let last_byte = match state.config.io {
+                Io::Bytes   => {
+                    output.write_all(&[cell as u8])?;
+                    cell as u8
+                },
+                Io::Numeric => {
+                    write_number(output, cell as u32)?;
+                    b'\n'
+                }
+            };
+            if state.config.flush.after(last_byte) {
+                output.flush()?;
+            }
+        },
+        Op::JumpIfZero(offset) => {
+            if state.tape.is_zero(state.ptr as usize) {
+                next = pc + offset;
+            }
+        },
+        Op::JumpIfNonZero(offset) => {
+            if !state.tape.is_zero(state.ptr as usize) {
+                next = pc - offset;
+            }
+        },
+        Op::Debug(offset) => {
+            hooks.breakpoint(offset, state, output)?;
+        },
+        Op::Proc(offset) => {
+            state.procedures.insert(state.tape.get(state.ptr as usize), pc + 1);
+            next = pc + offset;
+        },
+        Op::Return => {
+            /* only reached from a call, which the body is skipped without */
+            next = state.calls.pop().unwrap_or(next);
+        },
+        Op::Call => {
+            let number = state.tape.get(state.ptr as usize);
+            next = match state.procedures.get(&number) {
+                Some(&start) => start,
+                None         => return Err(RustfuckError::RuntimeError(
+                        format!("call of undefined procedure {}", number as u32), None))
+            };
+            if state.calls.len() >= MAX_CALLS {
+                return Err(RustfuckError::RuntimeError("procedure calls nested too deeply".to_owned(), None));
+            }
+            state.calls.push(pc + 1);
+        },
+        Op::Fork => {
+            if state.threads.len() >= MAX_THREADS {
+                return Err(RustfuckError::RuntimeError("too many threads forked".to_owned(), None));
+            }
+            let child = beside(state, 1)?;
+            let child = cell(state, child)?;
+            state.tape.set(state.ptr as usize, 0);
+            state.tape.set(child, 1);
+            state.threads.push_back(Thread { ptr: child as i32, pc: next, calls: state.calls.clone() });
+        },
+        Op::Random => {
+            state.tape.set(state.ptr as usize, state.random.next_byte() as i32);
+        }
+    }
+    return Ok(next);
+}
+
+/* Where the pointer is after moving it `n` cells; on an error it stays where it was, so the state can still be looked at */
+#[inline(always)]
+fn moved(state : &mut ProgramState, n : i32) -> Result<i32, RustfuckError>
+{
+    let target = beside(state, n)?;
+    if target < 0 || target as usize >= state.tape.len() {
+        return Ok(cell(state, target)? as i32);
+    }
+    return Ok(target);
+}
+
+/*
+ * Gives the next Brainfork thread its turn, an instruction each, and returns
+ * its pc. The one that ran waits for its next turn at `pc`, unless it has
+ * finished by reaching `end`.
+ */
+fn switch_thread(state : &mut ProgramState, pc : usize, end : usize) -> usize
+{
+    let next = state.threads.pop_front().unwrap();
+    if pc < end {
+        state.threads.push_back(Thread { ptr: state.ptr, pc, calls: std::mem::take(&mut state.calls) });
+    }
+    state.ptr = next.ptr;
+    state.calls = next.calls;
+    return next.pc;
+}
+
+
+/* The source offset of the instruction at `pc`, if there is one */
+fn location(sources : &[Span], pc : usize) -> Option<usize>
+{
+    return sources.get(pc).map(|span| span.start);
+}
+
+/* The error for stopping at `pc`, pointing at where in the source it is */
+fn limit_exceeded(msg : String, sources : &[Span], pc : usize) -> RustfuckError
+{
+    return RustfuckError::LimitExceeded(msg, location(sources, pc));
+}
+
+fn read_byte(input : &mut dyn Read) -> io::Result<Option<u8>>
+{
+    let mut byte = [0u8; 1];
+    if input.read(&mut byte)? == 0 {
+        return Ok(None);
+    }
+    return Ok(Some(byte[0]));
+}
+
+/// Reads a decimal number for `,` in numeric I/O mode: leading whitespace is
+/// skipped, a sign is optional and the first byte after the digits is
+/// consumed. Returns `None` at the end of input or if there are no digits.
+/// Overflow wraps around.
+pub fn read_number(input : &mut dyn Read) -> io::Result<Option<i64>>
+{
+    let mut byte = read_byte(input)?;
+    while let Some(b) = byte {
+        if !b.is_ascii_whitespace() {
+            break;
+        }
+        byte = read_byte(input)?;
+    }
+    let negative = byte == Some(b'-');
+    if negative || byte == Some(b'+') {
+        byte = read_byte(input)?;
+    }
+    let mut number : Option<i64> = None;
+    while let Some(b) = byte {
+        if !b.is_ascii_digit() {
+            break;
+        }
+        number = Some(number.unwrap_or(0).wrapping_mul(10).wrapping_add((b - b'0') as i64));
+        byte = read_byte(input)?;
+    }
+    return Ok(number.map(|n| if negative { n.wrapping_neg() } else { n }));
+}
+
+/// Writes a cell for `.` in numeric I/O mode, as a decimal number and a
+/// newline. `cell` is the cell's bit pattern, so it is never negative.
+pub fn write_number(output : &mut dyn Write, cell : u32) -> io::Result<()>
+{
+    return write!(output, "{}\n", cell);
+}
+
+/*
+ * Finds the first zero cell from the pointer onwards in steps of `stride`.
+ * On a dynamic tape the cells past either end are zero, so the scan stops
+ * at the first of them it would reach. On a wrapping tape the scan comes
+ * round to where it started without finding one if there is none on the
+ * way, and never ends: that fails unless other threads could still write a
+ * zero, and then there is no cell to return.
+ */
+fn scan(state : &mut ProgramState, stride : i32) -> Result<Option<i32>, RustfuckError>
+{
+    let len = state.tape.len() as i32;
+    let found = state.tape.find_zero(state.ptr as usize, stride);
+    match (found, state.config.tape, state.config.bounds) {
+        (Some(i), _, _)                   => Ok(Some(i as i32)),
+        (None, Tape::Dynamic, _)          => {
+            let steps = if stride > 0 { (len - state.ptr + stride - 1) / stride } else { state.ptr / -stride + 1 };
+            Ok(Some(grow(state, state.ptr + steps * stride) as i32))
+        },
+        (None, Tape::Fixed, Bounds::Wrap) => match scan_wrapping(state, stride) {
+            None if state.threads.is_empty() => Err(RustfuckError::RuntimeError(
+                    "scanning for a zero cell never ends, there is none on the way round the tape".to_owned(), None)),
+            found                            => Ok(found)
+        },
+        (None, Tape::Fixed, _)            => Err(RustfuckError::RuntimeError(
+                "pointer moved outside of tape while scanning for a zero cell".to_owned(), None))
+    }
+}
+
+/* Scans around a wrapping tape until it comes back to where it started, for the first zero cell on the way */
+fn scan_wrapping(state : &ProgramState, stride : i32) -> Option<i32>
+{
+    let len = state.tape.len() as i64;
+    let start = state.ptr as i64;
+    let mut i = start;
+    loop {
+        if state.tape.is_zero(i as usize) {
+            return Some(i as i32);
+        }
+        i = (i + stride as i64).rem_euclid(len);
+        if i == start {
+            return None;
+        }
+    }
+}
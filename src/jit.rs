@@ -0,0 +1,209 @@
+/*
+ * In-process JIT for x86-64 unix systems. Lowered bytecode is translated
+ * straight into machine code by the `x86_64` module, which is then mapped
+ * executable and called. No external toolchain is involved.
+ */
+
+use bytecode::Op;
+use config::Bounds;
+use config::Flush;
+use config::Io;
+use config::Tape;
+use interpreter;
+use interpreter::ProgramState;
+use x86_64;
+use x86_64::IoCalls;
+use RustfuckError;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::io::Read;
+
+/* state shared between the generated code and the I/O callbacks */
+#[repr(C)]
+struct JitContext<'a> {
+    ptr    : u64, // written by the generated code before returning
+    number : i32, // read by the generated code after numeric input
+    error  : Option<RustfuckError>,
+    input  : &'a mut dyn Read,
+    output : &'a mut dyn Write,
+    flush  : Flush
+}
+
+/* records an I/O error for `execute()` to report, and tells the generated code to stop */
+fn fail(ctx : &mut JitContext, e : io::Error) -> i32
+{
+    ctx.error = Some(RustfuckError::IO(e));
+    return x86_64::CALLBACK_ERROR;
+}
+
+/* an interactive program's prompt should be visible before it blocks */
+fn flush_before_input(ctx : &mut JitContext) -> io::Result<()>
+{
+    if ctx.flush == Flush::Line {
+        return ctx.output.flush();
+    }
+    Ok(())
+}
+
+/* flushes after output ending in `last_byte` if the flush policy says so */
+fn finish_output(ctx : &mut JitContext, written : io::Result<()>, last_byte : u8) -> i32
+{
+    let flush = ctx.flush.after(last_byte);
+    match written.and_then(|_| if flush { ctx.output.flush() } else { Ok(()) }) {
+        Ok(_)  => return 0,
+        Err(e) => return fail(ctx, e)
+    }
+}
+
+extern "C" fn jit_getchar(ctx : *mut JitContext) -> i32
+{
+    let ctx = unsafe { &mut *ctx };
+    let mut byte = [0u8; 1];
+    match flush_before_input(ctx).and_then(|_| ctx.input.read(&mut byte)) {
+        Ok(0)  => return -1,
+        Ok(_)  => return byte[0] as i32,
+        Err(e) => return fail(ctx, e)
+    }
+}
+
+extern "C" fn jit_getnum(ctx : *mut JitContext) -> i32
+{
+    let ctx = unsafe { &mut *ctx };
+    match flush_before_input(ctx).and_then(|_| interpreter::read_number(ctx.input)) {
+        Ok(Some(n)) => {
+            ctx.number = n as i32;
+            return 1;
+        },
+        Ok(None)    => return -1,
+        Err(e)      => return fail(ctx, e)
+    }
+}
+
+extern "C" fn jit_putchar(ctx : *mut JitContext, cell : u32) -> i32
+{
+    let ctx = unsafe { &mut *ctx };
+    let written = ctx.output.write_all(&[cell as u8]);
+    return finish_output(ctx, written, cell as u8);
+}
+
+extern "C" fn jit_putnum(ctx : *mut JitContext, cell : u32) -> i32
+{
+    let ctx = unsafe { &mut *ctx };
+    let written = interpreter::write_number(ctx.output, cell);
+    return finish_output(ctx, written, b'\n');
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+mod sys {
+    use std::ffi::c_void;
+
+    pub const PROT_READ     : i32 = 1;
+    pub const PROT_WRITE    : i32 = 2;
+    pub const PROT_EXEC     : i32 = 4;
+    pub const MAP_PRIVATE   : i32 = 0x02;
+    #[cfg(target_os = "linux")]
+    pub const MAP_ANONYMOUS : i32 = 0x20;
+    #[cfg(not(target_os = "linux"))]
+    pub const MAP_ANONYMOUS : i32 = 0x1000;
+
+    extern "C" {
+        pub fn mmap(addr : *mut c_void, len : usize, prot : i32, flags : i32, fd : i32, offset : i64) -> *mut c_void;
+        pub fn mprotect(addr : *mut c_void, len : usize, prot : i32) -> i32;
+        pub fn munmap(addr : *mut c_void, len : usize) -> i32;
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+fn run_native(code : &[u8], tape : &mut [u8], ctx : &mut JitContext) -> Result<u64, RustfuckError>
+{
+    use std::ptr;
+
+    unsafe {
+        let mem = sys::mmap(ptr::null_mut(), code.len(), sys::PROT_READ | sys::PROT_WRITE,
+                            sys::MAP_PRIVATE | sys::MAP_ANONYMOUS, -1, 0);
+        if mem as isize == -1 {
+            return Err(RustfuckError::IO(io::Error::last_os_error()));
+        }
+        ptr::copy_nonoverlapping(code.as_ptr(), mem as *mut u8, code.len());
+        if sys::mprotect(mem, code.len(), sys::PROT_READ | sys::PROT_EXEC) != 0 {
+            let err = io::Error::last_os_error();
+            sys::munmap(mem, code.len());
+            return Err(RustfuckError::IO(err));
+        }
+
+        let f : extern "C" fn(*mut u8, *mut JitContext) -> u64 = std::mem::transmute(mem);
+        let status = f(tape.as_mut_ptr(), ctx);
+        sys::munmap(mem, code.len());
+        return Ok(status);
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+fn run_native(_code : &[u8], _tape : &mut [u8], _ctx : &mut JitContext) -> Result<u64, RustfuckError>
+{
+    return Err(RustfuckError::RuntimeError("the JIT is only supported on x86-64 unix systems".to_owned(), None));
+}
+
+/// Compiles `ops` to native code and runs it against `state`, reading `,`
+/// from `input` and writing `.` to `output`, with the same observable
+/// behavior as `interpreter::execute_ops()`.
+pub fn execute(ops : &[Op], state : &mut ProgramState, input : &mut dyn Read, output : &mut dyn Write)
+        -> Result<(), RustfuckError>
+{
+    if state.config.tape == Tape::Dynamic {
+        return Err(RustfuckError::RuntimeError("the JIT does not support a dynamic tape".to_owned(), None));
+    }
+    if state.config.max_steps.is_some() || state.config.timeout.is_some() {
+        return Err(RustfuckError::RuntimeError("the JIT does not support step limits or timeouts".to_owned(), None));
+    }
+    if ops.iter().any(|&op| matches!(op, Op::Proc(_) | Op::Call)) {
+        return Err(RustfuckError::RuntimeError("the JIT does not support pbrain procedures".to_owned(), None));
+    }
+    if ops.contains(&Op::Fork) {
+        return Err(RustfuckError::RuntimeError("the JIT does not support Brainfork threads".to_owned(), None));
+    }
+    if ops.contains(&Op::Random) {
+        return Err(RustfuckError::RuntimeError("the JIT does not support random bytes".to_owned(), None));
+    }
+    if state.pc != 0 {
+        return Err(RustfuckError::RuntimeError("the JIT can't resume a program in the middle".to_owned(), None));
+    }
+    let io = match state.config.io {
+        Io::Bytes   => IoCalls::Callbacks {
+            input:  jit_getchar as *const () as u64,
+            output: jit_putchar as *const () as u64
+        },
+        Io::Numeric => IoCalls::Callbacks {
+            input:  jit_getnum as *const () as u64,
+            output: jit_putnum as *const () as u64
+        }
+    };
+    /* unchecked accesses could corrupt rustfuck's own memory */
+    let mut config = state.config.clone();
+    config.tape_size = state.tape.len();
+    if config.bounds == Bounds::Unchecked {
+        config.bounds = Bounds::Error;
+    }
+    let code = x86_64::assemble(ops, state.ptr as u64, &config, &io).code;
+    let mut tape = state.tape.to_le_bytes();
+    let mut output = BufWriter::new(output);
+    let mut ctx = JitContext { ptr: state.ptr as u64, number: 0, error: None, input, output: &mut output, flush: config.flush };
+
+    /* whatever the program wrote before stopping is still shown */
+    let status = run_native(&code, &mut tape, &mut ctx);
+    let flushed = ctx.output.flush();
+    let status = status?;
+    flushed?;
+
+    state.tape.copy_from_le_bytes(&tape);
+    state.ptr = ctx.ptr as i32;
+
+    match status {
+        x86_64::STATUS_OK            => Ok(()),
+        x86_64::STATUS_OUT_OF_BOUNDS => Err(RustfuckError::RuntimeError(
+                format!("pointer moved outside of tape (cell {})", ctx.ptr as i64), None)),
+        _                            => Err(ctx.error.take().unwrap_or_else(||
+                RustfuckError::RuntimeError("I/O callback failed".to_owned(), None)))
+    }
+}
@@ -0,0 +1,585 @@
+/*
+ * Emits a JVM class file whose `main` runs the program, so it can be run
+ * with nothing but `java`. The tape is an array of the cell type, which the
+ * code works on through the locals of a static `run` method. Parts of a big
+ * program are outlined into more such methods, as the JVM limits a method to
+ * 64KiB of bytecode, and only compiles small ones to machine code.
+ *
+ * The class file is version 49 (Java 5), which needs no stack map frames,
+ * but the library methods called are from Java 8.
+ */
+
+use Config;
+use config::CellWidth;
+use config::Eof;
+use config::Io;
+use Stmt;
+use std::collections::HashMap;
+
+const CLASS_VERSION : u16 = 49;
+
+const ACC_PUBLIC  : u16 = 0x0001;
+const ACC_PRIVATE : u16 = 0x0002;
+const ACC_STATIC  : u16 = 0x0008;
+const ACC_FINAL   : u16 = 0x0010;
+const ACC_SUPER   : u16 = 0x0020;
+
+const CONSTANT_UTF8          : u8 = 1;
+const CONSTANT_INTEGER       : u8 = 3;
+const CONSTANT_CLASS         : u8 = 7;
+const CONSTANT_STRING        : u8 = 8;
+const CONSTANT_FIELDREF      : u8 = 9;
+const CONSTANT_METHODREF     : u8 = 10;
+const CONSTANT_NAME_AND_TYPE : u8 = 12;
+
+const ICONST_M1     : u8 = 0x02;
+const ICONST_0      : u8 = 0x03;
+const ICONST_1      : u8 = 0x04;
+const BIPUSH        : u8 = 0x10;
+const SIPUSH        : u8 = 0x11;
+const LDC           : u8 = 0x12;
+const LDC_W         : u8 = 0x13;
+const ILOAD_1       : u8 = 0x1b;
+const ILOAD_2       : u8 = 0x1c;
+const ALOAD_0       : u8 = 0x2a;
+const ISTORE_1      : u8 = 0x3c;
+const ISTORE_2      : u8 = 0x3d;
+const POP           : u8 = 0x57;
+const DUP           : u8 = 0x59;
+const DUP2          : u8 = 0x5c;
+const IADD          : u8 = 0x60;
+const IMUL          : u8 = 0x68;
+const IINC          : u8 = 0x84;
+const IFEQ          : u8 = 0x99;
+const IFLT          : u8 = 0x9b;
+const GOTO          : u8 = 0xa7;
+const IRETURN       : u8 = 0xac;
+const RETURN        : u8 = 0xb1;
+const GETSTATIC     : u8 = 0xb2;
+const PUTSTATIC     : u8 = 0xb3;
+const INVOKEVIRTUAL : u8 = 0xb6;
+const INVOKESPECIAL : u8 = 0xb7;
+const INVOKESTATIC  : u8 = 0xb8;
+const NEW           : u8 = 0xbb;
+const NEWARRAY      : u8 = 0xbc;
+const WIDE          : u8 = 0xc4;
+
+/*
+ * Most bytecode a method gets before parts of it are outlined, leaving room
+ * for loops around it. HotSpot only compiles methods of up to 8000 bytes,
+ * and optimizes small ones much better.
+ */
+const METHOD_SIZE : usize = 1024;
+
+/* the operand stack and locals every method is given: the tape, the pointer and a scratch int */
+const MAX_STACK  : u16 = 8;
+const MAX_LOCALS : u16 = 3;
+
+const BOUNDS_MESSAGE : &str = "Error: runtime error: pointer moved outside of tape";
+
+/* the constant pool of the class, which adds each constant once and returns its index */
+struct ConstantPool {
+    bytes   : Vec<u8>,
+    count   : u16,
+    indices : HashMap<Vec<u8>, u16>
+}
+
+impl ConstantPool {
+    fn new() -> ConstantPool
+    {
+        return ConstantPool { bytes: Vec::new(), count: 1, indices: HashMap::new() };
+    }
+
+    fn add(&mut self, entry : Vec<u8>) -> u16
+    {
+        if let Some(&index) = self.indices.get(&entry) {
+            return index;
+        }
+        let index = self.count;
+        self.bytes.extend_from_slice(&entry);
+        self.indices.insert(entry, index);
+        self.count += 1;
+        return index;
+    }
+
+    fn add_ref(&mut self, tag : u8, first : u16, second : u16) -> u16
+    {
+        let mut entry = vec![tag];
+        entry.extend_from_slice(&first.to_be_bytes());
+        entry.extend_from_slice(&second.to_be_bytes());
+        return self.add(entry);
+    }
+
+    fn utf8(&mut self, s : &str) -> u16
+    {
+        let mut entry = vec![CONSTANT_UTF8];
+        entry.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        entry.extend_from_slice(s.as_bytes());
+        return self.add(entry);
+    }
+
+    fn class(&mut self, name : &str) -> u16
+    {
+        let name = self.utf8(name);
+        let mut entry = vec![CONSTANT_CLASS];
+        entry.extend_from_slice(&name.to_be_bytes());
+        return self.add(entry);
+    }
+
+    fn string(&mut self, s : &str) -> u16
+    {
+        let s = self.utf8(s);
+        let mut entry = vec![CONSTANT_STRING];
+        entry.extend_from_slice(&s.to_be_bytes());
+        return self.add(entry);
+    }
+
+    fn integer(&mut self, n : i32) -> u16
+    {
+        let mut entry = vec![CONSTANT_INTEGER];
+        entry.extend_from_slice(&n.to_be_bytes());
+        return self.add(entry);
+    }
+
+    fn member(&mut self, tag : u8, class : &str, name : &str, descriptor : &str) -> u16
+    {
+        let class = self.class(class);
+        let (name, descriptor) = (self.utf8(name), self.utf8(descriptor));
+        let name_and_type = self.add_ref(CONSTANT_NAME_AND_TYPE, name, descriptor);
+        return self.add_ref(tag, class, name_and_type);
+    }
+
+    fn field(&mut self, class : &str, name : &str, descriptor : &str) -> u16
+    {
+        return self.member(CONSTANT_FIELDREF, class, name, descriptor);
+    }
+
+    fn method(&mut self, class : &str, name : &str, descriptor : &str) -> u16
+    {
+        return self.member(CONSTANT_METHODREF, class, name, descriptor);
+    }
+}
+
+/* a method of the class, with the bytecode of its body */
+struct Method {
+    access     : u16,
+    name       : String,
+    descriptor : String,
+    code       : Vec<u8>,
+    /* the range of the code that catches exceptions of the class, and where they go */
+    handler    : Option<(u16, u16, u16, &'static str)>
+}
+
+struct CodeGenContext<'a> {
+    class      : &'a str,
+    pool       : ConstantPool,
+    /* the methods outlined from `run` */
+    methods    : Vec<Method>,
+    /* the descriptor of `run` and the outlined methods, taking the tape and pointer, returning the pointer */
+    descriptor : String,
+    width      : CellWidth,
+    load       : u8,
+    store      : u8,
+    io         : Io,
+    eof        : Eof
+}
+
+fn write_u16(code : &mut Vec<u8>, op : u8, operand : u16)
+{
+    code.push(op);
+    code.extend_from_slice(&operand.to_be_bytes());
+}
+
+fn write_push(code : &mut Vec<u8>, context : &mut CodeGenContext, n : i32)
+{
+    if (-1..=5).contains(&n) {
+        code.push((ICONST_0 as i32 + n) as u8);
+    } else if n as i8 as i32 == n {
+        code.extend_from_slice(&[BIPUSH, n as u8]);
+    } else if n as i16 as i32 == n {
+        write_u16(code, SIPUSH, n as u16);
+    } else {
+        let index = context.pool.integer(n);
+        if index < 256 {
+            code.extend_from_slice(&[LDC, index as u8]);
+        } else {
+            write_u16(code, LDC_W, index);
+        }
+    }
+}
+
+/* pushes the tape and the index of the cell at `offset` from the pointer */
+fn write_cell_ref(code : &mut Vec<u8>, context : &mut CodeGenContext, offset : i32)
+{
+    code.extend_from_slice(&[ALOAD_0, ILOAD_1]);
+    if offset != 0 {
+        write_push(code, context, offset);
+        code.push(IADD);
+    }
+}
+
+fn write_move(code : &mut Vec<u8>, context : &mut CodeGenContext, n : i32)
+{
+    if n as i8 as i32 == n {
+        code.extend_from_slice(&[IINC, 1, n as u8]);
+    } else if n as i16 as i32 == n {
+        code.extend_from_slice(&[WIDE, IINC, 0, 1]);
+        code.extend_from_slice(&(n as i16).to_be_bytes());
+    } else {
+        code.push(ILOAD_1);
+        write_push(code, context, n);
+        code.extend_from_slice(&[IADD, ISTORE_1]);
+    }
+}
+
+/* adds the value pushed by `value` to the cell at `offset` */
+fn write_add_at<F>(code : &mut Vec<u8>, context : &mut CodeGenContext, offset : i32, value : F)
+    where F : FnOnce(&mut Vec<u8>, &mut CodeGenContext)
+{
+    write_cell_ref(code, context, offset);
+    code.extend_from_slice(&[DUP2, context.load]);
+    value(code, context);
+    code.extend_from_slice(&[IADD, context.store]);
+}
+
+/* writes a branch with its offset to fill in, returning where the instruction is */
+fn write_branch(code : &mut Vec<u8>, op : u8) -> usize
+{
+    code.extend_from_slice(&[op, 0, 0]);
+    return code.len() - 3;
+}
+
+/* points the branch at `branch` to `target`, both positions in `code` */
+fn patch_branch(code : &mut Vec<u8>, branch : usize, target : usize)
+{
+    let offset = (target as isize - branch as isize) as i16;
+    code[branch + 1..branch + 3].copy_from_slice(&offset.to_be_bytes());
+}
+
+fn write_flush(code : &mut Vec<u8>, context : &mut CodeGenContext)
+{
+    let out = context.pool.field("java/lang/System", "out", "Ljava/io/PrintStream;");
+    let flush = context.pool.method("java/io/PrintStream", "flush", "()V");
+    write_u16(code, GETSTATIC, out);
+    write_u16(code, INVOKEVIRTUAL, flush);
+}
+
+/*
+ * System.in.read() returns -1 on EOF, and the Scanner of numeric I/O says
+ * whether there is a number left. Output is flushed first, so a prompt is
+ * shown before the program waits for an answer.
+ */
+fn write_getc(code : &mut Vec<u8>, context : &mut CodeGenContext)
+{
+    write_flush(code, context);
+    let eof = match context.io {
+        Io::Bytes   => {
+            let input = context.pool.field("java/lang/System", "in", "Ljava/io/InputStream;");
+            let read = context.pool.method("java/io/InputStream", "read", "()I");
+            write_u16(code, GETSTATIC, input);
+            write_u16(code, INVOKEVIRTUAL, read);
+            code.extend_from_slice(&[ISTORE_2, ILOAD_2]);
+            write_branch(code, IFLT)
+        },
+        Io::Numeric => {
+            let scanner = context.pool.field(context.class, "in", "Ljava/util/Scanner;");
+            let has_next = context.pool.method("java/util/Scanner", "hasNextInt", "()Z");
+            let next = context.pool.method("java/util/Scanner", "nextInt", "()I");
+            write_u16(code, GETSTATIC, scanner);
+            write_u16(code, INVOKEVIRTUAL, has_next);
+            let eof = write_branch(code, IFEQ);
+            write_u16(code, GETSTATIC, scanner);
+            write_u16(code, INVOKEVIRTUAL, next);
+            code.push(ISTORE_2);
+            eof
+        }
+    };
+    code.extend_from_slice(&[ALOAD_0, ILOAD_1, ILOAD_2, context.store]);
+    let end = write_branch(code, GOTO);
+    let at_eof = code.len();
+    patch_branch(code, eof, at_eof);
+    match context.eof {
+        Eof::MinusOne  => code.extend_from_slice(&[ALOAD_0, ILOAD_1, ICONST_M1, context.store]),
+        Eof::Zero      => code.extend_from_slice(&[ALOAD_0, ILOAD_1, ICONST_0, context.store]),
+        Eof::Unchanged => ()
+    }
+    let after = code.len();
+    patch_branch(code, end, after);
+}
+
+/* prints the cell as a byte, or as an unsigned number and a newline */
+fn write_putc(code : &mut Vec<u8>, context : &mut CodeGenContext)
+{
+    let out = context.pool.field("java/lang/System", "out", "Ljava/io/PrintStream;");
+    write_u16(code, GETSTATIC, out);
+    code.extend_from_slice(&[ALOAD_0, ILOAD_1, context.load]);
+    let print = match context.io {
+        Io::Bytes   => context.pool.method("java/io/PrintStream", "write", "(I)V"),
+        Io::Numeric => {
+            /* bytes are loaded sign-extended, and chars zero-extended */
+            let unsigned = match context.width {
+                CellWidth::Bits8 => context.pool.method("java/lang/Byte", "toUnsignedLong", "(B)J"),
+                _                => context.pool.method("java/lang/Integer", "toUnsignedLong", "(I)J")
+            };
+            write_u16(code, INVOKESTATIC, unsigned);
+            context.pool.method("java/io/PrintStream", "println", "(J)V")
+        }
+    };
+    write_u16(code, INVOKEVIRTUAL, print);
+}
+
+/* `while (tape[p] != 0) body`, with `body` already generated */
+fn write_loop(code : &mut Vec<u8>, body : &[u8], context : &CodeGenContext)
+{
+    let start = code.len();
+    code.extend_from_slice(&[ALOAD_0, ILOAD_1, context.load]);
+    let exit = write_branch(code, IFEQ);
+    code.extend_from_slice(body);
+    let back = write_branch(code, GOTO);
+    patch_branch(code, back, start);
+    let end = code.len();
+    patch_branch(code, exit, end);
+}
+
+fn write_stmt(code : &mut Vec<u8>, stmt : &Stmt, context : &mut CodeGenContext, width : CellWidth)
+{
+    let cell = |n : i32| { width.wrap(n) };
+    match stmt {
+        Stmt::Move(n, _)      => write_move(code, context, *n),
+        Stmt::Add(n, _)       => write_add_at(code, context, 0, |code, context| write_push(code, context, cell(*n))),
+        Stmt::AddAt(o, n, _)  => write_add_at(code, context, *o, |code, context| write_push(code, context, cell(*n))),
+        Stmt::Set(n, _)       => {
+            write_cell_ref(code, context, 0);
+            write_push(code, context, cell(*n));
+            code.push(context.store);
+        },
+        Stmt::MulAdd(o, f, _) => {
+            code.extend_from_slice(&[ALOAD_0, ILOAD_1, context.load]);
+            let skip = write_branch(code, IFEQ);
+            let load = context.load;
+            write_add_at(code, context, *o, |code, context| {
+                code.extend_from_slice(&[ALOAD_0, ILOAD_1, load]);
+                write_push(code, context, cell(*f));
+                code.push(IMUL);
+            });
+            let end = code.len();
+            patch_branch(code, skip, end);
+        },
+        Stmt::Scan(n, _)      => {
+            let mut body = Vec::new();
+            write_move(&mut body, context, *n);
+            write_loop(code, &body, context);
+        },
+        Stmt::Input(_)        => write_getc(code, context),
+        Stmt::Output(_)       => write_putc(code, context),
+        Stmt::Loop(loop_code, _) => {
+            let body = write_block(loop_code, context, width);
+            write_loop(code, &body, context);
+        },
+        /* breakpoints are only shown by the interpreter */
+        Stmt::Debug(_)        => (),
+        /* only the interpreter and the llvm backend run them, see `uses_procedures()` */
+        Stmt::Proc(..) | Stmt::Call(_) => panic!("the JVM translation has no pbrain procedures"),
+        /* only the interpreter runs them, see `uses_forks()` */
+        Stmt::Fork(_)         => panic!("the JVM translation has no Brainfork threads"),
+        /* only the interpreter and the llvm backend run it, see `uses_random()` */
+        Stmt::Random(_)       => panic!("the JVM translation has no random bytes")
+    }
+}
+
+/*
+ * Generates `code`, outlining runs of its statements into methods of their
+ * own if it would take up more than METHOD_SIZE bytes. Branch offsets are
+ * relative, so the bytecode of each statement can be moved around as is.
+ */
+fn write_block(code : &[Stmt], context : &mut CodeGenContext, width : CellWidth) -> Vec<u8>
+{
+    let stmts : Vec<Vec<u8>> = code.iter().map(|stmt| {
+        let mut bytes = Vec::new();
+        write_stmt(&mut bytes, stmt, context, width);
+        bytes
+    }).collect();
+    if stmts.iter().map(Vec::len).sum::<usize>() <= METHOD_SIZE {
+        return stmts.concat();
+    }
+    let mut block = Vec::new();
+    let mut run : Vec<u8> = Vec::new();
+    for stmt in stmts {
+        if !run.is_empty() && run.len() + stmt.len() > METHOD_SIZE {
+            write_chunk(&mut block, run, context);
+            run = Vec::new();
+        }
+        run.extend_from_slice(&stmt);
+    }
+    write_chunk(&mut block, run, context);
+    return block;
+}
+
+/* outlines `run` into a method of its own, and calls it */
+fn write_chunk(block : &mut Vec<u8>, mut run : Vec<u8>, context : &mut CodeGenContext)
+{
+    let name = format!("chunk{}", context.methods.len());
+    run.extend_from_slice(&[ILOAD_1, IRETURN]);
+    let method = context.pool.method(context.class, &name, &context.descriptor);
+    block.extend_from_slice(&[ALOAD_0, ILOAD_1]);
+    write_u16(block, INVOKESTATIC, method);
+    block.push(ISTORE_1);
+    context.methods.push(Method { access: ACC_PRIVATE | ACC_STATIC, name, descriptor: context.descriptor.clone(),
+                                  code: run, handler: None });
+}
+
+/*
+ * `main` makes the tape, and the Scanner for numeric I/O, and runs the
+ * program. It reports the pointer moving off the tape like the interpreter,
+ * exiting with status 1.
+ */
+fn write_main(context : &mut CodeGenContext, config : &Config) -> Method
+{
+    let mut code = Vec::new();
+    if config.io == Io::Numeric {
+        let scanner = context.pool.class("java/util/Scanner");
+        let input = context.pool.field("java/lang/System", "in", "Ljava/io/InputStream;");
+        let init = context.pool.method("java/util/Scanner", "<init>", "(Ljava/io/InputStream;)V");
+        let field = context.pool.field(context.class, "in", "Ljava/util/Scanner;");
+        write_u16(&mut code, NEW, scanner);
+        code.push(DUP);
+        write_u16(&mut code, GETSTATIC, input);
+        write_u16(&mut code, INVOKESPECIAL, init);
+        write_u16(&mut code, PUTSTATIC, field);
+    }
+    let start = code.len() as u16;
+    write_push(&mut code, context, config.tape_size as i32);
+    code.extend_from_slice(&[NEWARRAY, array_of(context.width).1, ICONST_0]);
+    let run = context.pool.method(context.class, "run", &context.descriptor);
+    write_u16(&mut code, INVOKESTATIC, run);
+    code.push(POP);
+    let end = code.len() as u16;
+    write_flush(&mut code, context);
+    code.push(RETURN);
+
+    let handler = code.len() as u16;
+    code.push(POP);
+    write_flush(&mut code, context);
+    let err = context.pool.field("java/lang/System", "err", "Ljava/io/PrintStream;");
+    let message = context.pool.string(BOUNDS_MESSAGE);
+    let println = context.pool.method("java/io/PrintStream", "println", "(Ljava/lang/String;)V");
+    let exit = context.pool.method("java/lang/System", "exit", "(I)V");
+    write_u16(&mut code, GETSTATIC, err);
+    write_u16(&mut code, LDC_W, message);
+    write_u16(&mut code, INVOKEVIRTUAL, println);
+    code.push(ICONST_1);
+    write_u16(&mut code, INVOKESTATIC, exit);
+    code.push(RETURN);
+    return Method { access: ACC_PUBLIC | ACC_STATIC, name: "main".to_owned(), descriptor: "([Ljava/lang/String;)V".to_owned(),
+                    code, handler: Some((start, end, handler, "java/lang/ArrayIndexOutOfBoundsException")) };
+}
+
+fn write_method(class : &mut Vec<u8>, method : &Method, pool : &mut ConstantPool)
+{
+    class.extend_from_slice(&method.access.to_be_bytes());
+    class.extend_from_slice(&pool.utf8(&method.name).to_be_bytes());
+    class.extend_from_slice(&pool.utf8(&method.descriptor).to_be_bytes());
+    class.extend_from_slice(&1u16.to_be_bytes());
+
+    let mut attribute = Vec::new();
+    attribute.extend_from_slice(&MAX_STACK.to_be_bytes());
+    attribute.extend_from_slice(&MAX_LOCALS.to_be_bytes());
+    attribute.extend_from_slice(&(method.code.len() as u32).to_be_bytes());
+    attribute.extend_from_slice(&method.code);
+    match method.handler {
+        Some((start, end, handler, exception)) => {
+            attribute.extend_from_slice(&1u16.to_be_bytes());
+            let exception = pool.class(exception);
+            for n in &[start, end, handler, exception] {
+                attribute.extend_from_slice(&n.to_be_bytes());
+            }
+        },
+        None => attribute.extend_from_slice(&0u16.to_be_bytes())
+    }
+    attribute.extend_from_slice(&0u16.to_be_bytes());
+    class.extend_from_slice(&pool.utf8("Code").to_be_bytes());
+    class.extend_from_slice(&(attribute.len() as u32).to_be_bytes());
+    class.extend_from_slice(&attribute);
+}
+
+/* the array type of a tape of these cells, bytes, chars or ints: its descriptor, `newarray` type, and load and store instructions */
+fn array_of(width : CellWidth) -> (&'static str, u8, u8, u8)
+{
+    match width {
+        CellWidth::Bits8  => ("[B", 8, 0x33, 0x54),  // T_BYTE, baload, bastore
+        CellWidth::Bits16 => ("[C", 5, 0x34, 0x55),  // T_CHAR, caload, castore
+        CellWidth::Bits32 => ("[I", 10, 0x2e, 0x4f)  // T_INT, iaload, iastore
+    }
+}
+
+/// Turns `name`, like the file name of a program, into a Java class name,
+/// which the class file has to be named after to be run.
+pub fn class_name(name : &str) -> String
+{
+    let name : String = name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '$' { c } else { '_' })
+            .collect();
+    if !name.starts_with(|c : char| c.is_ascii_alphabetic() || c == '_' || c == '$') {
+        return format!("_{}", name);
+    }
+    return name;
+}
+
+/// Generates a JVM class file for a class `class` whose `main` runs `code`,
+/// with a tape of `config.tape_size` wrapping cells of `config.cell_width`,
+/// using `System.in` and `System.out` for I/O. Numeric I/O reads numbers
+/// with a `java.util.Scanner`, which are ints, like C's scanf. The class
+/// must be saved as `<class>.class`, and is run with `java <class>`, on Java
+/// 8 or later. Experimental.
+pub fn code_gen(code : &[Stmt], config : &Config, class : &str) -> Vec<u8>
+{
+    let width = config.cell_width;
+    let (array, _, load, store) = array_of(width);
+    let mut context = CodeGenContext {
+        class,
+        pool:       ConstantPool::new(),
+        methods:    Vec::new(),
+        descriptor: format!("({}I)I", array),
+        width,
+        load,
+        store,
+        io:         config.io,
+        eof:        config.eof
+    };
+
+    let mut run = write_block(code, &mut context, width);
+    run.extend_from_slice(&[ILOAD_1, IRETURN]);
+    let run = Method { access: ACC_PRIVATE | ACC_STATIC, name: "run".to_owned(), descriptor: context.descriptor.clone(),
+                       code: run, handler: None };
+    let main = write_main(&mut context, config);
+    let this = context.pool.class(class);
+    let object = context.pool.class("java/lang/Object");
+
+    let mut methods = Vec::new();
+    for method in [&main, &run].iter().cloned().chain(context.methods.iter()) {
+        write_method(&mut methods, method, &mut context.pool);
+    }
+    let mut fields = Vec::new();
+    if config.io == Io::Numeric {
+        fields.extend_from_slice(&(ACC_PRIVATE | ACC_STATIC).to_be_bytes());
+        fields.extend_from_slice(&context.pool.utf8("in").to_be_bytes());
+        fields.extend_from_slice(&context.pool.utf8("Ljava/util/Scanner;").to_be_bytes());
+        fields.extend_from_slice(&0u16.to_be_bytes());
+    }
+
+    let mut bytes = vec![0xCA, 0xFE, 0xBA, 0xBE];
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&CLASS_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&context.pool.count.to_be_bytes());
+    bytes.extend_from_slice(&context.pool.bytes);
+    bytes.extend_from_slice(&(ACC_PUBLIC | ACC_FINAL | ACC_SUPER).to_be_bytes());
+    bytes.extend_from_slice(&this.to_be_bytes());
+    bytes.extend_from_slice(&object.to_be_bytes());
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&(if fields.is_empty() { 0u16 } else { 1u16 }).to_be_bytes());
+    bytes.extend_from_slice(&fields);
+    bytes.extend_from_slice(&(2 + context.methods.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(&methods);
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    return bytes;
+}
@@ -0,0 +1,625 @@
+//! rustfuck - a brainfuck interpreter and LLVM based compiler.
+//!
+//! The typical flow is to `parse()` brainfuck source into a program
+//! representation, and then either `execute()` it directly or hand it to
+//! `llvm_ir_generator::code_gen()` (or `compiler::compile()`) to produce a
+//! native executable. `to_brainfuck()` prints a program back as source.
+
+pub mod error;
+pub mod config;
+pub mod diagnostic;
+pub mod cells;
+pub mod interpreter;
+pub mod stats;
+pub mod profile;
+pub mod trace;
+pub mod bytecode;
+pub mod bundle;
+pub mod analysis;
+pub mod optimizer;
+pub mod partial_eval;
+pub mod jit;
+pub mod terminal;
+pub mod debugger;
+pub mod repl;
+pub mod snapshot;
+pub mod verify;
+pub mod equiv;
+pub mod test_runner;
+pub mod arbitrary;
+pub mod minify;
+pub mod formatter;
+pub mod generate;
+pub mod explain;
+pub mod preprocessor;
+pub mod ook;
+pub mod token_map;
+pub mod ast_json;
+pub mod lint;
+pub mod random;
+mod x86_64;
+pub mod llvm_ir_generator;
+pub mod c_generator;
+pub mod rust_generator;
+pub mod wasm_generator;
+pub mod jvm_generator;
+pub mod object_generator;
+#[cfg(llvm_in_process)]
+mod llvm_in_process;
+pub mod compiler;
+
+pub use error::RustfuckError;
+pub use config::Config;
+pub use diagnostic::Diagnostic;
+pub use interpreter::ProgramState;
+pub use interpreter::execute;
+pub use bytecode::Op;
+pub use token_map::TokenMap;
+
+use std::borrow::Cow;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+/// Default number of cells on the tape, see `Config::tape_size`.
+pub const TAPE_SIZE : usize = 65536;
+
+/// The bytes of the source a statement came from, from `start` up to but
+/// not including `end`.
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start : usize,
+    pub end   : usize
+}
+
+impl Span {
+    pub fn new(start : usize, end : usize) -> Span
+    {
+        return Span { start, end };
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other : Span) -> Span
+    {
+        return Span { start: self.start.min(other.start), end: self.end.max(other.end) };
+    }
+}
+
+/* Printed like a range, to keep printed programs readable */
+impl std::fmt::Debug for Span {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// A single statement in the brainfuck program representation, with the
+/// span of the source it came from. Consecutive `>`/`<` and `+`/`-` are
+/// folded into a single `Move` or `Add`, spanning all of them. Statements
+/// the optimizer makes span what they replace.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Stmt
+{
+    Move(i32, Span),
+    Add(i32, Span),
+    Input(Span),
+    Output(Span),
+    /// A loop body, and the span from its `[` to its `]`.
+    Loop(Vec<Stmt>, Span),
+    /// Sets the current cell to a constant, produced by the optimizer.
+    Set(i32, Span),
+    /// `MulAdd(offset, factor, _)` adds the current cell times `factor` to
+    /// the cell at `offset` from the pointer, produced by the optimizer.
+    MulAdd(i32, i32, Span),
+    /// `AddAt(offset, n, _)` adds `n` to the cell at `offset` from the
+    /// pointer without moving it, produced by the optimizer.
+    AddAt(i32, i32, Span),
+    /// Moves the pointer in steps of the given stride until it reaches a
+    /// zero cell, produced by the optimizer from loops like `[>]`.
+    Scan(i32, Span),
+    /// A `#` breakpoint. Only parsed if asked for, see
+    /// `ParseOptions::debug`. The interpreter passes it on to its hooks,
+    /// e.g. `ShowBreakpoints`, programs built by the llvm backend show the
+    /// cells around the pointer like it does, and the other backends ignore
+    /// it.
+    Debug(Span),
+    /// A pbrain procedure body, and the span from its `(` to its `)`. When
+    /// it runs, it defines the procedure numbered by the current cell,
+    /// replacing any with the same number, without running the body. Only
+    /// parsed if asked for, see `ParseOptions::procedures`.
+    Proc(Vec<Stmt>, Span),
+    /// A pbrain `:`, which runs the procedure numbered by the current cell.
+    Call(Span),
+    /// A Brainfork `Y`, which splits the running thread in two sharing the
+    /// tape: the current cell is set to 0, and a new thread starts after it
+    /// with the pointer on the next cell, set to 1. Only parsed in
+    /// `Lang::Brainfork`.
+    Fork(Span),
+    /// A `?`, which stores a random byte in the current cell. Only parsed
+    /// if asked for, see `ParseOptions::random`.
+    Random(Span)
+}
+
+impl Stmt {
+    /// The span of the source this statement came from.
+    pub fn span(&self) -> Span
+    {
+        match *self {
+            Stmt::Move(_, span) | Stmt::Add(_, span) | Stmt::Set(_, span) | Stmt::Scan(_, span) => span,
+            Stmt::MulAdd(_, _, span) | Stmt::AddAt(_, _, span)                                => span,
+            Stmt::Input(span) | Stmt::Output(span) | Stmt::Loop(_, span) | Stmt::Debug(span)  => span,
+            Stmt::Proc(_, span) | Stmt::Call(span) | Stmt::Fork(span) | Stmt::Random(span)     => span
+        }
+    }
+}
+
+/// Languages `parse_with()` can read, each a different spelling of the
+/// same eight commands, or with a few more.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Lang {
+    #[default]
+    Brainfuck,
+    /// Ook!, where each command is a pair of `Ook.`, `Ook?` or `Ook!`
+    /// words, see `ook::commands()`.
+    Ook,
+    /// Brainfork, brainfuck with `Y` to fork a thread, see `Stmt::Fork`.
+    Brainfork
+}
+
+/// Extensions to the brainfuck syntax that `parse_with()` can accept.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// Parse `#` into a `Stmt::Debug` breakpoint instead of a comment.
+    pub debug      : bool,
+    /// With `debug`, parse `!` as the breakpoint instead of `#`, for source
+    /// that has `#` in its comments.
+    pub bang_breakpoints : bool,
+    /// Parse pbrain's procedures, `(`, `)` and `:`, into `Stmt::Proc` and
+    /// `Stmt::Call` instead of comments.
+    pub procedures : bool,
+    /// Parse `?` into a `Stmt::Random` instead of a comment.
+    pub random     : bool,
+    /// Reject brainfuck source with anything but commands and whitespace
+    /// in it, instead of reading the rest as comments.
+    pub strict     : bool,
+    /// The language of the source, brainfuck by default.
+    pub lang       : Lang
+}
+
+/*
+ * Dropping a deeply nested program recursively would overflow the stack, so
+ * loop bodies are unnested onto a heap allocated worklist instead.
+ */
+impl Drop for Stmt {
+    fn drop(&mut self)
+    {
+        if let Stmt::Loop(body, _) | Stmt::Proc(body, _) = self {
+            let mut pending = std::mem::take(body);
+            while let Some(mut stmt) = pending.pop() {
+                if let Stmt::Loop(inner, _) | Stmt::Proc(inner, _) = &mut stmt {
+                    pending.append(inner);
+                }
+            }
+        }
+    }
+}
+
+/// Parses brainfuck source into its program representation. Characters
+/// other than the eight brainfuck commands are treated as comments.
+/// Unbalanced brackets are reported as a `ParseError` pointing at the
+/// offending bracket.
+pub fn parse(src : &[u8]) -> Result<Vec<Stmt>, RustfuckError>
+{
+    return parse_with(src, &ParseOptions::default());
+}
+
+/// Like `parse()`, with the syntax extensions enabled in `options`, and in
+/// the language it says. Whatever the language, spans and diagnostics point
+/// into `src` itself. A `#!` line at the start, which makes a source file
+/// an executable script on Unix, is skipped. With `options.strict`, the
+/// first character in brainfuck source that is neither a command nor
+/// whitespace is a `ParseError`.
+pub fn parse_with(src : &[u8], options : &ParseOptions) -> Result<Vec<Stmt>, RustfuckError>
+{
+    let src = &*without_shebang(src);
+    match options.lang {
+        Lang::Brainfuck | Lang::Brainfork => {
+            if options.strict {
+                check_strict(src, options)?;
+            }
+            let commands = src.iter().enumerate().map(|(i, &b)| (b, Span::new(i, i + 1)));
+            return parse_commands(commands, &|c| c.to_string(), options).map_err(|e| parse_error(src, e));
+        },
+        Lang::Ook => return parse_commands(ook::commands(src)?.into_iter(), &ook::spelling, options).map_err(|e| parse_error(src, e))
+    }
+}
+
+/// Like `parse_with()`, but with the commands spelled as `map` says instead
+/// of in the language in `options`.
+pub fn parse_mapped(src : &[u8], map : &TokenMap, options : &ParseOptions) -> Result<Vec<Stmt>, RustfuckError>
+{
+    let src = &*without_shebang(src);
+    return parse_commands(map.commands(src).into_iter(), &|c| map.spelling(c), options).map_err(|e| parse_error(src, e));
+}
+
+/* How many bytes of source `parse_reader()` reads at a time */
+const CHUNK_SIZE : usize = 1 << 16;
+
+/// Like `parse_with()`, but reading the source from `reader` a chunk at a
+/// time, so only the program is kept in memory, never the source. For
+/// huge generated programs, which would otherwise need room for both at
+/// once. Ook! and strict source are read whole, and parsed like
+/// `parse_with()` does. Diagnostics need the source, so the reader is
+/// rewound and read again for one if the source doesn't parse.
+pub fn parse_reader<R>(mut reader : R, options : &ParseOptions) -> Result<Vec<Stmt>, RustfuckError>
+    where R : Read + Seek
+{
+    if options.lang == Lang::Ook || options.strict {
+        let mut src = Vec::new();
+        reader.read_to_end(&mut src)?;
+        return parse_with(&src, options);
+    }
+
+    let mut chunks = Chunks { reader: &mut reader, buf: vec![0; CHUNK_SIZE], len: 0, pos: 0, offset: 0,
+                              shebang: false, error: None };
+    chunks.fill()?;
+    chunks.shebang = chunks.buf[..chunks.len].starts_with(b"#!");
+    let parsed = parse_commands(&mut chunks, &|c| c.to_string(), options);
+    if let Some(e) = chunks.error {
+        return Err(RustfuckError::IO(e));
+    }
+    return parsed.or_else(|e| {
+        let mut src = Vec::new();
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_to_end(&mut src)?;
+        return Err(parse_error(&without_shebang(&src), e));
+    });
+}
+
+/*
+ * The bytes `reader` reads, a chunk at a time, each with its span, like
+ * `parse_with()` iterates over brainfuck source. The `#!` line the source
+ * starts with, if `shebang`, is skipped. Iteration ends at the first read
+ * that fails, with its error kept for after parsing.
+ */
+struct Chunks<'a, R : Read> {
+    reader  : &'a mut R,
+    buf     : Vec<u8>,
+    /* how much of `buf` was read, and how much of that iterated over */
+    len     : usize,
+    pos     : usize,
+    /* the offset in the source of the start of `buf` */
+    offset  : usize,
+    shebang : bool,
+    error   : Option<io::Error>
+}
+
+impl<'a, R : Read> Chunks<'a, R> {
+    /* Reads the next chunk into `buf`, or at least two bytes of it, to tell if the source starts with `#!`; none at the end */
+    fn fill(&mut self) -> io::Result<()>
+    {
+        self.offset += self.len;
+        self.len = 0;
+        self.pos = 0;
+        while self.len < 2 {
+            let n = match self.reader.read(&mut self.buf[self.len..]) {
+                Ok(n)                                                => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e)                                               => return Err(e)
+            };
+            if n == 0 {
+                break;
+            }
+            self.len += n;
+        }
+        return Ok(());
+    }
+}
+
+impl<'a, R : Read> Iterator for Chunks<'a, R> {
+    type Item = (u8, Span);
+
+    fn next(&mut self) -> Option<(u8, Span)>
+    {
+        loop {
+            if self.pos == self.len {
+                if let Err(e) = self.fill() {
+                    self.error = Some(e);
+                    return None;
+                }
+                if self.len == 0 {
+                    return None;
+                }
+            }
+            let b = self.buf[self.pos];
+            let offset = self.offset + self.pos;
+            self.pos += 1;
+            if self.shebang {
+                self.shebang = b != b'\n';
+                continue;
+            }
+            return Some((b, Span::new(offset, offset + 1)));
+        }
+    }
+}
+
+/* A `ParseError` for the offset in `src` and message `parse_commands()` failed with */
+fn parse_error(src : &[u8], (offset, msg) : (usize, String)) -> RustfuckError
+{
+    return RustfuckError::ParseError(Diagnostic::new(src, offset, &msg));
+}
+
+/*
+ * Fails on the first byte of `src` that is neither whitespace nor a command
+ * with the extensions in `options`
+ */
+fn check_strict(src : &[u8], options : &ParseOptions) -> Result<(), RustfuckError>
+{
+    let is_command = |b : u8| match b {
+        b'>' | b'<' | b'+' | b'-' | b'.' | b',' | b'[' | b']' => true,
+        b'#'                => options.debug && !options.bang_breakpoints,
+        b'!'                => options.debug && options.bang_breakpoints,
+        b'(' | b')' | b':'  => options.procedures,
+        b'Y'                => options.lang == Lang::Brainfork,
+        b'?'                => options.random,
+        _                   => false
+    };
+    if let Some(i) = src.iter().position(|&b| !b.is_ascii_whitespace() && !is_command(b)) {
+        /* the whole character, which may be several bytes long */
+        let rest = String::from_utf8_lossy(&src[i..src.len().min(i + 4)]);
+        let c = rest.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER);
+        let msg = format!("'{}' is neither a command nor whitespace", c.escape_debug());
+        return Err(RustfuckError::ParseError(Diagnostic::new(src, i, &msg)));
+    }
+    return Ok(());
+}
+
+/*
+ * `src` with the `#!` line it may start with blanked out, so no commands are
+ * read from the interpreter path in it, and offsets stay the same
+ */
+fn without_shebang(src : &[u8]) -> Cow<'_, [u8]>
+{
+    if !src.starts_with(b"#!") {
+        return Cow::Borrowed(src);
+    }
+    let end = src.iter().position(|&b| b == b'\n').unwrap_or(src.len());
+    let mut blanked = src.to_vec();
+    blanked[..end].fill(b' ');
+    return Cow::Owned(blanked);
+}
+
+/* An entry of the stack `parse_commands()` keeps the blocks it hasn't closed yet on */
+enum Node {
+    Stmt(Stmt),
+    /* the opening bracket of a loop or procedure whose body follows it, with its span and which it is */
+    Open(Span, char)
+}
+
+/*
+ * Parses brainfuck commands, each with the span of the source it was spelled
+ * in, and fails on an unmatched bracket with its offset and a message
+ * spelling it as `spelling` says the source does.
+ * The blocks that are still open share one stack, each right after the
+ * bracket that opens it, and a block is only moved off it into a `Vec` of
+ * its own when it closes, allocated once and at its final size, so parsing
+ * a huge program doesn't allocate and regrow a `Vec` for every loop. The
+ * program it returns is the usual tree, a `Vec` for every loop body.
+ */
+fn parse_commands<I>(commands : I, spelling : &dyn Fn(char) -> String, options : &ParseOptions)
+    -> Result<Vec<Stmt>, (usize, String)>
+    where I : Iterator<Item = (u8, Span)>
+{
+    let mut nodes : Vec<Node> = Vec::new();
+    /* the indices in `nodes` of the brackets of the enclosing loops and procedures */
+    let mut open_loops : Vec<usize> = Vec::new();
+
+    for (b, span) in commands {
+        let c = b as char;
+
+        /* Handle loop or procedure entry */
+        if c == '[' || (c == '(' && options.procedures) {
+            open_loops.push(nodes.len());
+            nodes.push(Node::Open(span, c));
+            continue;
+        }
+
+        /* Handle loop or procedure exit, which must match the entry */
+        if c == ']' || (c == ')' && options.procedures) {
+            let open = open_loops.pop().map(|i| match nodes[i] {
+                Node::Open(open, kind) => (i, open, kind),
+                Node::Stmt(_)          => unreachable!()
+            });
+            let make : fn(Vec<Stmt>, Span) -> Stmt = match open {
+                Some((_, _, '[')) if c == ']' => Stmt::Loop,
+                Some((_, _, '(')) if c == ')' => Stmt::Proc,
+                _ => return Err((span.start, format!("unmatched '{}'", spelling(c))))
+            };
+            let (i, open, _) = open.unwrap();
+            let body = take_block(&mut nodes, i + 1);
+            nodes[i] = Node::Stmt(make(body, open.to(span)));
+            continue;
+        }
+
+        /* handle regular statements */
+        let maybe_statement = match c {
+            '>' => Some(Stmt::Move(1, span)),
+            '<' => Some(Stmt::Move(-1, span)),
+            '+' => Some(Stmt::Add(1, span)),
+            '-' => Some(Stmt::Add(-1, span)),
+            ',' => Some(Stmt::Input(span)),
+            '.' => Some(Stmt::Output(span)),
+            '#' if options.debug && !options.bang_breakpoints => Some(Stmt::Debug(span)),
+            '!' if options.debug && options.bang_breakpoints  => Some(Stmt::Debug(span)),
+            ':' if options.procedures => Some(Stmt::Call(span)),
+            'Y' if options.lang == Lang::Brainfork => Some(Stmt::Fork(span)),
+            '?' if options.random => Some(Stmt::Random(span)),
+             _  => None
+        };
+
+        /* add to program representation, folding it into the statement before it in the same block */
+        if let Some(s) = maybe_statement {
+            match (nodes.last_mut(), &s) {
+                (Some(Node::Stmt(Stmt::Move(n, a))), Stmt::Move(m, b)) => { *n += m; *a = a.to(*b); },
+                (Some(Node::Stmt(Stmt::Add(n, a))),  Stmt::Add(m, b))  => { *n += m; *a = a.to(*b); },
+                (_, _)                                                 => nodes.push(Node::Stmt(s))
+            }
+        }
+    }
+
+    if let Some(&i) = open_loops.last() {
+        if let Node::Open(open, c) = nodes[i] {
+            return Err((open.start, format!("unmatched '{}'", spelling(c))));
+        }
+    }
+
+    return Ok(take_block(&mut nodes, 0));
+}
+
+/* Moves the statements of `nodes` from `start` on, a closed block, into a `Vec` of their own */
+fn take_block(nodes : &mut Vec<Node>, start : usize) -> Vec<Stmt>
+{
+    return nodes.drain(start..).map(|node| match node {
+        Node::Stmt(stmt) => stmt,
+        Node::Open(..)   => unreachable!()
+    }).collect();
+}
+
+/// Prints a program, optimized or not, as brainfuck source without comments
+/// or whitespace. Parsing the result gives an equivalent program, and for a
+/// program from `parse()` the same one, up to spans and the moves
+/// and adds that cancelled out, like `+-`, which print as nothing.
+/// Statements the optimizer made are printed as the loops they replaced: a
+/// run of `MulAdd`s becomes one multiply loop, which clears the cell like the
+/// `Set(0)` after it does. A `MulAdd` without that `Set(0)` can't be printed
+/// without a scratch cell, and is printed as if it had one.
+pub fn to_brainfuck(code : &[Stmt]) -> String
+{
+    let mut out = String::new();
+    /* iterators over the blocks the next statement is in, outermost first, and what closes them */
+    let mut blocks : Vec<(std::slice::Iter<Stmt>, char)> = vec![(code.iter(), ' ')];
+    while let Some((block, _)) = blocks.last_mut() {
+        let stmt = match block.next() {
+            Some(stmt) => stmt,
+            None       => {
+                let (_, close) = blocks.pop().unwrap();
+                if !blocks.is_empty() {
+                    out.push(close);
+                }
+                continue;
+            }
+        };
+        match *stmt {
+            Stmt::Move(n, _)      => push_move(&mut out, n),
+            Stmt::Add(n, _)       => push_add(&mut out, n),
+            Stmt::Input(_)        => out.push(','),
+            Stmt::Output(_)       => out.push('.'),
+            Stmt::Debug(_)        => out.push('#'),
+            Stmt::Call(_)         => out.push(':'),
+            Stmt::Fork(_)         => out.push('Y'),
+            Stmt::Random(_)       => out.push('?'),
+            Stmt::Set(n, _)       => {
+                out.push_str("[-]");
+                push_add(&mut out, n);
+            },
+            Stmt::AddAt(offset, n, _) => {
+                push_move(&mut out, offset);
+                push_add(&mut out, n);
+                push_move(&mut out, -offset);
+            },
+            Stmt::Scan(stride, _) => {
+                out.push('[');
+                push_move(&mut out, stride);
+                out.push(']');
+            },
+            Stmt::MulAdd(offset, factor, _) => {
+                out.push_str("[-");
+                push_move(&mut out, offset);
+                push_add(&mut out, factor);
+                let mut at = offset;
+                while let Some(&Stmt::MulAdd(offset, factor, _)) = block.as_slice().first() {
+                    block.next();
+                    push_move(&mut out, offset - at);
+                    push_add(&mut out, factor);
+                    at = offset;
+                }
+                push_move(&mut out, -at);
+                out.push(']');
+                if let Some(&Stmt::Set(0, _)) = block.as_slice().first() {
+                    block.next();
+                }
+            },
+            Stmt::Loop(ref body, _) => {
+                out.push('[');
+                blocks.push((body.iter(), ']'));
+            },
+            Stmt::Proc(ref body, _) => {
+                out.push('(');
+                blocks.push((body.iter(), ')'));
+            }
+        }
+    }
+    return out;
+}
+
+/// Whether `code` defines or calls pbrain procedures anywhere, which only
+/// the interpreter and the llvm backend can run.
+pub fn uses_procedures(code : &[Stmt]) -> bool
+{
+    return any_stmt(code, |stmt| matches!(stmt, Stmt::Proc(..) | Stmt::Call(_)));
+}
+
+/// Whether `code` forks Brainfork threads anywhere, which only the
+/// interpreter can run.
+pub fn uses_forks(code : &[Stmt]) -> bool
+{
+    return any_stmt(code, |stmt| matches!(stmt, Stmt::Fork(_)));
+}
+
+/// Whether `code` has `#` breakpoints anywhere, which the llvm backend
+/// generates a dump of the tape for.
+pub fn uses_breakpoints(code : &[Stmt]) -> bool
+{
+    return any_stmt(code, |stmt| matches!(stmt, Stmt::Debug(_)));
+}
+
+/// Whether `code` stores random bytes anywhere, which only the interpreter
+/// and the llvm backend can run.
+pub fn uses_random(code : &[Stmt]) -> bool
+{
+    return any_stmt(code, |stmt| matches!(stmt, Stmt::Random(_)));
+}
+
+/*
+ * Whether `f` holds for any statement of `code`, looking into loop and
+ * procedure bodies too. Uses an explicit stack so deeply nested programs
+ * don't overflow.
+ */
+fn any_stmt(code : &[Stmt], f : fn(&Stmt) -> bool) -> bool
+{
+    let mut blocks = vec![code];
+    while let Some(block) = blocks.pop() {
+        for stmt in block {
+            if f(stmt) {
+                return true;
+            }
+            if let Stmt::Loop(body, _) | Stmt::Proc(body, _) = stmt {
+                blocks.push(body);
+            }
+        }
+    }
+    return false;
+}
+
+fn push_move(out : &mut String, n : i32)
+{
+    let c = if n < 0 { '<' } else { '>' };
+    out.extend(std::iter::repeat_n(c, n.unsigned_abs() as usize));
+}
+
+fn push_add(out : &mut String, n : i32)
+{
+    let c = if n < 0 { '-' } else { '+' };
+    out.extend(std::iter::repeat_n(c, n.unsigned_abs() as usize));
+}
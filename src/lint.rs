@@ -0,0 +1,88 @@
+/*
+ * Warnings about source that parses, but probably not into what its author
+ * meant.
+ */
+
+use Stmt;
+
+/* Characters that are easily mistaken for a command, and what they look like */
+const LOOKALIKES : &[(char, &str)] = &[
+    ('\u{FF0B}', "+"), ('\u{FF0D}', "-"), ('\u{2212}', "-"), ('\u{2013}', "-"), ('\u{2014}', "--"),
+    ('\u{FF1C}', "<"), ('\u{FF1E}', ">"), ('\u{2039}', "<"), ('\u{203A}', ">"), ('\u{3008}', "<"),
+    ('\u{3009}', ">"), ('\u{27E8}', "<"), ('\u{27E9}', ">"), ('\u{FF0E}', "."), ('\u{2026}', "..."),
+    ('\u{3002}', "."), ('\u{FF0C}', ","), ('\u{201A}', ","), ('\u{3001}', ","), ('\u{FF3B}', "["),
+    ('\u{FF3D}', "]"), ('\u{3010}', "["), ('\u{3011}', "]"), ('\u{3014}', "["), ('\u{3015}', "]")
+];
+
+/// Characters in brainfuck source `src` that look like commands but are
+/// comments, like a full-width `＋`, or that shouldn't be there at all, like
+/// a byte order mark. Source copied through a word processor or a chat is
+/// easily mangled like that, and would silently run without the commands.
+/// Returns the offset of the first of each such character, with a warning
+/// saying what it is and how often it occurs if more than once.
+pub fn lookalikes(src : &[u8]) -> Vec<(usize, String)>
+{
+    /* each character found, with its first offset and count, in the order they first occur */
+    let mut found : Vec<(char, usize, usize)> = Vec::new();
+    let mut offset = 0;
+    for chunk in src.utf8_chunks() {
+        for (i, c) in chunk.valid().char_indices() {
+            if c.is_ascii() || (c != '\u{FEFF}' && !LOOKALIKES.iter().any(|&(l, _)| l == c)) {
+                continue;
+            }
+            match found.iter_mut().find(|(f, _, _)| *f == c) {
+                Some((_, _, count)) => *count += 1,
+                None                => found.push((c, offset + i, 1))
+            }
+        }
+        offset += chunk.valid().len() + chunk.invalid().len();
+    }
+
+    return found.into_iter().map(|(c, offset, count)| {
+        let what = match LOOKALIKES.iter().find(|&&(l, _)| l == c) {
+            Some(&(_, like)) => format!("'{}' (U+{:04X}) looks like '{}' but is a comment", c, c as u32, like),
+            None             => "a byte order mark (U+FEFF) is in the source".to_owned()
+        };
+        let more = match count {
+            1 => String::new(),
+            _ => format!(" ({} times)", count)
+        };
+        (offset, what + &more)
+    }).collect();
+}
+
+/// Static analyses of a parsed, unoptimized program, for code that runs but
+/// can't do what it was written for: loops right after a loop, which never
+/// run since the cell is zero after it, empty loops, which never end unless
+/// the cell is zero, and moves and adds that cancel out, like `+-`. Returns
+/// the source offset of each, with a warning saying what it is, in the
+/// order they are in the source.
+pub fn analyze(code : &[Stmt]) -> Vec<(usize, String)>
+{
+    let mut warnings = Vec::new();
+    /* the blocks left to look at, on a stack of their own so deeply nested programs don't overflow */
+    let mut blocks = vec![code];
+    while let Some(block) = blocks.pop() {
+        let mut after_loop = false;
+        for stmt in block {
+            match stmt {
+                Stmt::Loop(_, span) if after_loop => {
+                    warnings.push((span.start, "this loop never runs, the cell is zero after the loop before it".to_owned()));
+                },
+                Stmt::Loop(body, span) if body.is_empty() => {
+                    warnings.push((span.start, "this loop is empty, so it never ends unless the cell is zero".to_owned()));
+                },
+                Stmt::Move(0, span) | Stmt::Add(0, span) => {
+                    warnings.push((span.start, "these commands cancel out".to_owned()));
+                },
+                _ => ()
+            }
+            if let Stmt::Loop(body, _) | Stmt::Proc(body, _) = stmt {
+                blocks.push(body);
+            }
+            after_loop = matches!(stmt, Stmt::Loop(..));
+        }
+    }
+    warnings.sort_by_key(|&(offset, _)| offset);
+    return warnings;
+}
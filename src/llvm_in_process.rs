@@ -0,0 +1,253 @@
+/*
+ * Runs the LLVM part of the compile pipeline inside the rustfuck process by
+ * calling the LLVM C API, instead of shelling out to `opt` and `llc`. Only
+ * built with `--cfg llvm_in_process` (see `make LLVM=1`), which links
+ * against libLLVM. The module is parsed from the IR `llvm_ir_generator`
+ * writes rather than built up instruction by instruction, so the IR kept by
+ * `--emit=llvm-ir`, fed to `opt` and compiled here is always the same.
+ */
+
+use RustfuckError;
+use compiler::BuildOptions;
+use compiler::OptLevel;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::Once;
+
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::c_char;
+
+    pub enum LLVMOpaqueContext {}
+    pub enum LLVMOpaqueModule {}
+    pub enum LLVMOpaqueMemoryBuffer {}
+    pub enum LLVMTarget {}
+    pub enum LLVMOpaqueTargetMachine {}
+    pub enum LLVMOpaqueTargetData {}
+    pub enum LLVMOpaquePassBuilderOptions {}
+    pub enum LLVMOpaqueError {}
+
+    pub const LLVM_CODEGEN_LEVEL_NONE       : u32 = 0;
+    pub const LLVM_CODEGEN_LEVEL_LESS       : u32 = 1;
+    pub const LLVM_CODEGEN_LEVEL_DEFAULT    : u32 = 2;
+    pub const LLVM_CODEGEN_LEVEL_AGGRESSIVE : u32 = 3;
+    pub const LLVM_RELOC_STATIC             : u32 = 1;
+    pub const LLVM_RELOC_PIC                : u32 = 2;
+    pub const LLVM_CODE_MODEL_DEFAULT       : u32 = 0;
+    pub const LLVM_ASSEMBLY_FILE            : u32 = 0;
+    pub const LLVM_OBJECT_FILE              : u32 = 1;
+    pub const LLVM_RETURN_STATUS_ACTION     : u32 = 2;
+
+    extern "C" {
+        pub fn LLVMInitializeX86TargetInfo();
+        pub fn LLVMInitializeX86Target();
+        pub fn LLVMInitializeX86TargetMC();
+        pub fn LLVMInitializeX86AsmPrinter();
+        pub fn LLVMInitializeX86AsmParser();
+        pub fn LLVMInitializeAArch64TargetInfo();
+        pub fn LLVMInitializeAArch64Target();
+        pub fn LLVMInitializeAArch64TargetMC();
+        pub fn LLVMInitializeAArch64AsmPrinter();
+        pub fn LLVMInitializeARMTargetInfo();
+        pub fn LLVMInitializeARMTarget();
+        pub fn LLVMInitializeARMTargetMC();
+        pub fn LLVMInitializeARMAsmPrinter();
+        pub fn LLVMInitializeRISCVTargetInfo();
+        pub fn LLVMInitializeRISCVTarget();
+        pub fn LLVMInitializeRISCVTargetMC();
+        pub fn LLVMInitializeRISCVAsmPrinter();
+        pub fn LLVMInitializeWebAssemblyTargetInfo();
+        pub fn LLVMInitializeWebAssemblyTarget();
+        pub fn LLVMInitializeWebAssemblyTargetMC();
+        pub fn LLVMInitializeWebAssemblyAsmPrinter();
+
+        pub fn LLVMContextCreate() -> *mut LLVMOpaqueContext;
+        pub fn LLVMContextDispose(ctx : *mut LLVMOpaqueContext);
+        pub fn LLVMCreateMemoryBufferWithMemoryRangeCopy(data : *const c_char, len : usize, name : *const c_char) -> *mut LLVMOpaqueMemoryBuffer;
+        pub fn LLVMParseIRInContext(ctx : *mut LLVMOpaqueContext, buf : *mut LLVMOpaqueMemoryBuffer,
+                                    module : *mut *mut LLVMOpaqueModule, msg : *mut *mut c_char) -> i32;
+        pub fn LLVMVerifyModule(module : *mut LLVMOpaqueModule, action : u32, msg : *mut *mut c_char) -> i32;
+        pub fn LLVMDisposeModule(module : *mut LLVMOpaqueModule);
+        pub fn LLVMDisposeMessage(msg : *mut c_char);
+        pub fn LLVMSetTarget(module : *mut LLVMOpaqueModule, triple : *const c_char);
+        pub fn LLVMSetModuleDataLayout(module : *mut LLVMOpaqueModule, layout : *mut LLVMOpaqueTargetData);
+        pub fn LLVMWriteBitcodeToFile(module : *mut LLVMOpaqueModule, path : *const c_char) -> i32;
+
+        pub fn LLVMGetDefaultTargetTriple() -> *mut c_char;
+        pub fn LLVMGetHostCPUName() -> *mut c_char;
+        pub fn LLVMGetHostCPUFeatures() -> *mut c_char;
+        pub fn LLVMGetTargetFromTriple(triple : *const c_char, target : *mut *mut LLVMTarget, msg : *mut *mut c_char) -> i32;
+        pub fn LLVMCreateTargetMachine(target : *mut LLVMTarget, triple : *const c_char, cpu : *const c_char, features : *const c_char,
+                                       level : u32, reloc : u32, code_model : u32) -> *mut LLVMOpaqueTargetMachine;
+        pub fn LLVMDisposeTargetMachine(tm : *mut LLVMOpaqueTargetMachine);
+        pub fn LLVMCreateTargetDataLayout(tm : *mut LLVMOpaqueTargetMachine) -> *mut LLVMOpaqueTargetData;
+        pub fn LLVMDisposeTargetData(layout : *mut LLVMOpaqueTargetData);
+        pub fn LLVMTargetMachineEmitToFile(tm : *mut LLVMOpaqueTargetMachine, module : *mut LLVMOpaqueModule,
+                                           filename : *mut c_char, kind : u32, msg : *mut *mut c_char) -> i32;
+
+        pub fn LLVMCreatePassBuilderOptions() -> *mut LLVMOpaquePassBuilderOptions;
+        pub fn LLVMDisposePassBuilderOptions(opts : *mut LLVMOpaquePassBuilderOptions);
+        pub fn LLVMRunPasses(module : *mut LLVMOpaqueModule, passes : *const c_char,
+                             tm : *mut LLVMOpaqueTargetMachine, opts : *mut LLVMOpaquePassBuilderOptions) -> *mut LLVMOpaqueError;
+        pub fn LLVMGetErrorMessage(err : *mut LLVMOpaqueError) -> *mut c_char;
+        pub fn LLVMDisposeErrorMessage(msg : *mut c_char);
+    }
+}
+
+/* takes ownership of an LLVM message, turning it into a ToolchainError */
+unsafe fn llvm_error(stage : &str, msg : *mut c_char) -> RustfuckError
+{
+    let text = if msg.is_null() {
+        "unknown error".to_owned()
+    } else {
+        let text = CStr::from_ptr(msg).to_string_lossy().into_owned();
+        ffi::LLVMDisposeMessage(msg);
+        text
+    };
+    return RustfuckError::ToolchainError(format!("LLVM {} failed: {}", stage, text.trim_end()));
+}
+
+/* takes ownership of an LLVM message, returning a copy of it */
+unsafe fn take_message(msg : *mut c_char) -> CString
+{
+    let text = CStr::from_ptr(msg).to_owned();
+    ffi::LLVMDisposeMessage(msg);
+    return text;
+}
+
+fn c_path(path : Option<&str>) -> Result<Option<Vec<u8>>, RustfuckError>
+{
+    match path {
+        Some(path) => CString::new(path)
+                .map(|p| Some(p.into_bytes_with_nul()))
+                .map_err(|_| RustfuckError::ToolchainError("output path contains a NUL byte".to_owned())),
+        None       => Ok(None)
+    }
+}
+
+static INIT_TARGETS : Once = Once::new();
+
+/// Parses textual IR and optimizes it with the pipeline for the level in
+/// `build`, for its target or the host CPU if it says so, then writes
+/// whichever of optimized bitcode, assembly and an object file are given a
+/// path, all without leaving the process. Besides x86, it can build for
+/// AArch64, ARM, RISC-V and WebAssembly.
+pub fn compile_ir(ir : &str, build : &BuildOptions, bc_file : Option<&str>, asm_file : Option<&str>, o_file : Option<&str>) -> Result<(), RustfuckError>
+{
+    let name = CString::new("rustfuck").unwrap();
+    let (passes, level) = match build.opt_level {
+        OptLevel::O0 => ("default<O0>", ffi::LLVM_CODEGEN_LEVEL_NONE),
+        OptLevel::O1 => ("default<O1>", ffi::LLVM_CODEGEN_LEVEL_LESS),
+        OptLevel::O2 => ("default<O2>", ffi::LLVM_CODEGEN_LEVEL_DEFAULT),
+        OptLevel::O3 => ("default<O3>", ffi::LLVM_CODEGEN_LEVEL_AGGRESSIVE),
+        OptLevel::Os => ("default<Os>", ffi::LLVM_CODEGEN_LEVEL_DEFAULT),
+        OptLevel::Oz => ("default<Oz>", ffi::LLVM_CODEGEN_LEVEL_DEFAULT)
+    };
+    let passes = CString::new(passes).unwrap();
+    let bc_file = c_path(bc_file)?;
+    let outputs = [(c_path(asm_file)?, ffi::LLVM_ASSEMBLY_FILE), (c_path(o_file)?, ffi::LLVM_OBJECT_FILE)];
+
+    unsafe {
+        /* registering the targets isn't thread safe, and batch builds run in parallel */
+        INIT_TARGETS.call_once(|| {
+            ffi::LLVMInitializeX86TargetInfo();
+            ffi::LLVMInitializeX86Target();
+            ffi::LLVMInitializeX86TargetMC();
+            ffi::LLVMInitializeX86AsmPrinter();
+            ffi::LLVMInitializeX86AsmParser();
+            ffi::LLVMInitializeAArch64TargetInfo();
+            ffi::LLVMInitializeAArch64Target();
+            ffi::LLVMInitializeAArch64TargetMC();
+            ffi::LLVMInitializeAArch64AsmPrinter();
+            ffi::LLVMInitializeARMTargetInfo();
+            ffi::LLVMInitializeARMTarget();
+            ffi::LLVMInitializeARMTargetMC();
+            ffi::LLVMInitializeARMAsmPrinter();
+            ffi::LLVMInitializeRISCVTargetInfo();
+            ffi::LLVMInitializeRISCVTarget();
+            ffi::LLVMInitializeRISCVTargetMC();
+            ffi::LLVMInitializeRISCVAsmPrinter();
+            ffi::LLVMInitializeWebAssemblyTargetInfo();
+            ffi::LLVMInitializeWebAssemblyTarget();
+            ffi::LLVMInitializeWebAssemblyTargetMC();
+            ffi::LLVMInitializeWebAssemblyAsmPrinter();
+        });
+
+        /* parse (the context owns the module, disposing it frees everything) */
+        let ctx = ffi::LLVMContextCreate();
+        let buf = ffi::LLVMCreateMemoryBufferWithMemoryRangeCopy(ir.as_ptr() as *const c_char, ir.len(), name.as_ptr());
+        let mut module = ptr::null_mut();
+        let mut msg : *mut c_char = ptr::null_mut();
+        if ffi::LLVMParseIRInContext(ctx, buf, &mut module, &mut msg) != 0 {
+            ffi::LLVMContextDispose(ctx);
+            return Err(llvm_error("IR parsing", msg));
+        }
+        if ffi::LLVMVerifyModule(module, ffi::LLVM_RETURN_STATUS_ACTION, &mut msg) != 0 {
+            ffi::LLVMDisposeModule(module);
+            ffi::LLVMContextDispose(ctx);
+            return Err(llvm_error("verification", msg));
+        }
+        if !msg.is_null() {
+            ffi::LLVMDisposeMessage(msg);
+        }
+
+        /* target machine for the target, or the host */
+        let triple = match build.target {
+            Some(ref triple) => CString::new(triple.as_str()).unwrap_or_default(),
+            None             => take_message(ffi::LLVMGetDefaultTargetTriple())
+        };
+        let reloc = if build.targets_wasm() { ffi::LLVM_RELOC_STATIC } else { ffi::LLVM_RELOC_PIC };
+        let (cpu, features) = if build.native_cpu && build.target.is_none() {
+            (take_message(ffi::LLVMGetHostCPUName()), take_message(ffi::LLVMGetHostCPUFeatures()))
+        } else {
+            (CString::new("generic").unwrap(), CString::new("").unwrap())
+        };
+        let mut target = ptr::null_mut();
+        if ffi::LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut msg) != 0 {
+            ffi::LLVMDisposeModule(module);
+            ffi::LLVMContextDispose(ctx);
+            return Err(llvm_error("target lookup", msg));
+        }
+        let tm = ffi::LLVMCreateTargetMachine(target, triple.as_ptr(), cpu.as_ptr(), features.as_ptr(),
+                                              level, reloc,
+                                              ffi::LLVM_CODE_MODEL_DEFAULT);
+        let layout = ffi::LLVMCreateTargetDataLayout(tm);
+        ffi::LLVMSetTarget(module, triple.as_ptr());
+        ffi::LLVMSetModuleDataLayout(module, layout);
+
+        /* optimize */
+        let opts = ffi::LLVMCreatePassBuilderOptions();
+        let err = ffi::LLVMRunPasses(module, passes.as_ptr(), tm, opts);
+        ffi::LLVMDisposePassBuilderOptions(opts);
+        let mut result = Ok(());
+        if !err.is_null() {
+            let msg = ffi::LLVMGetErrorMessage(err);
+            let text = CStr::from_ptr(msg).to_string_lossy().into_owned();
+            ffi::LLVMDisposeErrorMessage(msg);
+            result = Err(RustfuckError::ToolchainError(format!("LLVM optimization failed: {}", text)));
+        }
+
+        if let (true, Some(path)) = (result.is_ok(), &bc_file) {
+            if ffi::LLVMWriteBitcodeToFile(module, path.as_ptr() as *const c_char) != 0 {
+                result = Err(RustfuckError::ToolchainError("LLVM failed to write bitcode".to_owned()));
+            }
+        }
+
+        /* emit assembly and object file */
+        for (path, kind) in outputs {
+            if let (true, Some(mut path)) = (result.is_ok(), path) {
+                if ffi::LLVMTargetMachineEmitToFile(tm, module, path.as_mut_ptr() as *mut c_char, kind, &mut msg) != 0 {
+                    result = Err(llvm_error("code generation", msg));
+                }
+            }
+        }
+
+        ffi::LLVMDisposeTargetData(layout);
+        ffi::LLVMDisposeTargetMachine(tm);
+        ffi::LLVMDisposeModule(module);
+        ffi::LLVMContextDispose(ctx);
+        return result;
+    }
+}
@@ -1,126 +1,1861 @@
+use Config;
+use config::Bounds;
+use compiler::BuildOptions;
+use compiler::OptLevel;
+use config::CellWidth;
+use config::Eof;
+use config::Flush;
+use config::Io;
+use config::Tape;
+use diagnostic::LineIndex;
+use interpreter::WINDOW_CELLS;
+use random;
 use Stmt;
+use to_brainfuck;
+use uses_breakpoints;
+use uses_procedures;
+use uses_random;
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::path::Path;
 
-struct CodeGenContext {
-    regc  : u32,
-    loopc : u32
+/// The source file a program was parsed from, which `code_gen_for()`
+/// points the generated code back at.
+pub struct DebugSource<'a> {
+    /// The path of the file, best made absolute so debuggers find it from
+    /// anywhere.
+    pub path : &'a Path,
+    pub src  : &'a [u8]
 }
 
-fn write_header(ir : &mut String)
+/*
+ * Debug metadata for `main`: the fixed nodes up to FIRST_LOCATION, and after
+ * them a `DILocation` for every line and column instructions were generated
+ * for in each function, in the order they were first used, and the
+ * `DISubprogram` of every function outlined from `main`.
+ */
+const FIRST_LOCATION : usize = 8;
+
+/*
+ * Statements, counting those in loops, that are generated into one function.
+ * LLVM takes much longer than linearly to optimize huge functions, so parts
+ * of bigger programs are outlined into functions of their own.
+ */
+const FUNCTION_SIZE : usize = 4096;
+
+/* Bytes of output a program buffers before it writes them */
+const OUTPUT_BUFFER : usize = 8192;
+
+/* Characters of source shown in a comment, see `BuildOptions::ir_comments` */
+const COMMENT_WIDTH : usize = 60;
+
+struct DebugInfo<'a> {
+    source    : &'a DebugSource<'a>,
+    lines     : LineIndex<'a>,
+    /* the nodes from FIRST_LOCATION on */
+    nodes     : Vec<String>,
+    /* the node of each line and column in each scope */
+    ids       : HashMap<(usize, usize, usize), usize>,
+    /* the subprogram of the function being generated */
+    scope     : usize
+}
+
+struct CodeGenContext<'a> {
+    regc       : u32,
+    loopc      : u32,
+    /* with comments, the number of loops begun so far at each depth of the loop being generated */
+    loop_path  : Option<Vec<u32>>,
+    growc      : u32,
+    checkc     : u32,
+    chunkc     : u32,
+    procc      : u32,
+    callc      : u32,
+    /* whether the program has pbrain procedures, kept in @procedures by the cell they are defined under */
+    procedures : bool,
+    /* whether the program stores random bytes, from the generator in @random_state */
+    random     : bool,
+    /* what the generator starts from, or somewhere different in each run */
+    seed       : Option<u64>,
+    /* whether the program has `#` breakpoints, which dump the tape to stderr */
+    breakpoints : bool,
+    /* the functions outlined from `main`, written after it */
+    functions  : String,
+    tape_size  : usize,
+    tape       : Tape,
+    /* what moving off the tape does, always unchecked for a dynamic one */
+    bounds     : Bounds,
+    /* whether going off the tape is reported, with the source offset of the statement being generated */
+    safe       : bool,
+    offset     : usize,
+    cell_width : CellWidth,
+    /* the integer type of a cell, e.g. `i8` */
+    cell       : String,
+    io         : Io,
+    eof        : Eof,
+    /* whether `,` calls `@get()` instead of being generated in place, for smaller code */
+    shared_input : bool,
+    /* when bytes written to the output buffer are flushed; numbers are printed by printf() */
+    flush      : Flush,
+    /* the integer type of `size_t` on the target, for the libc calls */
+    size       : &'static str,
+    /* how to call the kernel instead of libc, for a freestanding program */
+    syscalls   : Option<Syscalls>,
+    /* whether this is a shared library's `bf_run()`, which reads its input from and writes its output to memory */
+    library    : bool,
+    debug      : Option<DebugInfo<'a>>
+}
+
+impl<'a> CodeGenContext<'a> {
+    /* `n` as a constant of the cell type, which LLVM wants in its signed range */
+    fn cell_const(&self, n : i32) -> i32
+    {
+        match self.cell_width {
+            CellWidth::Bits8  => n as i8 as i32,
+            CellWidth::Bits16 => n as i16 as i32,
+            CellWidth::Bits32 => n
+        }
+    }
+}
+
+/*
+ * The Linux system calls a freestanding program makes instead of calling
+ * libc, for the architectures it can be built for, or the WASI functions
+ * it imports instead on WebAssembly.
+ */
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Syscalls {
+    X86_64,
+    Aarch64,
+    Wasi
+}
+
+#[derive(Clone, Copy)]
+enum Syscall {
+    Read,
+    Write,
+    Exit,
+    GetRandom
+}
+
+impl Syscalls {
+    fn for_arch(arch : &str) -> Option<Syscalls>
+    {
+        match arch {
+            "x86_64"  => Some(Syscalls::X86_64),
+            "aarch64" => Some(Syscalls::Aarch64),
+            "wasm32"  => Some(Syscalls::Wasi),
+            _         => None
+        }
+    }
+
+    fn number(self, call : Syscall) -> u32
+    {
+        match (self, call) {
+            (Syscalls::X86_64, Syscall::Read)       => 0,
+            (Syscalls::X86_64, Syscall::Write)      => 1,
+            (Syscalls::X86_64, Syscall::Exit)       => 60,
+            (Syscalls::X86_64, Syscall::GetRandom)  => 318,
+            (Syscalls::Aarch64, Syscall::Read)      => 63,
+            (Syscalls::Aarch64, Syscall::Write)     => 64,
+            (Syscalls::Aarch64, Syscall::Exit)      => 93,
+            (Syscalls::Aarch64, Syscall::GetRandom) => 278,
+            (Syscalls::Wasi, _)                     => unreachable!("WASI has functions for system calls")
+        }
+    }
+}
+
+/// Whether `code_gen_for()` can generate a freestanding program for the
+/// architecture `arch`, like `x86_64`, which only Linux on x86-64 and
+/// AArch64 can run, and WASI on 32 bit WebAssembly.
+pub fn supports_freestanding(arch : &str) -> bool
+{
+    return Syscalls::for_arch(arch).is_some();
+}
+
+/* The integer type of `size_t` for a target triple, which is 32 bits wide on the 32 bit architectures */
+fn size_type(triple : &str) -> &'static str
 {
-    write!(ir, "@memory = global [65536 x i8] zeroinitializer, align 16\n\n").unwrap();
-    write!(ir, "@memory_idx = global i32 0, align 4\n\n").unwrap();
-    write!(ir, "define i32 @main() {{\n").unwrap(); 
+    let arch = triple.split('-').next().unwrap_or("");
+    let is_x86_32 = arch.len() == 4 && arch.starts_with('i') && arch.ends_with("86");
+    if is_x86_32 || arch.starts_with("arm") || arch.starts_with("thumb")
+            || ["wasm32", "riscv32", "mips", "mipsel", "powerpc", "sparc"].contains(&arch) {
+        return "i32";
+    }
+    return "i64";
+}
+
+/*
+ * The libc argument for `value`, a 64 bit size, truncating it to a new
+ * value called `name` where `size_t` is narrower.
+ */
+fn write_size_arg(ir : &mut String, context : &CodeGenContext, value : &str, name : &str) -> String
+{
+    if context.size == "i64" {
+        return format!("i64 {}", value);
+    }
+    write!(ir, "  %{} = trunc i64 {} to {}\n", name, value, context.size).unwrap();
+    return format!("{} %{}", context.size, name);
+}
+
+fn write_header(ir : &mut String, context : &CodeGenContext, triple : Option<&str>)
+{
+    let cell = &context.cell;
+    if let Some(triple) = triple {
+        write!(ir, "target triple = {}\n\n", metadata_string(triple)).unwrap();
+    }
+    /* which a library mustn't export, or programs linking it could replace them */
+    let linkage = if context.library { "internal " } else { "" };
+    if context.tape == Tape::Dynamic {
+        write!(ir, "@memory = global {}* null, align 8\n\n", cell).unwrap();
+        write!(ir, "@memory_len = global i64 0, align 8\n\n").unwrap();
+    } else {
+        write!(ir, "@memory = {}global [{} x {}] zeroinitializer, align 16\n\n", linkage, context.tape_size, cell).unwrap();
+    }
+    write!(ir, "@memory_idx = {}global i32 0, align 4\n\n", linkage).unwrap();
+    if context.io == Io::Numeric {
+        write!(ir, "@number = global i32 0, align 4\n\n").unwrap();
+        write!(ir, "@input_format = private constant [3 x i8] c\"%d\\00\"\n\n").unwrap();
+        write!(ir, "@output_format = private constant [4 x i8] c\"%u\\0A\\00\"\n\n").unwrap();
+    }
+    if context.io == Io::Bytes {
+        write!(ir, "@output = internal global [{} x i8] zeroinitializer, align 16\n\n", OUTPUT_BUFFER).unwrap();
+        write!(ir, "@output_len = internal global i32 0, align 4\n\n").unwrap();
+    }
+    if context.library {
+        let size = context.size;
+        write!(ir, "@input_data = internal global i8* null, align 8\n\n").unwrap();
+        write!(ir, "@input_len = internal global {} 0, align 8\n\n", size).unwrap();
+        write!(ir, "@input_pos = internal global {} 0, align 8\n\n", size).unwrap();
+        write!(ir, "@result = internal global i8* null, align 8\n\n").unwrap();
+        write!(ir, "@result_len = internal global {} 0, align 8\n\n", size).unwrap();
+    }
+    if context.syscalls == Some(Syscalls::Wasi) {
+        write!(ir, "@iovec = internal global {{ i8*, i32 }} zeroinitializer, align 4\n\n").unwrap();
+        write!(ir, "@iovec_count = internal global i32 0, align 4\n\n").unwrap();
+    }
+    if context.safe {
+        write!(ir, "@bounds_message = private constant [{} x i8] c{}\n\n", BOUNDS_MESSAGE.len(), metadata_string(BOUNDS_MESSAGE)).unwrap();
+    }
+    if context.safe || context.procedures {
+        write!(ir, "@offset_message = private constant [{} x i8] c{}\n\n", OFFSET_MESSAGE.len(), metadata_string(OFFSET_MESSAGE)).unwrap();
+    }
+    if context.random {
+        write!(ir, "@random_state = internal global i64 0, align 8\n\n").unwrap();
+    }
+    if context.breakpoints {
+        for (i, message) in BREAKPOINT_MESSAGES.iter().enumerate() {
+            write!(ir, "@breakpoint_message{} = private constant [{} x i8] c{}\n\n", i, message.len(), metadata_string(message)).unwrap();
+        }
+        if context.tape == Tape::Dynamic {
+            write!(ir, "@memory_origin = internal global i32 0, align 4\n\n").unwrap();
+        }
+    }
+    if context.procedures {
+        let table = procedure_table(context);
+        write!(ir, "@procedures = internal global {} zeroinitializer, align 16\n\n", table).unwrap();
+        write!(ir, "@procedure_message = private constant [{} x i8] c{}\n\n", PROCEDURE_MESSAGE.len(), metadata_string(PROCEDURE_MESSAGE)).unwrap();
+    }
+    let dbg = if context.debug.is_some() { " !dbg !2" } else { "" };
+    if context.syscalls.is_some() {
+        /* the kernel enters with an aligned stack, where calls expect it off by a return address */
+        write!(ir, "define void @_start() #0{} {{\n", dbg).unwrap();
+        write!(ir, "entry:\n").unwrap();
+        write!(ir, "  %input_byte = alloca i8, align 1\n\n").unwrap();
+        write_seed_call(ir, context);
+        return;
+    }
+    if context.library {
+        write_library_entry(ir, context, dbg);
+        write_seed_call(ir, context);
+        return;
+    }
+    write!(ir, "define i32 @main(){} {{\n", dbg).unwrap(); 
     write!(ir, "entry:\n").unwrap(); 
+    if context.tape == Tape::Dynamic {
+        write!(ir, "  %tape = call i8* @calloc({} {}, {} {})\n", context.size, context.tape_size, context.size, context.cell_width.bytes()).unwrap();
+        write!(ir, "  %tape_cells = bitcast i8* %tape to {}*\n", cell).unwrap();
+        write!(ir, "  store {}* %tape_cells, {}** @memory, align 8\n", cell, cell).unwrap();
+        write!(ir, "  store i64 {}, i64* @memory_len, align 8\n\n", context.tape_size).unwrap();
+    }
+    write_seed_call(ir, context);
 }
 
-fn write_footer(ir : &mut String)
+fn write_footer(ir : &mut String, context : &mut CodeGenContext)
 {
-    write!(ir, "  ret i32 0\n").unwrap(); 
+    if context.syscalls.is_some() {
+        write!(ir, "  call void @flush()\n").unwrap();
+        write_exit(ir, context, 0);
+        write!(ir, "  unreachable\n").unwrap();
+        if context.bounds == Bounds::Error && !context.safe {
+            write!(ir, "out_of_bounds:\n").unwrap();
+            write_exit_failure(ir, context);
+        }
+        write!(ir, "}}\n\n").unwrap();
+        ir.push_str(&context.functions);
+        write_output_buffer(ir, context);
+        if context.shared_input {
+            write_get(ir, context);
+        }
+        if context.safe {
+            write_out_of_bounds(ir, context);
+        }
+        if context.procedures {
+            write_undefined_procedure(ir, context);
+        }
+        if context.breakpoints {
+            write_breakpoint(ir, context);
+        }
+        if context.safe || context.procedures || context.breakpoints {
+            write_number(ir, context);
+        }
+        if context.random {
+            write_random(ir, context);
+        }
+        if context.syscalls == Some(Syscalls::Wasi) {
+            write!(ir, "declare i32 @fd_read(i32, {{ i8*, i32 }}*, i32, i32*) #1\n").unwrap();
+            write!(ir, "declare i32 @fd_write(i32, {{ i8*, i32 }}*, i32, i32*) #2\n").unwrap();
+            write!(ir, "declare void @proc_exit(i32) noreturn #3\n").unwrap();
+            let seeds = context.random && context.seed.is_none();
+            if seeds {
+                write!(ir, "declare i32 @random_get(i8*, i32) #4\n").unwrap();
+            }
+            write!(ir, "\n").unwrap();
+            /* which the linker leaves to the runtime to provide */
+            let imports = if seeds { 4 } else { 3 };
+            for (group, name) in ["fd_read", "fd_write", "proc_exit", "random_get"].iter().take(imports).enumerate() {
+                write!(ir, "attributes #{} = {{ \"wasm-import-module\"=\"wasi_snapshot_preview1\" \"wasm-import-name\"=\"{}\" }}\n", group + 1, name).unwrap();
+            }
+        }
+        /* nor may LLVM turn loops into calls of libc's memset and the like */
+        write!(ir, "attributes #0 = {{ nounwind \"no-builtins\" \"stackrealign\" }}\n").unwrap();
+        if let Some(ref debug) = context.debug {
+            write_debug_info(ir, debug, "_start");
+        }
+        return;
+    }
+    write_return(ir, context, 0);
+    if context.bounds == Bounds::Error && !context.safe {
+        write!(ir, "out_of_bounds:\n").unwrap();
+        write_return(ir, context, 1);
+    }
     write!(ir, "}}\n\n").unwrap(); 
-    write!(ir, "declare i32 @putchar(i32)\n").unwrap(); 
+    ir.push_str(&context.functions);
+    if context.io == Io::Bytes {
+        write_output_buffer(ir, context);
+    }
+    if context.shared_input {
+        write_get(ir, context);
+    }
+    if context.library {
+        write_read_input(ir, context);
+    }
+    if context.tape == Tape::Dynamic {
+        write_grow(ir, context);
+    }
+    if context.safe {
+        write_out_of_bounds(ir, context);
+    }
+    if context.procedures {
+        write_undefined_procedure(ir, context);
+    }
+    if context.breakpoints {
+        write_breakpoint(ir, context);
+    }
+    if context.safe || context.procedures || context.breakpoints {
+        write_number(ir, context);
+    }
+    if context.breakpoints && context.io == Io::Numeric {
+        write!(ir, "declare i32 @fflush(i8*)\n").unwrap();
+    }
+    if context.random {
+        write_random(ir, context);
+    }
+    if context.random && context.seed.is_none() {
+        write!(ir, "declare {} @time({}*)\n", context.size, context.size).unwrap();
+    }
     write!(ir, "declare i32 @getchar()\n").unwrap(); 
+    write!(ir, "declare i8* @memchr(i8*, i32, {})\n", context.size).unwrap();
+    write!(ir, "declare void @exit(i32) noreturn\n").unwrap();
+    write!(ir, "declare {} @write(i32, i8*, {})\n", context.size, context.size).unwrap();
+    if context.io == Io::Numeric {
+        write!(ir, "declare i32 @scanf(i8*, ...)\n").unwrap();
+        write!(ir, "declare i32 @printf(i8*, ...)\n").unwrap();
+    }
+    if context.library {
+        write!(ir, "declare i8* @realloc(i8*, {})\n", context.size).unwrap();
+        write!(ir, "declare void @llvm.memset.p0i8.{}(i8*, i8, {}, i1)\n", context.size, context.size).unwrap();
+        write!(ir, "declare void @llvm.memcpy.p0i8.p0i8.{}(i8*, i8*, {}, i1)\n", context.size, context.size).unwrap();
+    }
+    if let Some(ref debug) = context.debug {
+        write_debug_info(ir, debug, if context.library { "bf_run" } else { "main" });
+    }
+}
+
+/* Calls the kernel with `args`, leaving its 64 bit result in the register it returns */
+fn write_syscall(ir : &mut String, context : &mut CodeGenContext, call : Syscall, args : [&str; 3]) -> u32
+{
+    let syscalls = context.syscalls.expect("system call in a program using libc");
+    let (asm, constraints) = match syscalls {
+        Syscalls::X86_64  => ("syscall", "={rax},{rax},{rdi},{rsi},{rdx},~{rcx},~{r11},~{memory}"),
+        Syscalls::Aarch64 => ("svc #0", "={x0},{x8},{x0},{x1},{x2},~{memory}"),
+        Syscalls::Wasi    => return write_wasi_call(ir, context, call, args)
+    };
+    write!(ir, "  %{} = call i64 asm sideeffect \"{}\", \"{}\"(i64 {}, i64 {}, i64 {}, i64 {})\n",
+           context.regc, asm, constraints, syscalls.number(call), args[0], args[1], args[2]).unwrap();
+    context.regc += 1;
+    return context.regc - 1;
+}
+
+/*
+ * Reads or writes like `write_syscall()` with WASI's fd_read() or
+ * fd_write(), which take the buffer in @iovec and store the count in
+ * @iovec_count, returning 0 or an error number.
+ */
+fn write_wasi_call(ir : &mut String, context : &mut CodeGenContext, call : Syscall, args : [&str; 3]) -> u32
+{
+    let function = match call {
+        Syscall::Read      => "fd_read",
+        Syscall::Write     => "fd_write",
+        Syscall::Exit      => unreachable!("exits are written by write_exit()"),
+        Syscall::GetRandom => unreachable!("random_get() is called by write_random()")
+    };
+    let r = context.regc;
+    write!(ir, "  %{} = inttoptr i64 {} to i8*\n", r, args[1]).unwrap();
+    write!(ir, "  store i8* %{}, i8** getelementptr inbounds ({{ i8*, i32 }}, {{ i8*, i32 }}* @iovec, i32 0, i32 0), align 4\n", r).unwrap();
+    write!(ir, "  %{} = trunc i64 {} to i32\n", r + 1, args[2]).unwrap();
+    write!(ir, "  store i32 %{}, i32* getelementptr inbounds ({{ i8*, i32 }}, {{ i8*, i32 }}* @iovec, i32 0, i32 1), align 4\n", r + 1).unwrap();
+    write!(ir, "  %{} = call i32 @{}(i32 {}, {{ i8*, i32 }}* @iovec, i32 1, i32* @iovec_count)\n", r + 2, function, args[0]).unwrap();
+    write!(ir, "  %{} = load i32, i32* @iovec_count, align 4\n", r + 3).unwrap();
+    write!(ir, "  %{} = zext i32 %{} to i64\n", r + 4, r + 3).unwrap();
+    write!(ir, "  %{} = icmp eq i32 %{}, 0\n", r + 5, r + 2).unwrap();
+    write!(ir, "  %{} = select i1 %{}, i64 %{}, i64 -1\n", r + 6, r + 5, r + 4).unwrap();
+    context.regc += 7;
+    return r + 6;
+}
+
+/* Exits a freestanding program with `status` */
+fn write_exit(ir : &mut String, context : &mut CodeGenContext, status : u32)
+{
+    if context.syscalls == Some(Syscalls::Wasi) {
+        write!(ir, "  call void @proc_exit(i32 {})\n", status).unwrap();
+        return;
+    }
+    write_syscall(ir, context, Syscall::Exit, [&status.to_string(), "0", "0"]);
+}
+
+/* Quotes `text` as an LLVM metadata string */
+fn metadata_string(text : &str) -> String
+{
+    let mut quoted = String::from("\"");
+    for &b in text.as_bytes() {
+        if b == b'"' || b == b'\\' || !(b' '..=b'~').contains(&b) {
+            write!(quoted, "\\{:02X}", b).unwrap();
+        } else {
+            quoted.push(b as char);
+        }
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+/*
+ * The compile unit, with the entry point `function` and those outlined from
+ * it, and the locations the instructions in them refer to.
+ */
+fn write_debug_info(ir : &mut String, debug : &DebugInfo, function : &str)
+{
+    let path = debug.source.path;
+    let file = path.file_name().map_or(path.as_os_str(), |name| name).to_string_lossy();
+    let directory = path.parent().map_or(String::new(), |dir| dir.to_string_lossy().into_owned());
+    write!(ir, "\n!llvm.dbg.cu = !{{!0}}\n").unwrap();
+    write!(ir, "!llvm.module.flags = !{{!6, !7}}\n\n").unwrap();
+    write!(ir, "!0 = distinct !DICompileUnit(language: DW_LANG_C99, file: !1, producer: \"rustfuck\", isOptimized: false, runtimeVersion: 0, emissionKind: FullDebug)\n").unwrap();
+    write!(ir, "!1 = !DIFile(filename: {}, directory: {})\n", metadata_string(&file), metadata_string(&directory)).unwrap();
+    write!(ir, "!2 = distinct !DISubprogram(name: \"{}\", scope: !1, file: !1, line: 1, type: !3, scopeLine: 1, spFlags: DISPFlagDefinition, unit: !0)\n", function).unwrap();
+    write!(ir, "!3 = !DISubroutineType(types: !4)\n").unwrap();
+    write!(ir, "!4 = !{{!5}}\n").unwrap();
+    write!(ir, "!5 = !DIBasicType(name: \"int\", size: 32, encoding: DW_ATE_signed)\n").unwrap();
+    write!(ir, "!6 = !{{i32 7, !\"Dwarf Version\", i32 4}}\n").unwrap();
+    write!(ir, "!7 = !{{i32 2, !\"Debug Info Version\", i32 3}}\n").unwrap();
+    for (i, node) in debug.nodes.iter().enumerate() {
+        write!(ir, "!{} = {}\n", FIRST_LOCATION + i, node).unwrap();
+    }
+}
+
+/* Attaches the source location of `offset` to the instructions written since `start` */
+fn write_location(ir : &mut String, context : &mut CodeGenContext, start : usize, offset : usize)
+{
+    let debug = match context.debug {
+        Some(ref mut debug) => debug,
+        None                => return
+    };
+    let (line, col) = debug.lines.locate(offset);
+    let next = FIRST_LOCATION + debug.nodes.len();
+    let id = *debug.ids.entry((line, col, debug.scope)).or_insert(next);
+    if id == next {
+        debug.nodes.push(format!("!DILocation(line: {}, column: {}, scope: !{})", line, col, debug.scope));
+    }
+    let code = ir.split_off(start);
+    for line in code.split_inclusive('\n') {
+        /* labels and blank lines aren't indented */
+        if line.starts_with("  ") {
+            write!(ir, "{}, !dbg !{}\n", line.trim_end(), id).unwrap();
+        } else {
+            ir.push_str(line);
+        }
+    }
+}
+
+/*
+ * Defines `@grow(i64 target)` for the dynamic tape, which reallocates the
+ * tape to include cell `target`, at least doubling it. Growing to the left
+ * moves every cell, and the pointer with them. Lengths are counted in cells
+ * and only scaled to bytes for the libc calls.
+ */
+fn write_grow(ir : &mut String, context : &CodeGenContext)
+{
+    let cell = &context.cell;
+    let bytes = context.cell_width.bytes();
+    write!(ir, "define internal void @grow(i64 %target) {{\n").unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %len = load i64, i64* @memory_len, align 8\n").unwrap();
+    write!(ir, "  %len_bytes = mul i64 %len, {}\n", bytes).unwrap();
+    write!(ir, "  %old_cells = load {}*, {}** @memory, align 8\n", cell, cell).unwrap();
+    write!(ir, "  %old = bitcast {}* %old_cells to i8*\n", cell).unwrap();
+    write!(ir, "  %to_left = icmp slt i64 %target, 0\n").unwrap();
+    write!(ir, "  br i1 %to_left, label %grow_left, label %grow_right\n").unwrap();
+    write!(ir, "grow_right:\n").unwrap();
+    write!(ir, "  %doubled = shl i64 %len, 1\n").unwrap();
+    write!(ir, "  %needed = add i64 %target, 1\n").unwrap();
+    write!(ir, "  %more = icmp ugt i64 %needed, %doubled\n").unwrap();
+    write!(ir, "  %right_len = select i1 %more, i64 %needed, i64 %doubled\n").unwrap();
+    write!(ir, "  %right_bytes = mul i64 %right_len, {}\n", bytes).unwrap();
+    let right_bytes = write_size_arg(ir, context, "%right_bytes", "right_size");
+    write!(ir, "  %right = call i8* @realloc(i8* %old, {})\n", right_bytes).unwrap();
+    write!(ir, "  %right_failed = icmp eq i8* %right, null\n").unwrap();
+    write!(ir, "  br i1 %right_failed, label %out_of_memory, label %right_ok\n").unwrap();
+    write!(ir, "right_ok:\n").unwrap();
+    write!(ir, "  %tail = getelementptr i8, i8* %right, i64 %len_bytes\n").unwrap();
+    write!(ir, "  %tail_len = sub i64 %right_bytes, %len_bytes\n").unwrap();
+    write!(ir, "  call void @llvm.memset.p0i8.i64(i8* %tail, i8 0, i64 %tail_len, i1 false)\n").unwrap();
+    write!(ir, "  %right_cells = bitcast i8* %right to {}*\n", cell).unwrap();
+    write!(ir, "  store {}* %right_cells, {}** @memory, align 8\n", cell, cell).unwrap();
+    write!(ir, "  store i64 %right_len, i64* @memory_len, align 8\n").unwrap();
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "grow_left:\n").unwrap();
+    write!(ir, "  %missing = sub i64 0, %target\n").unwrap();
+    write!(ir, "  %more_left = icmp ugt i64 %missing, %len\n").unwrap();
+    write!(ir, "  %shift = select i1 %more_left, i64 %missing, i64 %len\n").unwrap();
+    write!(ir, "  %left_len = add i64 %len, %shift\n").unwrap();
+    write!(ir, "  %left_bytes = mul i64 %left_len, {}\n", bytes).unwrap();
+    let left_bytes = write_size_arg(ir, context, "%left_bytes", "left_size");
+    write!(ir, "  %left = call i8* @realloc(i8* %old, {})\n", left_bytes).unwrap();
+    write!(ir, "  %left_failed = icmp eq i8* %left, null\n").unwrap();
+    write!(ir, "  br i1 %left_failed, label %out_of_memory, label %left_ok\n").unwrap();
+    write!(ir, "left_ok:\n").unwrap();
+    write!(ir, "  %shift_bytes = mul i64 %shift, {}\n", bytes).unwrap();
+    write!(ir, "  %moved = getelementptr i8, i8* %left, i64 %shift_bytes\n").unwrap();
+    write!(ir, "  call void @llvm.memmove.p0i8.p0i8.i64(i8* %moved, i8* %left, i64 %len_bytes, i1 false)\n").unwrap();
+    write!(ir, "  call void @llvm.memset.p0i8.i64(i8* %left, i8 0, i64 %shift_bytes, i1 false)\n").unwrap();
+    write!(ir, "  %left_cells = bitcast i8* %left to {}*\n", cell).unwrap();
+    write!(ir, "  store {}* %left_cells, {}** @memory, align 8\n", cell, cell).unwrap();
+    write!(ir, "  store i64 %left_len, i64* @memory_len, align 8\n").unwrap();
+    write!(ir, "  %idx = load i32, i32* @memory_idx, align 4\n").unwrap();
+    write!(ir, "  %shift32 = trunc i64 %shift to i32\n").unwrap();
+    write!(ir, "  %shifted = add i32 %idx, %shift32\n").unwrap();
+    write!(ir, "  store i32 %shifted, i32* @memory_idx, align 4\n").unwrap();
+    if context.breakpoints {
+        write!(ir, "  %origin = load i32, i32* @memory_origin, align 4\n").unwrap();
+        write!(ir, "  %new_origin = add i32 %origin, %shift32\n").unwrap();
+        write!(ir, "  store i32 %new_origin, i32* @memory_origin, align 4\n").unwrap();
+    }
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "out_of_memory:\n").unwrap();
+    write!(ir, "  call void @abort()\n").unwrap();
+    write!(ir, "  unreachable\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+    write!(ir, "declare i8* @calloc({}, {})\n", context.size, context.size).unwrap();
+    write!(ir, "declare i8* @realloc(i8*, {})\n", context.size).unwrap();
+    write!(ir, "declare void @abort()\n").unwrap();
+    write!(ir, "declare void @llvm.memset.p0i8.i64(i8*, i8, i64, i1)\n").unwrap();
+    write!(ir, "declare void @llvm.memmove.p0i8.p0i8.i64(i8*, i8*, i64, i1)\n").unwrap();
+}
+
+/*
+ * What a safe program writes to stderr when the pointer goes off the tape,
+ * around the cell it went to and the source offset of the statement, like
+ * the interpreter's `RuntimeError` is printed.
+ */
+const BOUNDS_MESSAGE : &str = "Error: runtime error: pointer moved outside of tape (cell ";
+const OFFSET_MESSAGE : &str = ") at offset ";
+
+/* What a program calling an undefined procedure writes to stderr, before its number and the offset */
+const PROCEDURE_MESSAGE : &str = "Error: runtime error: call of undefined procedure ";
+
+/*
+ * What a breakpoint writes to stderr around its source offset, the pointer
+ * and the two rows of cells, like `interpreter::ShowBreakpoints` does.
+ */
+const BREAKPOINT_MESSAGES : [&str; 4] = ["# breakpoint at offset ", ", pointer at cell ", "\ncell: ", "\nvalue:"];
+
+/* Exits the program with status 1 from any function, with what it wrote flushed */
+fn write_exit_failure(ir : &mut String, context : &mut CodeGenContext)
+{
+    if context.io == Io::Bytes {
+        write!(ir, "  call void @flush()\n").unwrap();
+    }
+    if context.syscalls.is_some() {
+        write_exit(ir, context, 1);
+    } else {
+        write!(ir, "  call void @exit(i32 1)\n").unwrap();
+    }
+    write!(ir, "  unreachable\n").unwrap();
+}
+
+/*
+ * Writes up to `len`, a 64 bit count, of the bytes at the i8* `data` to the
+ * file descriptor `fd`, and returns the register with the 64 bit count
+ * written, or a negative one on errors.
+ */
+fn write_fd(ir : &mut String, context : &mut CodeGenContext, fd : u32, data : &str, len : &str) -> u32
+{
+    if context.syscalls.is_some() {
+        write!(ir, "  %{} = ptrtoint i8* {} to i64\n", context.regc, data).unwrap();
+        context.regc += 1;
+        let data = format!("%{}", context.regc - 1);
+        return write_syscall(ir, context, Syscall::Write, [&fd.to_string(), &data, len]);
+    }
+    let len = write_size_arg(ir, context, len, &format!("write_len{}", context.regc));
+    write!(ir, "  %{} = call {} @write(i32 {}, i8* {}, {})\n", context.regc, context.size, fd, data, len).unwrap();
+    context.regc += 1;
+    if context.size != "i64" {
+        write!(ir, "  %{} = sext {} %{} to i64\n", context.regc, context.size, context.regc - 1).unwrap();
+        context.regc += 1;
+    }
+    return context.regc - 1;
+}
+
+/*
+ * Defines `@put(i8 byte)`, which adds a byte to the output buffer and
+ * flushes it when full or as `context.flush` says, and `@flush()`, which
+ * writes out what is in the buffer, giving up on what is left if writing
+ * fails.
+ */
+fn write_output_buffer(ir : &mut String, context : &mut CodeGenContext)
+{
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+    write!(ir, "define internal void @put(i8 %byte){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %len = load i32, i32* @output_len, align 4\n").unwrap();
+    write!(ir, "  %slot = getelementptr inbounds [{} x i8], [{} x i8]* @output, i64 0, i32 %len\n", OUTPUT_BUFFER, OUTPUT_BUFFER).unwrap();
+    write!(ir, "  store i8 %byte, i8* %slot, align 1\n").unwrap();
+    write!(ir, "  %new_len = add i32 %len, 1\n").unwrap();
+    write!(ir, "  store i32 %new_len, i32* @output_len, align 4\n").unwrap();
+    write!(ir, "  %full = icmp eq i32 %new_len, {}\n", OUTPUT_BUFFER).unwrap();
+    match context.flush {
+        Flush::Line      => {
+            write!(ir, "  %newline = icmp eq i8 %byte, 10\n").unwrap();
+            write!(ir, "  %line_done = or i1 %full, %newline\n").unwrap();
+            write!(ir, "  br i1 %line_done, label %flush, label %done\n").unwrap();
+        },
+        Flush::Full      => write!(ir, "  br i1 %full, label %flush, label %done\n").unwrap(),
+        Flush::EveryByte => write!(ir, "  br label %flush\n").unwrap()
+    }
+    write!(ir, "flush:\n").unwrap();
+    write!(ir, "  call void @flush()\n").unwrap();
+    write!(ir, "  br label %done\n").unwrap();
+    write!(ir, "done:\n").unwrap();
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+
+    context.regc = 0;
+    if context.library {
+        write_library_flush(ir, context);
+        return;
+    }
+    write!(ir, "define internal void @flush(){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %len32 = load i32, i32* @output_len, align 4\n").unwrap();
+    write!(ir, "  %len = zext i32 %len32 to i64\n").unwrap();
+    write!(ir, "  store i32 0, i32* @output_len, align 4\n").unwrap();
+    write!(ir, "  %empty = icmp eq i64 %len, 0\n").unwrap();
+    write!(ir, "  br i1 %empty, label %done, label %more\n").unwrap();
+    write!(ir, "more:\n").unwrap();
+    write!(ir, "  %written = phi i64 [ 0, %entry ], [ %total, %wrote ]\n").unwrap();
+    write!(ir, "  %rest = sub i64 %len, %written\n").unwrap();
+    write!(ir, "  %data = getelementptr inbounds [{} x i8], [{} x i8]* @output, i64 0, i64 %written\n", OUTPUT_BUFFER, OUTPUT_BUFFER).unwrap();
+    let count = write_fd(ir, context, 1, "%data", "%rest");
+    write!(ir, "  %failed = icmp slt i64 %{}, 1\n", count).unwrap();
+    write!(ir, "  br i1 %failed, label %done, label %wrote\n").unwrap();
+    write!(ir, "wrote:\n").unwrap();
+    write!(ir, "  %total = add i64 %written, %{}\n", count).unwrap();
+    write!(ir, "  %all = icmp uge i64 %total, %len\n").unwrap();
+    write!(ir, "  br i1 %all, label %done, label %more\n").unwrap();
+    write!(ir, "done:\n").unwrap();
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/*
+ * Starts a library's `bf_run()`, which runs the program from the start with
+ * the input it is passed each time, so it clears the tape and what is left
+ * of the last call.
+ */
+fn write_library_entry(ir : &mut String, context : &CodeGenContext, dbg : &str)
+{
+    let (size, cell) = (context.size, &context.cell);
+    let bytes = context.tape_size * context.cell_width.bytes();
+    write!(ir, "define i32 @bf_run(i8* %input, {} %input_len, i8** %output, {}* %output_len){} {{\n", size, size, dbg).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  call void @llvm.memset.p0i8.{}(i8* bitcast ([{} x {}]* @memory to i8*), i8 0, {} {}, i1 false)\n",
+           size, context.tape_size, cell, size, bytes).unwrap();
+    write!(ir, "  store i32 0, i32* @memory_idx, align 4\n").unwrap();
+    write!(ir, "  store i8* %input, i8** @input_data, align 8\n").unwrap();
+    write!(ir, "  store {} %input_len, {}* @input_len, align 8\n", size, size).unwrap();
+    write!(ir, "  store {} 0, {}* @input_pos, align 8\n", size, size).unwrap();
+    write!(ir, "  store i8* null, i8** @result, align 8\n").unwrap();
+    write!(ir, "  store {} 0, {}* @result_len, align 8\n\n", size, size).unwrap();
+}
+
+/* Returns `status` from `main`, or from `bf_run()` along with the output */
+fn write_return(ir : &mut String, context : &CodeGenContext, status : u32)
+{
+    if context.io == Io::Bytes {
+        write!(ir, "  call void @flush()\n").unwrap();
+    }
+    if context.library {
+        let size = context.size;
+        write!(ir, "  %result{} = load i8*, i8** @result, align 8\n", status).unwrap();
+        write!(ir, "  store i8* %result{}, i8** %output, align 8\n", status).unwrap();
+        write!(ir, "  %result_len{} = load {}, {}* @result_len, align 8\n", status, size, size).unwrap();
+        write!(ir, "  store {} %result_len{}, {}* %output_len, align 8\n", size, status, size).unwrap();
+    }
+    write!(ir, "  ret i32 {}\n", status).unwrap();
+}
+
+/*
+ * Defines a library's `@flush()`, which appends the output buffer to the
+ * output `bf_run()` returns, or drops it if there is no memory for it.
+ */
+fn write_library_flush(ir : &mut String, context : &CodeGenContext)
+{
+    let size = context.size;
+    write!(ir, "define internal void @flush() {{\n").unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %len32 = load i32, i32* @output_len, align 4\n").unwrap();
+    write!(ir, "  %len = zext i32 %len32 to {}\n", size).unwrap();
+    write!(ir, "  store i32 0, i32* @output_len, align 4\n").unwrap();
+    write!(ir, "  %empty = icmp eq {} %len, 0\n", size).unwrap();
+    write!(ir, "  br i1 %empty, label %done, label %grow\n").unwrap();
+    write!(ir, "grow:\n").unwrap();
+    write!(ir, "  %old = load i8*, i8** @result, align 8\n").unwrap();
+    write!(ir, "  %old_len = load {}, {}* @result_len, align 8\n", size, size).unwrap();
+    write!(ir, "  %new_len = add {} %old_len, %len\n", size).unwrap();
+    write!(ir, "  %new = call i8* @realloc(i8* %old, {} %new_len)\n", size).unwrap();
+    write!(ir, "  %failed = icmp eq i8* %new, null\n").unwrap();
+    write!(ir, "  br i1 %failed, label %done, label %copy\n").unwrap();
+    write!(ir, "copy:\n").unwrap();
+    write!(ir, "  %end = getelementptr inbounds i8, i8* %new, {} %old_len\n", size).unwrap();
+    write!(ir, "  call void @llvm.memcpy.p0i8.p0i8.{}(i8* %end, i8* getelementptr inbounds ([{} x i8], [{} x i8]* @output, i64 0, i64 0), {} %len, i1 false)\n",
+           size, OUTPUT_BUFFER, OUTPUT_BUFFER, size).unwrap();
+    write!(ir, "  store i8* %new, i8** @result, align 8\n").unwrap();
+    write!(ir, "  store {} %new_len, {}* @result_len, align 8\n", size, size).unwrap();
+    write!(ir, "  br label %done\n").unwrap();
+    write!(ir, "done:\n").unwrap();
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/* Defines a library's `@read_input()`, which reads the input to `bf_run()` like getchar() */
+fn write_read_input(ir : &mut String, context : &CodeGenContext)
+{
+    let size = context.size;
+    write!(ir, "define internal i32 @read_input() {{\n").unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %pos = load {}, {}* @input_pos, align 8\n", size, size).unwrap();
+    write!(ir, "  %len = load {}, {}* @input_len, align 8\n", size, size).unwrap();
+    write!(ir, "  %more = icmp ult {} %pos, %len\n", size).unwrap();
+    write!(ir, "  br i1 %more, label %read, label %eof\n").unwrap();
+    write!(ir, "read:\n").unwrap();
+    write!(ir, "  %data = load i8*, i8** @input_data, align 8\n").unwrap();
+    write!(ir, "  %byte_ref = getelementptr inbounds i8, i8* %data, {} %pos\n", size).unwrap();
+    write!(ir, "  %byte = load i8, i8* %byte_ref, align 1\n").unwrap();
+    write!(ir, "  %next = add {} %pos, 1\n", size).unwrap();
+    write!(ir, "  store {} %next, {}* @input_pos, align 8\n", size, size).unwrap();
+    write!(ir, "  %value = zext i8 %byte to i32\n").unwrap();
+    write!(ir, "  ret i32 %value\n").unwrap();
+    write!(ir, "eof:\n").unwrap();
+    write!(ir, "  ret i32 -1\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/*
+ * Defines `@out_of_bounds(i32 offset, i32 cell)` for a safe program, which
+ * reports that the statement at `offset` in the source moved the pointer to
+ * `cell`, off the tape, and exits with status 1, writing both numbers with
+ * `@write_number()`. Unnamed registers are counted from zero again in each
+ * function.
+ */
+fn write_out_of_bounds(ir : &mut String, context : &mut CodeGenContext)
+{
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+    let bounds_message = format!("getelementptr inbounds ([{} x i8], [{} x i8]* @bounds_message, i64 0, i64 0)",
+                                 BOUNDS_MESSAGE.len(), BOUNDS_MESSAGE.len());
+    let offset_message = format!("getelementptr inbounds ([{} x i8], [{} x i8]* @offset_message, i64 0, i64 0)",
+                                 OFFSET_MESSAGE.len(), OFFSET_MESSAGE.len());
+    context.regc = 0;
+    write!(ir, "define internal void @out_of_bounds(i32 %offset, i32 %cell) cold noreturn{} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %newline = alloca i8, align 1\n").unwrap();
+    write!(ir, "  store i8 10, i8* %newline, align 1\n").unwrap();
+    if context.io == Io::Bytes {
+        write!(ir, "  call void @flush()\n").unwrap();
+    }
+    write_fd(ir, context, 2, &bounds_message, &BOUNDS_MESSAGE.len().to_string());
+    write!(ir, "  call void @write_number(i32 %cell)\n").unwrap();
+    write_fd(ir, context, 2, &offset_message, &OFFSET_MESSAGE.len().to_string());
+    write!(ir, "  call void @write_number(i32 %offset)\n").unwrap();
+    write_fd(ir, context, 2, "%newline", "1");
+    write_exit_failure(ir, context);
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/*
+ * Defines `@undefined_procedure(i32 offset, i32 number)` for a program with
+ * procedures, which reports that the `:` at `offset` in the source called
+ * procedure `number`, which isn't defined, and exits with status 1.
+ */
+fn write_undefined_procedure(ir : &mut String, context : &mut CodeGenContext)
+{
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+    let procedure_message = format!("getelementptr inbounds ([{} x i8], [{} x i8]* @procedure_message, i64 0, i64 0)",
+                                    PROCEDURE_MESSAGE.len(), PROCEDURE_MESSAGE.len());
+    let offset_message = format!("getelementptr inbounds ([{} x i8], [{} x i8]* @offset_message, i64 0, i64 0)",
+                                 OFFSET_MESSAGE.len(), OFFSET_MESSAGE.len());
+    context.regc = 0;
+    write!(ir, "define internal void @undefined_procedure(i32 %offset, i32 %number) cold noreturn{} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %newline = alloca i8, align 1\n").unwrap();
+    write!(ir, "  store i8 10, i8* %newline, align 1\n").unwrap();
+    if context.io == Io::Bytes {
+        write!(ir, "  call void @flush()\n").unwrap();
+    }
+    write_fd(ir, context, 2, &procedure_message, &PROCEDURE_MESSAGE.len().to_string());
+    write!(ir, "  call void @write_number(i32 %number)\n").unwrap();
+    /* without the parenthesis the message starts with */
+    write_fd(ir, context, 2, &format!("getelementptr inbounds (i8, i8* {}, i64 1)", offset_message), &(OFFSET_MESSAGE.len() - 1).to_string());
+    write!(ir, "  call void @write_number(i32 %offset)\n").unwrap();
+    write_fd(ir, context, 2, "%newline", "1");
+    write_exit_failure(ir, context);
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/* Defines `@write_number(i32)`, which writes a number to stderr */
+fn write_number(ir : &mut String, context : &mut CodeGenContext)
+{
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+
+    /* the digits are put in backwards, the sign in front of them even if there is none */
+    context.regc = 0;
+    write!(ir, "define internal void @write_number(i32 %n){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %digits = alloca [12 x i8], align 1\n").unwrap();
+    write!(ir, "  %negative = icmp slt i32 %n, 0\n").unwrap();
+    write!(ir, "  %negated = sub i32 0, %n\n").unwrap();
+    write!(ir, "  %magnitude = select i1 %negative, i32 %negated, i32 %n\n").unwrap();
+    write!(ir, "  br label %digit\n").unwrap();
+    write!(ir, "digit:\n").unwrap();
+    write!(ir, "  %rest = phi i32 [ %magnitude, %entry ], [ %quotient, %digit ]\n").unwrap();
+    write!(ir, "  %end = phi i32 [ 12, %entry ], [ %at, %digit ]\n").unwrap();
+    write!(ir, "  %at = sub i32 %end, 1\n").unwrap();
+    write!(ir, "  %quotient = udiv i32 %rest, 10\n").unwrap();
+    write!(ir, "  %remainder = urem i32 %rest, 10\n").unwrap();
+    write!(ir, "  %remainder8 = trunc i32 %remainder to i8\n").unwrap();
+    write!(ir, "  %char = add i8 %remainder8, 48\n").unwrap();
+    write!(ir, "  %slot = getelementptr inbounds [12 x i8], [12 x i8]* %digits, i32 0, i32 %at\n").unwrap();
+    write!(ir, "  store i8 %char, i8* %slot, align 1\n").unwrap();
+    write!(ir, "  %more = icmp ne i32 %quotient, 0\n").unwrap();
+    write!(ir, "  br i1 %more, label %digit, label %sign\n").unwrap();
+    write!(ir, "sign:\n").unwrap();
+    write!(ir, "  %minus_at = sub i32 %at, 1\n").unwrap();
+    write!(ir, "  %minus = getelementptr inbounds [12 x i8], [12 x i8]* %digits, i32 0, i32 %minus_at\n").unwrap();
+    write!(ir, "  store i8 45, i8* %minus, align 1\n").unwrap();
+    write!(ir, "  %first = select i1 %negative, i32 %minus_at, i32 %at\n").unwrap();
+    write!(ir, "  %start = getelementptr inbounds [12 x i8], [12 x i8]* %digits, i32 0, i32 %first\n").unwrap();
+    write!(ir, "  %len32 = sub i32 12, %first\n").unwrap();
+    write!(ir, "  %len = zext i32 %len32 to i64\n").unwrap();
+    write_fd(ir, context, 2, "%start", "%len");
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/*
+ * Defines `@breakpoint(i32 offset)`, which shows the cells around the
+ * pointer on stderr, and what it writes them with: `@column_width(i32
+ * value, i1 current)`, the width of the column of a cell, and
+ * `@write_column(i32 n, i1 signed, i1 current, i32 width)`, which writes a
+ * space and `n` right-aligned to `width`, in brackets for the current cell.
+ */
+fn write_breakpoint(ir : &mut String, context : &mut CodeGenContext)
+{
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+    let cell = context.cell.clone();
+    let message = |i : usize| format!("getelementptr inbounds ([{} x i8], [{} x i8]* @breakpoint_message{}, i64 0, i64 0)",
+                                      BREAKPOINT_MESSAGES[i].len(), BREAKPOINT_MESSAGES[i].len(), i);
+
+    write!(ir, "define internal i32 @column_width(i32 %value, i1 %current){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  br label %digit\n").unwrap();
+    write!(ir, "digit:\n").unwrap();
+    write!(ir, "  %rest = phi i32 [ %value, %entry ], [ %quotient, %digit ]\n").unwrap();
+    write!(ir, "  %digits = phi i32 [ 1, %entry ], [ %more_digits, %digit ]\n").unwrap();
+    write!(ir, "  %quotient = udiv i32 %rest, 10\n").unwrap();
+    write!(ir, "  %more_digits = add i32 %digits, 1\n").unwrap();
+    write!(ir, "  %more = icmp uge i32 %rest, 10\n").unwrap();
+    write!(ir, "  br i1 %more, label %digit, label %done\n").unwrap();
+    write!(ir, "done:\n").unwrap();
+    write!(ir, "  %brackets = select i1 %current, i32 2, i32 0\n").unwrap();
+    write!(ir, "  %len = add i32 %digits, %brackets\n").unwrap();
+    write!(ir, "  %short = icmp ult i32 %len, 5\n").unwrap();
+    write!(ir, "  %width = select i1 %short, i32 5, i32 %len\n").unwrap();
+    write!(ir, "  ret i32 %width\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+
+    /* the column is put together backwards from the end of a buffer, which the widest one fits in */
+    context.regc = 0;
+    write!(ir, "define internal void @write_column(i32 %n, i1 %signed, i1 %current, i32 %width){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %column = alloca [24 x i8], align 1\n").unwrap();
+    write!(ir, "  %below_zero = icmp slt i32 %n, 0\n").unwrap();
+    write!(ir, "  %negative = and i1 %signed, %below_zero\n").unwrap();
+    write!(ir, "  %negated = sub i32 0, %n\n").unwrap();
+    write!(ir, "  %magnitude = select i1 %negative, i32 %negated, i32 %n\n").unwrap();
+    write!(ir, "  %close = getelementptr inbounds [24 x i8], [24 x i8]* %column, i32 0, i32 23\n").unwrap();
+    write!(ir, "  store i8 93, i8* %close, align 1\n").unwrap();
+    write!(ir, "  %end = select i1 %current, i32 23, i32 24\n").unwrap();
+    write!(ir, "  br label %digit\n").unwrap();
+    write!(ir, "digit:\n").unwrap();
+    write!(ir, "  %rest = phi i32 [ %magnitude, %entry ], [ %quotient, %digit ]\n").unwrap();
+    write!(ir, "  %after = phi i32 [ %end, %entry ], [ %at, %digit ]\n").unwrap();
+    write!(ir, "  %at = sub i32 %after, 1\n").unwrap();
+    write!(ir, "  %quotient = udiv i32 %rest, 10\n").unwrap();
+    write!(ir, "  %remainder = urem i32 %rest, 10\n").unwrap();
+    write!(ir, "  %remainder8 = trunc i32 %remainder to i8\n").unwrap();
+    write!(ir, "  %char = add i8 %remainder8, 48\n").unwrap();
+    write!(ir, "  %slot = getelementptr inbounds [24 x i8], [24 x i8]* %column, i32 0, i32 %at\n").unwrap();
+    write!(ir, "  store i8 %char, i8* %slot, align 1\n").unwrap();
+    write!(ir, "  %more = icmp ne i32 %quotient, 0\n").unwrap();
+    write!(ir, "  br i1 %more, label %digit, label %sign\n").unwrap();
+    write!(ir, "sign:\n").unwrap();
+    write!(ir, "  %minus_at = sub i32 %at, 1\n").unwrap();
+    write!(ir, "  %minus = getelementptr inbounds [24 x i8], [24 x i8]* %column, i32 0, i32 %minus_at\n").unwrap();
+    write!(ir, "  store i8 45, i8* %minus, align 1\n").unwrap();
+    write!(ir, "  %number_at = select i1 %negative, i32 %minus_at, i32 %at\n").unwrap();
+    write!(ir, "  %open_at = sub i32 %number_at, 1\n").unwrap();
+    write!(ir, "  %open = getelementptr inbounds [24 x i8], [24 x i8]* %column, i32 0, i32 %open_at\n").unwrap();
+    write!(ir, "  store i8 91, i8* %open, align 1\n").unwrap();
+    write!(ir, "  %first = select i1 %current, i32 %open_at, i32 %number_at\n").unwrap();
+    write!(ir, "  %start = sub i32 23, %width\n").unwrap();
+    write!(ir, "  br label %pad\n").unwrap();
+    write!(ir, "pad:\n").unwrap();
+    write!(ir, "  %pad_at = phi i32 [ %start, %sign ], [ %pad_next, %space ]\n").unwrap();
+    write!(ir, "  %padded = icmp sge i32 %pad_at, %first\n").unwrap();
+    write!(ir, "  br i1 %padded, label %write, label %space\n").unwrap();
+    write!(ir, "space:\n").unwrap();
+    write!(ir, "  %space_slot = getelementptr inbounds [24 x i8], [24 x i8]* %column, i32 0, i32 %pad_at\n").unwrap();
+    write!(ir, "  store i8 32, i8* %space_slot, align 1\n").unwrap();
+    write!(ir, "  %pad_next = add i32 %pad_at, 1\n").unwrap();
+    write!(ir, "  br label %pad\n").unwrap();
+    write!(ir, "write:\n").unwrap();
+    write!(ir, "  %data = getelementptr inbounds [24 x i8], [24 x i8]* %column, i32 0, i32 %start\n").unwrap();
+    write!(ir, "  %len32 = sub i32 24, %start\n").unwrap();
+    write!(ir, "  %len = zext i32 %len32 to i64\n").unwrap();
+    write_fd(ir, context, 2, "%data", "%len");
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+
+    /* the dump comes after everything the program printed before it */
+    context.regc = 0;
+    write!(ir, "define internal void @breakpoint(i32 %offset){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %newline = alloca i8, align 1\n").unwrap();
+    write!(ir, "  store i8 10, i8* %newline, align 1\n").unwrap();
+    if context.io == Io::Bytes {
+        write!(ir, "  call void @flush()\n").unwrap();
+    } else {
+        write!(ir, "  %flushed = call i32 @fflush(i8* null)\n").unwrap();
+    }
+    write_fd(ir, context, 2, &message(0), &BREAKPOINT_MESSAGES[0].len().to_string());
+    write!(ir, "  call void @write_number(i32 %offset)\n").unwrap();
+    write_fd(ir, context, 2, &message(1), &BREAKPOINT_MESSAGES[1].len().to_string());
+    write!(ir, "  %idx = load i32, i32* @memory_idx, align 4\n").unwrap();
+    let (origin, last_idx) = if context.tape == Tape::Dynamic {
+        write!(ir, "  %origin = load i32, i32* @memory_origin, align 4\n").unwrap();
+        write!(ir, "  %tape_len = load i64, i64* @memory_len, align 8\n").unwrap();
+        write!(ir, "  %tape_len32 = trunc i64 %tape_len to i32\n").unwrap();
+        write!(ir, "  %last_idx = sub i32 %tape_len32, 1\n").unwrap();
+        write!(ir, "  %cells = load {}*, {}** @memory, align 8\n", cell, cell).unwrap();
+        ("%origin".to_owned(), "%last_idx".to_owned())
+    } else {
+        ("0".to_owned(), (context.tape_size - 1).to_string())
+    };
+    write!(ir, "  %position = sub i32 %idx, {}\n", origin).unwrap();
+    write!(ir, "  call void @write_number(i32 %position)\n").unwrap();
+    write_fd(ir, context, 2, &message(2), &BREAKPOINT_MESSAGES[2].len().to_string());
+    write!(ir, "  %low = sub i32 %idx, {}\n", WINDOW_CELLS).unwrap();
+    write!(ir, "  %below = icmp slt i32 %low, 0\n").unwrap();
+    write!(ir, "  %first = select i1 %below, i32 0, i32 %low\n").unwrap();
+    write!(ir, "  %high = add i32 %idx, {}\n", WINDOW_CELLS).unwrap();
+    write!(ir, "  %above = icmp sgt i32 %high, {}\n", last_idx).unwrap();
+    write!(ir, "  %last = select i1 %above, i32 {}, i32 %high\n", last_idx).unwrap();
+    write!(ir, "  br label %cell_column\n").unwrap();
+    /* a row of cell numbers, then one of their values, each as wide as the wider of the two */
+    write_breakpoint_row(ir, context, "cell", "entry", "value_row", &origin);
+    write!(ir, "value_row:\n").unwrap();
+    write_fd(ir, context, 2, &message(3), &BREAKPOINT_MESSAGES[3].len().to_string());
+    write!(ir, "  br label %value_column\n").unwrap();
+    write_breakpoint_row(ir, context, "value", "value_row", "done", &origin);
+    write!(ir, "done:\n").unwrap();
+    write_fd(ir, context, 2, "%newline", "1");
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/*
+ * Writes the loop over the cells from %first to %last in `@breakpoint()`
+ * that writes the `row` of cell numbers or values, entered from the block
+ * `from` and going on to `next`.
+ */
+fn write_breakpoint_row(ir : &mut String, context : &mut CodeGenContext, row : &str, from : &str, next : &str, origin : &str)
+{
+    let cell = &context.cell;
+    write!(ir, "{}_column:\n", row).unwrap();
+    write!(ir, "  %{}_i = phi i32 [ %first, %{} ], [ %{}_next, %{}_column ]\n", row, from, row, row).unwrap();
+    if context.tape == Tape::Dynamic {
+        write!(ir, "  %{}_ref = getelementptr inbounds {}, {}* %cells, i32 %{}_i\n", row, cell, cell, row).unwrap();
+    } else {
+        write!(ir, "  %{}_ref = getelementptr inbounds [{} x {}], [{} x {}]* @memory, i32 0, i32 %{}_i\n",
+               row, context.tape_size, cell, context.tape_size, cell, row).unwrap();
+    }
+    write!(ir, "  %{}_cell = load {}, {}* %{}_ref, align {}\n", row, cell, cell, row, context.cell_width.bytes()).unwrap();
+    let value = if context.cell_width == CellWidth::Bits32 {
+        format!("%{}_cell", row)
+    } else {
+        write!(ir, "  %{}_value = zext {} %{}_cell to i32\n", row, cell, row).unwrap();
+        format!("%{}_value", row)
+    };
+    write!(ir, "  %{}_current = icmp eq i32 %{}_i, %idx\n", row, row).unwrap();
+    write!(ir, "  %{}_width = call i32 @column_width(i32 {}, i1 %{}_current)\n", row, value, row).unwrap();
+    if row == "cell" {
+        write!(ir, "  %cell_number = sub i32 %cell_i, {}\n", origin).unwrap();
+        write!(ir, "  call void @write_column(i32 %cell_number, i1 true, i1 false, i32 %cell_width)\n").unwrap();
+    } else {
+        write!(ir, "  call void @write_column(i32 {}, i1 false, i1 %value_current, i32 %value_width)\n", value).unwrap();
+    }
+    write!(ir, "  %{}_next = add i32 %{}_i, 1\n", row, row).unwrap();
+    write!(ir, "  %{}_done = icmp sgt i32 %{}_next, %last\n", row, row).unwrap();
+    write!(ir, "  br i1 %{}_done, label %{}, label %{}_column\n", row, next, row).unwrap();
+}
+
+/* Seeds the generator of random bytes when the program starts, if it has one */
+fn write_seed_call(ir : &mut String, context : &CodeGenContext)
+{
+    if !context.random {
+        return;
+    }
+    match context.seed {
+        Some(seed) => write!(ir, "  store i64 {}, i64* @random_state, align 8\n\n", seed as i64).unwrap(),
+        None       => write!(ir, "  call void @seed_random()\n\n").unwrap()
+    }
+}
+
+/*
+ * Defines `@random_byte()`, which returns the next byte from @random_state
+ * as `random::Random` would, and without a seed, `@seed_random()`, which
+ * starts it somewhere different in each run: from the kernel's random bytes
+ * in a freestanding program, and otherwise from the time and where the
+ * stack is, which moves with address space layout randomization.
+ */
+fn write_random(ir : &mut String, context : &mut CodeGenContext)
+{
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+
+    /* SplitMix64, with its constants in the signed range LLVM wants */
+    write!(ir, "define internal i8 @random_byte(){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %old = load i64, i64* @random_state, align 8\n").unwrap();
+    write!(ir, "  %state = add i64 %old, {}\n", random::INCREMENT as i64).unwrap();
+    write!(ir, "  store i64 %state, i64* @random_state, align 8\n").unwrap();
+    write!(ir, "  %shifted0 = lshr i64 %state, 30\n").unwrap();
+    write!(ir, "  %mixed0 = xor i64 %state, %shifted0\n").unwrap();
+    write!(ir, "  %z0 = mul i64 %mixed0, {}\n", random::MIX[0] as i64).unwrap();
+    write!(ir, "  %shifted1 = lshr i64 %z0, 27\n").unwrap();
+    write!(ir, "  %mixed1 = xor i64 %z0, %shifted1\n").unwrap();
+    write!(ir, "  %z1 = mul i64 %mixed1, {}\n", random::MIX[1] as i64).unwrap();
+    write!(ir, "  %shifted2 = lshr i64 %z1, 31\n").unwrap();
+    write!(ir, "  %z2 = xor i64 %z1, %shifted2\n").unwrap();
+    write!(ir, "  %top = lshr i64 %z2, 56\n").unwrap();
+    write!(ir, "  %byte = trunc i64 %top to i8\n").unwrap();
+    write!(ir, "  ret i8 %byte\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+
+    if context.seed.is_some() {
+        return;
+    }
+    context.regc = 0;
+    write!(ir, "define internal void @seed_random(){} {{\n", attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    write!(ir, "  %seed = alloca i64, align 8\n").unwrap();
+    write!(ir, "  %stack = ptrtoint i64* %seed to i64\n").unwrap();
+    write!(ir, "  store i64 %stack, i64* %seed, align 8\n").unwrap();
+    match context.syscalls {
+        Some(Syscalls::Wasi) => {
+            write!(ir, "  %bytes = bitcast i64* %seed to i8*\n").unwrap();
+            write!(ir, "  %status = call i32 @random_get(i8* %bytes, i32 8)\n").unwrap();
+        },
+        /* on failure the stack address stays */
+        Some(_) => {
+            write_syscall(ir, context, Syscall::GetRandom, ["%stack", "8", "0"]);
+        },
+        None    => {
+            write!(ir, "  %time = call {} @time({}* null)\n", context.size, context.size).unwrap();
+            let time = if context.size == "i64" {
+                "%time"
+            } else {
+                write!(ir, "  %time64 = zext {} %time to i64\n", context.size).unwrap();
+                "%time64"
+            };
+            write!(ir, "  %mixed = xor i64 %stack, {}\n", time).unwrap();
+            write!(ir, "  store i64 %mixed, i64* %seed, align 8\n").unwrap();
+        }
+    }
+    write!(ir, "  %state = load i64, i64* %seed, align 8\n").unwrap();
+    write!(ir, "  store i64 %state, i64* @random_state, align 8\n").unwrap();
+    write!(ir, "  ret void\n").unwrap();
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/* Stores the next random byte in the current cell */
+fn write_random_byte(ir : &mut String, context : &mut CodeGenContext)
+{
+    let mem_ref = write_get_memory_ref(ir, context);
+    let (cell, align) = (context.cell.clone(), context.cell_width.bytes());
+    write!(ir, "  %{} = call i8 @random_byte()\n", context.regc).unwrap();
+    let mut value = context.regc;
+    context.regc += 1;
+    if context.cell_width != CellWidth::Bits8 {
+        write!(ir, "  %{} = zext i8 %{} to {}\n", context.regc, value, cell).unwrap();
+        value = context.regc;
+        context.regc += 1;
+    }
+    write!(ir, "  store {} %{}, {}* %{}, align {}\n\n", cell, value, cell, mem_ref, align).unwrap();
+}
+
+/* On a dynamic tape, grows the tape if the cell at `offset` from the pointer isn't on it */
+fn write_ensure_cell(ir : &mut String, context : &mut CodeGenContext, offset : i32)
+{
+    if context.tape != Tape::Dynamic {
+        return;
+    }
+    let grow_num = context.growc;
+    write!(ir, "  %{} = load i32, i32* @memory_idx, align 4\n", context.regc).unwrap();
+    write!(ir, "  %{} = add i32 %{}, {}\n", context.regc + 1, context.regc, offset).unwrap();
+    write!(ir, "  %{} = sext i32 %{} to i64\n", context.regc + 2, context.regc + 1).unwrap();
+    write!(ir, "  %{} = load i64, i64* @memory_len, align 8\n", context.regc + 3).unwrap();
+    write!(ir, "  %{} = icmp uge i64 %{}, %{}\n", context.regc + 4, context.regc + 2, context.regc + 3).unwrap();
+    write!(ir, "  br i1 %{}, label %grow{}, label %grown{}\n", context.regc + 4, grow_num, grow_num).unwrap();
+    write!(ir, "grow{}:\n", grow_num).unwrap();
+    write!(ir, "  call void @grow(i64 %{})\n", context.regc + 2).unwrap();
+    write!(ir, "  br label %grown{}\n", grow_num).unwrap();
+    write!(ir, "grown{}:\n", grow_num).unwrap();
+    context.regc += 5;
+    context.growc += 1;
 }
 
 /*
  * Puts &memory[memory_idx] at register %(return - 1) where `return` is
- * the returned u32.
+ * the returned u32. The index itself is at %(return - 2), and on a dynamic
+ * tape the base address of the tape at %(return - 3).
  */
 fn write_get_memory_ref(ir : &mut String, context : &mut CodeGenContext) -> u32
 {
+    let cell = &context.cell;
+    if context.tape == Tape::Dynamic {
+        write!(ir, "  %{} = load {}*, {}** @memory, align 8\n", context.regc, cell, cell).unwrap();
+        write!(ir, "  %{} = load i32, i32* @memory_idx, align 4\n", context.regc + 1).unwrap();
+        write!(ir, "  %{} = zext i32 %{} to i64\n", context.regc + 2, context.regc + 1).unwrap();
+        write!(ir, "  %{} = getelementptr inbounds {}, {}* %{}, i64 %{}\n", context.regc + 3, cell, cell, context.regc, context.regc + 2).unwrap();
+        context.regc += 4;
+        return context.regc - 1;
+    }
     write!(ir, "  %{} = load i32, i32* @memory_idx, align 4\n", context.regc).unwrap();
     write!(ir, "  %{} = zext i32 %{} to i64\n", context.regc + 1, context.regc).unwrap();
-    write!(ir, "  %{} = getelementptr inbounds [65536 x i8], [65536 x i8]* @memory, i64 0, i64 %{}\n", context.regc + 2, context.regc + 1).unwrap();
+    write!(ir, "  %{} = getelementptr inbounds [{} x {}], [{} x {}]* @memory, i64 0, i64 %{}\n",
+           context.regc + 2, context.tape_size, cell, context.tape_size, cell, context.regc + 1).unwrap();
     context.regc += 3;
     return context.regc - 1;
 }
 
+/* `n` reduced to a step forward that wraps around the tape, if it does */
+fn wrap_step(context : &CodeGenContext, n : i32) -> i32
+{
+    if context.bounds == Bounds::Wrap {
+        return n.rem_euclid(context.tape_size as i32);
+    }
+    return n;
+}
+
+/*
+ * Applies the bounds policy to the index stepped to in register `idx` and
+ * returns the register with the index to use: wrapped around the tape, or
+ * checked with a branch to the out of bounds exit, unless the i1 in `skip`
+ * says the cell isn't accessed after all.
+ */
+fn write_check_idx(ir : &mut String, context : &mut CodeGenContext, idx : u32, skip : Option<u32>) -> u32
+{
+    match context.bounds {
+        Bounds::Unchecked => return idx,
+        Bounds::Wrap      => {
+            /* the step was wrapped already, so the index is less than two tapes */
+            write!(ir, "  %{} = icmp uge i32 %{}, {}\n", context.regc, idx, context.tape_size).unwrap();
+            write!(ir, "  %{} = sub i32 %{}, {}\n", context.regc + 1, idx, context.tape_size).unwrap();
+            write!(ir, "  %{} = select i1 %{}, i32 %{}, i32 %{}\n", context.regc + 2, context.regc, context.regc + 1, idx).unwrap();
+            context.regc += 3;
+            return context.regc - 1;
+        },
+        Bounds::Error     => {
+            /* an index below zero is above the tape as an unsigned one */
+            write!(ir, "  %{} = icmp uge i32 %{}, {}\n", context.regc, idx, context.tape_size).unwrap();
+            let mut outside = context.regc;
+            context.regc += 1;
+            if let Some(skip) = skip {
+                write!(ir, "  %{} = select i1 %{}, i1 false, i1 %{}\n", context.regc, skip, outside).unwrap();
+                outside = context.regc;
+                context.regc += 1;
+            }
+            write_bounds_branch(ir, context, outside, &format!("%{}", idx));
+            return idx;
+        }
+    }
+}
+
+/*
+ * Leaves the program if the i1 in `outside` says the pointer went off the
+ * tape, to `cell`, and reports where if it is safe.
+ */
+fn write_bounds_branch(ir : &mut String, context : &mut CodeGenContext, outside : u32, cell : &str)
+{
+    let check_num = context.checkc;
+    context.checkc += 1;
+    if !context.safe {
+        write!(ir, "  br i1 %{}, label %out_of_bounds, label %in_bounds{}\n", outside, check_num).unwrap();
+        write!(ir, "in_bounds{}:\n", check_num).unwrap();
+        return;
+    }
+    write!(ir, "  br i1 %{}, label %off_tape{}, label %in_bounds{}\n", outside, check_num, check_num).unwrap();
+    write!(ir, "off_tape{}:\n", check_num).unwrap();
+    write!(ir, "  call void @out_of_bounds(i32 {}, i32 {})\n", context.offset, cell).unwrap();
+    write!(ir, "  unreachable\n").unwrap();
+    write!(ir, "in_bounds{}:\n", check_num).unwrap();
+}
+
+/* A pointer to the cell `offset` from the one at `mem_ref`, with the bounds policy applied */
+fn write_get_offset_ref(ir : &mut String, context : &mut CodeGenContext, mem_ref : u32, offset : i32, skip : Option<u32>) -> u32
+{
+    let idx = mem_ref - 2;
+    write!(ir, "  %{} = add i32 %{}, {}\n", context.regc, idx, wrap_step(context, offset)).unwrap();
+    context.regc += 1;
+    let target = write_check_idx(ir, context, context.regc - 1, skip);
+    write!(ir, "  %{} = zext i32 %{} to i64\n", context.regc, target).unwrap();
+    write!(ir, "  %{} = getelementptr inbounds [{} x {}], [{} x {}]* @memory, i64 0, i64 %{}\n",
+           context.regc + 1, context.tape_size, context.cell, context.tape_size, context.cell, context.regc).unwrap();
+    context.regc += 2;
+    return context.regc - 1;
+}
+
 fn write_move(ir : &mut String, context : &mut CodeGenContext, n : i32)
 {
     write!(ir, "  %{} = load i32, i32* @memory_idx, align 4\n", context.regc).unwrap();
-    write!(ir, "  %{} = add i32 %{}, {}\n", context.regc + 1, context.regc, n).unwrap();
-    write!(ir, "  store i32 %{}, i32* @memory_idx, align 4\n\n", context.regc + 1).unwrap();
+    write!(ir, "  %{} = add i32 %{}, {}\n", context.regc + 1, context.regc, wrap_step(context, n)).unwrap();
     context.regc += 2;
+    let idx = write_check_idx(ir, context, context.regc - 1, None);
+    write!(ir, "  store i32 %{}, i32* @memory_idx, align 4\n\n", idx).unwrap();
+    write_ensure_cell(ir, context, 0);
 }
 
 fn write_add(ir : &mut String, context : &mut CodeGenContext, n : i32)
 {
     let mem_ref = write_get_memory_ref(ir, context);
-    write!(ir, "  %{} = load i8, i8* %{}, align 1\n", context.regc, mem_ref).unwrap();
-    write!(ir, "  %{} = add i8 %{}, {}\n", context.regc + 1, context.regc, n).unwrap();
-    write!(ir, "  store i8 %{}, i8* %{}, align 1\n\n", context.regc + 1, mem_ref).unwrap();
+    let (cell, align) = (&context.cell, context.cell_width.bytes());
+    write!(ir, "  %{} = load {}, {}* %{}, align {}\n", context.regc, cell, cell, mem_ref, align).unwrap();
+    write!(ir, "  %{} = add {} %{}, {}\n", context.regc + 1, cell, context.regc, context.cell_const(n)).unwrap();
+    write!(ir, "  store {} %{}, {}* %{}, align {}\n\n", cell, context.regc + 1, cell, mem_ref, align).unwrap();
     context.regc += 2;
 }
 
-fn write_getc(ir : &mut String, context : &mut CodeGenContext)
+fn write_add_at(ir : &mut String, context : &mut CodeGenContext, offset : i32, n : i32)
 {
-    write!(ir, "  %{} = call i32 @getchar()\n", context.regc).unwrap();
-    let value = context.regc + 1;
-    write!(ir, "  %{} = trunc i32 %{} to i8\n", value, context.regc).unwrap();
+    write_ensure_cell(ir, context, offset);
+    let mem_ref = write_get_memory_ref(ir, context);
+    let target = if context.bounds == Bounds::Unchecked {
+        write!(ir, "  %{} = getelementptr inbounds {}, {}* %{}, i32 {}\n", context.regc, context.cell, context.cell, mem_ref, offset).unwrap();
+        context.regc += 1;
+        context.regc - 1
+    } else {
+        write_get_offset_ref(ir, context, mem_ref, offset, None)
+    };
+    let (cell, align) = (&context.cell, context.cell_width.bytes());
+    write!(ir, "  %{} = load {}, {}* %{}, align {}\n", context.regc, cell, cell, target, align).unwrap();
+    write!(ir, "  %{} = add {} %{}, {}\n", context.regc + 1, cell, context.regc, context.cell_const(n)).unwrap();
+    write!(ir, "  store {} %{}, {}* %{}, align {}\n\n", cell, context.regc + 1, cell, target, align).unwrap();
     context.regc += 2;
+}
+
+fn write_set(ir : &mut String, context : &mut CodeGenContext, n : i32)
+{
+    let mem_ref = write_get_memory_ref(ir, context);
+    let (cell, align) = (&context.cell, context.cell_width.bytes());
+    write!(ir, "  store {} {}, {}* %{}, align {}\n\n", cell, context.cell_const(n), cell, mem_ref, align).unwrap();
+}
+
+fn write_mul_add(ir : &mut String, context : &mut CodeGenContext, offset : i32, factor : i32)
+{
+    write_ensure_cell(ir, context, offset);
     let mem_ref = write_get_memory_ref(ir, context);
-    write!(ir, "  store i8 %{}, i8* %{}, align 1\n\n", value, mem_ref).unwrap();
+    let value = context.regc;
+    write!(ir, "  %{} = load {}, {}* %{}, align {}\n", value, context.cell, context.cell, mem_ref, context.cell_width.bytes()).unwrap();
+    /* the original loop never runs on a zero cell, so redirect the access to the current cell then */
+    write!(ir, "  %{} = icmp eq {} %{}, 0\n", context.regc + 1, context.cell, value).unwrap();
+    let zero = context.regc + 1;
+    context.regc += 2;
+    let target = if context.bounds == Bounds::Unchecked {
+        write!(ir, "  %{} = getelementptr {}, {}* %{}, i32 {}\n", context.regc, context.cell, context.cell, mem_ref, offset).unwrap();
+        context.regc += 1;
+        context.regc - 1
+    } else {
+        write_get_offset_ref(ir, context, mem_ref, offset, Some(zero))
+    };
+    let (cell, align) = (&context.cell, context.cell_width.bytes());
+    write!(ir, "  %{} = select i1 %{}, {}* %{}, {}* %{}\n", context.regc, zero, cell, mem_ref, cell, target).unwrap();
+    write!(ir, "  %{} = load {}, {}* %{}, align {}\n", context.regc + 1, cell, cell, context.regc, align).unwrap();
+    write!(ir, "  %{} = mul {} %{}, {}\n", context.regc + 2, cell, value, context.cell_const(factor)).unwrap();
+    write!(ir, "  %{} = add {} %{}, %{}\n", context.regc + 3, cell, context.regc + 1, context.regc + 2).unwrap();
+    write!(ir, "  store {} %{}, {}* %{}, align {}\n\n", cell, context.regc + 3, cell, context.regc, align).unwrap();
+    context.regc += 4;
 }
 
+/*
+ * Reads the value `,` stores and returns the register with it. `old` is
+ * the value of the cell, which is needed if it is kept at the end of input.
+ * getchar() returns -1 on EOF, which is stored as is unless the EOF policy
+ * says otherwise. scanf() reads numbers into @number and returns 1 if it
+ * could read one.
+ */
+fn write_read(ir : &mut String, context : &mut CodeGenContext, old : Option<&str>) -> u32
+{
+    /* so a prompt is shown before the program waits for an answer */
+    if context.io == Io::Bytes && !context.library {
+        write!(ir, "  call void @flush()\n").unwrap();
+    }
+    let input = context.regc;
+    let (mut value, eof) = if context.io == Io::Numeric {
+        write!(ir, "  %{} = call i32 (i8*, ...) @scanf(i8* getelementptr inbounds ([3 x i8], [3 x i8]* @input_format, i64 0, i64 0), i32* @number)\n", input).unwrap();
+        write!(ir, "  %{} = icmp ne i32 %{}, 1\n", input + 1, input).unwrap();
+        write!(ir, "  %{} = load i32, i32* @number, align 4\n", input + 2).unwrap();
+        context.regc += 3;
+        (input + 2, Some(input + 1))
+    } else if context.syscalls.is_some() {
+        /* like getchar(), but the read returns 0 at the end of input */
+        write!(ir, "  %{} = ptrtoint i8* %input_byte to i64\n", input).unwrap();
+        context.regc += 1;
+        let read = write_syscall(ir, context, Syscall::Read, ["0", &format!("%{}", input), "1"]);
+        write!(ir, "  %{} = icmp slt i64 %{}, 1\n", context.regc, read).unwrap();
+        write!(ir, "  %{} = load i8, i8* %input_byte, align 1\n", context.regc + 1).unwrap();
+        write!(ir, "  %{} = zext i8 %{} to i32\n", context.regc + 2, context.regc + 1).unwrap();
+        context.regc += 3;
+        (context.regc - 1, Some(context.regc - 3))
+    } else {
+        let getchar = if context.library { "read_input" } else { "getchar" };
+        write!(ir, "  %{} = call i32 @{}()\n", input, getchar).unwrap();
+        context.regc += 1;
+        (input, None)
+    };
+    if context.cell_width != CellWidth::Bits32 {
+        write!(ir, "  %{} = trunc i32 %{} to {}\n", context.regc, value, context.cell).unwrap();
+        value = context.regc;
+        context.regc += 1;
+    }
+    let on_eof = match context.eof {
+        Eof::MinusOne if eof.is_none() => None,
+        Eof::MinusOne  => Some("-1"),
+        Eof::Zero      => Some("0"),
+        Eof::Unchanged => Some(old.expect("the cell kept at the end of input"))
+    };
+    if let Some(on_eof) = on_eof {
+        let eof = match eof {
+            Some(eof) => eof,
+            None      => {
+                write!(ir, "  %{} = icmp eq i32 %{}, -1\n", context.regc, input).unwrap();
+                context.regc += 1;
+                context.regc - 1
+            }
+        };
+        let cell = &context.cell;
+        write!(ir, "  %{} = select i1 %{}, {} {}, {} %{}\n", context.regc, eof, cell, on_eof, cell, value).unwrap();
+        value = context.regc;
+        context.regc += 1;
+    }
+    return value;
+}
+
+/* Stores what `,` reads in the current cell, read by `@get()` when optimizing for size */
+fn write_getc(ir : &mut String, context : &mut CodeGenContext)
+{
+    let mem_ref = write_get_memory_ref(ir, context);
+    let (cell, align) = (context.cell.clone(), context.cell_width.bytes());
+    let old = if context.shared_input || context.eof == Eof::Unchanged {
+        write!(ir, "  %{} = load {}, {}* %{}, align {}\n", context.regc, cell, cell, mem_ref, align).unwrap();
+        context.regc += 1;
+        Some(format!("%{}", context.regc - 1))
+    } else {
+        None
+    };
+    let value = if context.shared_input {
+        write!(ir, "  %{} = call {} @get({} {})\n", context.regc, cell, cell, old.unwrap()).unwrap();
+        context.regc += 1;
+        context.regc - 1
+    } else {
+        write_read(ir, context, old.as_deref())
+    };
+    write!(ir, "  store {} %{}, {}* %{}, align {}\n\n", cell, value, cell, mem_ref, align).unwrap();
+}
+
+/* Defines `@get(old)`, which returns what `,` stores in a cell holding `old` */
+fn write_get(ir : &mut String, context : &mut CodeGenContext)
+{
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+    let cell = context.cell.clone();
+    context.regc = 0;
+    write!(ir, "define internal {} @get({} %old){} {{\n", cell, cell, attributes).unwrap();
+    write!(ir, "entry:\n").unwrap();
+    if context.syscalls.is_some() {
+        write!(ir, "  %input_byte = alloca i8, align 1\n").unwrap();
+    }
+    let value = write_read(ir, context, Some("%old"));
+    write!(ir, "  ret {} %{}\n", cell, value).unwrap();
+    write!(ir, "}}\n\n").unwrap();
+}
+
+/*
+ * Bytes go to the output buffer, the low byte of wider cells like putchar()
+ * would write. printf() is passed the zero-extended cell.
+ */
 fn write_putc(ir : &mut String, context : &mut CodeGenContext)
 {
     write_get_memory_ref(ir, context);
-    write!(ir, "  %{} = load i8, i8* %{}, align 1\n", context.regc, context.regc - 1).unwrap();
-    write!(ir, "  %{} = zext i8 %{} to i32\n", context.regc + 1, context.regc).unwrap();
-    write!(ir, "  %{} = call i32  @putchar(i32 %{})\n\n", context.regc + 2, context.regc + 1).unwrap();
-    context.regc += 3;
+    let (cell, align) = (&context.cell, context.cell_width.bytes());
+    write!(ir, "  %{} = load {}, {}* %{}, align {}\n", context.regc, cell, cell, context.regc - 1, align).unwrap();
+    let mut value = context.regc;
+    context.regc += 1;
+    if context.io == Io::Bytes {
+        if context.cell_width != CellWidth::Bits8 {
+            write!(ir, "  %{} = trunc {} %{} to i8\n", context.regc, cell, value).unwrap();
+            value = context.regc;
+            context.regc += 1;
+        }
+        write!(ir, "  call void @put(i8 %{})\n\n", value).unwrap();
+        return;
+    }
+    if context.cell_width != CellWidth::Bits32 {
+        write!(ir, "  %{} = zext {} %{} to i32\n", context.regc, cell, value).unwrap();
+        value = context.regc;
+        context.regc += 1;
+    }
+    write!(ir, "  %{} = call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @output_format, i64 0, i64 0), i32 %{})\n\n", context.regc, value).unwrap();
+    context.regc += 1;
 }
 
-fn write_loop_begin(ir : &mut String, context : &mut CodeGenContext) -> u32
+/*
+ * Begins a loop and returns what its labels are numbered with: a count of
+ * the loops so far, or with comments, its path through the loops it is
+ * nested in, like `2_1` for the first loop in the second at the top.
+ */
+fn write_loop_begin(ir : &mut String, context : &mut CodeGenContext) -> String
 {
-    let loop_num = context.loopc;
+    let loop_num = match context.loop_path {
+        Some(ref mut path) => {
+            *path.last_mut().unwrap() += 1;
+            let name = path.iter().map(|n| n.to_string()).collect::<Vec<_>>().join("_");
+            path.push(0);
+            name
+        },
+        None => context.loopc.to_string()
+    };
     write!(ir, "  br label %loop_cond{}\n", loop_num).unwrap();
     write!(ir, "loop_cond{}:\n", loop_num).unwrap();
     write_get_memory_ref(ir, context);
-    write!(ir, "  %{} = load i8, i8* %{}, align 1\n", context.regc, context.regc - 1).unwrap();
-    write!(ir, "  %{} = icmp eq i8 %{}, 0\n", context.regc + 1, context.regc).unwrap();
+    let (cell, align) = (&context.cell, context.cell_width.bytes());
+    write!(ir, "  %{} = load {}, {}* %{}, align {}\n", context.regc, cell, cell, context.regc - 1, align).unwrap();
+    write!(ir, "  %{} = icmp eq {} %{}, 0\n", context.regc + 1, cell, context.regc).unwrap();
     write!(ir, "  br i1 %{}, label %loop_end{}, label %loop_begin{}\n", context.regc + 1, loop_num, loop_num).unwrap();
     write!(ir, "loop_begin{}:\n", loop_num).unwrap();
     context.regc += 2;
     context.loopc += 1;
-    return context.loopc - 1;
+    return loop_num;
 }
 
-fn write_loop_end(ir : &mut String, loop_num : u32)
+fn write_loop_end(ir : &mut String, context : &mut CodeGenContext, loop_num : &str)
 {
+    if let Some(ref mut path) = context.loop_path {
+        path.pop();
+    }
     write!(ir, "  br label %loop_cond{}\n", loop_num).unwrap();
     write!(ir, "loop_end{}:\n\n", loop_num).unwrap();
 }
 
+/*
+ * Forward scans by one byte sized cell are a memchr for the next zero byte;
+ * anything else is emitted as a plain loop which LLVM is free to vectorize.
+ * So are scans on a tape that wraps around, which may continue at its start.
+ */
+fn write_scan(ir : &mut String, context : &mut CodeGenContext, stride : i32)
+{
+    if stride != 1 || context.cell_width != CellWidth::Bits8 || context.syscalls.is_some() || context.bounds == Bounds::Wrap {
+        let loop_num = write_loop_begin(ir, context);
+        write_move(ir, context, stride);
+        write_loop_end(ir, context, &loop_num);
+        return;
+    }
+
+    let mem_ref = write_get_memory_ref(ir, context);
+    let idx = mem_ref - 2;
+    if context.tape == Tape::Dynamic {
+        /* past the end of the tape every cell is zero */
+        let base = mem_ref - 3;
+        write!(ir, "  %{} = load i64, i64* @memory_len, align 8\n", context.regc).unwrap();
+        write!(ir, "  %{} = sub i64 %{}, %{}\n", context.regc + 1, context.regc, mem_ref - 1).unwrap();
+        let len = write_size_arg(ir, context, &format!("%{}", context.regc + 1), &format!("scan_len{}", context.regc));
+        write!(ir, "  %{} = call i8* @memchr(i8* %{}, i32 0, {})\n", context.regc + 2, mem_ref, len).unwrap();
+        write!(ir, "  %{} = ptrtoint i8* %{} to i64\n", context.regc + 3, context.regc + 2).unwrap();
+        write!(ir, "  %{} = ptrtoint i8* %{} to i64\n", context.regc + 4, base).unwrap();
+        write!(ir, "  %{} = sub i64 %{}, %{}\n", context.regc + 5, context.regc + 3, context.regc + 4).unwrap();
+        write!(ir, "  %{} = icmp eq i8* %{}, null\n", context.regc + 6, context.regc + 2).unwrap();
+        write!(ir, "  %{} = select i1 %{}, i64 %{}, i64 %{}\n", context.regc + 7, context.regc + 6, context.regc, context.regc + 5).unwrap();
+        write!(ir, "  %{} = trunc i64 %{} to i32\n", context.regc + 8, context.regc + 7).unwrap();
+        write!(ir, "  store i32 %{}, i32* @memory_idx, align 4\n\n", context.regc + 8).unwrap();
+        context.regc += 9;
+        write_ensure_cell(ir, context, 0);
+        return;
+    }
+    write!(ir, "  %{} = sub i32 {}, %{}\n", context.regc, context.tape_size, idx).unwrap();
+    write!(ir, "  %{} = zext i32 %{} to i64\n", context.regc + 1, context.regc).unwrap();
+    let len = write_size_arg(ir, context, &format!("%{}", context.regc + 1), &format!("scan_len{}", context.regc));
+    write!(ir, "  %{} = call i8* @memchr(i8* %{}, i32 0, {})\n", context.regc + 2, mem_ref, len).unwrap();
+    let found = context.regc + 2;
+    context.regc += 3;
+    if context.bounds == Bounds::Error {
+        /* no zero up to the end means the pointer runs off it */
+        write!(ir, "  %{} = icmp eq i8* %{}, null\n", context.regc, found).unwrap();
+        context.regc += 1;
+        write_bounds_branch(ir, context, context.regc - 1, &context.tape_size.to_string());
+    }
+    write!(ir, "  %{} = ptrtoint i8* %{} to i64\n", context.regc, found).unwrap();
+    write!(ir, "  %{} = sub i64 %{}, ptrtoint ([{} x i8]* @memory to i64)\n", context.regc + 1, context.regc, context.tape_size).unwrap();
+    write!(ir, "  %{} = trunc i64 %{} to i32\n", context.regc + 2, context.regc + 1).unwrap();
+    write!(ir, "  store i32 %{}, i32* @memory_idx, align 4\n\n", context.regc + 2).unwrap();
+    context.regc += 3;
+}
+
+/* The type of @procedures, a function for each value of a cell */
+fn procedure_table(context : &CodeGenContext) -> String
+{
+    return format!("[{} x void()*]", 1u64 << context.cell_width.bits());
+}
+
+/* Puts the function pointer to the procedure numbered by the current cell at %(return - 1) */
+fn write_get_procedure_ref(ir : &mut String, context : &mut CodeGenContext) -> u32
+{
+    let mem_ref = write_get_memory_ref(ir, context);
+    let (cell, align) = (&context.cell, context.cell_width.bytes());
+    let table = procedure_table(context);
+    write!(ir, "  %{} = load {}, {}* %{}, align {}\n", context.regc, cell, cell, mem_ref, align).unwrap();
+    write!(ir, "  %{} = zext {} %{} to i64\n", context.regc + 1, cell, context.regc).unwrap();
+    write!(ir, "  %{} = getelementptr inbounds {}, {}* @procedures, i64 0, i64 %{}\n",
+           context.regc + 2, table, table, context.regc + 1).unwrap();
+    context.regc += 3;
+    return context.regc - 1;
+}
+
+/* Generates `body` into a function of its own, and defines it as the procedure numbered by the current cell */
+fn write_proc(ir : &mut String, context : &mut CodeGenContext, body : &[Stmt], offset : usize)
+{
+    let proc_num = context.procc;
+    context.procc += 1;
+    write_function(body, context, &format!("proc{}", proc_num), offset, true);
+    let slot = write_get_procedure_ref(ir, context);
+    write!(ir, "  store void()* @proc{}, void()** %{}, align 8\n\n", proc_num, slot).unwrap();
+}
+
+/* Calls the procedure numbered by the current cell, failing if there is none */
+fn write_call(ir : &mut String, context : &mut CodeGenContext)
+{
+    let call_num = context.callc;
+    context.callc += 1;
+    let slot = write_get_procedure_ref(ir, context);
+    let number = slot - 2;
+    write!(ir, "  %{} = load void()*, void()** %{}, align 8\n", context.regc, slot).unwrap();
+    write!(ir, "  %{} = icmp eq void()* %{}, null\n", context.regc + 1, context.regc).unwrap();
+    write!(ir, "  br i1 %{}, label %undefined{}, label %defined{}\n", context.regc + 1, call_num, call_num).unwrap();
+    write!(ir, "undefined{}:\n", call_num).unwrap();
+    write!(ir, "  %{} = zext {} %{} to i32\n", context.regc + 2, context.cell, number).unwrap();
+    write!(ir, "  call void @undefined_procedure(i32 {}, i32 %{})\n", context.offset, context.regc + 2).unwrap();
+    write!(ir, "  unreachable\n").unwrap();
+    write!(ir, "defined{}:\n", call_num).unwrap();
+    write!(ir, "  call void %{}()\n\n", context.regc).unwrap();
+    context.regc += 3;
+}
+
+/* Writes `src` as a comment on what follows, shortened to a line, if comments are on */
+fn write_comment(ir : &mut String, context : &CodeGenContext, src : &str)
+{
+    if context.loop_path.is_none() {
+        return;
+    }
+    if src.len() > COMMENT_WIDTH {
+        write!(ir, "  ; src: {}...\n", &src[..COMMENT_WIDTH - 3]).unwrap();
+    } else {
+        write!(ir, "  ; src: {}\n", src).unwrap();
+    }
+}
+
 fn write_code(ir : &mut String, code : &[Stmt], context : &mut CodeGenContext)
 {
-    for stmt in code {
+    for (i, stmt) in code.iter().enumerate() {
+        if context.loop_path.is_some() {
+            write_comment(ir, context, &to_brainfuck(&code[i..i + 1]));
+        }
+        let start = ir.len();
+        context.offset = stmt.span().start;
         match stmt {
-            Stmt::Move(n)     => write_move(ir, context, *n),
-            Stmt::Add(n)      => write_add(ir, context, *n),
-            Stmt::Input       => write_getc(ir, context),
-            Stmt::Output      => write_putc(ir, context),
-            Stmt::Loop(loop_code) => {
+            Stmt::Move(n, _)      => write_move(ir, context, *n),
+            Stmt::Add(n, _)       => write_add(ir, context, *n),
+            Stmt::Input(_)        => write_getc(ir, context),
+            Stmt::Output(_)       => write_putc(ir, context),
+            Stmt::Set(n, _)       => write_set(ir, context, *n),
+            Stmt::MulAdd(o, f, _) => write_mul_add(ir, context, *o, *f),
+            Stmt::AddAt(o, n, _)  => write_add_at(ir, context, *o, *n),
+            Stmt::Scan(n, _)      => write_scan(ir, context, *n),
+            Stmt::Loop(loop_code, span) => {
                 let loop_num = write_loop_begin(ir, context);
-                write_code(ir, loop_code, context);
-                write_loop_end(ir, loop_num);
+                write_location(ir, context, start, span.start);
+                write_block(ir, loop_code, context);
+                write_comment(ir, context, "]");
+                let end = ir.len();
+                write_loop_end(ir, context, &loop_num);
+                /* the jump back belongs to the `]` */
+                write_location(ir, context, end, span.end - 1);
+                continue;
+            },
+            Stmt::Proc(body, span) => write_proc(ir, context, body, span.start),
+            Stmt::Call(_)         => write_call(ir, context),
+            /* only the interpreter runs them, see `uses_forks()` */
+            Stmt::Fork(_)         => panic!("LLVM IR generation has no Brainfork threads"),
+            Stmt::Random(_)       => write_random_byte(ir, context),
+            Stmt::Debug(_)        => write!(ir, "  call void @breakpoint(i32 {})\n\n", context.offset).unwrap()
+        }
+        write_location(ir, context, start, stmt.span().start);
+    }
+}
+
+/* Number of statements in `code`, counting those in loops but not in procedures, which are functions anyway */
+fn code_size(code : &[Stmt]) -> usize
+{
+    return code.iter().map(|stmt| match stmt {
+        Stmt::Loop(body, _) => 1 + code_size(body),
+        _                   => 1
+    }).sum();
+}
+
+/*
+ * Writes `code` into the function being generated if it is small enough,
+ * and otherwise splits it into runs of statements that are, each outlined
+ * into a function of its own. A loop too big by itself stays, and its body
+ * is split in turn.
+ */
+fn write_block(ir : &mut String, code : &[Stmt], context : &mut CodeGenContext)
+{
+    /* a library can't exit from an outlined function when the pointer moves off the tape */
+    if code_size(code) <= FUNCTION_SIZE || context.library {
+        write_code(ir, code, context);
+        return;
+    }
+    let mut rest = code;
+    while !rest.is_empty() {
+        let mut size = code_size(&rest[..1]);
+        let mut n = 1;
+        while n < rest.len() && size <= FUNCTION_SIZE {
+            let next = code_size(&rest[n..n + 1]);
+            if size + next > FUNCTION_SIZE {
+                break;
             }
+            size += next;
+            n += 1;
+        }
+        let (run, tail) = rest.split_at(n);
+        if size > FUNCTION_SIZE {
+            write_code(ir, run, context);
+        } else {
+            write_chunk(ir, run, context);
         }
+        rest = tail;
+    }
+}
+
+/*
+ * Outlines `code` into a function of its own and calls it. LLVM may not
+ * inline it back.
+ */
+fn write_chunk(ir : &mut String, code : &[Stmt], context : &mut CodeGenContext)
+{
+    let chunk_num = context.chunkc;
+    context.chunkc += 1;
+    let start = ir.len();
+    write!(ir, "  call void @chunk{}()\n\n", chunk_num).unwrap();
+    write_location(ir, context, start, code[0].span().start);
+    write_function(code, context, &format!("chunk{}", chunk_num), code[0].span().start, false);
+}
+
+/*
+ * Generates `code` into `void @name()`, which starts at `offset` in the
+ * source, as one function or, if it may be `split`, as many as
+ * `write_block()` makes of it. Its registers are counted from zero, and it
+ * has its own exit for when the pointer goes off the tape.
+ */
+fn write_function(code : &[Stmt], context : &mut CodeGenContext, name : &str, offset : usize, split : bool)
+{
+    let regc = context.regc;
+    context.regc = 0;
+    let mut dbg = String::new();
+    let mut scope = 0;
+    if let Some(ref mut debug) = context.debug {
+        let (line, _) = debug.lines.locate(offset);
+        scope = debug.scope;
+        debug.scope = FIRST_LOCATION + debug.nodes.len();
+        debug.nodes.push(format!("distinct !DISubprogram(name: \"{}\", scope: !1, file: !1, line: {}, type: !3, scopeLine: {}, spFlags: DISPFlagLocalToUnit | DISPFlagDefinition, unit: !0)",
+                                 name, line, line));
+        dbg = format!(" !dbg !{}", debug.scope);
+    }
+    let attributes = if context.syscalls.is_some() { " #0" } else { "" };
+    let mut function = String::new();
+    write!(function, "define internal void @{}() noinline{}{} {{\n", name, attributes, dbg).unwrap();
+    write!(function, "entry:\n").unwrap();
+    if context.syscalls.is_some() {
+        write!(function, "  %input_byte = alloca i8, align 1\n\n").unwrap();
     }
+    if split {
+        write_block(&mut function, code, context);
+    } else {
+        write_code(&mut function, code, context);
+    }
+    write!(function, "  ret void\n").unwrap();
+    if context.bounds == Bounds::Error && !context.safe {
+        write!(function, "out_of_bounds:\n").unwrap();
+        write_exit_failure(&mut function, context);
+    }
+    write!(function, "}}\n\n").unwrap();
+    context.functions.push_str(&function);
+
+    context.regc = regc;
+    if let Some(ref mut debug) = context.debug {
+        debug.scope = scope;
+    }
+}
+
+/// Generates a textual LLVM IR module whose `main` runs `code` on a tape of
+/// `config.tape_size` cells of `config.cell_width`, which is reallocated as
+/// it grows if the tape is dynamic. Parts of big programs are outlined into
+/// functions of their own, which LLVM optimizes much faster than one huge
+/// `main`.
+pub fn code_gen(code : &[Stmt], config : &Config) -> String
+{
+    return code_gen_for(code, config, &BuildOptions::default(), None);
 }
 
-pub fn code_gen(code : &[Stmt]) -> String
+/// Like `code_gen()`, for the target in `build` instead of the host if it
+/// has one, and as a freestanding program if it says so, which must be
+/// supported there (see `supports_freestanding()`) and can only use a fixed
+/// tape and bytes for I/O. With a `debug_source`, adds DWARF debug info
+/// mapping the instructions back to the lines and columns in it that `code`
+/// was parsed from, so debuggers and profilers show where in the program
+/// they are.
+pub fn code_gen_for(code : &[Stmt], config : &Config, build : &BuildOptions, debug_source : Option<&DebugSource>) -> String
+{
+    return generate(code, config, build, debug_source, false);
+}
+
+/// Like `code_gen_for()`, but for a shared library exporting
+/// `int bf_run(const char *input, size_t len, char **output, size_t *out_len)`
+/// instead of `main`, which runs `code` on a blank tape with `input` and
+/// returns the output in a buffer from `malloc()` which the caller frees,
+/// and 0, or 1 if the pointer moved off the tape. Only bytes for I/O, a
+/// fixed tape, and libc, and calls must not overlap.
+pub fn code_gen_library(code : &[Stmt], config : &Config, build : &BuildOptions, debug_source : Option<&DebugSource>) -> String
+{
+    return generate(code, config, build, debug_source, true);
+}
+
+fn generate(code : &[Stmt], config : &Config, build : &BuildOptions, debug_source : Option<&DebugSource>, library : bool) -> String
 {
     let mut ir : String = String::new();
+    let triple = build.target.as_deref();
+
+    let debug = debug_source.map(|source| DebugInfo {
+        source,
+        lines:     LineIndex::new(source.src),
+        nodes:     Vec::new(),
+        ids:       HashMap::new(),
+        scope:     2
+    });
 
     let mut context = CodeGenContext {
-        regc:  0,
-        loopc: 0,
+        regc:       0,
+        loopc:      0,
+        loop_path:  if build.ir_comments { Some(vec![0]) } else { None },
+        growc:      0,
+        checkc:     0,
+        chunkc:     0,
+        procc:      0,
+        callc:      0,
+        procedures: uses_procedures(code),
+        random:     uses_random(code),
+        seed:       config.seed,
+        breakpoints: uses_breakpoints(code),
+        functions:  String::new(),
+        tape_size:  config.tape_size,
+        tape:       config.tape,
+        bounds:     match (config.tape, config.bounds) {
+            (Tape::Dynamic, _)                             => Bounds::Unchecked,
+            (Tape::Fixed, Bounds::Unchecked) if build.safe => Bounds::Error,
+            (Tape::Fixed, bounds)                          => bounds
+        },
+        safe:       build.safe && config.tape == Tape::Fixed,
+        offset:     0,
+        cell_width: config.cell_width,
+        cell:       format!("i{}", config.cell_width.bits()),
+        io:         config.io,
+        eof:        config.eof,
+        shared_input: build.opt_level == OptLevel::Oz,
+        /* the output is only copied out of the buffer when it is full, or at the end */
+        flush:      if library { Flush::Full } else { config.flush },
+        size:       triple.map_or("i64", size_type),
+        syscalls:   if build.freestanding { Syscalls::for_arch(build.arch()) } else { None },
+        library,
+        debug
     };
 
-    write_header(&mut ir);
-    write_code(&mut ir, code, &mut context);
-    write_footer(&mut ir);
+    write_header(&mut ir, &context, triple);
+    write_block(&mut ir, code, &mut context);
+    write_footer(&mut ir, &mut context);
 
     //println!("{}", ir);
     return ir;
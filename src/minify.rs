@@ -0,0 +1,84 @@
+/*
+ * Minification: the shortest source this can find for a program that behaves
+ * the same. Comments go, and so does whatever cancels out, like `+-`, `<>` or
+ * the `<` and `>` left around `+-` once it is gone, and loops that can never
+ * run: those before the program first changes a cell, when the whole tape is
+ * still zero, and those right after another loop, which only ends on a zero.
+ */
+
+use config::Tape;
+use optimizer;
+use Config;
+use Lang;
+use ParseOptions;
+use to_brainfuck;
+use Stmt;
+
+/// The shortest equivalent source for `code`, printed either as it is or as
+/// the optimizer rewrote it, whichever minifies smaller. Like the optimizer,
+/// it doesn't keep moves off the tape and back that touch nothing there, so a
+/// program that fails only because of one doesn't fail minified.
+pub fn minify(code : Vec<Stmt>) -> String
+{
+    let plain = shrink(&to_brainfuck(&code));
+    /* on a dynamic tape no two offsets are one cell, and what is printed runs the same on any tape */
+    let dynamic = Config { tape: Tape::Dynamic, ..Config::default() };
+    let optimized = shrink(&to_brainfuck(&optimizer::optimize(code, &dynamic)));
+    if optimized.len() < plain.len() {
+        return optimized;
+    }
+    return plain;
+}
+
+/* Parses printed source again and prints it without what it doesn't need */
+fn shrink(src : &str) -> String
+{
+    let options = ParseOptions { procedures: true, random: true, lang: Lang::Brainfork, ..ParseOptions::default() };
+    let code = ::parse_with(src.as_bytes(), &options).expect("printed source doesn't parse");
+    let code = drop_leading_loops(code);
+    let code = optimizer::rewrite_blocks(code, &mut cancel);
+    return to_brainfuck(&code);
+}
+
+/* Drops the loops before the first statement that can change a cell */
+fn drop_leading_loops(code : Vec<Stmt>) -> Vec<Stmt>
+{
+    let mut zero = true;
+    return code.into_iter().filter(|stmt| {
+        match *stmt {
+            Stmt::Loop(..) if zero          => false,
+            Stmt::Move(..) | Stmt::Output(_) => true,
+            _                               => {
+                zero = false;
+                true
+            }
+        }
+    }).collect();
+}
+
+/*
+ * Folds what is left of runs of moves or adds once what was between them
+ * cancelled out, dropping the runs that add up to nothing and loops right
+ * after another loop.
+ */
+fn cancel(block : Vec<Stmt>) -> Vec<Stmt>
+{
+    let mut code : Vec<Stmt> = Vec::with_capacity(block.len());
+    for stmt in block {
+        let folded = match (code.last(), &stmt) {
+            (_, &Stmt::Move(0, _)) | (_, &Stmt::Add(0, _))      => continue,
+            (Some(&Stmt::Loop(..)), &Stmt::Loop(..))            => continue,
+            (Some(&Stmt::Move(a, x)), &Stmt::Move(b, y))        => Stmt::Move(a + b, x.to(y)),
+            (Some(&Stmt::Add(a, x)), &Stmt::Add(b, y))          => Stmt::Add(a + b, x.to(y)),
+            _                                                   => {
+                code.push(stmt);
+                continue;
+            }
+        };
+        code.pop();
+        if !matches!(folded, Stmt::Move(0, _) | Stmt::Add(0, _)) {
+            code.push(folded);
+        }
+    }
+    return code;
+}
@@ -0,0 +1,242 @@
+/*
+ * Emits a relocatable x86-64 ELF object defining `main`, so an executable
+ * can be produced with nothing but a linker. The program body is generated
+ * by the `x86_64` module; this module only wraps it in a `main` and writes
+ * out the sections, symbols and relocations.
+ */
+
+use Config;
+use config::Io;
+use Stmt;
+use bytecode;
+use x86_64;
+use x86_64::IoCalls;
+
+const SHT_PROGBITS : u32 = 1;
+const SHT_SYMTAB   : u32 = 2;
+const SHT_STRTAB   : u32 = 3;
+const SHT_RELA     : u32 = 4;
+const SHT_NOBITS   : u32 = 8;
+
+const SHF_WRITE     : u64 = 0x1;
+const SHF_ALLOC     : u64 = 0x2;
+const SHF_EXECINSTR : u64 = 0x4;
+const SHF_INFO_LINK : u64 = 0x40;
+
+const STB_LOCAL  : u8 = 0;
+const STB_GLOBAL : u8 = 1;
+const STT_NOTYPE : u8 = 0;
+const STT_OBJECT : u8 = 1;
+const STT_FUNC   : u8 = 2;
+
+const R_X86_64_PC32  : u64 = 2;
+const R_X86_64_PLT32 : u64 = 4;
+
+/* section indices, in the order their headers are written */
+const SECTION_TEXT     : u16 = 1;
+const SECTION_BSS      : u16 = 2;
+const SECTION_SYMTAB   : u16 = 4;
+const SECTION_STRTAB   : u16 = 5;
+const SECTION_SHSTRTAB : u16 = 7;
+const SECTION_COUNT    : u16 = 8;
+
+/* symbol indices (bf_run, memory, main, input, output); locals have to come first */
+const SYM_MEMORY       : u64 = 2;
+const SYM_INPUT        : u64 = 4;
+const SYM_OUTPUT       : u64 = 5;
+const FIRST_GLOBAL_SYM : u32 = 3;
+
+struct Section {
+    name    : &'static str,
+    kind    : u32,
+    flags   : u64,
+    data    : Vec<u8>,
+    size    : u64, // differs from data.len() for NOBITS sections
+    link    : u32,
+    info    : u32,
+    align   : u64,
+    entsize : u64
+}
+
+/* string table builder, returns the offset of each added string */
+fn add_string(table : &mut Vec<u8>, s : &str) -> u32
+{
+    let offset = table.len() as u32;
+    table.extend_from_slice(s.as_bytes());
+    table.push(0);
+    return offset;
+}
+
+fn write_symbol(symtab : &mut Vec<u8>, name : u32, bind : u8, kind : u8, shndx : u16, value : u64, size : u64)
+{
+    symtab.extend_from_slice(&name.to_le_bytes());
+    symtab.push((bind << 4) | kind);
+    symtab.push(0);
+    symtab.extend_from_slice(&shndx.to_le_bytes());
+    symtab.extend_from_slice(&value.to_le_bytes());
+    symtab.extend_from_slice(&size.to_le_bytes());
+}
+
+fn write_rela(rela : &mut Vec<u8>, offset : usize, sym : u64, kind : u64, addend : i64)
+{
+    rela.extend_from_slice(&(offset as u64).to_le_bytes());
+    rela.extend_from_slice(&((sym << 32) | kind).to_le_bytes());
+    rela.extend_from_slice(&addend.to_le_bytes());
+}
+
+/*
+ * Appends `main` to the text, which calls the program body with the tape and
+ * a scratch context on the stack and returns its status as the exit code.
+ * Returns the offset of `main` and of the rel32 addressing the tape.
+ */
+fn write_main(text : &mut Vec<u8>) -> (usize, usize)
+{
+    while !text.len().is_multiple_of(16) {
+        text.push(0xCC); // int3 padding
+    }
+    let main = text.len();
+    text.extend_from_slice(&[0x48, 0x83, 0xEC, 0x18]); // sub rsp, 24
+    text.extend_from_slice(&[0x48, 0x8D, 0x3D]);       // lea rdi, [rip + memory]
+    let memory_ref = text.len();
+    text.extend_from_slice(&[0, 0, 0, 0]);
+    text.extend_from_slice(&[0x48, 0x89, 0xE6]);       // mov rsi, rsp
+    text.push(0xE8);                                   // call bf_run
+    let rel = -((text.len() + 4) as i64) as i32;
+    text.extend_from_slice(&rel.to_le_bytes());
+    text.extend_from_slice(&[0x48, 0x83, 0xC4, 0x18]); // add rsp, 24
+    text.push(0xC3);                                   // ret
+    return (main, memory_ref);
+}
+
+/// Generates a relocatable x86-64 ELF object file whose `main` runs `code`,
+/// calling libc `getchar`/`putchar` for I/O, or `scanf`/`printf` for
+/// numeric I/O. The result only needs to be
+/// linked (e.g. with `gcc prog.o -o prog`). The tape has `config.tape_size`
+/// cells of `config.cell_width`; moving the pointer off it exits with status
+/// 1 unless `config` says to wrap or not to check.
+pub fn code_gen(code : &[Stmt], config : &Config) -> Vec<u8>
+{
+    let tape_bytes = (config.tape_size * config.cell_width.bytes()) as u64;
+    let assembly = x86_64::assemble(&bytecode::lower(code), 0, config, &IoCalls::Extern);
+    let mut text = assembly.code;
+    let bf_run_size = text.len() as u64;
+    let (main, memory_ref) = write_main(&mut text);
+
+    /* symbols */
+    let mut strtab : Vec<u8> = vec![0];
+    let mut symtab : Vec<u8> = vec![0; 24];
+    let name = add_string(&mut strtab, "bf_run");
+    write_symbol(&mut symtab, name, STB_LOCAL, STT_FUNC, SECTION_TEXT, 0, bf_run_size);
+    let name = add_string(&mut strtab, "memory");
+    write_symbol(&mut symtab, name, STB_LOCAL, STT_OBJECT, SECTION_BSS, 0, tape_bytes);
+    let name = add_string(&mut strtab, "main");
+    write_symbol(&mut symtab, name, STB_GLOBAL, STT_FUNC, SECTION_TEXT, main as u64, (text.len() - main) as u64);
+    let (input, output) = match config.io {
+        Io::Bytes   => ("getchar", "putchar"),
+        Io::Numeric => ("scanf", "printf")
+    };
+    let name = add_string(&mut strtab, input);
+    write_symbol(&mut symtab, name, STB_GLOBAL, STT_NOTYPE, 0, 0, 0);
+    let name = add_string(&mut strtab, output);
+    write_symbol(&mut symtab, name, STB_GLOBAL, STT_NOTYPE, 0, 0, 0);
+
+    /* relocations */
+    let mut rela : Vec<u8> = Vec::new();
+    write_rela(&mut rela, memory_ref, SYM_MEMORY, R_X86_64_PC32, -4);
+    for pos in assembly.input_calls {
+        write_rela(&mut rela, pos, SYM_INPUT, R_X86_64_PLT32, -4);
+    }
+    for pos in assembly.output_calls {
+        write_rela(&mut rela, pos, SYM_OUTPUT, R_X86_64_PLT32, -4);
+    }
+
+    let text_size = text.len() as u64;
+    let symtab_size = symtab.len() as u64;
+    let strtab_size = strtab.len() as u64;
+    let rela_size = rela.len() as u64;
+    let sections = vec![
+        Section { name: ".text", kind: SHT_PROGBITS, flags: SHF_ALLOC | SHF_EXECINSTR, data: text, size: text_size,
+                  link: 0, info: 0, align: 16, entsize: 0 },
+        Section { name: ".bss", kind: SHT_NOBITS, flags: SHF_ALLOC | SHF_WRITE, data: Vec::new(), size: tape_bytes,
+                  link: 0, info: 0, align: 16, entsize: 0 },
+        Section { name: ".note.GNU-stack", kind: SHT_PROGBITS, flags: 0, data: Vec::new(), size: 0,
+                  link: 0, info: 0, align: 1, entsize: 0 },
+        Section { name: ".symtab", kind: SHT_SYMTAB, flags: 0, data: symtab, size: symtab_size,
+                  link: SECTION_STRTAB as u32, info: FIRST_GLOBAL_SYM, align: 8, entsize: 24 },
+        Section { name: ".strtab", kind: SHT_STRTAB, flags: 0, data: strtab, size: strtab_size,
+                  link: 0, info: 0, align: 1, entsize: 0 },
+        Section { name: ".rela.text", kind: SHT_RELA, flags: SHF_INFO_LINK, data: rela, size: rela_size,
+                  link: SECTION_SYMTAB as u32, info: SECTION_TEXT as u32, align: 8, entsize: 24 }
+    ];
+    return write_elf(sections);
+}
+
+fn align_to(out : &mut Vec<u8>, align : u64)
+{
+    while !(out.len() as u64).is_multiple_of(align.max(1)) {
+        out.push(0);
+    }
+}
+
+/* lays out the ELF header, section contents and section header table */
+fn write_elf(sections : Vec<Section>) -> Vec<u8>
+{
+    let mut shstrtab : Vec<u8> = vec![0];
+    let names : Vec<u32> = sections.iter().map(|s| add_string(&mut shstrtab, s.name)).collect();
+    let shstrtab_name = add_string(&mut shstrtab, ".shstrtab");
+
+    let mut out : Vec<u8> = vec![0; 64];
+    let mut offsets : Vec<u64> = Vec::new();
+    for section in &sections {
+        align_to(&mut out, section.align);
+        offsets.push(out.len() as u64);
+        out.extend_from_slice(&section.data);
+    }
+    let shstrtab_offset = out.len() as u64;
+    out.extend_from_slice(&shstrtab);
+    align_to(&mut out, 8);
+    let shoff = out.len() as u64;
+
+    /* section headers, starting with the null section */
+    out.extend_from_slice(&[0; 64]);
+    for (i, section) in sections.iter().enumerate() {
+        write_section_header(&mut out, names[i], section.kind, section.flags, offsets[i], section.size,
+                             section.link, section.info, section.align, section.entsize);
+    }
+    write_section_header(&mut out, shstrtab_name, SHT_STRTAB, 0, shstrtab_offset, shstrtab.len() as u64, 0, 0, 1, 0);
+
+    /* ELF header */
+    let mut header : Vec<u8> = vec![0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    header.extend_from_slice(&1u16.to_le_bytes());   // e_type: relocatable
+    header.extend_from_slice(&62u16.to_le_bytes());  // e_machine: x86-64
+    header.extend_from_slice(&1u32.to_le_bytes());   // e_version
+    header.extend_from_slice(&0u64.to_le_bytes());   // e_entry
+    header.extend_from_slice(&0u64.to_le_bytes());   // e_phoff
+    header.extend_from_slice(&shoff.to_le_bytes());  // e_shoff
+    header.extend_from_slice(&0u32.to_le_bytes());   // e_flags
+    header.extend_from_slice(&64u16.to_le_bytes());  // e_ehsize
+    header.extend_from_slice(&0u16.to_le_bytes());   // e_phentsize
+    header.extend_from_slice(&0u16.to_le_bytes());   // e_phnum
+    header.extend_from_slice(&64u16.to_le_bytes());  // e_shentsize
+    header.extend_from_slice(&SECTION_COUNT.to_le_bytes());
+    header.extend_from_slice(&SECTION_SHSTRTAB.to_le_bytes());
+    out[..64].copy_from_slice(&header);
+
+    return out;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_section_header(out : &mut Vec<u8>, name : u32, kind : u32, flags : u64, offset : u64, size : u64,
+                        link : u32, info : u32, align : u64, entsize : u64)
+{
+    out.extend_from_slice(&name.to_le_bytes());
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(&flags.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes()); // sh_addr
+    out.extend_from_slice(&offset.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&link.to_le_bytes());
+    out.extend_from_slice(&info.to_le_bytes());
+    out.extend_from_slice(&align.to_le_bytes());
+    out.extend_from_slice(&entsize.to_le_bytes());
+}
@@ -0,0 +1,62 @@
+/*
+ * Ook!, brainfuck for orang-utans: the same eight commands, each spelled as
+ * a pair of the words `Ook.`, `Ook?` and `Ook!`. Anything between the words
+ * is a comment, like anything that isn't a command is in brainfuck.
+ */
+
+use Diagnostic;
+use RustfuckError;
+use Span;
+
+/* The command each pair of words spells, by the punctuation of the pair */
+const PAIRS : &[(u8, u8, u8)] = &[
+    (b'.', b'?', b'>'),
+    (b'?', b'.', b'<'),
+    (b'.', b'.', b'+'),
+    (b'!', b'!', b'-'),
+    (b'!', b'.', b'.'),
+    (b'.', b'!', b','),
+    (b'!', b'?', b'['),
+    (b'?', b'!', b']')
+];
+
+/// The brainfuck commands that Ook! source `src` spells, each with the span
+/// of the pair of words spelling it. Fails at a pair that spells nothing,
+/// `Ook? Ook?`, or at a word left without a pair at the end.
+pub fn commands(src : &[u8]) -> Result<Vec<(u8, Span)>, RustfuckError>
+{
+    /* each word, by its punctuation */
+    let words : Vec<(u8, usize)> = src.windows(4)
+            .enumerate()
+            .filter(|&(_, w)| &w[..3] == b"Ook" && b".?!".contains(&w[3]))
+            .map(|(i, w)| (w[3], i))
+            .collect();
+
+    let mut commands = Vec::with_capacity(words.len() / 2);
+    for pair in words.chunks(2) {
+        let (first, start) = pair[0];
+        let (second, end) = match pair.get(1) {
+            Some(&(second, i)) => (second, i + 4),
+            None               => return Err(error(src, start, &format!("'Ook{}' has no second word", first as char)))
+        };
+        match PAIRS.iter().find(|&&(a, b, _)| a == first && b == second) {
+            Some(&(_, _, command)) => commands.push((command, Span::new(start, end))),
+            None                   => return Err(error(src, start, &format!("'Ook{} Ook{}' is not a command", first as char, second as char)))
+        }
+    }
+    return Ok(commands);
+}
+
+/// How Ook! spells the brainfuck `command`, like `Ook! Ook?` for `[`.
+pub fn spelling(command : char) -> String
+{
+    return PAIRS.iter()
+            .find(|&&(_, _, c)| c as char == command)
+            .map_or_else(String::new, |&(a, b, _)| format!("Ook{} Ook{}", a as char, b as char));
+}
+
+/* A parse error at `offset` */
+fn error(src : &[u8], offset : usize, message : &str) -> RustfuckError
+{
+    return RustfuckError::ParseError(Diagnostic::new(src, offset, message));
+}
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use analysis;
+use analysis::Layout;
+use analysis::LoopFacts;
+use analysis::LoopSummary;
+use Config;
+use Span;
+use Stmt;
+use uses_forks;
+
+/* Most statements a loop may be unrolled into */
+const MAX_UNROLLED : usize = 256;
+
+/// Runs the optimization passes that hold on any tape configured as
+/// `config`, whatever it holds, over a parsed program. Every backend, and
+/// the interpreter, runs the program this returns.
+pub fn optimize(code : Vec<Stmt>, config : &Config) -> Vec<Stmt>
+{
+    return optimize_from(code, Layout::of(config), None);
+}
+
+/// Like `optimize()`, and also evaluates what it can of the program with
+/// the cells it knows the values of, for a program that starts on a tape of
+/// zeroes as configured by `config`, which it only runs right on.
+pub fn optimize_for(code : Vec<Stmt>, config : &Config) -> Vec<Stmt>
+{
+    return optimize_from(code, Layout::of(config), Some(true));
+}
+
+/// Like `optimize_for()`, for code that runs on a tape something else ran on
+/// before, like a REPL snippet, so it can't count on the cells being zero.
+pub fn optimize_snippet(code : Vec<Stmt>, config : &Config) -> Vec<Stmt>
+{
+    return optimize_from(code, Layout::of(config), Some(false));
+}
+
+/* Runs the passes over code for a tape laid out as given, folding constants if `fold` says whether it starts zeroed */
+fn optimize_from(code : Vec<Stmt>, layout : Layout, fold : Option<bool>) -> Vec<Stmt>
+{
+    let code = rewrite_blocks(code, &mut clear_loops);
+    let code = rewrite_blocks(code, &mut |block| multiply_loops(block, layout));
+    let code = rewrite_blocks(code, &mut |block| scan_loops(block, layout));
+    /* other threads change the cells whenever they like, so nothing is known about them */
+    let code = match fold {
+        Some(zeroed) if !uses_forks(&code) => {
+            /* the summaries of the blocks folded whose block hasn't been yet, and of the loops in the last one */
+            let mut done : Vec<LoopSummary> = Vec::new();
+            let mut outermost = Vec::new();
+            /* only the outermost block starts on known cells, once every block has been folded without them */
+            let code = rewrite_blocks(code, &mut |block| {
+                let inner = loop_summaries(&block, &mut done);
+                let (block, kept) = fold_constants(block, layout, false, inner);
+                done.push(analysis::summarize(&block, layout, &kept));
+                outermost = kept;
+                block
+            });
+            fold_constants(code, layout, zeroed, outermost).0
+        },
+        _ => code
+    };
+    let code = rewrite_blocks(code, &mut fuse_offsets);
+    return code;
+}
+
+/// Applies `f` to every block of the program, innermost blocks first, so `f`
+/// always sees loop and procedure bodies that have already been rewritten.
+/// Uses an explicit stack so deeply nested programs don't overflow.
+pub fn rewrite_blocks(code : Vec<Stmt>, f : &mut dyn FnMut(Vec<Stmt>) -> Vec<Stmt>) -> Vec<Stmt>
+{
+    /* each frame is the statements left to visit, the rewritten ones so far, and the span and kind of its block */
+    let mut frames : Vec<(std::vec::IntoIter<Stmt>, Vec<Stmt>, Span, fn(Vec<Stmt>, Span) -> Stmt)> =
+            vec![(code.into_iter(), Vec::new(), Span::default(), Stmt::Loop)];
+    loop {
+        let next = frames.last_mut().unwrap().0.next();
+        match next {
+            Some(mut stmt) => {
+                match &mut stmt {
+                    Stmt::Loop(body, span) => frames.push((std::mem::take(body).into_iter(), Vec::new(), *span, Stmt::Loop)),
+                    Stmt::Proc(body, span) => frames.push((std::mem::take(body).into_iter(), Vec::new(), *span, Stmt::Proc)),
+                    _                      => frames.last_mut().unwrap().1.push(stmt)
+                }
+            },
+            None => {
+                let (_, done, span, block_stmt) = frames.pop().unwrap();
+                let block = f(done);
+                match frames.last_mut() {
+                    Some((_, outer, _, _)) => outer.push(block_stmt(block, span)),
+                    None                   => return block
+                }
+            }
+        }
+    }
+}
+
+/* Rewrites `[-]` and `[+]` (or any odd step) into `Set(0)` */
+fn clear_loops(block : Vec<Stmt>) -> Vec<Stmt>
+{
+    return block.into_iter().map(|stmt| {
+        match &stmt {
+            Stmt::Loop(body, span) if is_clear_loop(body) => Stmt::Set(0, *span),
+            _                                         => stmt
+        }
+    }).collect();
+}
+
+fn is_clear_loop(body : &[Stmt]) -> bool
+{
+    return matches!(body, [Stmt::Add(n, _)] if n % 2 != 0);
+}
+
+/*
+ * Rewrites loops that only move the pointer, e.g. `[>]` or `[<<]`, into
+ * `Scan`, unless the move comes back round to the same cell on a wrapping
+ * tape
+ */
+fn scan_loops(block : Vec<Stmt>, layout : Layout) -> Vec<Stmt>
+{
+    return block.into_iter().map(|stmt| {
+        match &stmt {
+            Stmt::Loop(body, span) => match body.as_slice() {
+                [Stmt::Move(n, _)] if layout.key(*n) != 0 => Stmt::Scan(*n, *span),
+                _                                         => stmt
+            },
+            _ => stmt
+        }
+    }).collect();
+}
+
+/*
+ * Rewrites balanced loops that decrement the current cell by one and only add
+ * to other cells, e.g. `[->+++>+<<]`, into one `MulAdd` per touched cell,
+ * spanning its adds, followed by `Set(0)` spanning the loop.
+ */
+fn multiply_loops(block : Vec<Stmt>, layout : Layout) -> Vec<Stmt>
+{
+    let mut code : Vec<Stmt> = Vec::with_capacity(block.len());
+    for stmt in block {
+        let maybe_deltas = match &stmt {
+            Stmt::Loop(body, span) => multiply_loop_deltas(body, layout).map(|deltas| (deltas, *span)),
+            _                      => None
+        };
+        match maybe_deltas {
+            Some((deltas, span)) => {
+                for (offset, factor, add_span) in deltas {
+                    code.push(Stmt::MulAdd(offset, factor, add_span));
+                }
+                code.push(Stmt::Set(0, span));
+            },
+            None => code.push(stmt)
+        }
+    }
+    return code;
+}
+
+/*
+ * Returns the (offset, factor, span of the adds) of each cell a multiply loop
+ * body changes in order of first appearance, or None if the body isn't a
+ * multiply loop. Offsets that are the same cell on a tape laid out as
+ * `layout` are one cell, at the first of them.
+ */
+fn multiply_loop_deltas(body : &[Stmt], layout : Layout) -> Option<Vec<(i32, i32, Span)>>
+{
+    let mut offset : i32 = 0;
+    let mut deltas : Vec<(i32, i32, Span)> = Vec::new();
+    for stmt in body {
+        match stmt {
+            Stmt::Move(n, _)    => offset = offset.checked_add(*n)?,
+            Stmt::Add(n, span)  => {
+                match deltas.iter_mut().find(|(o, _, _)| layout.key(*o) == layout.key(offset)) {
+                    Some((_, factor, spans)) => {
+                        *factor += n;
+                        *spans = spans.to(*span);
+                    },
+                    None => deltas.push((offset, *n, *span))
+                }
+            },
+            _ => return None
+        }
+    }
+
+    /* the loop must end where it started and count the current cell down by one */
+    let counter = deltas.iter().position(|&(o, _, _)| layout.key(o) == 0)?;
+    if offset != 0 || deltas[counter].1 != -1 {
+        return None;
+    }
+    deltas.remove(counter);
+    deltas.retain(|&(_, factor, _)| factor != 0);
+    return Some(deltas);
+}
+
+/*
+ * The summaries of the loops of `block` out of those of its blocks, the last
+ * ones in `done`, which `rewrite_blocks()` has just rewritten
+ */
+fn loop_summaries(block : &[Stmt], done : &mut Vec<LoopSummary>) -> Vec<LoopSummary>
+{
+    let blocks : Vec<&Stmt> = block.iter().filter(|stmt| matches!(stmt, Stmt::Loop(..) | Stmt::Proc(..))).collect();
+    let summaries = done.split_off(done.len() - blocks.len());
+    return blocks.into_iter().zip(summaries)
+            .filter(|(stmt, _)| matches!(stmt, Stmt::Loop(..)))
+            .map(|(_, summary)| summary)
+            .collect();
+}
+
+/*
+ * Evaluates what it can of the statements with the values of the cells that
+ * are known: all of them at the start of the block if `zeroed`, a cell after
+ * a statement which leaves it zero, like a loop, or sets or adds a constant to
+ * it, and every cell a loop before didn't write to. A loop that counts the
+ * current cell down by a constant to zero runs a known number of times, and
+ * is unrolled, or dropped if that is none; a `MulAdd` adds a constant; a `Set`
+ * overwrites the `Add` or `Set` just before it, and is dropped if it stores
+ * what the cell already holds; an `Add` after a `Set` is folded into it. Only
+ * values from 0 to 255 are trusted, which every cell width holds without
+ * wrapping. What the loops do comes from `inner`, their summaries in order,
+ * and those of the loops that are kept are returned with the statements.
+ */
+fn fold_constants(block : Vec<Stmt>, layout : Layout, zeroed : bool, inner : Vec<LoopSummary>) -> (Vec<Stmt>, Vec<LoopSummary>)
+{
+    let mut code : Vec<Stmt> = Vec::with_capacity(block.len());
+    /* unrolled loops have no loops in them, so every loop left is one of `inner`, in order */
+    let mut inner = inner.into_iter();
+    let mut kept = Vec::new();
+    let mut cells = Cells { values: HashMap::new(), pos: 0, layout, zeroed };
+    /* the statements left, last first, which unrolled loops are copied onto */
+    let mut pending : Vec<Stmt> = block.into_iter().rev().collect();
+    while let Some(stmt) = pending.pop() {
+        let current = cells.get(0);
+        match stmt {
+            Stmt::Loop(ref body, _) => {
+                let summary = inner.next().expect("no summary for a loop");
+                if current == Some(0) {
+                    continue;
+                }
+                if let Some(trips) = current.and_then(|value| trip_count(body, value, &summary.facts)) {
+                    for _ in 0..trips {
+                        pending.extend(body.iter().rev().map(|stmt| copy(stmt).unwrap()));
+                    }
+                    continue;
+                }
+                match summary.writes {
+                    Some(ref written) => written.iter().for_each(|&offset| cells.set(offset, None)),
+                    None              => cells.forget()
+                }
+                cells.set(0, Some(0));
+                kept.push(summary);
+            },
+            Stmt::Scan(_, _) if current == Some(0) => continue,
+            Stmt::Scan(_, _) => {
+                cells.forget();
+                cells.set(0, Some(0));
+            },
+            Stmt::MulAdd(offset, factor, span) => match current.map(|value| value.checked_mul(factor)) {
+                Some(Some(0)) => continue,
+                Some(Some(n)) => {
+                    pending.push(Stmt::AddAt(offset, n, span));
+                    continue;
+                },
+                _             => cells.set(offset, None)
+            },
+            Stmt::AddAt(offset, n, _) => {
+                let value = cells.get(offset).map(|value| value + n);
+                cells.set(offset, value);
+            },
+            Stmt::Add(n, span) => {
+                let value = current.map(|value| value + n).filter(|value| KNOWN.contains(value));
+                if let (Some(value), Some(i)) = (value, last_store(&code)) {
+                    if let Stmt::Set(_, _) = code[i] {
+                        pending.push(Stmt::Set(value, span));
+                        continue;
+                    }
+                }
+                cells.set(0, value);
+            },
+            Stmt::Set(n, span) => {
+                /* what was added to the current cell or stored in it just before is overwritten */
+                let (before, span) = match last_store(&code).map(|i| code.remove(i)) {
+                    Some(Stmt::Add(k, added)) => (current.map(|value| value - k), added.to(span)),
+                    Some(Stmt::Set(_, set))   => (None, set.to(span)),
+                    _                         => (current, span)
+                };
+                if before != Some(n) {
+                    code.push(Stmt::Set(n, span));
+                }
+                cells.set(0, Some(n));
+                continue;
+            },
+            Stmt::Move(n, _)                     => cells.shift(n),
+            Stmt::Input(_) | Stmt::Random(_)     => cells.set(0, None),
+            Stmt::Call(_) | Stmt::Fork(_)        => cells.forget(),
+            Stmt::Output(_) | Stmt::Debug(_) | Stmt::Proc(_, _) => ()
+        }
+        code.push(stmt);
+    }
+    return (code, kept);
+}
+
+/* The values `fold_constants()` trusts */
+const KNOWN : std::ops::RangeInclusive<i32> = 0..=255;
+
+/* What `fold_constants()` knows about the cells, as the statements before have left them */
+struct Cells {
+    /* the known values, and the cells known not to be known, by `key()` */
+    values : HashMap<i32, Option<i32>>,
+    /* how far the pointer has moved since the start of the block */
+    pos    : i32,
+    layout : Layout,
+    /* whether the cells not in `values` are zero, which also makes `pos` the cell the pointer is on */
+    zeroed : bool
+}
+
+impl Cells {
+    /* The value of the cell at `offset` from the pointer, if known */
+    fn get(&self, offset : i32) -> Option<i32>
+    {
+        match self.values.get(&self.key(offset)) {
+            Some(&value) => value,
+            None         => Some(0).filter(|_| self.zeroed)
+        }
+    }
+
+    fn set(&mut self, offset : i32, value : Option<i32>)
+    {
+        let value = value.filter(|value| KNOWN.contains(value));
+        let key = self.key(offset);
+        if value.is_none() && !self.zeroed {
+            self.values.remove(&key);
+            return;
+        }
+        self.values.insert(key, value);
+    }
+
+    /* Moves the pointer by `n`. Past the end of a bounded tape the program stops, so nothing after is known. */
+    fn shift(&mut self, n : i32)
+    {
+        self.pos = self.pos.saturating_add(n);
+        if let Layout::Bounded(size) = self.layout {
+            if self.zeroed && (self.pos < 0 || self.pos >= size) {
+                self.forget();
+            }
+        }
+    }
+
+    /* Where the cell at `offset` from the pointer is in `values` */
+    fn key(&self, offset : i32) -> i32
+    {
+        return self.layout.key(self.pos.saturating_add(offset));
+    }
+
+    fn forget(&mut self)
+    {
+        self.values.clear();
+        self.zeroed = false;
+    }
+}
+
+/* The index in `code` of an `Add` or `Set` of the current cell with only `AddAt`s of other cells after it */
+fn last_store(code : &[Stmt]) -> Option<usize>
+{
+    let i = code.iter().rposition(|stmt| !matches!(stmt, Stmt::AddAt(offset, _, _) if *offset != 0))?;
+    return Some(i).filter(|_| matches!(code[i], Stmt::Add(_, _) | Stmt::Set(_, _)));
+}
+
+/*
+ * How often a loop with `body` runs when the current cell is `value` as it
+ * starts, given the `facts` about it: if the body moves back to where it
+ * started and changes that cell only by adding the same negative constant
+ * each time round, which `value` is a multiple of, and is short and plain
+ * enough to copy that often.
+ */
+fn trip_count(body : &[Stmt], value : i32, facts : &LoopFacts) -> Option<usize>
+{
+    let step = match facts.counter_step {
+        Some(step) if step < 0 && value % step == 0 => step,
+        _                                           => return None
+    };
+    if !body.iter().all(|stmt| copy(stmt).is_some()) {
+        return None;
+    }
+    let trips = (value / -step) as usize;
+    return Some(trips).filter(|trips| trips * body.len() <= MAX_UNROLLED);
+}
+
+/* A copy of a statement in a loop `trip_count()` unrolls, which has no blocks */
+fn copy(stmt : &Stmt) -> Option<Stmt>
+{
+    return Some(match *stmt {
+        Stmt::Move(n, span)         => Stmt::Move(n, span),
+        Stmt::Add(n, span)          => Stmt::Add(n, span),
+        Stmt::AddAt(o, n, span)     => Stmt::AddAt(o, n, span),
+        Stmt::Set(n, span)          => Stmt::Set(n, span),
+        Stmt::MulAdd(o, f, span)    => Stmt::MulAdd(o, f, span),
+        Stmt::Input(span)           => Stmt::Input(span),
+        Stmt::Output(span)          => Stmt::Output(span),
+        Stmt::Random(span)          => Stmt::Random(span),
+        Stmt::Debug(span)           => Stmt::Debug(span),
+        _                           => return None
+    });
+}
+
+/*
+ * Rewrites runs of `Move` and `Add`, e.g. `>+++>-<<`, into `AddAt`s relative
+ * to the pointer at the start of the run, each spanning the adds it replaces,
+ * followed by one net `Move` spanning the moves.
+ */
+fn fuse_offsets(block : Vec<Stmt>) -> Vec<Stmt>
+{
+    let mut code : Vec<Stmt> = Vec::with_capacity(block.len());
+    let mut run = Run { offset: 0, moves: None, adds: Vec::new() };
+    for stmt in block {
+        match stmt {
+            Stmt::Move(n, span) => {
+                /* a net move an i32 can't hold starts a new run */
+                if run.offset.checked_add(n).is_none() {
+                    flush_offsets(&mut code, &mut run);
+                }
+                run.offset += n;
+                run.moves = Some(run.moves.map_or(span, |moves| moves.to(span)));
+            },
+            Stmt::Add(n, span)  => {
+                let offset = run.offset;
+                match run.adds.iter_mut().find(|(o, _, _)| *o == offset) {
+                    Some((_, sum, spans)) => {
+                        *sum += n;
+                        *spans = spans.to(span);
+                    },
+                    None => run.adds.push((offset, n, span))
+                }
+            },
+            _ => {
+                flush_offsets(&mut code, &mut run);
+                code.push(stmt);
+            }
+        }
+    }
+    flush_offsets(&mut code, &mut run);
+    return code;
+}
+
+/* A run of moves and adds being fused: the net move so far, the span of the moves, and the adds */
+struct Run {
+    offset : i32,
+    moves  : Option<Span>,
+    adds   : Vec<(i32, i32, Span)>
+}
+
+fn flush_offsets(code : &mut Vec<Stmt>, run : &mut Run)
+{
+    for (o, n, span) in run.adds.drain(..) {
+        match (o, n) {
+            (_, 0) => {},
+            (0, n) => code.push(Stmt::Add(n, span)),
+            (o, n) => code.push(Stmt::AddAt(o, n, span))
+        }
+    }
+    if let Some(span) = run.moves.take() {
+        if run.offset != 0 {
+            code.push(Stmt::Move(run.offset, span));
+        }
+    }
+    run.offset = 0;
+}
@@ -0,0 +1,65 @@
+/*
+ * Partial evaluation: a program that reads no input always prints the same,
+ * so if it finishes soon enough it can be run while it is being compiled,
+ * and what gets compiled only has to print what it printed.
+ */
+
+use std::io;
+use interpreter;
+use interpreter::ProgramState;
+use config::Io;
+use Config;
+use Span;
+use Stmt;
+use uses_breakpoints;
+use uses_random;
+
+/// Default number of instructions `evaluate()` runs a program for.
+pub const STEPS : u64 = 100_000_000;
+
+/// Runs `code` in the interpreter for up to `steps` instructions, if it
+/// never reads input or random bytes and has no breakpoints, and returns a
+/// program that prints what it printed with the same `config`. Returns
+/// `None` if it reads input, runs longer or fails, so it can be compiled as
+/// it is instead, and fail at runtime.
+pub fn evaluate(code : &[Stmt], config : &Config, steps : u64) -> Option<Vec<Stmt>>
+{
+    if reads_input(code) || uses_random(code) || uses_breakpoints(code) {
+        return None;
+    }
+    let config = Config { max_steps: Some(steps), timeout: None, ..config.clone() };
+    let mut state = ProgramState::with_config(&config);
+    let mut output = Vec::new();
+    if interpreter::execute(code, &mut state, &mut io::empty(), &mut output).is_err() {
+        return None;
+    }
+
+    /* the cells printed, which in numeric mode are one number a line */
+    let cells : Vec<i32> = match config.io {
+        Io::Bytes   => output.iter().map(|&b| b as i32).collect(),
+        Io::Numeric => String::from_utf8_lossy(&output).lines()
+                .map(|line| line.parse::<u32>().map(|n| n as i32))
+                .collect::<Result<_, _>>()
+                .ok()?
+    };
+    let mut printer = Vec::new();
+    let mut current = 0;
+    for (i, &cell) in cells.iter().enumerate() {
+        if i == 0 || cell != current {
+            printer.push(Stmt::Set(cell, Span::default()));
+            current = cell;
+        }
+        printer.push(Stmt::Output(Span::default()));
+    }
+    return Some(printer);
+}
+
+/* Whether there is a `,` anywhere in `code` */
+fn reads_input(code : &[Stmt]) -> bool
+{
+    return code.iter().any(|stmt| match stmt {
+        Stmt::Input(_)                            => true,
+        Stmt::Loop(body, _) | Stmt::Proc(body, _) => reads_input(body),
+        _                                         => false
+    });
+}
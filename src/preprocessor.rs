@@ -0,0 +1,348 @@
+/*
+ * The preprocessor, which runs over source before it is parsed. A line
+ *
+ *     #include "path/to/file.b"
+ *
+ * is replaced by the file it names, relative to the directory of the file
+ * the line is in, with the includes in that file replaced in turn. A file
+ * may be included any number of times, but not from within itself.
+ *
+ * Then, if asked for, macros are expanded, in the source with the includes
+ * in it:
+ *
+ *     @def zero [-] @end     defines @zero, which expands to [-]
+ *     @zero                  expands a macro defined before
+ *     @rep 10 >+             expands the next word, >+, 10 times
+ *     @rep 2 @rep 3 +        expands @rep 3 + twice, so + 6 times
+ *
+ * A macro may use the macros defined before it is used, but not itself.
+ * `@` followed by anything else is a comment, like in brainfuck. Without
+ * macros every `@` is a comment, so plain brainfuck that mentions them in
+ * its comments means the same as ever.
+ *
+ * Diagnostics about the preprocessed source point back into the file that
+ * part of it came from; everything else, like traces and the debugger,
+ * shows the preprocessed source.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use Diagnostic;
+use RustfuckError;
+
+const INCLUDE : &[u8] = b"#include";
+
+/* Longest the source may get with its macros expanded, so a `@rep` can't eat all memory */
+const MAX_EXPANSION : usize = 1 << 26;
+
+/// Source after preprocessing, which remembers where each part of it came
+/// from.
+pub struct Source {
+    /// The preprocessed source, the one to parse.
+    pub text : Vec<u8>,
+    /* the files the text came from, the one that was preprocessed first */
+    files    : Vec<SourceFile>,
+    /* runs of the text before macros were expanded copied from a file: where each starts in it, which file it is from and where in it */
+    runs     : Vec<(usize, usize, usize)>,
+    /* runs of the text copied from the text before macros were expanded: where each starts in both, if any were */
+    expanded : Vec<(usize, usize)>
+}
+
+/* A file that went into the text, and the file and offset of the include it came from, unless it is the first */
+struct SourceFile {
+    path        : PathBuf,
+    src         : Vec<u8>,
+    included_at : Option<(usize, usize)>
+}
+
+/// Preprocesses `src`, read from `path`, reading the files it includes
+/// relative to the directory of `path`, and expanding its macros if
+/// `macros`. Fails with a `ParseError` at an include that is malformed,
+/// can't be read or would include a file from within itself, or at a macro
+/// that can't be expanded.
+pub fn expand(path : &Path, src : &[u8], macros : bool) -> Result<Source, RustfuckError>
+{
+    let mut source = Source {
+        text:  Vec::with_capacity(src.len()),
+        files:    vec![SourceFile { path: path.to_owned(), src: src.to_vec(), included_at: None }],
+        runs:     Vec::new(),
+        expanded: Vec::new()
+    };
+    if src.windows(INCLUDE.len()).any(|w| w == INCLUDE) {
+        let mut including = vec![fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())];
+        expand_file(&mut source, 0, &mut including)?;
+    } else {
+        source.text = src.to_vec();
+        source.runs.push((0, 0, 0));
+    }
+
+    if macros && source.text.contains(&b'@') {
+        let text = std::mem::take(&mut source.text);
+        let mut expander = Expander { text: &text, out: Vec::with_capacity(text.len()), runs: Vec::new(),
+                                      macros: HashMap::new(), active: Vec::new() };
+        if let Err((offset, msg)) = expander.expand(0, text.len(), true) {
+            return Err(RustfuckError::ParseError(source.unexpanded_diagnostic(offset, &msg)));
+        }
+        source.text = expander.out;
+        source.expanded = expander.runs;
+    }
+    return Ok(source);
+}
+
+/* Appends file `file` to the text, expanding its includes; `including` are the files being expanded */
+fn expand_file(source : &mut Source, file : usize, including : &mut Vec<PathBuf>) -> Result<(), RustfuckError>
+{
+    let mut start = 0;
+    while start < source.files[file].src.len() {
+        let src = &source.files[file].src;
+        let end = src[start..].iter().position(|&b| b == b'\n').map_or(src.len(), |i| start + i);
+        let name = match include_name(&src[start..end]) {
+            Some(name) => name.map_err(|msg| source.error(file, start, &msg))?,
+            None       => {
+                /* the newline after an include is kept too, in case the file doesn't end in one */
+                let stop = (end + 1).min(src.len());
+                source.runs.push((source.text.len(), file, start));
+                source.text.extend_from_slice(&source.files[file].src[start..stop]);
+                start = stop;
+                continue;
+            }
+        };
+
+        let dir = source.files[file].path.parent().unwrap_or(Path::new(""));
+        let path = dir.join(&name);
+        let included = fs::canonicalize(&path).and_then(|canonical| Ok((fs::read(&canonical)?, canonical)));
+        let (included, canonical) = match included {
+            Ok(included) => included,
+            Err(e)       => return Err(source.error(file, start, &format!("can't include '{}': {}", name, e)))
+        };
+        if including.contains(&canonical) {
+            return Err(source.error(file, start, &format!("'{}' would include itself", name)));
+        }
+        source.files.push(SourceFile { path, src: included, included_at: Some((file, start)) });
+        including.push(canonical);
+        expand_file(source, source.files.len() - 1, including)?;
+        including.pop();
+        start = end;
+    }
+    Ok(())
+}
+
+/*
+ * Expands the macros in `text`, into `out`. Errors are a message and the
+ * offset in `text` it is about.
+ */
+struct Expander<'a> {
+    text   : &'a [u8],
+    out    : Vec<u8>,
+    /* runs of `out` copied from `text`, see `Source::expanded` */
+    runs   : Vec<(usize, usize)>,
+    /* the macros defined so far, by name, with the part of `text` each expands to */
+    macros : HashMap<String, (usize, usize)>,
+    /* the macros being expanded, innermost last */
+    active : Vec<String>
+}
+
+impl<'a> Expander<'a> {
+    /* Expands `text[start..end]`, where macros may only be defined at the `top` level */
+    fn expand(&mut self, start : usize, end : usize, top : bool) -> Result<(), (usize, String)>
+    {
+        let text = self.text;
+        let mut copied = start;
+        let mut i = start;
+        while i < end {
+            if text[i] != b'@' {
+                i += 1;
+                continue;
+            }
+            let name_end = word_end(text, i + 1, end, |b| b.is_ascii_alphanumeric() || b == b'_');
+            let name = String::from_utf8_lossy(&text[i + 1..name_end]).into_owned();
+            let next = match name.as_str() {
+                "def" if !top => return Err((i, "macros can only be defined outside of macros".to_owned())),
+                "def" => {
+                    let name_start = skip_space(text, name_end, end);
+                    let body_start = word_end(text, name_start, end, |b| b.is_ascii_alphanumeric() || b == b'_');
+                    let defined = String::from_utf8_lossy(&text[name_start..body_start]).into_owned();
+                    if defined.is_empty() {
+                        return Err((i, "expected a macro name after @def".to_owned()));
+                    }
+                    if matches!(defined.as_str(), "def" | "rep" | "end") {
+                        return Err((name_start, format!("'@{}' can't be redefined", defined)));
+                    }
+                    let body_end = match text[body_start..end].windows(4).position(|w| w == b"@end") {
+                        Some(n) => body_start + n,
+                        None    => return Err((i, format!("'@{}' has no @end", defined)))
+                    };
+                    self.copy(copied, i);
+                    self.macros.insert(defined, (body_start, body_end));
+                    copied = body_end + 4;
+                    body_end + 4
+                },
+                "rep" => {
+                    let (count, word_start, repeated_end) = rep_parts(text, name_end, end).map_err(|msg| (i, msg))?;
+                    self.copy(copied, i);
+                    for _ in 0..count {
+                        self.expand(word_start, repeated_end, false)?;
+                    }
+                    copied = repeated_end;
+                    repeated_end
+                },
+                "end" => return Err((i, "@end without @def".to_owned())),
+                _ => match self.macros.get(&name) {
+                    Some(&(body_start, body_end)) => {
+                        if self.active.contains(&name) {
+                            return Err((i, format!("'@{}' uses itself", name)));
+                        }
+                        self.copy(copied, i);
+                        self.active.push(name);
+                        self.expand(body_start, body_end, false)?;
+                        self.active.pop();
+                        copied = name_end;
+                        name_end
+                    },
+                    None => i + 1
+                }
+            };
+            if self.out.len() > MAX_EXPANSION {
+                return Err((i, "expanding macros makes the program too long".to_owned()));
+            }
+            i = next;
+        }
+        self.copy(copied, end);
+        Ok(())
+    }
+
+    /* Copies `text[start..end]` to the output as it is */
+    fn copy(&mut self, start : usize, end : usize)
+    {
+        if start >= end {
+            return;
+        }
+        let continues = self.runs.last().is_some_and(|&(out, from)| from + (self.out.len() - out) == start);
+        if !continues {
+            self.runs.push((self.out.len(), start));
+        }
+        self.out.extend_from_slice(&self.text[start..end]);
+    }
+}
+
+/*
+ * The count of the `@rep` whose name ends at `start`, and where the word it
+ * repeats starts and ends: up to the next whitespace, or, if it is another
+ * `@rep`, with that one's count and word. Errors are a message.
+ */
+fn rep_parts(text : &[u8], start : usize, end : usize) -> Result<(usize, usize, usize), String>
+{
+    /* the count and word of the outermost `@rep`, once read */
+    let mut outer = None;
+    /* how often the innermost word gets repeated, for every `@rep` around it */
+    let mut total : usize = 1;
+    let mut name_end = start;
+    loop {
+        let count_start = skip_space(text, name_end, end);
+        let count_end = word_end(text, count_start, end, |b| b.is_ascii_digit());
+        let n = match String::from_utf8_lossy(&text[count_start..count_end]).parse::<usize>() {
+            Ok(n)  => n,
+            Err(_) => return Err("expected a count after @rep".to_owned())
+        };
+        let next = skip_space(text, count_end, end);
+        let (count, word_start) = *outer.get_or_insert((n, next));
+        total = total.saturating_mul(n);
+        name_end = next + 4;
+        if !text[next..end].starts_with(b"@rep") || word_end(text, name_end, end, |b| b.is_ascii_alphanumeric() || b == b'_') != name_end {
+            let repeated_end = word_end(text, next, end, |b| !b.is_ascii_whitespace());
+            if next == repeated_end {
+                return Err("expected something to repeat after the count".to_owned());
+            }
+            if total.saturating_mul(repeated_end - next) > MAX_EXPANSION {
+                return Err("repeating that makes the program too long".to_owned());
+            }
+            return Ok((count, word_start, repeated_end));
+        }
+    }
+}
+
+/* Where the bytes from `start` that are `part` of a word end, at `end` at the latest */
+fn word_end(text : &[u8], start : usize, end : usize, part : impl Fn(u8) -> bool) -> usize
+{
+    return text[start..end].iter().position(|&b| !part(b)).map_or(end, |n| start + n);
+}
+
+/* Where the whitespace from `start` ends */
+fn skip_space(text : &[u8], start : usize, end : usize) -> usize
+{
+    return word_end(text, start, end, |b| b.is_ascii_whitespace());
+}
+
+/* The file an include line names, an error if the line is a malformed one, or `None` if it isn't one */
+fn include_name(line : &[u8]) -> Option<Result<String, String>>
+{
+    let line = line.trim_ascii();
+    let rest = line.strip_prefix(INCLUDE)?.trim_ascii_start();
+    let name = match rest.strip_prefix(b"\"").and_then(|rest| rest.strip_suffix(b"\"")) {
+        Some(name) if !name.is_empty() => name,
+        _                              => return Some(Err("expected #include \"<file>\"".to_owned()))
+    };
+    return Some(Ok(String::from_utf8_lossy(name).into_owned()));
+}
+
+impl Source {
+    /// A diagnostic for `offset` in the preprocessed source, pointing at
+    /// the file that part of it came from, and saying where that was
+    /// included from unless it is the first.
+    pub fn diagnostic(&self, offset : usize, message : &str) -> Diagnostic
+    {
+        let run = self.expanded.partition_point(|&(start, _)| start <= offset);
+        let unexpanded = match run.checked_sub(1).map(|run| self.expanded[run]) {
+            Some((start, from)) => from + (offset - start),
+            None                => offset
+        };
+        let mut diagnostic = self.unexpanded_diagnostic(unexpanded, message);
+        diagnostic.offset = offset;
+        return diagnostic;
+    }
+
+    /* Like `diagnostic()`, for `offset` in the text before macros were expanded */
+    fn unexpanded_diagnostic(&self, offset : usize, message : &str) -> Diagnostic
+    {
+        let run = self.runs.partition_point(|&(start, _, _)| start <= offset);
+        let (file, file_offset) = match run.checked_sub(1).map(|run| self.runs[run]) {
+            Some((start, file, file_offset)) => (file, file_offset + (offset - start)),
+            None                             => (0, 0)
+        };
+        return self.error_in(file, file_offset, message);
+    }
+
+    /// Points a `ParseError` about the preprocessed source at the file the
+    /// error is in, see `diagnostic()`; other errors are returned as they are.
+    pub fn relocate(&self, err : RustfuckError) -> RustfuckError
+    {
+        match err {
+            RustfuckError::ParseError(diagnostic) => RustfuckError::ParseError(self.diagnostic(diagnostic.offset, &diagnostic.message)),
+            _                                     => err
+        }
+    }
+
+    /* A parse error at `offset` in file `file` */
+    fn error(&self, file : usize, offset : usize, message : &str) -> RustfuckError
+    {
+        return RustfuckError::ParseError(self.error_in(file, offset, message));
+    }
+
+    /* A diagnostic at `offset` in file `file`, naming it and the includes it came through if it isn't the first */
+    fn error_in(&self, file : usize, offset : usize, message : &str) -> Diagnostic
+    {
+        if file == 0 {
+            return Diagnostic::new(&self.files[0].src, offset, message);
+        }
+        let mut message = format!("{} in {}", message, self.files[file].path.display());
+        let mut current = file;
+        while let Some((parent, at)) = self.files[current].included_at {
+            let line = Diagnostic::new(&self.files[parent].src, at, "").line;
+            message += &format!(", included from {} line {}", self.files[parent].path.display(), line);
+            current = parent;
+        }
+        return Diagnostic::new(&self.files[file].src, offset, &message);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+use bytecode;
+use bytecode::Op;
+use diagnostic::Diagnostic;
+use interpreter::Hooks;
+use interpreter::ProgramState;
+use Span;
+use Stmt;
+
+/* Longest snippet of a loop's source shown in a report, in characters */
+const SNIPPET_LEN : usize = 40;
+
+/// Counts how often the body of each loop runs, collected by passing it as
+/// the hooks to `interpreter::execute_with()` for the same program it was
+/// created for. Loops the optimizer replaced by other instructions are not
+/// counted, they don't loop anymore.
+pub struct Profile {
+    /* per op, the source it came from and for `[` ops the iterations of its loop */
+    sources    : Vec<Span>,
+    iterations : Vec<u64>
+}
+
+impl Profile {
+    pub fn new(code : &[Stmt]) -> Profile
+    {
+        let (ops, sources) = bytecode::lower_with_sources(code);
+        return Profile {
+            sources,
+            iterations: vec![0; ops.len()]
+        };
+    }
+
+    /// The source offset of the `[` of each loop that ran at least once and
+    /// its number of iterations, most iterations first.
+    pub fn hottest(&self) -> Vec<(usize, u64)>
+    {
+        let mut loops : Vec<(usize, u64)> = self.iterations.iter().enumerate()
+                .filter(|&(_, &n)| n > 0)
+                .map(|(pc, &n)| (self.sources[pc].start, n))
+                .collect();
+        loops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        return loops;
+    }
+
+    /// Renders the `count` hottest loops of `src`, the program this profile
+    /// is for, with their location and the start of their code.
+    pub fn report(&self, src : &[u8], count : usize) -> String
+    {
+        let loops = self.hottest();
+        let total : u64 = loops.iter().map(|&(_, n)| n).sum();
+        let mut report = String::new();
+        writeln!(report, "hottest loops ({} of {} that ran):", loops.len().min(count), loops.len()).unwrap();
+        writeln!(report, "    {:>14} {:>6}  {:<18} code", "iterations", "share", "offset (line:col)").unwrap();
+        for &(offset, n) in loops.iter().take(count) {
+            let diag = Diagnostic::new(src, offset, "");
+            writeln!(report, "    {:>14} {:>5.1}%  {:<18} {}", n, n as f64 * 100.0 / total as f64,
+                    format!("{} ({}:{})", offset, diag.line, diag.col), snippet(src, offset)).unwrap();
+        }
+        return report;
+    }
+}
+
+impl Hooks for Profile {
+    fn before_op(&mut self, pc : usize, op : Op, state : &ProgramState)
+    {
+        if state.tape.is_zero(state.ptr as usize) {
+            return;
+        }
+        match op {
+            Op::JumpIfZero(_)         => self.iterations[pc] += 1,
+            Op::JumpIfNonZero(offset) => self.iterations[pc - offset - 1] += 1,
+            _                         => ()
+        }
+    }
+}
+
+/*
+ * The source of the loop starting at `offset`, up to its matching `]`, on a
+ * single line with runs of whitespace collapsed and cut off if it's long.
+ */
+fn snippet(src : &[u8], offset : usize) -> String
+{
+    let mut depth = 0;
+    let mut end = src.len();
+    for (i, &b) in src.iter().enumerate().skip(offset) {
+        match b {
+            b'[' => depth += 1,
+            b']' => depth -= 1,
+            _    => ()
+        }
+        if depth == 0 {
+            end = i + 1;
+            break;
+        }
+    }
+    let code = String::from_utf8_lossy(&src[offset..end]);
+    let code = code.split_whitespace().collect::<Vec<_>>().join(" ");
+    if code.chars().count() <= SNIPPET_LEN {
+        return code;
+    }
+    return code.chars().take(SNIPPET_LEN - 3).collect::<String>() + "...";
+}
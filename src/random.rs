@@ -0,0 +1,52 @@
+/*
+ * The generator behind the `?` extension: SplitMix64, which is small enough
+ * to be generated into compiled programs as it is, so that they and the
+ * interpreter give the same bytes from the same state.
+ */
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::time::SystemTime;
+
+/// What the state is advanced by before each byte.
+pub const INCREMENT : u64 = 0x9E37_79B9_7F4A_7C15;
+/// The multipliers that mix the state into the number a byte is taken from.
+pub const MIX : [u64; 2] = [0xBF58_476D_1CE4_E5B9, 0x94D0_49BB_1331_11EB];
+
+/// A source of random bytes for `?`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Random {
+    pub state : u64
+}
+
+impl Random {
+    /// A generator starting from `state`, which gives the same bytes each
+    /// time.
+    pub fn new(state : u64) -> Random
+    {
+        return Random { state };
+    }
+
+    /// A generator starting from a different state in each run.
+    pub fn from_entropy() -> Random
+    {
+        /* std keys its hashers randomly for each process */
+        let mut hasher = RandomState::new().build_hasher();
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(now.as_nanos());
+        }
+        return Random::new(hasher.finish());
+    }
+
+    /// The next byte, the top one of the next number.
+    pub fn next_byte(&mut self) -> u8
+    {
+        self.state = self.state.wrapping_add(INCREMENT);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(MIX[0]);
+        z = (z ^ (z >> 27)).wrapping_mul(MIX[1]);
+        z ^= z >> 31;
+        return (z >> 56) as u8;
+    }
+}
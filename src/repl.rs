@@ -0,0 +1,106 @@
+/*
+ * An interactive read-eval-print loop: snippets of brainfuck are read from
+ * stdin a line at a time and run against a tape and pointer that persist
+ * between them. A `,` reads whatever comes next on stdin.
+ */
+
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use Config;
+use interpreter;
+use interpreter::ProgramState;
+use optimizer;
+use RustfuckError;
+
+const PROMPT              : &str = "bf> ";
+const CONTINUATION_PROMPT : &str = "... ";
+
+/* Passes output on while keeping track of the last byte written */
+struct Tracked<W : Write> {
+    inner : W,
+    last  : Option<u8>
+}
+
+impl<W : Write> Write for Tracked<W> {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize>
+    {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.last = Some(buf[n - 1]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        return self.inner.flush();
+    }
+}
+
+/// Runs the REPL with a tape as configured until stdin ends. After each
+/// snippet, its output is followed by the cells around the pointer. A snippet
+/// with unclosed loops is continued on the next line. Errors are reported
+/// without ending the session; a snippet that fails at runtime keeps what it
+/// did to the tape up to the error.
+pub fn run(config : &Config) -> Result<(), RustfuckError>
+{
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut state = ProgramState::with_config(config);
+    let mut snippet : Vec<u8> = Vec::new();
+    loop {
+        print!("{}", if snippet.is_empty() { PROMPT } else { CONTINUATION_PROMPT });
+        io::stdout().flush()?;
+        if input.read_until(b'\n', &mut snippet)? == 0 {
+            println!();
+            return Ok(());
+        }
+        if open_loops(&snippet) > 0 {
+            continue;
+        }
+
+        let program = match ::parse(&snippet) {
+            Ok(program) => optimizer::optimize_snippet(program, config),
+            Err(e)      => {
+                eprintln!("Error: {}", e);
+                snippet.clear();
+                continue;
+            }
+        };
+        snippet.clear();
+        let mut output = Tracked { inner: stdout.lock(), last: None };
+        let result = interpreter::execute(&program, &mut state, &mut input, &mut output);
+        if output.last.is_some_and(|b| b != b'\n') {
+            writeln!(output)?;
+        }
+        drop(output);
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            /* the next snippet is a different program, it starts at its beginning */
+            state.pc = 0;
+        }
+        print!("{}", state.tape_window());
+    }
+}
+
+/*
+ * The number of loops left open at the end of `src`, or 0 if a `]` closes
+ * a loop that was never opened (for the parser to report).
+ */
+fn open_loops(src : &[u8]) -> usize
+{
+    let mut depth : usize = 0;
+    for &b in src {
+        match b {
+            b'[' => depth += 1,
+            b']' => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None    => return 0
+            },
+            _ => ()
+        }
+    }
+    return depth;
+}
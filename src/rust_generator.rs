@@ -0,0 +1,188 @@
+use Config;
+use config::Eof;
+use config::Io;
+use Stmt;
+use std::fmt::Write;
+
+/* How deep loops are indented at most; deeper ones line up with those, keeping the source linear in size */
+const MAX_INDENT : usize = 32;
+
+fn write_header(rs : &mut String, config : &Config)
+{
+    write!(rs, "#![allow(dead_code, unused_mut, unused_variables, unused_assignments)]\n\n").unwrap();
+    write!(rs, "use std::io::Read;\n").unwrap();
+    write!(rs, "use std::io::Write;\n\n").unwrap();
+    write!(rs, "const TAPE_SIZE : usize = {};\n\n", config.tape_size).unwrap();
+    write!(rs, "type Cell = u{};\n\n", config.cell_width.bits()).unwrap();
+    write!(rs, "fn getchar(input : &mut dyn Read, output : &mut dyn Write) -> Option<u8>\n").unwrap();
+    write!(rs, "{{\n").unwrap();
+    write!(rs, "    let mut byte = [0u8; 1];\n").unwrap();
+    write!(rs, "    output.flush().unwrap();\n").unwrap();
+    write!(rs, "    if input.read(&mut byte).unwrap() == 0 {{\n").unwrap();
+    write!(rs, "        return None;\n").unwrap();
+    write!(rs, "    }}\n").unwrap();
+    write!(rs, "    return Some(byte[0]);\n").unwrap();
+    write!(rs, "}}\n\n").unwrap();
+    if config.io == Io::Numeric {
+        write_getnum(rs);
+    }
+    write!(rs, "fn main()\n").unwrap();
+    write!(rs, "{{\n").unwrap();
+    write!(rs, "    let mut memory = vec![0 as Cell; TAPE_SIZE];\n").unwrap();
+    write!(rs, "    let mut p : usize = 0;\n").unwrap();
+    write!(rs, "    let stdin = std::io::stdin();\n").unwrap();
+    write!(rs, "    let stdout = std::io::stdout();\n").unwrap();
+    write!(rs, "    let mut input = stdin.lock();\n").unwrap();
+    write!(rs, "    let mut output = stdout.lock();\n\n").unwrap();
+}
+
+/* reads a decimal number like `interpreter::read_number()` */
+fn write_getnum(rs : &mut String)
+{
+    write!(rs, "fn getnum(input : &mut dyn Read, output : &mut dyn Write) -> Option<i64>\n").unwrap();
+    write!(rs, "{{\n").unwrap();
+    write!(rs, "    let mut byte = getchar(input, output);\n").unwrap();
+    write!(rs, "    while byte.map_or(false, |b| b.is_ascii_whitespace()) {{\n").unwrap();
+    write!(rs, "        byte = getchar(input, output);\n").unwrap();
+    write!(rs, "    }}\n").unwrap();
+    write!(rs, "    let negative = byte == Some(b'-');\n").unwrap();
+    write!(rs, "    if negative || byte == Some(b'+') {{\n").unwrap();
+    write!(rs, "        byte = getchar(input, output);\n").unwrap();
+    write!(rs, "    }}\n").unwrap();
+    write!(rs, "    let mut number : Option<i64> = None;\n").unwrap();
+    write!(rs, "    while let Some(b) = byte.filter(|b| b.is_ascii_digit()) {{\n").unwrap();
+    write!(rs, "        number = Some(number.unwrap_or(0).wrapping_mul(10).wrapping_add((b - b'0') as i64));\n").unwrap();
+    write!(rs, "        byte = getchar(input, output);\n").unwrap();
+    write!(rs, "    }}\n").unwrap();
+    write!(rs, "    return number.map(|n| if negative {{ n.wrapping_neg() }} else {{ n }});\n").unwrap();
+    write!(rs, "}}\n\n").unwrap();
+}
+
+fn write_footer(rs : &mut String)
+{
+    write!(rs, "\n    output.flush().unwrap();\n").unwrap();
+    write!(rs, "}}\n").unwrap();
+}
+
+fn write_line(rs : &mut String, depth : usize, line : &str)
+{
+    write!(rs, "{}{}\n", "    ".repeat(depth.min(MAX_INDENT) + 1), line).unwrap();
+}
+
+/* formats `p + offset` as an index expression */
+fn index(offset : i32) -> String
+{
+    match offset {
+        0          => "p".to_owned(),
+        o if o < 0 => format!("p - {}", -o),
+        o          => format!("p + {}", o)
+    }
+}
+
+/* formats a pointer move by `n`, which must not underflow */
+fn move_ptr(n : i32) -> String
+{
+    if n < 0 {
+        return format!("p -= {};", -n);
+    }
+    return format!("p += {};", n);
+}
+
+fn write_code(rs : &mut String, code : &[Stmt], config : &Config, depth : usize)
+{
+    let width = config.cell_width;
+    let cell = |n : i32| { width.wrap(n) as u32 };
+    for stmt in code {
+        match stmt {
+            Stmt::Move(n, _)      => write_line(rs, depth, &move_ptr(*n)),
+            Stmt::Add(n, _)       => write_line(rs, depth, &format!("memory[p] = memory[p].wrapping_add({});", cell(*n))),
+            Stmt::Set(n, _)       => write_line(rs, depth, &format!("memory[p] = {};", cell(*n))),
+            Stmt::AddAt(o, n, _)  => {
+                let i = index(*o);
+                write_line(rs, depth, &format!("memory[{}] = memory[{}].wrapping_add({});", i, i, cell(*n)));
+            },
+            Stmt::MulAdd(o, f, _) => {
+                let i = index(*o);
+                write_line(rs, depth, "if memory[p] != 0 {");
+                write_line(rs, depth + 1, &format!("memory[{}] = memory[{}].wrapping_add(memory[p].wrapping_mul({}));", i, i, cell(*f)));
+                write_line(rs, depth, "}");
+            },
+            Stmt::Scan(1, _)      => write_line(rs, depth, "p += memory[p..].iter().position(|&c| c == 0).unwrap();"),
+            Stmt::Scan(-1, _)     => write_line(rs, depth, "p = memory[..=p].iter().rposition(|&c| c == 0).unwrap();"),
+            Stmt::Scan(n, _)      => {
+                write_line(rs, depth, "while memory[p] != 0 {");
+                write_line(rs, depth + 1, &move_ptr(*n));
+                write_line(rs, depth, "}");
+            },
+            Stmt::Input(_)        => {
+                let read = match config.io {
+                    Io::Bytes   => "getchar(&mut input, &mut output)",
+                    Io::Numeric => "getnum(&mut input, &mut output)"
+                };
+                write_line(rs, depth, &match config.eof {
+                    Eof::MinusOne  => format!("memory[p] = {}.map_or(Cell::MAX, |v| v as Cell);", read),
+                    Eof::Zero      => format!("memory[p] = {}.map_or(0, |v| v as Cell);", read),
+                    Eof::Unchanged => format!("if let Some(v) = {} {{ memory[p] = v as Cell; }}", read)
+                });
+            },
+            Stmt::Output(_)       => write_line(rs, depth, match config.io {
+                Io::Bytes   => "output.write_all(&[memory[p] as u8]).unwrap();",
+                Io::Numeric => "writeln!(output, \"{}\", memory[p]).unwrap();"
+            }),
+            Stmt::Loop(loop_code, _) => {
+                write_line(rs, depth, "while memory[p] != 0 {");
+                write_code(rs, loop_code, config, depth + 1);
+                write_line(rs, depth, "}");
+            },
+            /* breakpoints are only shown by the interpreter */
+            Stmt::Debug(_)        => (),
+            /* only the interpreter and the llvm backend run them, see `uses_procedures()` */
+            Stmt::Proc(..) | Stmt::Call(_) => panic!("the Rust translation has no pbrain procedures"),
+            /* only the interpreter runs them, see `uses_forks()` */
+            Stmt::Fork(_)         => panic!("the Rust translation has no Brainfork threads"),
+            /* only the interpreter and the llvm backend run it, see `uses_random()` */
+            Stmt::Random(_)       => panic!("the Rust translation has no random bytes")
+        }
+    }
+}
+
+/// Generates a standalone, safe Rust `main.rs` which runs `code` with the
+/// configured tape size and wrapping cells of the configured width.
+pub fn code_gen(code : &[Stmt], config : &Config) -> String
+{
+    let mut rs : String = String::new();
+
+    write_header(&mut rs, config);
+    write_code(&mut rs, code, config, 0);
+    write_footer(&mut rs);
+
+    return rs;
+}
+
+/// Turns `name`, like the file name of a program, into a valid Cargo
+/// package name: letters, digits, `-` and `_`, starting with a letter.
+pub fn package_name(name : &str) -> String
+{
+    let name : String = name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+    if !name.starts_with(|c : char| c.is_ascii_alphabetic()) {
+        return format!("bf-{}", name);
+    }
+    return name;
+}
+
+/// Generates the `Cargo.toml` of a crate named `name` with the `main.rs`
+/// from `code_gen()` as its binary, which needs no dependencies.
+pub fn cargo_manifest(name : &str) -> String
+{
+    let mut toml : String = String::new();
+
+    write!(toml, "[package]\n").unwrap();
+    write!(toml, "name = \"{}\"\n", name).unwrap();
+    write!(toml, "version = \"0.1.0\"\n").unwrap();
+    write!(toml, "edition = \"2021\"\n\n").unwrap();
+    write!(toml, "[dependencies]\n").unwrap();
+
+    return toml;
+}
@@ -1,215 +1,1947 @@
+extern crate rustfuck;
+
 use std::env;
 use std::process;
 use std::io;
-use std::io::stdout;
-use std::io::Write;
 use std::io::Read;
+use std::io::Write;
+use std::io::Cursor;
 use std::io::BufReader;
-use std::io::BufWriter;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::fs;
 use std::fs::File;
-use std::process::Command;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
 
-mod llvm_ir_generator;
+use rustfuck::Config;
+use rustfuck::config::Bounds;
+use rustfuck::config::CellWidth;
+use rustfuck::config::Eof;
+use rustfuck::config::Flush;
+use rustfuck::config::Io;
+use rustfuck::config::Tape;
+use rustfuck::Lang;
+use rustfuck::ParseOptions;
+use rustfuck::TokenMap;
+use rustfuck::ProgramState;
+use rustfuck::interpreter::ShowBreakpoints;
+use rustfuck::RustfuckError;
+use rustfuck::Stmt;
+use rustfuck::stats::Stats;
+use rustfuck::profile::Profile;
+use rustfuck::trace::Trace;
+use rustfuck::compiler::Artifact;
+use rustfuck::compiler::Backend;
+use rustfuck::compiler::BatchProgram;
+use rustfuck::compiler::BuildOptions;
+use rustfuck::compiler::OptLevel;
+use rustfuck::llvm_ir_generator::DebugSource;
+use rustfuck::preprocessor::Source;
+use rustfuck::terminal::RawMode;
 
-const COLOR_GREEN  : &str = "\x1b[92m";
-const COLOR_PURPLE : &str = "\x1b[95m";
-const COLOR_NONE   : &str = "\x1b[0m";
-const USAGE_STR    : &str = "Usage: ./rustfuck <file> [-C] [-P] [-I]";
+const USAGE_STR : &str = "\
+Usage: rustfuck <command> [options] <file>
+       rustfuck [<command>] [options] -e <code>
+       rustfuck repl [options]
+       rustfuck test [options] <dir>
+       rustfuck generate --print <text>
+       rustfuck equiv [options] <file> <file>
 
-const TAPE_SIZE    : usize = 65536;
+Commands:
+    run      Interpret a program (the default when no command is given)
+    build    Compile a program to a native executable
+    check    Parse programs and report errors without running them
+    verify   Check that the interpreter and a compiled program agree
+    test     Run the programs in a directory and compare their output with
+             what is expected
+    debug    Step through a program in an interactive terminal debugger
+    repl     Run snippets of brainfuck as they are typed, on a tape that is
+             kept between them
+    emit     Translate a program to C, Rust or WebAssembly text
+    minify   Print the shortest source this can find for a program
+    fmt      Reformat a program's source, indenting its loops
+    generate Write a program that prints a given text
+    explain  Print a program as C-like pseudocode, naming the idioms in it
+    equiv    Check that two programs print the same on a set of inputs
 
-#[derive(PartialEq, Eq, Debug)]
-pub enum Stmt 
-{
-    Move(i32),
-    Add(i32),
-    Input,
-    Output,
-    Loop(Vec<Stmt>)
+A <file> of - reads the program from stdin, leaving none for its input unless
+it comes from --input or --input-str.
+
+Run `rustfuck <command> --help` for the options of each command.";
+
+const RUN_USAGE_STR : &str = "\
+Usage: rustfuck run [options] <file>
+       rustfuck run [options] -e <code>
+
+A .bfc file, written by build --emit=bytecode, is run as it is, without being
+parsed or optimized.
+
+Pressing Ctrl-C while the interpreter runs stops the program and shows where
+it was, the pointer and the non-zero part of the tape. Press it again to quit
+right away.
+
+Options:
+    --jit               Compile to native code in-process instead of interpreting
+    --engine <name>     How the interpreter runs instructions: match (default)
+                        to match on each one, or threaded to resolve each to
+                        the function that runs it up front (not with --stats,
+                        --profile, --trace or --debug)
+    --input <file>      Read the program's input from a file instead of stdin
+    --input-str <text>  Use the given text as the program's input
+    --guest <file>      Read the file and a ! before the program's input, the
+                        way self-interpreters read the program they run
+    --record-input <file>
+                        Also write everything the program reads to a file
+    --replay-input <file>
+                        Read the program's input from a file written by
+                        --record-input, to reproduce a session exactly
+    --raw-input         Read keypresses from the terminal as they are typed,
+                        without waiting for Enter and without echoing them
+    --output <file>     Write the program's output to a file instead of stdout
+    --flush <policy>    When output is flushed: line (default, also before
+                        reading input), full buffers only, or every-byte
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --tape <mode>       fixed, or dynamic to grow the tape in either direction
+                        whenever the pointer moves past an end (not with --jit)
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around, or unchecked (same as error here)
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --preset dbfi       What self-interpreters like dbfi.b expect: 8-bit cells,
+                        a dynamic tape and --eof zero; later options override
+    --max-steps <n>     Stop the program after executing n instructions
+    --timeout <secs>    Stop the program after running for secs seconds
+                        (neither works with --jit)
+    --snapshot-out <file>
+                        When the program is stopped by a limit or Ctrl-C, save
+                        the tape, pointer, position in the program and unread
+                        input to a file (not with --jit)
+    --snapshot-in <file>
+                        Resume the program from a saved snapshot, reading the
+                        input it hadn't read yet before any other (not with
+                        --jit)
+    --stats             Print how many instructions of each kind were run,
+                        the peak pointer position, the number of cells read
+                        or written and the wall time to stderr (not with --jit)
+    --profile           Print the loops that ran the most iterations, with
+                        their location in the source, to stderr (not with --jit)
+    --trace             Print every instruction as it is executed, with its
+                        line and column in the source and the pointer and cell
+                        value after it, to stderr (not with --jit)
+    --trace-limit <n>   Stop tracing after n instructions
+    --trace-lines <range>
+                        Only trace instructions from these source lines, given
+                        as first-last or as a single line
+    --debug[=!]         Treat # as a breakpoint which prints the cells around
+                        the pointer to stderr, or ! instead with --debug=!
+                        (ignored with --jit)
+    -P, --print         Print the optimized program before running it
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --seed <n>          Start the random bytes of --ext random from n, so they
+                        come out the same in every run
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    -e <code>           Use the given code as the program instead of a file
+    --load-ast <file>   Read the program from JSON written by check --dump-ast,
+                        or by another tool, instead of from source
+    -h, --help          Print this help";
+
+const BUILD_USAGE_STR : &str = "\
+Usage: rustfuck build [options] <file>...
+
+Given several files, builds each of them like it was the only one, several
+at a time, then lists which ones failed.
+
+Options:
+    -o <path>           Where to write the executable, or the one other file
+                        --emit keeps (default: the input's file name without
+                        extension, or with .wasm for a WebAssembly --target
+                        or that file's extension, in the current directory),
+                        or - to print the LLVM IR of --emit=llvm-ir to stdout
+                        instead of building anything. Only for one file
+    --backend <name>    Code generator to use: native, llvm, or bundle for a
+                        copy of rustfuck that interprets the program, which
+                        needs no toolchain (exe and bytecode only)
+    --emit <kinds>      Comma-separated files to keep: llvm-ir, bc, asm, obj
+                        and/or exe (default: exe). The only one is written
+                        to -o as it is; with more, each is named after the
+                        executable, with an extension (.ll, .bc, .s or .o).
+                        Or cdylib for a shared library (.so) instead of exe,
+                        with the C function int bf_run(const char *input,
+                        size_t len, char **output, size_t *out_len), which
+                        returns 1 if the pointer moved off the tape; free()
+                        the output (llvm backend, the default then).
+                        And bytecode for the optimized program as bytecode
+                        (.bfc), which run runs without parsing it
+    -g, --debug-info    Add DWARF line info, so gdb and perf show where in the
+                        source the program is (llvm backend, the default then)
+    -O0, -O1, -O2, -O3, -Os, -Oz
+                        How much the llvm backend optimizes, from not at all
+                        to the most (default: -O3), or -Os and -Oz for smaller
+                        code
+    --opt-size          Make the executable as small as possible: -Oz, with
+                        its symbols stripped and input read by one shared
+                        function (llvm backend, the default then)
+    --native-cpu <on|off>
+                        Use every instruction this machine's CPU has, which
+                        others may lack (llvm backend, default: on)
+    --target <triple>   Build for another machine, like aarch64-linux-gnu,
+                        x86_64-unknown-linux-musl or wasm32-wasi, linking
+                        with its gcc cross compiler, musl-gcc, or clang or
+                        wasm-ld (llvm backend, the default then). wasm32-wasi
+                        programs call WASI directly when they can, and run
+                        with wasmtime or wasmer
+    --static            Link libc into the executable so it runs without one,
+                        like in a container or initramfs; smallest with a
+                        musl --target, such as x86_64-unknown-linux-musl
+    --freestanding      Make system calls instead of using libc, for tiny
+                        static executables (llvm backend, the default then;
+                        Linux on x86_64 and aarch64, or WASI on wasm32, which
+                        is the default there; fixed tape, byte I/O)
+    --safe              Check every access of a fixed tape, even with unchecked
+                        --bounds, and report the source offset of one off it
+                        like the interpreter does (llvm backend, the default
+                        then)
+    --partial-eval[=<steps>]
+                        Run a program that reads no input while building it,
+                        for up to 100000000 instructions or the given number,
+                        and only print its output if it finished in time
+    --ir-comments       Comment the LLVM IR with the brainfuck each part of
+                        it comes from, and name loop labels after how the
+                        loops nest, like loop_cond2_1 (llvm backend)
+    --debug[=!]         Treat # as a breakpoint where the program prints the
+                        cells around the pointer to stderr like run --debug
+                        does, or ! instead with --debug=! (llvm backend, the
+                        default then)
+    --linker <program>  Compiler driver to link with, like gcc, clang or cc,
+                        or a path to one (default: the first installed of
+                        gcc, cc and clang, or cross compilers for a --target)
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --tape <mode>       fixed, or dynamic to grow the tape in either direction
+                        whenever the pointer moves past an end (llvm backend)
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around or unchecked. Programs exit with
+                        status 1 on errors
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --preset dbfi       What self-interpreters like dbfi.b expect: 8-bit cells,
+                        a dynamic tape and --eof zero; later options override
+    --flush <policy>    When buffered output is written: line (default, also
+                        before reading input), full buffers only, or
+                        every-byte (llvm backend)
+    -P, --print         Print the optimized program before compiling it
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --seed <n>          Start the random bytes of --ext random from n, so they
+                        come out the same in every run
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    --load-ast <file>   Read the program from JSON written by check --dump-ast,
+                        or by another tool, instead of from source
+    -h, --help          Print this help";
+
+const CHECK_USAGE_STR : &str = "\
+Usage: rustfuck check [options] <file>...
+       rustfuck check [options] -e <code>
+
+Parses the program and reports what is wrong with it, without running or
+compiling it. Given several files, checks each of them, then lists which ones
+failed.
+
+Options:
+    --lint              Also warn about code that can't do what it looks like
+                        it's for: loops right after a loop, which never run,
+                        empty loops, and commands that cancel out, like +-
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    -e <code>           Use the given code as the program instead of a file
+    --load-ast <file>   Read the program from JSON written by check --dump-ast,
+                        or by another tool, instead of from source
+    --dump-ast[=json]   Print the parsed program as JSON, which --load-ast
+                        reads, instead of ok
+    --dump-analysis     Print, for every loop, whether its body moves the
+                        pointer back to where it started and what it adds
+                        to the loop's counter each time round, instead of ok
+    --tape-size <cells> Number of cells on the tape --dump-analysis assumes
+                        (default: 65536)
+    --tape <mode>       fixed, or dynamic for a tape that grows in either
+                        direction
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around, where offsets a tape apart are one cell,
+                        or unchecked
+    -h, --help          Print this help";
+
+const DEBUG_USAGE_STR : &str = "\
+Usage: rustfuck debug [options] <file>
+       rustfuck debug [options] -e <code>
+
+Runs the program one instruction at a time, showing the source around it, the
+tape around the pointer and the program's output. Any # in the program is a
+breakpoint. Keys: s or space to step, c to continue to the next breakpoint
+(any key interrupts), b to toggle a breakpoint on the next instruction, h and
+l to scroll the tape, p to follow the pointer again, and q to quit.
+
+Options:
+    --input <file>      Read the program's input from a file (default: none,
+                        the keyboard controls the debugger)
+    --input-str <text>  Use the given text as the program's input
+    --guest <file>      Read the file and a ! before the program's input, the
+                        way self-interpreters read the program they run
+    --record-input <file>
+                        Also write everything the program reads to a file
+    --replay-input <file>
+                        Read the program's input from a file written by
+                        --record-input
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --tape <mode>       fixed, or dynamic to grow the tape in either direction
+                        whenever the pointer moves past an end
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around, or unchecked (same as error here)
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --preset dbfi       What self-interpreters like dbfi.b expect: 8-bit cells,
+                        a dynamic tape and --eof zero; later options override
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --seed <n>          Start the random bytes of --ext random from n, so they
+                        come out the same in every run
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    -e <code>           Use the given code as the program instead of a file
+    -h, --help          Print this help";
+
+const REPL_USAGE_STR : &str = "\
+Usage: rustfuck repl [options]
+
+Reads brainfuck a line at a time and runs it, then shows the cells around the
+pointer. The tape and pointer are kept from one line to the next. A line with
+unclosed loops is continued on the next one. A , reads the input that follows
+the line. End the session with Ctrl-D.
+
+Options:
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --tape <mode>       fixed, or dynamic to grow the tape in either direction
+                        whenever the pointer moves past an end
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around, or unchecked (same as error here)
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --preset dbfi       What self-interpreters like dbfi.b expect: 8-bit cells,
+                        a dynamic tape and --eof zero; later options override
+    -h, --help          Print this help";
+
+const VERIFY_USAGE_STR : &str = "\
+Usage: rustfuck verify [options] <file>
+       rustfuck verify [options] -e <code>
+
+Runs the program in the interpreter and as a compiled executable, or through
+the JIT, with the same input, and reports the first byte where their outputs
+differ, or if only one of them failed. Exits with status 1 if they disagree.
+
+Options:
+    --jit               Compare with the JIT instead of a compiled executable
+    --backend <name>    Code generator to compile with: native, llvm or bundle
+    --input <file>      Read the program's input from a file instead of stdin
+    --input-str <text>  Use the given text as the program's input
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --tape <mode>       fixed, or dynamic to grow the tape in either direction
+                        whenever the pointer moves past an end (not with --jit)
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around or unchecked
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --preset dbfi       What self-interpreters like dbfi.b expect: 8-bit cells,
+                        a dynamic tape and --eof zero; later options override
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --seed <n>          Start the random bytes of --ext random from n, which
+                        both runs get the same ones from (default: 0)
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    -e <code>           Use the given code as the program instead of a file
+    --load-ast <file>   Read the program from JSON written by check --dump-ast,
+                        or by another tool, instead of from source
+    -h, --help          Print this help";
+
+const TEST_USAGE_STR : &str = "\
+Usage: rustfuck test [options] <dir>
+
+Runs every program in a directory and its subdirectories that has an expected
+output next to it: for foo.b, the file foo.expected, and reads its input from
+foo.in if there is one. foo.args holds options to run it with, like
+--bounds wrap or --debug, which adds the cells at each breakpoint to the
+output, and foo.error the error it should stop with, if it should. Reports
+each test as it runs, with a diff of the output of those that fail, and exits
+with status 1 if any did.
+
+Options:
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --tape <mode>       fixed, or dynamic to grow the tape in either direction
+                        whenever the pointer moves past an end
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around, or unchecked (same as error here)
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --preset dbfi       What self-interpreters like dbfi.b expect: 8-bit cells,
+                        a dynamic tape and --eof zero; later options override
+    --max-steps <n>     Fail a test after it executed n instructions
+    --timeout <secs>    Fail a test after it ran for secs seconds
+    -h, --help          Print this help";
+
+const EMIT_USAGE_STR : &str = "\
+Usage: rustfuck emit --to <lang> [options] <file>
+       rustfuck emit --cargo [options] <file>
+
+Options:
+    --to <lang>         Language to translate to: c, rust, wasm, or jvm for a
+                        class file to run with java (experimental)
+    --cargo             Write a Cargo project instead, with the Rust
+                        translation as its src/main.rs, to build with
+                        cargo build or add to a workspace
+    -o <path>           Where to write the translation (default: the input's
+                        file name with the extension of the language, or
+                        without one for the directory of a Cargo project)
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --bounds <policy>   What moving off the tape does in C: error (default),
+                        wrap around, or unchecked to not check it at all
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    --load-ast <file>   Read the program from JSON written by check --dump-ast,
+                        or by another tool, instead of from source
+    -h, --help          Print this help";
+
+const MINIFY_USAGE_STR : &str = "\
+Usage: rustfuck minify [options] <file>
+       rustfuck minify [options] -e <code>
+
+Prints the program without comments, without moves and adds that cancel out
+and without loops that can never run, as the shortest source it finds with or
+without optimizing it. The result behaves the same with any tape or cell
+width.
+
+Options:
+    -o <path>           Write the minified program to a file instead of stdout
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    -e <code>           Use the given code as the program instead of a file
+    --load-ast <file>   Read the program from JSON written by check --dump-ast,
+                        or by another tool, instead of from source
+    -h, --help          Print this help";
+
+const FMT_USAGE_STR : &str = "\
+Usage: rustfuck fmt [options] <file>
+
+Prints the program with each loop body indented one level deeper than its
+brackets and the commands between them reflowed to fit the line width.
+Comments are kept, on lines of their own or after the code they followed.
+
+Options:
+    --width <cols>      Line width to fit code into (default: 80)
+    -o <path>           Write the formatted program to a file instead of
+                        stdout, which may be the program itself
+    -h, --help          Print this help";
+
+const GENERATE_USAGE_STR : &str = "\
+Usage: rustfuck generate [options] --print <text>
+
+Writes a brainfuck program that prints the text, reaching each character from
+the one before with multiplication loops where they are shorter. The program
+is run before it is written, to check that it prints exactly the text.
+
+Options:
+    --print <text>      The text to print
+    -o <path>           Write the program to a file instead of stdout
+    -h, --help          Print this help";
+
+const EXPLAIN_USAGE_STR : &str = "\
+Usage: rustfuck explain [options] <file>
+       rustfuck explain [options] -e <code>
+
+Prints the optimized program as C-like pseudocode, with p for the pointer and
+mem for the tape, like mem[p] += 5; and while (mem[p]) { ... }. The idioms
+the optimizer recognized are named in comments: clearing a cell, scanning for
+a zero cell, and moving, copying or multiplying the current cell into others.
+
+Options:
+    -o <path>           Write the pseudocode to a file instead of stdout
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    -e <code>           Use the given code as the program instead of a file
+    --load-ast <file>   Read the program from JSON written by check --dump-ast,
+                        or by another tool, instead of from source
+    -h, --help          Print this help";
+
+const EQUIV_USAGE_STR : &str = "\
+Usage: rustfuck equiv [options] <file> <file>
+
+Runs both programs on each input and reports whether they print the same, to
+check that minifying, optimizing or rewriting a program didn't change what it
+does. Each program runs for at most a bounded number of steps: if one of them
+runs out before they print differently, or before it prints more than all the
+other one printed before finishing, nothing is known about that input. Exits
+with status 1 if they differ on any input.
+
+Options:
+    --inputs <dir>      Run the programs on each file in the directory (default:
+                        on no input)
+    --steps <n>         Steps each program may run on an input (default:
+                        10000000)
+    --tape-size <cells> Number of cells on the tape (default: 65536)
+    --tape <mode>       fixed, or dynamic to grow the tape in either direction
+                        whenever the pointer moves past an end
+    --bounds <policy>   What moving off a fixed tape does: error (default),
+                        wrap around or unchecked
+    --cell-width <bits> Width of a cell: 8 (default), 16 or 32
+    --io <mode>         bytes (default), or numeric to read and write cells as
+                        decimal numbers, one per line
+    --eof <value>       What , stores at the end of input: minus-one (default),
+                        zero, or unchanged
+    --preset dbfi       What self-interpreters like dbfi.b expect: 8-bit cells,
+                        a dynamic tape and --eof zero; later options override
+    --lang <name>       Language of the source: brainfuck, ook for Ook!, or
+                        brainfork for brainfuck with Y to fork a thread
+                        (default: ook for .ook files, brainfuck otherwise)
+    --pbrain            Read pbrain procedures: (...) defines the one numbered
+                        by the current cell, and : calls it
+    --ext <name>        Enable an extension: random, where ? stores a random
+                        byte in the current cell, or macros, which expands
+                        @def, @rep and the macros defined with them
+    --strict            Fail on any character that is neither a command nor
+                        whitespace instead of reading it as a comment
+    --seed <n>          Start the random bytes of --ext random from n, which
+                        both programs get the same ones from (default: 0)
+    --token-map <file>  Read the source with the commands spelled as the file
+                        says instead, a <command>=<token> a line, like >=moo;
+                        the commands it leaves out keep their spelling
+    --map <command>=<token>
+                        Spell one command like that, after --token-map
+    -h, --help          Print this help";
+
+/* number of loops listed by `--profile` */
+const PROFILE_LOOPS : usize = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Build,
+    Check,
+    Verify,
+    Test,
+    Debug,
+    Repl,
+    Emit,
+    Minify,
+    Fmt,
+    Generate,
+    Explain,
+    Equiv
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Language {
+    C,
+    Rust,
+    Wasm,
+    Jvm
+}
+
+/* how the interpreter gets from one instruction to the next */
+#[derive(Clone, Copy, PartialEq)]
+enum Engine {
+    Match,
+    Threaded
 }
 
-struct ProgramState {
-    ptr  : i32,
-    tape : [i32; TAPE_SIZE]
+/* where `,` reads from when running a program */
+enum Input {
+    Stdin,
+    File(String),
+    Str(String)
 }
 
-fn exit_with_error(msg : &str)
+struct Options {
+    command      : Command,
+    filepath     : String,
+    /* the program itself, given with `-e` instead of a file */
+    code         : Option<String>,
+    /* every file to build or check, if there is more than one */
+    batch        : Vec<String>,
+    lint         : bool,
+    dump_ast     : bool,
+    analyze      : bool,
+    /* whether `filepath` is a program written as JSON rather than source */
+    load_ast     : bool,
+    print        : bool,
+    use_jit      : bool,
+    engine       : Engine,
+    stats        : bool,
+    profile      : bool,
+    trace        : bool,
+    trace_limit  : Option<u64>,
+    trace_lines  : Option<(usize, usize)>,
+    snapshot_in  : Option<String>,
+    snapshot_out : Option<String>,
+    syntax       : ParseOptions,
+    /* whether to expand the macros in the source */
+    macros       : bool,
+    /* the language of the source, if not the one its extension says */
+    lang         : Option<Lang>,
+    token_map    : Option<TokenMap>,
+    /* `--map` overrides of the token map, applied after it */
+    map          : Vec<String>,
+    raw_input    : bool,
+    input        : Input,
+    /* a program for a self-interpreter to read before the input */
+    guest        : Option<String>,
+    record_input : Option<String>,
+    output       : Option<String>,
+    config       : Config,
+    backend      : Option<Backend>,
+    emit         : Vec<Artifact>,
+    debug_info   : bool,
+    partial_eval : Option<u64>,
+    build        : BuildOptions,
+    emit_to      : Option<Language>,
+    cargo        : bool,
+    width        : usize,
+    /* what the program `generate` writes prints */
+    text         : Option<String>,
+    /* the directory of inputs `equiv` runs the programs on */
+    inputs       : Option<String>
+}
+
+fn exit_with_usage(usage : &str, msg : &str) -> !
 {
-    println!("Error: {}", msg);
+    eprintln!("Error: {}\n\n{}", msg, usage);
     process::exit(1);
 }
 
-/* Parse into brainfuck program representation */
-fn parse(src : &[u8], start_idx : usize) -> (Vec<Stmt>, usize)
+/* an option accepted on the command line; the first name is the canonical one */
+struct OptionSpec {
+    names       : &'static [&'static str],
+    takes_value : bool,
+    commands    : &'static [Command]
+}
+
+/* flags that may also be given a value, but only as `--name=value` */
+const OPTIONAL_VALUES : &[&str] = &["--partial-eval", "--debug", "--dump-ast"];
+
+const OPTIONS : &[OptionSpec] = &[
+    OptionSpec { names: &["--help", "-h"],  takes_value: false, commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Test, Command::Debug, Command::Repl, Command::Emit, Command::Minify, Command::Fmt, Command::Generate, Command::Explain, Command::Equiv] },
+    OptionSpec { names: &["--print", "-P"], takes_value: false, commands: &[Command::Run, Command::Build] },
+    OptionSpec { names: &["--jit"],         takes_value: false, commands: &[Command::Run, Command::Verify] },
+    OptionSpec { names: &["--engine"],      takes_value: true,  commands: &[Command::Run] },
+    OptionSpec { names: &["--input"],       takes_value: true,  commands: &[Command::Run, Command::Verify, Command::Debug] },
+    OptionSpec { names: &["-e"],            takes_value: true,  commands: &[Command::Run, Command::Check, Command::Debug, Command::Verify, Command::Minify, Command::Explain] },
+    OptionSpec { names: &["--guest"],       takes_value: true,  commands: &[Command::Run, Command::Debug] },
+    OptionSpec { names: &["--preset"],      takes_value: true,  commands: &[Command::Run, Command::Debug, Command::Repl, Command::Build, Command::Verify, Command::Test, Command::Equiv] },
+    OptionSpec { names: &["--input-str"],   takes_value: true,  commands: &[Command::Run, Command::Verify, Command::Debug] },
+    OptionSpec { names: &["--record-input"], takes_value: true, commands: &[Command::Run, Command::Debug] },
+    OptionSpec { names: &["--replay-input"], takes_value: true, commands: &[Command::Run, Command::Debug] },
+    OptionSpec { names: &["--raw-input"],   takes_value: false, commands: &[Command::Run] },
+    OptionSpec { names: &["-o"],            takes_value: true,  commands: &[Command::Build, Command::Emit, Command::Minify, Command::Fmt, Command::Generate, Command::Explain] },
+    OptionSpec { names: &["--output"],      takes_value: true,  commands: &[Command::Run] },
+    OptionSpec { names: &["--flush"],       takes_value: true,  commands: &[Command::Run, Command::Build] },
+    OptionSpec { names: &["--max-steps"],   takes_value: true,  commands: &[Command::Run, Command::Test] },
+    OptionSpec { names: &["--timeout"],     takes_value: true,  commands: &[Command::Run, Command::Test] },
+    OptionSpec { names: &["--stats"],       takes_value: false, commands: &[Command::Run] },
+    OptionSpec { names: &["--profile"],     takes_value: false, commands: &[Command::Run] },
+    OptionSpec { names: &["--trace"],       takes_value: false, commands: &[Command::Run] },
+    OptionSpec { names: &["--trace-limit"], takes_value: true,  commands: &[Command::Run] },
+    OptionSpec { names: &["--trace-lines"], takes_value: true,  commands: &[Command::Run] },
+    OptionSpec { names: &["--debug"],       takes_value: false, commands: &[Command::Run, Command::Build] },
+    OptionSpec { names: &["--snapshot-out"], takes_value: true, commands: &[Command::Run] },
+    OptionSpec { names: &["--snapshot-in"], takes_value: true,  commands: &[Command::Run] },
+    OptionSpec { names: &["--tape-size"],   takes_value: true,  commands: &[Command::Run, Command::Check, Command::Debug, Command::Repl, Command::Build, Command::Verify, Command::Test, Command::Emit, Command::Equiv] },
+    OptionSpec { names: &["--tape"],        takes_value: true,  commands: &[Command::Run, Command::Check, Command::Debug, Command::Repl, Command::Build, Command::Verify, Command::Test, Command::Equiv] },
+    OptionSpec { names: &["--bounds"],      takes_value: true,  commands: &[Command::Run, Command::Check, Command::Debug, Command::Repl, Command::Build, Command::Verify, Command::Test, Command::Emit, Command::Equiv] },
+    OptionSpec { names: &["--cell-width"],  takes_value: true,  commands: &[Command::Run, Command::Debug, Command::Repl, Command::Build, Command::Verify, Command::Test, Command::Emit, Command::Equiv] },
+    OptionSpec { names: &["--io"],          takes_value: true,  commands: &[Command::Run, Command::Debug, Command::Repl, Command::Build, Command::Verify, Command::Test, Command::Emit, Command::Equiv] },
+    OptionSpec { names: &["--eof"],         takes_value: true,  commands: &[Command::Run, Command::Debug, Command::Repl, Command::Build, Command::Verify, Command::Test, Command::Emit, Command::Equiv] },
+    OptionSpec { names: &["--backend"],     takes_value: true,  commands: &[Command::Build, Command::Verify] },
+    OptionSpec { names: &["--emit"],        takes_value: true,  commands: &[Command::Build] },
+    OptionSpec { names: &["--debug-info", "-g"], takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["-O0"],           takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["-O1"],           takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["-O2"],           takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["-O3"],           takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["-Os"],           takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["-Oz"],           takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["--opt-size"],    takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["--native-cpu"],  takes_value: true,  commands: &[Command::Build] },
+    OptionSpec { names: &["--target"],      takes_value: true,  commands: &[Command::Build] },
+    OptionSpec { names: &["--static"],      takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["--freestanding"], takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["--safe"],        takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["--partial-eval"], takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["--ir-comments"], takes_value: false, commands: &[Command::Build] },
+    OptionSpec { names: &["--linker"],      takes_value: true,  commands: &[Command::Build] },
+    OptionSpec { names: &["--to"],          takes_value: true,  commands: &[Command::Emit] },
+    OptionSpec { names: &["--cargo"],       takes_value: false, commands: &[Command::Emit] },
+    OptionSpec { names: &["--lang"],        takes_value: true,  commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Debug, Command::Emit, Command::Minify, Command::Explain, Command::Equiv] },
+    OptionSpec { names: &["--pbrain"],      takes_value: false, commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Debug, Command::Emit, Command::Minify, Command::Explain, Command::Equiv] },
+    OptionSpec { names: &["--ext"],         takes_value: true,  commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Debug, Command::Emit, Command::Minify, Command::Explain, Command::Equiv] },
+    OptionSpec { names: &["--strict"],      takes_value: false, commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Debug, Command::Emit, Command::Minify, Command::Explain, Command::Equiv] },
+    OptionSpec { names: &["--seed"],        takes_value: true,  commands: &[Command::Run, Command::Build, Command::Verify, Command::Debug, Command::Equiv] },
+    OptionSpec { names: &["--token-map"],   takes_value: true,  commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Debug, Command::Emit, Command::Minify, Command::Explain, Command::Equiv] },
+    OptionSpec { names: &["--map"],         takes_value: true,  commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Debug, Command::Emit, Command::Minify, Command::Explain, Command::Equiv] },
+    OptionSpec { names: &["--lint"],        takes_value: false, commands: &[Command::Check] },
+    OptionSpec { names: &["--dump-ast"],    takes_value: false, commands: &[Command::Check] },
+    OptionSpec { names: &["--dump-analysis"], takes_value: false, commands: &[Command::Check] },
+    OptionSpec { names: &["--load-ast"],    takes_value: true,  commands: &[Command::Run, Command::Build, Command::Check, Command::Verify, Command::Emit, Command::Minify, Command::Explain] },
+    OptionSpec { names: &["--print"],       takes_value: true,  commands: &[Command::Generate] },
+    OptionSpec { names: &["--width"],       takes_value: true,  commands: &[Command::Fmt] },
+    OptionSpec { names: &["--inputs"],      takes_value: true,  commands: &[Command::Equiv] },
+    OptionSpec { names: &["--steps"],       takes_value: true,  commands: &[Command::Equiv] }
+];
+
+/* Applies a known option (by its canonical name) to `options` */
+fn apply_option(options : &mut Options, usage : &str, name : &str, value : String)
 {
-    let mut code : Vec<Stmt> = Vec::new();
-    let mut i = start_idx;
-    while i < src.len() {
-        let c = src[i] as char;
-        
-        /* Handle loop entry */
-        if c == '[' {
-            let (loop_code, idx_after_loop) = parse(src, i + 1);
-            code.push(Stmt::Loop(loop_code));
-            i = idx_after_loop;
-            continue;
-        }
-        
-        /* Handle loop exit */
-        if c == ']' {
-            return (code, i + 1);
-        }
-
-        /* handle regular statements */
-        let maybe_statement = match c {
-            '>' => Some(Stmt::Move(1)),
-            '<' => Some(Stmt::Move(-1)),
-            '+' => Some(Stmt::Add(1)),
-            '-' => Some(Stmt::Add(-1)),
-            ',' => Some(Stmt::Input),
-            '.' => Some(Stmt::Output),
-             _  => None
-        };
+    match name {
+        "--help" => {
+            println!("{}", usage);
+            process::exit(0);
+        },
+        "--print" if options.command == Command::Generate => options.text = Some(value),
+        "--print" => options.print = true,
+        "--jit" => options.use_jit = true,
+        "--engine" => options.engine = match value.as_str() {
+            "match"    => Engine::Match,
+            "threaded" => Engine::Threaded,
+            _          => exit_with_usage(usage, &format!("unknown engine '{}', expected match or threaded", value))
+        },
+        "--stats" => options.stats = true,
+        "--profile" => options.profile = true,
+        "--trace" => options.trace = true,
+        "--debug" => {
+            options.syntax.debug = true;
+            options.syntax.bang_breakpoints = match value.as_str() {
+                "" | "#" => false,
+                "!"      => true,
+                _        => exit_with_usage(usage, &format!("unknown breakpoint '{}', expected # or !", value))
+            };
+        },
+        "--pbrain" => options.syntax.procedures = true,
+        "--lint" => options.lint = true,
+        "--dump-ast" => match value.as_str() {
+            "" | "json" => options.dump_ast = true,
+            _           => exit_with_usage(usage, &format!("unknown AST format '{}', expected json", value))
+        },
+        "--dump-analysis" => options.analyze = true,
+        "--load-ast" => {
+            options.load_ast = true;
+            options.filepath = value;
+        },
+        "--strict" => options.syntax.strict = true,
+        "--ext" => match value.as_str() {
+            "random" => options.syntax.random = true,
+            "macros" => options.macros = true,
+            _        => exit_with_usage(usage, &format!("unknown extension '{}', expected random or macros", value))
+        },
+        "--seed" => options.config.seed = match value.parse::<u64>() {
+            Ok(n)  => Some(n),
+            Err(_) => exit_with_usage(usage, &format!("invalid seed '{}'", value))
+        },
+        "--lang" => options.lang = Some(match value.as_str() {
+            "brainfuck" => Lang::Brainfuck,
+            "ook"       => Lang::Ook,
+            "brainfork" => Lang::Brainfork,
+            _           => exit_with_usage(usage, &format!("unknown language '{}', expected brainfuck, ook or brainfork", value))
+        }),
+        "--token-map" => {
+            let text = fs::read(&value).unwrap_or_else(|e| exit_with_usage(usage, &format!("can't read '{}': {}", value, e)));
+            let mut map = TokenMap::new();
+            if let Err(e) = map.read(&text) {
+                exit_with_usage(usage, &format!("in token map '{}': {}", value, e));
+            }
+            options.token_map = Some(map);
+        },
+        "--map" => {
+            if let Err(msg) = TokenMap::new().set_from(&value) {
+                exit_with_usage(usage, &msg);
+            }
+            options.map.push(value);
+        },
+        "--debug-info" => options.debug_info = true,
+        "-O0" => options.build.opt_level = OptLevel::O0,
+        "-O1" => options.build.opt_level = OptLevel::O1,
+        "-O2" => options.build.opt_level = OptLevel::O2,
+        "-O3" => options.build.opt_level = OptLevel::O3,
+        "-Os" => options.build.opt_level = OptLevel::Os,
+        "-Oz" => options.build.opt_level = OptLevel::Oz,
+        "--opt-size" => {
+            options.build.opt_level = OptLevel::Oz;
+            options.build.strip = true;
+        },
+        "--native-cpu" => options.build.native_cpu = match value.as_str() {
+            "on"  => true,
+            "off" => false,
+            _     => exit_with_usage(usage, &format!("unknown --native-cpu setting '{}'", value))
+        },
+        "--target" => options.build.target = Some(value),
+        "--static" => options.build.static_link = true,
+        "--freestanding" => options.build.freestanding = true,
+        "--safe" => options.build.safe = true,
+        "--ir-comments" => options.build.ir_comments = true,
+        "--partial-eval" => options.partial_eval = match value.as_str() {
+            "" => Some(rustfuck::partial_eval::STEPS),
+            _  => match value.parse::<u64>() {
+                Ok(n)  => Some(n),
+                Err(_) => exit_with_usage(usage, &format!("invalid step limit '{}'", value))
+            }
+        },
+        "--linker" => options.build.linker = Some(value),
+        "--snapshot-out" => options.snapshot_out = Some(value),
+        "--snapshot-in" => options.snapshot_in = Some(value),
+        "--trace-limit" => options.trace_limit = match value.parse::<u64>() {
+            Ok(n)  => Some(n),
+            Err(_) => exit_with_usage(usage, &format!("invalid trace limit '{}'", value))
+        },
+        "--trace-lines" => {
+            let (first, last) = value.split_once('-').unwrap_or((&value, &value));
+            options.trace_lines = match (first.trim().parse::<usize>(), last.trim().parse::<usize>()) {
+                (Ok(first), Ok(last)) if first > 0 && first <= last => Some((first, last)),
+                _ => exit_with_usage(usage, &format!("invalid line range '{}'", value))
+            };
+        },
+        "--input" => options.input = Input::File(value),
+        "--inputs" => options.inputs = Some(value),
+        "--input-str" => options.input = Input::Str(value),
+        /* a recording is just the bytes that were read */
+        "--replay-input" => options.input = Input::File(value),
+        "-e" => options.code = Some(value),
+        "--guest" => options.guest = Some(value),
+        "--record-input" => options.record_input = Some(value),
+        "--raw-input" => options.raw_input = true,
+        "-o" | "--output" => options.output = Some(value),
+        "--width" => options.width = match value.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _              => exit_with_usage(usage, &format!("invalid width '{}'", value))
+        },
+        "--tape-size" => options.config.tape_size = match value.parse::<i32>() {
+            Ok(n) if n > 0 => n as usize,
+            _              => exit_with_usage(usage, &format!("invalid tape size '{}'", value))
+        },
+        "--tape" => options.config.tape = match value.as_str() {
+            "fixed"   => Tape::Fixed,
+            "dynamic" => Tape::Dynamic,
+            _         => exit_with_usage(usage, &format!("unknown tape mode '{}'", value))
+        },
+        "--bounds" => options.config.bounds = match value.as_str() {
+            "error"     => Bounds::Error,
+            "wrap"      => Bounds::Wrap,
+            "unchecked" => Bounds::Unchecked,
+            _           => exit_with_usage(usage, &format!("unknown bounds policy '{}'", value))
+        },
+        "--cell-width" => options.config.cell_width = match value.as_str() {
+            "8"  => CellWidth::Bits8,
+            "16" => CellWidth::Bits16,
+            "32" => CellWidth::Bits32,
+            _    => exit_with_usage(usage, &format!("unsupported cell width '{}'", value))
+        },
+        "--io" => options.config.io = match value.as_str() {
+            "bytes"   => Io::Bytes,
+            "numeric" => Io::Numeric,
+            _         => exit_with_usage(usage, &format!("unknown I/O mode '{}'", value))
+        },
+        "--eof" => options.config.eof = match value.as_str() {
+            "zero"      => Eof::Zero,
+            "minus-one" => Eof::MinusOne,
+            "unchanged" => Eof::Unchanged,
+            _           => exit_with_usage(usage, &format!("unknown EOF behavior '{}'", value))
+        },
+        "--preset" => options.config = match value.as_str() {
+            "dbfi" => options.config.for_self_interpreters(),
+            _      => exit_with_usage(usage, &format!("unknown preset '{}', expected dbfi", value))
+        },
+        "--flush" => options.config.flush = match value.as_str() {
+            "line"       => Flush::Line,
+            "full"       => Flush::Full,
+            "every-byte" => Flush::EveryByte,
+            _            => exit_with_usage(usage, &format!("unknown flush policy '{}'", value))
+        },
+        "--max-steps" | "--steps" => options.config.max_steps = match value.parse::<u64>() {
+            Ok(n)  => Some(n),
+            Err(_) => exit_with_usage(usage, &format!("invalid step limit '{}'", value))
+        },
+        "--timeout" => options.config.timeout = match value.parse::<f64>() {
+            Ok(secs) if secs > 0.0 && secs.is_finite() => Some(Duration::from_secs_f64(secs)),
+            _                                          => exit_with_usage(usage, &format!("invalid timeout '{}'", value))
+        },
+        "--backend" => options.backend = match value.as_str() {
+            "native" => Some(Backend::Native),
+            "llvm"   => Some(Backend::Llvm),
+            "bundle" => Some(Backend::Bundle),
+            _        => exit_with_usage(usage, &format!("unknown backend '{}'", value))
+        },
+        "--emit" => for kind in value.split(',') {
+            let artifact = match kind {
+                "llvm-ir"  => Artifact::LlvmIr,
+                "bc"       => Artifact::Bitcode,
+                "asm"      => Artifact::Assembly,
+                "obj"      => Artifact::Object,
+                "exe"      => Artifact::Executable,
+                "cdylib"   => Artifact::SharedLibrary,
+                "bytecode" => Artifact::Bytecode,
+                _          => exit_with_usage(usage, &format!("unknown artifact '{}'", kind))
+            };
+            if !options.emit.contains(&artifact) {
+                options.emit.push(artifact);
+            }
+        },
+        "--to" => options.emit_to = match value.as_str() {
+            "c"      => Some(Language::C),
+            "rust"   => Some(Language::Rust),
+            "wasm"   => Some(Language::Wasm),
+            "jvm"    => Some(Language::Jvm),
+            _        => exit_with_usage(usage, &format!("unknown language '{}'", value))
+        },
+        "--cargo" => options.cargo = true,
+        _ => unreachable!()
+    }
+}
 
-        /* add to program representation */
-        if let Some(s) = maybe_statement {
-            if code.len() == 0 {
-                code.push(s);
+/* Parses the command line, exiting with a usage message on bad arguments */
+fn parse_args(args : &[String]) -> Options
+{
+    let (command, usage, rest) = match args.first().map(|s| s.as_str()) {
+        Some("run")   => (Command::Run, RUN_USAGE_STR, &args[1..]),
+        Some("build") => (Command::Build, BUILD_USAGE_STR, &args[1..]),
+        Some("check") => (Command::Check, CHECK_USAGE_STR, &args[1..]),
+        Some("verify") => (Command::Verify, VERIFY_USAGE_STR, &args[1..]),
+        Some("test")  => (Command::Test, TEST_USAGE_STR, &args[1..]),
+        Some("debug") => (Command::Debug, DEBUG_USAGE_STR, &args[1..]),
+        Some("repl")  => (Command::Repl, REPL_USAGE_STR, &args[1..]),
+        Some("emit")  => (Command::Emit, EMIT_USAGE_STR, &args[1..]),
+        Some("minify") => (Command::Minify, MINIFY_USAGE_STR, &args[1..]),
+        Some("fmt")   => (Command::Fmt, FMT_USAGE_STR, &args[1..]),
+        Some("generate") => (Command::Generate, GENERATE_USAGE_STR, &args[1..]),
+        Some("explain") => (Command::Explain, EXPLAIN_USAGE_STR, &args[1..]),
+        Some("equiv") => (Command::Equiv, EQUIV_USAGE_STR, &args[1..]),
+        Some("-h") | Some("--help") | Some("help") => {
+            println!("{}", USAGE_STR);
+            process::exit(0);
+        },
+        Some(_)       => (Command::Run, RUN_USAGE_STR, args),
+        None          => exit_with_usage(USAGE_STR, "no input file")
+    };
+
+    let mut options = Options {
+        command,
+        filepath:     String::new(),
+        code:         None,
+        batch:        Vec::new(),
+        lint:         false,
+        dump_ast:     false,
+        analyze:      false,
+        load_ast:     false,
+        print:        false,
+        use_jit:      false,
+        engine:       Engine::Match,
+        stats:        false,
+        profile:      false,
+        trace:        false,
+        trace_limit:  None,
+        trace_lines:  None,
+        snapshot_in:  None,
+        snapshot_out: None,
+        syntax:       ParseOptions::default(),
+        macros:       false,
+        lang:         None,
+        token_map:    None,
+        map:          Vec::new(),
+        raw_input:    false,
+        input:        Input::Stdin,
+        guest:        None,
+        record_input: None,
+        output:       None,
+        config:       Config::default(),
+        backend:      None,
+        emit:         Vec::new(),
+        debug_info:   false,
+        partial_eval: None,
+        build:        BuildOptions::default(),
+        emit_to:      None,
+        cargo:        false,
+        width:        rustfuck::formatter::WIDTH,
+        text:         None,
+        inputs:       None
+    };
+
+    let mut files : Vec<String> = Vec::new();
+    let mut iter = rest.iter();
+    while let Some(arg) = iter.next() {
+        /* options take their value either as `--name=value` or `--name value` */
+        let (name, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_owned())),
+            _                                => (arg.as_str(), None)
+        };
+        if name.starts_with('-') && name.len() > 1 {
+            let spec = OPTIONS.iter().find(|spec| spec.names.contains(&name) && spec.commands.contains(&command));
+            let spec = match spec {
+                Some(spec) => spec,
+                None       => exit_with_usage(usage, &format!("unknown option '{}'", name))
+            };
+            let value = if spec.takes_value {
+                match inline_value.or_else(|| iter.next().cloned()) {
+                    Some(value) => value,
+                    None        => exit_with_usage(usage, &format!("option '{}' requires a value", name))
+                }
             } else {
-                let last_idx = code.len() - 1;
-                match (&code[last_idx], &s) {
-                    (Stmt::Move(n), Stmt::Move(m)) => code[last_idx] = Stmt::Move(n + m),
-                    (Stmt::Add(n),  Stmt::Add(m))  => code[last_idx] = Stmt::Add(n + m),
-                    (_, _)                         => code.push(s)
+                match inline_value {
+                    Some(value) if OPTIONAL_VALUES.contains(&spec.names[0]) => value,
+                    Some(_) => exit_with_usage(usage, &format!("option '{}' does not take a value", name)),
+                    None    => String::new()
                 }
+            };
+            apply_option(&mut options, usage, spec.names[0], value);
+            continue;
+        }
+
+        /* the REPL and generate take no file, and only build, check and equiv take several */
+        if (!files.is_empty() && command != Command::Build && command != Command::Check && command != Command::Equiv)
+                || command == Command::Repl
+                || command == Command::Generate {
+            exit_with_usage(usage, &format!("unexpected argument '{}'", arg));
+        }
+        files.push(arg.to_string());
+    }
+
+    if command == Command::Equiv && files.len() != 2 {
+        exit_with_usage(usage, "equiv compares two files");
+    }
+    if options.load_ast && (!files.is_empty() || options.code.is_some()) {
+        exit_with_usage(usage, "a file or '-e' can't be given with '--load-ast'");
+    }
+    match (files.first(), &options.code) {
+        (Some(_), Some(_)) => exit_with_usage(usage, "a file can't be given with '-e'"),
+        (Some(path), None) => options.filepath = path.clone(),
+        (None, Some(_))    => options.filepath = "-e".to_owned(),
+        (None, None) if options.load_ast => (),
+        (None, None)       => match command {
+            Command::Repl => (),
+            Command::Generate if options.text.is_some() => (),
+            Command::Generate => exit_with_usage(usage, "missing '--print <text>'"),
+            Command::Test => exit_with_usage(usage, "no test directory"),
+            _             => exit_with_usage(usage, "no input file")
+        }
+    }
+    if files.iter().any(|path| path == "-") {
+        if files.len() > 1 {
+            exit_with_usage(usage, "'-' can't be given with other files");
+        }
+        if command == Command::Debug || options.raw_input {
+            exit_with_usage(usage, "the program can't be read from stdin when keys are read from it");
+        }
+    }
+    if files.len() > 1 {
+        if options.output.is_some() {
+            exit_with_usage(usage, "'-o' can't be used when building several files");
+        }
+        options.batch = files;
+    }
+    if options.emit.is_empty() {
+        options.emit.push(Artifact::Executable);
+    }
+    if command == Command::Build && options.output.as_deref() == Some("-") && options.emit != [Artifact::LlvmIr] {
+        exit_with_usage(usage, "only '--emit=llvm-ir' can be written to stdout with '-o -'");
+    }
+    if options.cargo {
+        match options.emit_to {
+            None | Some(Language::Rust) => options.emit_to = Some(Language::Rust),
+            Some(_)                     => exit_with_usage(usage, "'--cargo' only goes with '--to rust'")
+        }
+    }
+    if command == Command::Emit && options.emit_to.is_none() {
+        exit_with_usage(usage, "missing '--to <lang>'");
+    }
+    if command == Command::Emit && options.emit_to != Some(Language::C) && options.config.bounds != Bounds::Error {
+        exit_with_usage(usage, "'--bounds' only goes with '--to c'");
+    }
+    /* java looks for a class in the file named after it */
+    if let (Some(Language::Jvm), Some(ref path)) = (options.emit_to, &options.output) {
+        let stem = Path::new(path).file_stem().unwrap_or_default().to_string_lossy();
+        if rustfuck::jvm_generator::class_name(&stem) != stem {
+            exit_with_usage(usage, &format!("'{}' is not a valid Java class name to name the class file after", stem));
+        }
+    }
+    /* WASI programs that can do without libc call it directly, so they link without a WASI sysroot */
+    if command == Command::Build && options.build.targets_wasi() && rustfuck::llvm_ir_generator::supports_freestanding(options.build.arch())
+            && options.config.tape == Tape::Fixed && options.config.io == Io::Bytes && options.backend != Some(Backend::Native) {
+        options.build.freestanding = true;
+    }
+    if !options.map.is_empty() {
+        let map = options.token_map.get_or_insert_with(TokenMap::new);
+        for spec in &options.map {
+            map.set_from(spec).unwrap();
+        }
+    }
+    if let Some(ref map) = options.token_map {
+        if let Err(msg) = map.check() {
+            exit_with_usage(usage, &msg);
+        }
+        if options.lang.is_some_and(|lang| lang != Lang::Brainfuck) {
+            exit_with_usage(usage, "a token map can only be used with '--lang brainfuck'");
+        }
+    }
+    /* Ook! and token maps read everything but their words as comments */
+    if options.syntax.strict && (options.token_map.is_some() || lang_of(&options, &options.filepath) == Lang::Ook) {
+        exit_with_usage(usage, "'--strict' only goes with brainfuck and brainfork");
+    }
+    /* the formatter keeps comments, which only brainfuck tells apart from code */
+    if command == Command::Fmt && lang_of(&options, &options.filepath) != Lang::Brainfuck {
+        exit_with_usage(usage, "only brainfuck can be formatted");
+    }
+    if options.config.seed.is_some() && !options.syntax.random {
+        exit_with_usage(usage, "'--seed' only goes with '--ext random'");
+    }
+    /* the interpreter and the compiled program can only agree on the same random bytes */
+    if (command == Command::Verify || command == Command::Equiv) && options.syntax.random && options.config.seed.is_none() {
+        options.config.seed = Some(0);
+    }
+    /* the debugger always stops at # */
+    if command == Command::Debug {
+        options.syntax.debug = true;
+    }
+    if options.use_jit && options.stats {
+        exit_with_usage(usage, "'--stats' can't be used with '--jit'");
+    }
+    if options.use_jit && options.profile {
+        exit_with_usage(usage, "'--profile' can't be used with '--jit'");
+    }
+    if options.use_jit && options.trace {
+        exit_with_usage(usage, "'--trace' can't be used with '--jit'");
+    }
+    if options.use_jit && (options.snapshot_in.is_some() || options.snapshot_out.is_some()) {
+        exit_with_usage(usage, "snapshots can't be used with '--jit'");
+    }
+    if options.engine == Engine::Threaded {
+        if options.use_jit {
+            exit_with_usage(usage, "'--engine' can't be used with '--jit'");
+        }
+        /* the threaded engine calls no hooks */
+        for &(used, name) in &[(options.stats, "--stats"), (options.profile, "--profile"), (options.trace, "--trace"), (options.syntax.debug, "--debug")] {
+            if used {
+                exit_with_usage(usage, &format!("'{}' can't be used with '--engine threaded'", name));
             }
         }
+    }
+    if options.guest.is_some() && options.snapshot_in.is_some() {
+        exit_with_usage(usage, "'--guest' can't be used with '--snapshot-in', which has what was left of it");
+    }
+    return options;
+}
+
+/* The language of the source file at `path` */
+fn lang_of(options : &Options, path : &str) -> Lang
+{
+    if let Some(lang) = options.lang {
+        return lang;
+    }
+    match Path::new(path).extension() {
+        Some(extension) if extension == "ook" => Lang::Ook,
+        _                                     => Lang::Brainfuck
+    }
+}
+
+/*
+ * The program in `raw`, read from `path`, with its source: bytecode or JSON,
+ * which have none, or source, which is preprocessed and parsed
+ */
+fn load_program(options : &Options, path : &str, raw : &[u8]) -> Result<(Source, Vec<Stmt>), RustfuckError>
+{
+    if Path::new(path).extension().is_some_and(|extension| extension == rustfuck::bytecode::EXTENSION) {
+        let program = rustfuck::bytecode::raise(&rustfuck::bytecode::decode(raw)?);
+        return Ok((rustfuck::preprocessor::expand(Path::new(path), b"", false)?, program));
+    }
+    if options.load_ast {
+        let program = rustfuck::ast_json::from_json(raw)?;
+        return Ok((rustfuck::preprocessor::expand(Path::new(path), b"", false)?, program));
+    }
+    let source = rustfuck::preprocessor::expand(Path::new(path), raw, options.macros)?;
+    let program = parse_source(options, path, &source)?;
+    return Ok((source, program));
+}
 
-        i += 1;
+/* Parses `source`, read from `path`, in its language or as the token map spells it */
+fn parse_source(options : &Options, path : &str, source : &Source) -> Result<Vec<Stmt>, RustfuckError>
+{
+    let lang = lang_of(options, path);
+    if options.token_map.is_none() && lang != Lang::Ook {
+        for (offset, msg) in rustfuck::lint::lookalikes(&source.text) {
+            eprintln!("Warning: {}", source.diagnostic(offset, &msg));
+        }
     }
+    let parsed = match options.token_map {
+        Some(ref map) => rustfuck::parse_mapped(&source.text, map, &options.syntax),
+        None          => rustfuck::parse_with(&source.text, &ParseOptions { lang, ..options.syntax })
+    };
+    return parsed.map_err(|e| source.relocate(e));
+}
 
-    return (code, 0);
+/*
+ * Whether the program is parsed as its source file is read, with
+ * `stream_program()`: a plain brainfuck file, for which nothing but the
+ * program is needed until it fails
+ */
+fn streamed_from(options : &Options) -> bool
+{
+    let path = Path::new(&options.filepath);
+    let plain = !path.extension().is_some_and(|extension| extension == rustfuck::bytecode::EXTENSION)
+            && !options.load_ast && options.token_map.is_none() && !options.syntax.strict
+            && lang_of(options, &options.filepath) != Lang::Ook;
+    let needs_source = options.lint || options.analyze || options.trace || options.profile || options.debug_info
+            || matches!(options.command, Command::Fmt | Command::Debug);
+    return options.code.is_none() && options.filepath != "-" && plain && !needs_source;
 }
 
-fn execute(code : &[Stmt], state : &mut ProgramState) {
-    let mut idx = 0;
-    let modulo = |v, m| { ((v % m) + m) % m };
-    while idx < code.len() {
-        match &code[idx] {
-            Stmt::Move(n) => state.ptr += n,
-            Stmt::Add(n)  => {
-                state.tape[state.ptr as usize] += n;
-                state.tape[state.ptr as usize]  =
-                        modulo(state.tape[state.ptr as usize], 256);
-            },
-            Stmt::Input   => {
-                let input: i32 = std::io::stdin()
-                    .bytes() 
-                    .next()
-                    .and_then(|result| result.ok())
-                    .map(|byte| byte as i32)
-                    .unwrap();
-                state.tape[state.ptr as usize] = modulo(input, 256);
-            },
-            Stmt::Output  => {
-                print!("{}", state.tape[state.ptr as usize] as u8 as char);
-                _ = stdout().flush();
+/*
+ * The program in the source file, parsed as it is read, if it is streamed,
+ * see `streamed_from()`, and needs no preprocessing or lint warnings. Source
+ * that does is read whole after all.
+ */
+fn stream_program(options : &Options) -> Result<Option<Vec<Stmt>>, RustfuckError>
+{
+    if !streamed_from(options) {
+        return Ok(None);
+    }
+    let mut file = Unplain { inner: BufReader::new(File::open(&options.filepath)?), tail: Vec::new(), macros: options.macros, found: false };
+    let program = rustfuck::parse_reader(&mut file, &ParseOptions { lang: lang_of(options, &options.filepath), ..options.syntax });
+    if file.found {
+        return Ok(None);
+    }
+    return program.map(Some);
+}
+
+const INCLUDE : &[u8] = b"#include";
+
+/*
+ * Notes if the source read through it has an include or, if they are
+ * expanded, a macro for the preprocessor in it, or anything but ASCII, which
+ * could be a lookalike of a command
+ */
+struct Unplain<R> {
+    inner  : R,
+    /* the end of what was read before, for an include read in two parts */
+    tail   : Vec<u8>,
+    macros : bool,
+    found  : bool
+}
+
+impl<R : Read> Read for Unplain<R> {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize>
+    {
+        let n = self.inner.read(buf)?;
+        let read = &buf[..n];
+        let mut seam = std::mem::take(&mut self.tail);
+        seam.extend_from_slice(&read[..n.min(INCLUDE.len() - 1)]);
+        self.found = self.found || read.iter().any(|&b| (self.macros && b == b'@') || !b.is_ascii())
+                || seam.windows(INCLUDE.len()).any(|w| w == INCLUDE)
+                || read.windows(INCLUDE.len()).any(|w| w == INCLUDE);
+        self.tail = read[n.saturating_sub(INCLUDE.len() - 1)..].to_vec();
+        return Ok(n);
+    }
+}
+
+impl<R : Seek> Seek for Unplain<R> {
+    fn seek(&mut self, pos : SeekFrom) -> io::Result<u64>
+    {
+        self.tail.clear();
+        return self.inner.seek(pos);
+    }
+}
+
+/* Opens what `,` reads from */
+fn open_input(input : &Input) -> io::Result<Box<dyn Read>>
+{
+    return Ok(match *input {
+        Input::Stdin          => Box::new(io::stdin()),
+        Input::File(ref path) => Box::new(BufReader::new(File::open(path)?)),
+        Input::Str(ref text)  => Box::new(Cursor::new(text.clone().into_bytes()))
+    });
+}
+
+/* Copies every byte read from `inner` to `record` as it is read */
+struct Recorder {
+    inner  : Box<dyn Read>,
+    record : File
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize>
+    {
+        let n = self.inner.read(buf)?;
+        /* unbuffered, so the recording is complete even if the program is killed */
+        self.record.write_all(&buf[..n])?;
+        return Ok(n);
+    }
+}
+
+/* Puts the guest program at `path`, if given, and a `!` before `input` */
+fn with_guest(input : Box<dyn Read>, path : &Option<String>) -> io::Result<Box<dyn Read>>
+{
+    let mut guest = match *path {
+        Some(ref path) => fs::read(path)?,
+        None           => return Ok(input)
+    };
+    guest.push(b'!');
+    return Ok(Box::new(Cursor::new(guest).chain(input)));
+}
+
+/* Records what is read from `input` to the file at `path`, if given */
+fn record_input(input : Box<dyn Read>, path : &Option<String>) -> io::Result<Box<dyn Read>>
+{
+    return Ok(match *path {
+        Some(ref path) => Box::new(Recorder { inner: input, record: File::create(path)? }),
+        None           => input
+    });
+}
+
+/* Maps an error to the exit code reported by the CLI */
+fn exit_code(err : &RustfuckError) -> i32
+{
+    match err {
+        RustfuckError::IO(_)             => 2,
+        RustfuckError::ParseError(_)     => 3,
+        RustfuckError::RuntimeError(..)  => 4,
+        RustfuckError::ToolchainError(_) => 5,
+        RustfuckError::LimitExceeded(..) => 6,
+        RustfuckError::Interrupted(_)    => 130
+    }
+}
+
+/*
+ * Points a runtime or limit error at the instruction it happened at, with a
+ * snippet of the source; a program loaded without source, from bytecode or
+ * JSON, has nothing to point into, so its errors only say what happened
+ */
+fn locate(err : RustfuckError, source : &Source) -> RustfuckError
+{
+    match err {
+        RustfuckError::RuntimeError(msg, Some(_)) if source.text.is_empty() => RustfuckError::RuntimeError(msg, None),
+        RustfuckError::LimitExceeded(msg, Some(_)) if source.text.is_empty() => RustfuckError::LimitExceeded(msg, None),
+        RustfuckError::RuntimeError(msg, Some(offset)) => {
+            RustfuckError::RuntimeError(source.diagnostic(offset, &msg).to_string(), None)
+        },
+        RustfuckError::LimitExceeded(msg, Some(offset)) => {
+            RustfuckError::LimitExceeded(source.diagnostic(offset, &msg).to_string(), None)
+        },
+        _ => err
+    }
+}
+
+/* Where an interrupted program was and what its tape holds, for finding out why it got stuck */
+fn interrupt_report(offset : Option<usize>, source : &Source, state : &ProgramState) -> String
+{
+    let location = match offset {
+        Some(offset) if !source.text.is_empty() => format!("{}\n", source.diagnostic(offset, "interrupted")),
+        _                                       => "interrupted\n".to_owned()
+    };
+    return format!("{}pointer at cell {}\nnon-zero tape region:\n{}", location, state.position(), state.hexdump());
+}
+
+/* The backend to build with, the llvm one if only it can do what was asked */
+fn build_backend(options : &Options) -> Backend
+{
+    let needs_llvm = options.debug_info || options.build.target.is_some() || options.build.freestanding
+            || options.build.safe || options.emit.contains(&Artifact::LlvmIr)
+            || options.emit.contains(&Artifact::SharedLibrary)
+            || options.build.opt_level == OptLevel::Oz || options.syntax.random || options.syntax.debug;
+    return match options.backend {
+        Some(backend)      => backend,
+        None if needs_llvm => Backend::Llvm,
+        None               => Backend::default_for(&options.config)
+    };
+}
+
+/* What the built file's name ends with: the extension of the only artifact, or the executable's */
+fn build_extension(options : &Options) -> &'static str
+{
+    match options.emit[..] {
+        [artifact] if artifact != Artifact::Executable => artifact.extension(),
+        _ if options.build.targets_wasm()               => ".wasm",
+        _                                               => ""
+    }
+}
+
+/* Prints the warnings of the static analyses about `program`, parsed from `source` */
+fn print_lints(program : &[Stmt], source : &Source)
+{
+    for (offset, msg) in rustfuck::lint::analyze(program) {
+        eprintln!("Warning: {}", source.diagnostic(offset, &msg));
+    }
+}
+
+/*
+ * Checks every file of `options.batch` like it had been checked alone, then
+ * lists how each went and fails if any didn't parse.
+ */
+fn check_batch(options : &Options) -> Result<(), RustfuckError>
+{
+    let mut failed = 0;
+    for filepath in &options.batch {
+        let result = fs::read(filepath).map_err(RustfuckError::IO)
+                .and_then(|raw| load_program(options, filepath, &raw));
+        match result {
+            Ok((source, program)) => {
+                if options.lint {
+                    print_lints(&program, &source);
+                }
+                println!("check {} ... ok", filepath);
             },
-            Stmt::Loop(code) => {
-                if state.tape[state.ptr as usize] > 0 {
-                    execute(&code, state);
-                    continue;
+            Err(e) => {
+                failed += 1;
+                println!("check {} ... FAILED", filepath);
+                for line in e.to_string().lines() {
+                    println!("    {}", line);
                 }
             }
         }
-        idx += 1;
     }
+    println!("\n{} ok, {} failed", options.batch.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
 }
 
-fn main() -> io::Result<()> 
+/* Compares the two programs of `options.batch` on each input, failing if they differ on any */
+fn equiv(options : &Options) -> Result<(), RustfuckError>
 {
-    /* read & parse args */
-    let args: Vec<_> = env::args().collect();
-    if args.len() < 2 {
-        exit_with_error(USAGE_STR);
+    let mut programs = Vec::new();
+    for filepath in &options.batch {
+        let (_, program) = load_program(options, filepath, &fs::read(filepath)?)?;
+        programs.push(rustfuck::optimizer::optimize_for(program, &options.config));
+    }
+    let inputs = match options.inputs {
+        Some(ref dir) => rustfuck::equiv::read_inputs(Path::new(dir))?,
+        None          => vec![("(none)".to_owned(), Vec::new())]
+    };
+    let summary = rustfuck::equiv::check(&programs[0], &programs[1], &options.config, &inputs);
+    if summary.different > 0 {
+        process::exit(1);
     }
-   
-    let mut maybe_filepath  : Option<String> = None;
-    let mut run_interpreter : bool = true; 
-    let mut run_compiler    : bool = false; 
-    let mut print           : bool = false; 
-    for arg in &args {
-        match arg.as_str() {
-            "-C" => {run_compiler = true; run_interpreter = false},
-            "-I" => run_interpreter = true,
-            "-P" => print = true,
-            _    => maybe_filepath = Some(arg.to_string()) 
+    Ok(())
+}
+
+/* A file of a batch build, ready to compile */
+struct BatchFile {
+    path       : PathBuf,
+    src        : Vec<u8>,
+    program    : Vec<Stmt>,
+    executable : String
+}
+
+/*
+ * Builds every file of `options.batch` into the current directory, like it
+ * had been built alone, but all at once; then lists how each went and fails
+ * if any didn't build.
+ */
+fn build_batch(options : &Options) -> Result<(), RustfuckError>
+{
+    let backend = build_backend(options);
+
+    /* files that can't be read or parsed fail without getting to the compiler */
+    let load = |filepath : &String| -> Result<BatchFile, RustfuckError> {
+        let (source, program) = load_program(options, filepath, &fs::read(filepath)?)?;
+        let program = rustfuck::optimizer::optimize_for(program, &options.config);
+        let program = options.partial_eval
+                .and_then(|steps| rustfuck::partial_eval::evaluate(&program, &options.config, steps))
+                .unwrap_or(program);
+        let stem = Path::new(filepath).file_stem().map_or("a.out".into(), |s| s.to_string_lossy());
+        Ok(BatchFile {
+            path: fs::canonicalize(filepath).unwrap_or_else(|_| PathBuf::from(filepath)),
+            src: source.text,
+            program,
+            executable: format!("{}{}", stem, build_extension(options))
+        })
+    };
+    let files : Vec<Result<BatchFile, RustfuckError>> = options.batch.iter().map(load).collect();
+    let programs : Vec<BatchProgram> = files.iter().filter_map(|file| file.as_ref().ok()).map(|file| BatchProgram {
+        program:         &file.program,
+        executable_file: &file.executable,
+        debug_source:    if options.debug_info { Some(DebugSource { path: &file.path, src: &file.src }) } else { None }
+    }).collect();
+    let mut built = rustfuck::compiler::compile_batch(&programs, &options.config, backend, &options.emit, &options.build)?.into_iter();
+
+    let mut failed = 0;
+    for (filepath, file) in options.batch.iter().zip(&files) {
+        let result = match file {
+            Ok(file) => built.next().unwrap().map(|()| file).map_err(|e| e.to_string()),
+            Err(e)   => Err(e.to_string())
+        };
+        match result {
+            Ok(file) => println!("build {} ... ok ({})", filepath, file.executable),
+            Err(e)   => {
+                failed += 1;
+                println!("build {} ... FAILED", filepath);
+                for line in e.lines() {
+                    println!("    {}", line);
+                }
+            }
         }
     }
+    println!("\n{} built, {} failed", options.batch.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
 
-    if maybe_filepath == None {
-        exit_with_error(USAGE_STR);
+fn main()
+{
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(exit_code(&e));
     }
+}
 
-    /* read brainfuck file */
-    let filepath = maybe_filepath.unwrap();
-    let file = File::open(&filepath)?;
-    let mut src = Vec::<u8>::new();
-    BufReader::new(file).read_to_end(&mut src)?;
+fn run() -> Result<(), RustfuckError>
+{
+    /* a bundled executable runs the program it was built with, whatever it is given */
+    if let Some((ops, config)) = rustfuck::bundle::load()? {
+        let mut state = ProgramState::with_config(&config);
+        return rustfuck::interpreter::execute_ops(&ops, &mut state, &mut io::stdin(), &mut io::stdout());
+    }
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = parse_args(&args);
+    if options.command == Command::Repl {
+        return rustfuck::repl::run(&options.config);
+    }
+    if let Some(ref text) = options.text {
+        let code = rustfuck::generate::printer(text.as_bytes())?;
+        match options.output {
+            Some(ref path) => fs::write(path, code)?,
+            None           => print!("{}", code)
+        }
+        return Ok(());
+    }
+    if options.command == Command::Test {
+        let summary = rustfuck::test_runner::run(Path::new(&options.filepath), &options.config)?;
+        if summary.failed > 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if !options.batch.is_empty() && options.command == Command::Check {
+        return check_batch(&options);
+    }
+    if options.command == Command::Equiv {
+        return equiv(&options);
+    }
+    if !options.batch.is_empty() {
+        return build_batch(&options);
+    }
+
+    /* a huge plain source file is parsed as it is read, rather than read whole first */
+    let streamed = stream_program(&options)?;
+
+    /* read brainfuck file, or the code given instead, and the files it includes */
+    let raw = match options.code {
+        Some(ref code)                  => code.clone().into_bytes(),
+        None if streamed.is_some()      => Vec::new(),
+        None if options.filepath == "-" => {
+            let mut raw = Vec::<u8>::new();
+            io::stdin().read_to_end(&mut raw)?;
+            raw
+        },
+        None                            => {
+            let file = File::open(&options.filepath)?;
+            let mut raw = Vec::<u8>::new();
+            BufReader::new(file).read_to_end(&mut raw)?;
+            raw
+        }
+    };
 
     /* Parse into brainfuck program representation */
-    let (program, _) = parse(&src, 0);
-  
+    let unread = streamed.is_some();
+    let (source, program) = match streamed {
+        Some(program) => (rustfuck::preprocessor::expand(Path::new(&options.filepath), b"", false)?, program),
+        None          => load_program(&options, &options.filepath, &raw)?
+    };
+    if options.command == Command::Check {
+        if options.lint {
+            print_lints(&program, &source);
+        }
+        if options.dump_ast {
+            print!("{}", rustfuck::ast_json::to_json(&program));
+        }
+        if options.analyze {
+            print!("{}", rustfuck::analysis::report(&program, &source.text, rustfuck::analysis::Layout::of(&options.config)));
+        }
+        if !options.dump_ast && !options.analyze {
+            println!("{}: ok", options.filepath);
+        }
+        return Ok(());
+    }
+
+    /* Format the source itself, comments and all */
+    if options.command == Command::Fmt {
+        let formatted = rustfuck::formatter::format(&raw, options.width)?;
+        match options.output {
+            Some(ref path) => fs::write(path, formatted)?,
+            None           => print!("{}", formatted)
+        }
+        return Ok(());
+    }
+
+    /* Minify the program as written, optimizing it only if that makes it shorter */
+    if options.command == Command::Minify {
+        let minified = rustfuck::minify::minify(program);
+        match options.output {
+            Some(ref path) => fs::write(path, minified + "\n")?,
+            None           => println!("{}", minified)
+        }
+        return Ok(());
+    }
+
+    /* Debug the program as written, so each step is something in the source */
+    if options.command == Command::Debug {
+        let input = match options.input {
+            Input::Stdin => Box::new(io::empty()),
+            ref input    => open_input(input)?
+        };
+        let mut input = record_input(with_guest(input, &options.guest)?, &options.record_input)?;
+        return rustfuck::debugger::run(&program, &source.text, &options.filepath, &options.config, &mut input);
+    }
+    let program = rustfuck::optimizer::optimize_for(program, &options.config);
+
+    /* Explain the optimized program, which has the idioms the optimizer recognized in it */
+    if options.command == Command::Explain {
+        let explanation = rustfuck::explain::explain(&program);
+        match options.output {
+            Some(ref path) => fs::write(path, explanation)?,
+            None           => print!("{}", explanation)
+        }
+        return Ok(());
+    }
+
     /* Debug print program */
-    if print {
+    if options.print {
         println!("{:?}", program);
     }
 
-    /* Execute program in interpreter */
-    if run_interpreter {
-        let mut state = ProgramState {
-            ptr: 0,
-            tape: [0; TAPE_SIZE]
-        };
-        execute(&program, &mut state);
-    }
-
-    /* compile program */
-    if run_compiler {
-        let executable_file = filepath
-                .split('/').last().unwrap() // strip path
-                .split('.').nth(0).unwrap() // strip extension
-                .to_owned();
-        let ll_file = format!("{}.ll", executable_file);
-        let bc_file = format!("{}.bc", executable_file);
-        let o_file  = format!("{}.o",  executable_file);
-
-        /* generate LLVM IR */
-        println!("[{}1/5{}] Generating LLVM ir...", COLOR_PURPLE, COLOR_NONE);
-        let ir = llvm_ir_generator::code_gen(&program);
-        let f = File::create(&ll_file)?;
-        BufWriter::new(f).write_all(ir.as_bytes())?;
-        
-        /* Run LLVM optimizer */
-        println!("[{}2/5{}] Running LLVM optimizer... (this step might take some time)", COLOR_PURPLE, COLOR_NONE);
-        Command::new("opt").arg(&ll_file)
-                .arg("-O3").arg("-march=native")
-                .arg("-o").arg(&bc_file).output()?;
-
-        /* Run LLVM static compiler */
-        println!("[{}3/5{}] Running LLVM compiler...", COLOR_PURPLE, COLOR_NONE); 
-        Command::new("llc").arg(&bc_file).arg("-filetype=obj").output()?;
-
-        /* Run LLVM linker */
-        println!("[{}4/5{}] Running linker...", COLOR_PURPLE, COLOR_NONE); 
-        Command::new("gcc").arg(&o_file).
-                arg("-o").arg(&executable_file).output()?;
-        
-        /* Cleanup dir */
-        println!("[{}5/5{}] Cleaning directory...", COLOR_PURPLE, COLOR_NONE); 
-        Command::new("rm").arg("-rf").arg(&ll_file).arg(&bc_file).arg(&o_file).output()?;
-        
-        println!("\n\t{}Successfully built executable{}: {}", COLOR_GREEN, COLOR_NONE, &executable_file); 
+    /* outputs default to the input's file name, minus its extension, in the CWD */
+    let stem = Path::new(&options.filepath).file_stem()
+            .filter(|_| options.filepath != "-")
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "a.out".to_owned());
+    let output_file = |extension : &str| match options.output {
+        Some(ref path) => path.clone(),
+        None           => format!("{}{}", stem, extension)
+    };
+
+    /* threads take turns, an instruction each, which only the interpreter steps through */
+    if rustfuck::uses_forks(&program) && (options.use_jit || options.emit_to.is_some()) {
+        return Err(RustfuckError::ToolchainError("Brainfork threads can only be run by the interpreter".to_owned()));
+    }
+
+    /* procedures are numbered at runtime, so only the interpreter and the llvm backend keep a table of them */
+    if rustfuck::uses_procedures(&program) {
+        if options.use_jit {
+            return Err(RustfuckError::RuntimeError("the JIT does not support pbrain procedures".to_owned(), None));
+        }
+        if options.emit_to.is_some() {
+            return Err(RustfuckError::ToolchainError(
+                    "pbrain procedures can only be compiled by the llvm backend, use build".to_owned()));
+        }
+    }
+
+    /* random bytes come from a generator only the interpreter and the llvm backend's runtime have */
+    if rustfuck::uses_random(&program) {
+        if options.use_jit {
+            return Err(RustfuckError::RuntimeError("the JIT does not support random bytes".to_owned(), None));
+        }
+        if options.emit_to.is_some() {
+            return Err(RustfuckError::ToolchainError(
+                    "random bytes can only be compiled by the llvm backend, use build".to_owned()));
+        }
+    }
+
+    match (options.command, options.emit_to) {
+        /* Execute program in interpreter, or natively through the JIT */
+        (Command::Run, _) => {
+            /* what a resumed program hadn't read yet comes first */
+            let (mut state, pending) = match options.snapshot_in {
+                Some(ref path) => rustfuck::snapshot::load(path, &program, &options.config)?,
+                None           => (ProgramState::with_config(&options.config), Vec::new())
+            };
+            let input = with_guest(open_input(&options.input)?, &options.guest)?;
+            let mut input = Cursor::new(pending).chain(record_input(input, &options.record_input)?);
+            let mut output : Box<dyn Write> = match options.output {
+                Some(ref path) => Box::new(File::create(path)?),
+                None           => Box::new(io::stdout())
+            };
+            /* restored when dropped at the end of this block, or on a panic */
+            let _raw_mode = if options.raw_input { Some(RawMode::enable()?) } else { None };
+            if options.use_jit {
+                rustfuck::jit::execute(&rustfuck::bytecode::lower(&program), &mut state, &mut input, &mut output)?;
+                return Ok(());
+            }
+            rustfuck::terminal::catch_interrupts();
+            let result = if options.stats || options.profile || options.trace || options.syntax.debug {
+                /* reported even if the program fails, which is when they are most interesting */
+                let stats = if options.stats { Some(Stats::new()) } else { None };
+                let profile = if options.profile { Some(Profile::new(&program)) } else { None };
+                let trace = if options.trace { Some(Trace::new(&program, &source.text, io::stderr(), options.trace_limit, options.trace_lines)) } else { None };
+                let breakpoints = if options.syntax.debug { Some(ShowBreakpoints) } else { None };
+                let mut hooks = (stats, (profile, (trace, breakpoints)));
+                let start = Instant::now();
+                let result = rustfuck::interpreter::execute_with(&program, &mut state, &mut input, &mut output, &mut hooks);
+                if let Some(ref mut stats) = hooks.0 {
+                    stats.elapsed = start.elapsed();
+                    eprint!("{}", stats);
+                }
+                if let Some(ref profile) = (hooks.1).0 {
+                    eprint!("{}", profile.report(&source.text, PROFILE_LOOPS));
+                }
+                result
+            } else if options.engine == Engine::Threaded {
+                rustfuck::interpreter::execute_threaded(&program, &mut state, &mut input, &mut output)
+            } else {
+                rustfuck::execute(&program, &mut state, &mut input, &mut output)
+            };
+            /* a streamed source is only read again to point into it */
+            let source = match result {
+                Err(_) if unread => rustfuck::preprocessor::expand(Path::new(&options.filepath), &fs::read(&options.filepath)?, options.macros)?,
+                _                => source
+            };
+            if let Err(RustfuckError::Interrupted(offset)) = result {
+                eprint!("{}", interrupt_report(offset, &source, &state));
+            }
+            let stopped = matches!(result, Err(RustfuckError::Interrupted(_)) | Err(RustfuckError::LimitExceeded(..)));
+            if let (Some(ref path), true) = (&options.snapshot_out, stopped) {
+                /* the rest of stdin is left for whoever resumes, reading it could block */
+                let (pending, rest) = input.get_mut();
+                let mut unread = pending.get_ref()[pending.position() as usize..].to_vec();
+                if !matches!(options.input, Input::Stdin) {
+                    rest.read_to_end(&mut unread)?;
+                }
+                rustfuck::snapshot::save(path, &program, &state, &unread)?;
+                eprintln!("Saved a snapshot to {}, resume with '--snapshot-in {}'", path, path);
+            }
+            if let Err(RustfuckError::Interrupted(_)) = result {
+                return Err(RustfuckError::Interrupted(None));
+            }
+            result.map_err(|e| locate(e, &source))?;
+        },
+
+        /* compile program */
+        (Command::Build, _) => {
+            let backend = build_backend(&options);
+            /* absolute, so debuggers find the source wherever they are run */
+            let path = fs::canonicalize(&options.filepath).unwrap_or_else(|_| PathBuf::from(&options.filepath));
+            let debug_file = DebugSource { path: &path, src: &source.text };
+            let debug_source = if options.debug_info { Some(&debug_file) } else { None };
+            /* a program that reads no input can be run now, leaving only its output to print */
+            let evaluated = options.partial_eval.and_then(|steps| rustfuck::partial_eval::evaluate(&program, &options.config, steps));
+            let program = evaluated.as_deref().unwrap_or(&program);
+            /* for piping into opt or llc, with none of the build's progress around it */
+            if options.output.as_deref() == Some("-") {
+                if backend != Backend::Llvm {
+                    return Err(RustfuckError::ToolchainError(
+                            "only the llvm backend can emit LLVM IR".to_owned()));
+                }
+                print!("{}", rustfuck::compiler::llvm_ir(program, &options.config, debug_source, &options.build)?);
+                return Ok(());
+            }
+            rustfuck::compiler::compile(program, &options.config, &output_file(build_extension(&options)), backend, &options.emit, debug_source, &options.build)?
+        },
+
+        /* translate program to C */
+        (Command::Emit, Some(Language::C)) => {
+            let c_file = output_file(".c");
+            fs::write(&c_file, rustfuck::c_generator::code_gen(&program, &options.config))?;
+            println!("Wrote C source: {}", c_file);
+        },
+
+        /* translate program to Rust */
+        (Command::Emit, Some(Language::Rust)) if options.cargo => {
+            let dir = PathBuf::from(output_file(""));
+            let name = dir.file_name().map_or(stem.clone(), |name| name.to_string_lossy().into_owned());
+            fs::create_dir_all(dir.join("src"))?;
+            fs::write(dir.join("Cargo.toml"), rustfuck::rust_generator::cargo_manifest(&rustfuck::rust_generator::package_name(&name)))?;
+            fs::write(dir.join(".gitignore"), "/target\n")?;
+            fs::write(dir.join("src").join("main.rs"), rustfuck::rust_generator::code_gen(&program, &options.config))?;
+            println!("Wrote Cargo project: {}", dir.display());
+        },
+        (Command::Emit, Some(Language::Rust)) => {
+            let rs_file = output_file(".rs");
+            fs::write(&rs_file, rustfuck::rust_generator::code_gen(&program, &options.config))?;
+            println!("Wrote Rust source: {}", rs_file);
+        },
+
+        /* translate program to WebAssembly */
+        (Command::Emit, Some(Language::Wasm)) => {
+            let wat_file = output_file(".wat");
+            fs::write(&wat_file, rustfuck::wasm_generator::code_gen(&program, &options.config))?;
+            println!("Wrote WebAssembly module: {}", wat_file);
+        },
+
+        /* compile program to a JVM class, which has to be named after its file */
+        (Command::Emit, Some(Language::Jvm)) => {
+            let class_file = match options.output {
+                Some(ref path) => path.clone(),
+                None           => format!("{}.class", rustfuck::jvm_generator::class_name(&stem))
+            };
+            let path = Path::new(&class_file);
+            let class = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            fs::write(&class_file, rustfuck::jvm_generator::code_gen(&program, &options.config, &class))?;
+            let classpath = match path.parent() {
+                Some(dir) if dir != Path::new("") => format!("-cp {} ", dir.display()),
+                _                                 => String::new()
+            };
+            println!("Wrote JVM class: {} (run it with java {}{})", class_file, classpath, class);
+        },
+
+        /* compare the interpreter with the JIT or a compiled executable */
+        (Command::Verify, _) => {
+            let mut input = Vec::new();
+            open_input(&options.input)?.read_to_end(&mut input)?;
+            let expected = rustfuck::verify::interpret(&program, &options.config, &input);
+            let (actual, name) = if options.use_jit {
+                (rustfuck::verify::run_jit(&program, &options.config, &input), "JIT")
+            } else {
+                let backend = build_backend(&options);
+                (rustfuck::verify::run_compiled(&program, &options.config, backend, &input)?, "compiled program")
+            };
+            match rustfuck::verify::compare(&expected, &actual, name) {
+                None         => println!("{}: ok, the interpreter and the {} agree ({} bytes of output)",
+                        options.filepath, name, expected.output.len()),
+                Some(report) => {
+                    println!("{}: {}", options.filepath, report);
+                    process::exit(1);
+                }
+            }
+        },
+
+        (Command::Emit, None) | (Command::Check, _) | (Command::Debug, _) | (Command::Repl, _) | (Command::Test, _)
+                | (Command::Minify, _) | (Command::Fmt, _) | (Command::Generate, _) | (Command::Explain, _) | (Command::Equiv, _)
+                => unreachable!()
     }
 
     Ok(())
@@ -0,0 +1,142 @@
+/*
+ * Snapshots of a program the interpreter stopped, to resume it later, maybe
+ * in another process. A snapshot is a small text file:
+ *
+ *     rustfuck snapshot 1
+ *     program <fingerprint of the lowered program, in hex>
+ *     pc <next instruction of the lowered program>
+ *     ptr <tape index of the pointer>
+ *     origin <cells a dynamic tape grew to the left>
+ *     tape <every cell, separated by spaces>
+ *     input <input the program hadn't read yet, in hex>
+ *
+ * followed, for a pbrain program that defined procedures, by
+ *
+ *     procedures <cell value>:<instruction the procedure starts at>, separated by spaces
+ *     calls <instruction each procedure called returns to, innermost last>
+ *
+ * An instruction position only means something in the program it came
+ * from, so a snapshot can only be resumed with a program that lowers to the
+ * same instructions, which the fingerprint checks.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use bytecode;
+use bytecode::Op;
+use Config;
+use RustfuckError;
+use Stmt;
+use cells::Cells;
+use interpreter::ProgramState;
+
+const HEADER : &str = "rustfuck snapshot 1";
+
+/// Writes `state`, a state of `code`, and `input`, the input it hasn't read
+/// yet, to a snapshot at `path`. A Brainfork program stopped with several
+/// threads can't be saved.
+pub fn save(path : &str, code : &[Stmt], state : &ProgramState, input : &[u8]) -> Result<(), RustfuckError>
+{
+    if !state.threads.is_empty() {
+        return Err(RustfuckError::IO(io::Error::new(io::ErrorKind::Unsupported,
+                "snapshots of several Brainfork threads are not supported")));
+    }
+    let tape : Vec<String> = state.tape.values().iter().map(|value| value.to_string()).collect();
+    let input : String = input.iter().map(|b| format!("{:02x}", b)).collect();
+    let mut snapshot = format!("{}\nprogram {:016x}\npc {}\nptr {}\norigin {}\ntape {}\ninput {}\n",
+            HEADER, fingerprint(&bytecode::lower(code)), state.pc, state.ptr, state.origin, tape.join(" "), input);
+    if !state.procedures.is_empty() {
+        let mut procedures : Vec<(&i32, &usize)> = state.procedures.iter().collect();
+        procedures.sort();
+        let procedures : Vec<String> = procedures.iter().map(|(number, pc)| format!("{}:{}", number, pc)).collect();
+        let calls : Vec<String> = state.calls.iter().map(|pc| pc.to_string()).collect();
+        snapshot += &format!("procedures {}\ncalls {}\n", procedures.join(" "), calls.join(" "));
+    }
+    fs::write(path, snapshot)?;
+    Ok(())
+}
+
+/// Reads the snapshot at `path` of a state of `code`, returning the state,
+/// configured by `config` except for the tape, and the input that was left.
+pub fn load(path : &str, code : &[Stmt], config : &Config) -> Result<(ProgramState, Vec<u8>), RustfuckError>
+{
+    let text = fs::read_to_string(path)?;
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(invalid("not a snapshot, or of an unsupported version"));
+    }
+    let mut field = |name : &str| -> Result<&str, RustfuckError> {
+        match lines.next().and_then(|line| line.strip_prefix(name)) {
+            Some(value) => Ok(value.trim_start()),
+            None        => Err(invalid(&format!("missing '{}'", name)))
+        }
+    };
+
+    let program = u64::from_str_radix(field("program")?, 16).map_err(|_| invalid("bad program fingerprint"))?;
+    let pc : usize = field("pc")?.parse().map_err(|_| invalid("bad instruction position"))?;
+    let ptr : i32 = field("ptr")?.parse().map_err(|_| invalid("bad pointer"))?;
+    let origin : usize = field("origin")?.parse().map_err(|_| invalid("bad origin"))?;
+    let tape = field("tape")?.split_whitespace()
+            .map(|value| value.parse::<i32>())
+            .collect::<Result<Vec<i32>, _>>()
+            .map_err(|_| invalid("bad tape"))?;
+    let input = field("input")?;
+    let input = (0..input.len()).step_by(2)
+            .map(|i| input.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| invalid("bad input"))?;
+    let procedures = match lines.next() {
+        Some(line) => line.strip_prefix("procedures").ok_or_else(|| invalid("unexpected line after 'input'"))?
+                .split_whitespace()
+                .map(|entry| entry.split_once(':').and_then(|(number, pc)| Some((number.parse::<i32>().ok()?, pc.parse::<usize>().ok()?))))
+                .collect::<Option<HashMap<i32, usize>>>()
+                .ok_or_else(|| invalid("bad procedures"))?,
+        None       => HashMap::new()
+    };
+    let calls = match lines.next() {
+        Some(line) => line.strip_prefix("calls").ok_or_else(|| invalid("missing 'calls'"))?
+                .split_whitespace()
+                .map(|pc| pc.parse::<usize>())
+                .collect::<Result<Vec<usize>, _>>()
+                .map_err(|_| invalid("bad calls"))?,
+        None       => Vec::new()
+    };
+
+    let ops = bytecode::lower(code);
+    if program != fingerprint(&ops) {
+        return Err(invalid("it was taken of a different program"));
+    }
+    if pc > ops.len() || ptr < 0 || ptr as usize >= tape.len() || origin > tape.len() {
+        return Err(invalid("the position, pointer or origin is out of range"));
+    }
+    if procedures.values().chain(&calls).any(|&pc| pc > ops.len()) {
+        return Err(invalid("a procedure or call is out of range"));
+    }
+    let mut state = ProgramState::with_config(config);
+    state.tape = Cells::from_values(config.cell_width, &tape);
+    state.ptr = ptr;
+    state.origin = origin;
+    state.pc = pc;
+    state.procedures = procedures;
+    state.calls = calls;
+    return Ok((state, input));
+}
+
+fn invalid(msg : &str) -> RustfuckError
+{
+    return RustfuckError::IO(io::Error::new(io::ErrorKind::InvalidData, format!("invalid snapshot: {}", msg)));
+}
+
+/* FNV-1a of the instructions as text, which stays the same across builds unlike `DefaultHasher` */
+fn fingerprint(ops : &[Op]) -> u64
+{
+    let mut hash : u64 = 0xcbf29ce484222325;
+    for op in ops {
+        for b in op.to_string().bytes().chain(Some(b'\n')) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    return hash;
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+use std::time::Duration;
+use bytecode::Op;
+use config::Bounds;
+use config::Tape;
+use interpreter::Hooks;
+use interpreter::ProgramState;
+
+/* Names of the instructions, in the order they are counted in */
+const OP_NAMES : [&str; 16] = [
+    "move", "add", "set", "mul-add", "add-at", "scan", "input", "output", "jump-if-zero", "jump-if-non-zero",
+    "debug", "proc", "return", "call", "fork", "random"
+];
+
+/* Fused ops, which are only run without hooks, are counted as their first op */
+fn op_index(op : Op) -> usize
+{
+    match op {
+        Op::Move(_) | Op::MoveJumpIfZero(..) | Op::MoveJumpIfNonZero(..) => 0,
+        Op::Add(_) | Op::AddMove(..)                                    => 1,
+        Op::Set(_) | Op::SetAdd(..)                                     => 2,
+        Op::MulAdd(_, _)                                                => 3,
+        Op::AddAt(_, _)                                                 => 4,
+        Op::Scan(_)                                                     => 5,
+        Op::Input                                                       => 6,
+        Op::Output                                                      => 7,
+        Op::JumpIfZero(_)                                               => 8,
+        Op::JumpIfNonZero(_)                                            => 9,
+        Op::Debug(_)                                                    => 10,
+        Op::Proc(_)                                                     => 11,
+        Op::Return                                                      => 12,
+        Op::Call                                                        => 13,
+        Op::Fork                                                        => 14,
+        Op::Random                                                      => 15
+    }
+}
+
+/// Execution metrics of an interpreted program, collected by passing it as
+/// the hooks to `interpreter::execute_with()`. Cells are numbered as the
+/// program sees them, so cells left of where it started are negative.
+pub struct Stats {
+    /// Number of instructions executed, per kind of instruction.
+    pub counts      : [u64; 16],
+    /// Rightmost cell the pointer has been at.
+    pub peak_ptr    : i64,
+    /// Wall time the program ran for, to be filled in by the caller.
+    pub elapsed     : Duration,
+    /* for every cell from `first_cell` on, whether it was read or written */
+    touched         : Vec<bool>,
+    first_cell      : i64,
+    /* where the pointer was before a scan */
+    scan_start      : i64
+}
+
+impl Stats {
+    pub fn new() -> Stats
+    {
+        return Stats {
+            counts:     [0; 16],
+            peak_ptr:   0,
+            elapsed:    Duration::default(),
+            touched:    Vec::new(),
+            first_cell: 0,
+            scan_start: 0
+        };
+    }
+
+    /// Total number of instructions executed.
+    pub fn total(&self) -> u64
+    {
+        return self.counts.iter().sum();
+    }
+
+    /// Number of distinct cells the program read or wrote.
+    pub fn cells_touched(&self) -> usize
+    {
+        return self.touched.iter().filter(|&&t| t).count();
+    }
+
+    fn touch(&mut self, cell : i64)
+    {
+        if self.touched.is_empty() {
+            self.first_cell = cell;
+        } else if cell < self.first_cell {
+            /* at least doubling, like a dynamic tape, so walking left stays linear */
+            let shift = ((self.first_cell - cell) as usize).max(self.touched.len());
+            self.touched.splice(0..0, std::iter::repeat_n(false, shift));
+            self.first_cell -= shift as i64;
+        }
+        let idx = (cell - self.first_cell) as usize;
+        if idx >= self.touched.len() {
+            self.touched.resize(idx + 1, false);
+        }
+        self.touched[idx] = true;
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Stats
+    {
+        return Stats::new();
+    }
+}
+
+/* The cell `offset` away from the pointer, or `None` if that is off a fixed tape that doesn't wrap */
+fn cell_at(state : &ProgramState, offset : i32) -> Option<i64>
+{
+    let cell = state.position() + offset as i64;
+    let len = state.tape.len() as i64;
+    if state.config.tape == Tape::Dynamic || (cell >= 0 && cell < len) {
+        return Some(cell);
+    }
+    match state.config.bounds {
+        Bounds::Wrap => Some(cell.rem_euclid(len)),
+        _            => None
+    }
+}
+
+impl Hooks for Stats {
+    fn before_op(&mut self, _pc : usize, op : Op, state : &ProgramState)
+    {
+        self.counts[op_index(op)] += 1;
+        match op {
+            Op::Move(_) | Op::Debug(_) | Op::Return => (),
+            Op::MulAdd(offset, _) => {
+                self.touch(state.position());
+                if !state.tape.is_zero(state.ptr as usize) {
+                    if let Some(cell) = cell_at(state, offset) {
+                        self.touch(cell);
+                    }
+                }
+            },
+            Op::AddAt(offset, _) => {
+                if let Some(cell) = cell_at(state, offset) {
+                    self.touch(cell);
+                }
+            },
+            Op::Scan(_) => self.scan_start = state.position(),
+            Op::Fork => {
+                self.touch(state.position());
+                if let Some(cell) = cell_at(state, 1) {
+                    self.touch(cell);
+                }
+            },
+            _ => self.touch(state.position())
+        }
+    }
+
+    fn after_op(&mut self, _pc : usize, op : Op, state : &ProgramState)
+    {
+        let ptr = state.position();
+        self.peak_ptr = self.peak_ptr.max(ptr);
+        if let Op::Scan(stride) = op {
+            /* every cell from the start up to the zero cell was looked at, possibly around a wrapping tape */
+            let len = state.tape.len() as i64;
+            let mut cell = self.scan_start;
+            loop {
+                self.touch(cell);
+                if cell == ptr {
+                    break;
+                }
+                cell += stride as i64;
+                if state.config.tape == Tape::Fixed {
+                    cell = cell.rem_euclid(len);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "instructions executed: {}\n", self.total())?;
+        for (name, count) in OP_NAMES.iter().zip(self.counts.iter()) {
+            write!(f, "    {:<20} {}\n", name, count)?;
+        }
+        write!(f, "peak pointer position: {}\n", self.peak_ptr)?;
+        write!(f, "cells touched:         {}\n", self.cells_touched())?;
+        write!(f, "wall time:             {:?}\n", self.elapsed)
+    }
+}
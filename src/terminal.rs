@@ -0,0 +1,224 @@
+/*
+ * Terminal control for interactive programs. Only what `--raw-input`, the
+ * debugger and catching Ctrl-C need is bound from libc: the termios of stdin,
+ * whether a key is waiting on it, the size of the terminal on stdout and a
+ * handler for SIGINT.
+ */
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use RustfuckError;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    #[cfg(target_os = "linux")]
+    mod consts {
+        pub type TcFlag = u32;
+        pub type NFds   = u64;
+        pub const NCCS       : usize  = 32;
+        pub const ISIG       : TcFlag = 0o000001;
+        pub const ICANON     : TcFlag = 0o000002;
+        pub const ECHO       : TcFlag = 0o000010;
+        pub const VTIME      : usize  = 5;
+        pub const VMIN       : usize  = 6;
+        pub const TIOCGWINSZ : u64    = 0x5413;
+    }
+
+    #[cfg(target_os = "macos")]
+    mod consts {
+        pub type TcFlag = u64;
+        pub type NFds   = u32;
+        pub const NCCS       : usize  = 20;
+        pub const ISIG       : TcFlag = 0x00000080;
+        pub const ICANON     : TcFlag = 0x00000100;
+        pub const ECHO       : TcFlag = 0x00000008;
+        pub const VMIN       : usize  = 16;
+        pub const VTIME      : usize  = 17;
+        pub const TIOCGWINSZ : u64    = 0x40087468;
+    }
+
+    pub use self::consts::*;
+
+    pub const STDIN_FILENO  : i32 = 0;
+    pub const STDOUT_FILENO : i32 = 1;
+    pub const TCSANOW       : i32 = 0;
+    pub const POLLIN        : i16 = 1;
+    pub const SIGINT        : i32 = 2;
+    pub const SIG_DFL       : usize = 0;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag  : TcFlag,
+        pub c_oflag  : TcFlag,
+        pub c_cflag  : TcFlag,
+        pub c_lflag  : TcFlag,
+        #[cfg(target_os = "linux")]
+        pub c_line   : u8,
+        pub c_cc     : [u8; NCCS],
+        pub c_ispeed : TcFlag,
+        pub c_ospeed : TcFlag
+    }
+
+    #[repr(C)]
+    pub struct PollFd {
+        pub fd      : i32,
+        pub events  : i16,
+        pub revents : i16
+    }
+
+    #[repr(C)]
+    pub struct WinSize {
+        pub ws_row    : u16,
+        pub ws_col    : u16,
+        pub ws_xpixel : u16,
+        pub ws_ypixel : u16
+    }
+
+    extern "C" {
+        pub fn isatty(fd : i32) -> i32;
+        pub fn poll(fds : *mut PollFd, nfds : NFds, timeout : i32) -> i32;
+        pub fn ioctl(fd : i32, request : u64, ...) -> i32;
+        pub fn tcgetattr(fd : i32, termios : *mut Termios) -> i32;
+        pub fn tcsetattr(fd : i32, action : i32, termios : *const Termios) -> i32;
+        pub fn signal(signum : i32, handler : usize) -> usize;
+    }
+}
+
+/// Keeps the terminal on stdin in raw mode, without line buffering or echo,
+/// so that every keypress can be read as soon as it is typed. Signal keys
+/// like Ctrl-C still work. The previous mode is restored when this is
+/// dropped, which also happens while unwinding from a panic.
+pub struct RawMode {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    saved : Option<sys::Termios>
+}
+
+impl RawMode {
+    /// Switches stdin to raw mode. If stdin is not a terminal there is
+    /// nothing to switch and this does nothing.
+    pub fn enable() -> Result<RawMode, RustfuckError>
+    {
+        return RawMode::switch(false);
+    }
+
+    /// Like `enable()`, but signal keys are read like any other, e.g. Ctrl-C
+    /// as byte 3, for programs that need to clean up the screen when quit.
+    pub fn enable_without_signals() -> Result<RawMode, RustfuckError>
+    {
+        return RawMode::switch(true);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn switch(no_signals : bool) -> Result<RawMode, RustfuckError>
+    {
+        use std::io;
+        use std::mem;
+
+        unsafe {
+            if sys::isatty(sys::STDIN_FILENO) == 0 {
+                return Ok(RawMode { saved: None });
+            }
+            let mut saved : sys::Termios = mem::zeroed();
+            if sys::tcgetattr(sys::STDIN_FILENO, &mut saved) != 0 {
+                return Err(RustfuckError::IO(io::Error::last_os_error()));
+            }
+            let mut raw = saved;
+            raw.c_lflag &= !(sys::ICANON | sys::ECHO);
+            if no_signals {
+                raw.c_lflag &= !sys::ISIG;
+            }
+            raw.c_cc[sys::VMIN] = 1;
+            raw.c_cc[sys::VTIME] = 0;
+            if sys::tcsetattr(sys::STDIN_FILENO, sys::TCSANOW, &raw) != 0 {
+                return Err(RustfuckError::IO(io::Error::last_os_error()));
+            }
+            return Ok(RawMode { saved: Some(saved) });
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn switch(_no_signals : bool) -> Result<RawMode, RustfuckError>
+    {
+        return Err(RustfuckError::RuntimeError("raw input is only supported on Linux and macOS".to_owned(), None));
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self)
+    {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            if let Some(ref saved) = self.saved {
+                unsafe { sys::tcsetattr(sys::STDIN_FILENO, sys::TCSANOW, saved); }
+            }
+        }
+    }
+}
+
+/// Whether input is waiting on stdin, so that reading it won't block.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn input_pending() -> bool
+{
+    let mut fd = sys::PollFd { fd: sys::STDIN_FILENO, events: sys::POLLIN, revents: 0 };
+    unsafe {
+        return sys::poll(&mut fd, 1, 0) > 0 && fd.revents & sys::POLLIN != 0;
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn input_pending() -> bool
+{
+    return false;
+}
+
+/// The number of rows and columns of the terminal on stdout, or the classic
+/// 24 by 80 if it isn't a terminal.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn size() -> (usize, usize)
+{
+    let mut size = sys::WinSize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    unsafe {
+        if sys::ioctl(sys::STDOUT_FILENO, sys::TIOCGWINSZ, &mut size as *mut sys::WinSize) != 0 || size.ws_row == 0 {
+            return (24, 80);
+        }
+    }
+    return (size.ws_row as usize, size.ws_col as usize);
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn size() -> (usize, usize)
+{
+    return (24, 80);
+}
+
+/* Set by the SIGINT handler, polled by the interpreter */
+static INTERRUPTED : AtomicBool = AtomicBool::new(false);
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+extern "C" fn on_interrupt(_signum : i32)
+{
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    /* a second Ctrl-C kills the process, in case the first one is never noticed */
+    unsafe { sys::signal(sys::SIGINT, sys::SIG_DFL); }
+}
+
+/// Catches Ctrl-C instead of letting it kill the process, so that the
+/// interpreter can stop the program and `interrupted()` reports it. A second
+/// Ctrl-C kills the process as usual.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn catch_interrupts()
+{
+    unsafe { sys::signal(sys::SIGINT, on_interrupt as extern "C" fn(i32) as usize); }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn catch_interrupts()
+{
+}
+
+/// Whether Ctrl-C was pressed since `catch_interrupts()`.
+pub fn interrupted() -> bool
+{
+    return INTERRUPTED.load(Ordering::Relaxed);
+}
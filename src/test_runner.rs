@@ -0,0 +1,291 @@
+/*
+ * Golden tests: brainfuck programs next to the output they should print. A
+ * test is a `.b` file with a sibling `.expected` file holding its expected
+ * output, and optionally a `.in` file with its input, e.g. `factor.b`,
+ * `factor.in` and `factor.expected`. Programs without an `.expected` file
+ * are skipped.
+ *
+ * A `.args` file gives the options the test runs with, on top of those the
+ * runner was given, like `--bounds wrap --tape-size 16`: those of `run` that
+ * change what a program does, and `--debug`, which prints the cells around
+ * the pointer at each breakpoint along with the output. A test that should
+ * fail has its error in a `.error` file, and passes only if it stops with
+ * that error after printing the expected output.
+ */
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use config::Bounds;
+use config::CellWidth;
+use config::Eof;
+use config::Io;
+use config::Tape;
+use Config;
+use ParseOptions;
+use RustfuckError;
+use interpreter;
+use interpreter::Hooks;
+use interpreter::ProgramState;
+use optimizer;
+
+/* Unchanged lines shown around each change in a diff */
+const CONTEXT : usize = 2;
+
+/* Above this many pairs of lines, a diff only shows the first changed line */
+const MAX_DIFF_CELLS : usize = 1 << 22;
+
+/// How many tests passed and failed, and how many programs had no
+/// `.expected` file.
+pub struct Summary {
+    pub passed  : usize,
+    pub failed  : usize,
+    pub skipped : usize
+}
+
+/// Runs every test in `dir` and its subdirectories in the interpreter with
+/// `config`, printing a line per test and a diff of the output of each one
+/// that fails.
+pub fn run(dir : &Path, config : &Config) -> Result<Summary, RustfuckError>
+{
+    let mut programs = Vec::new();
+    find_programs(dir, &mut programs)?;
+    let mut summary = Summary { passed: 0, failed: 0, skipped: 0 };
+    for program in programs {
+        let expected = match fs::read(program.with_extension("expected")) {
+            Ok(expected) => expected,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                summary.skipped += 1;
+                continue;
+            },
+            Err(e) => return Err(RustfuckError::IO(e))
+        };
+        let input = read_optional(&program.with_extension("in"))?.unwrap_or_default();
+        let error = read_optional(&program.with_extension("error"))?;
+        let args = read_optional(&program.with_extension("args"))?;
+        let result = match test_options(&String::from_utf8_lossy(&args.unwrap_or_default()), config) {
+            Ok((config, syntax)) => run_test(&program, &input, &expected, error.as_deref(), &config, &syntax)?,
+            Err(e)               => Some(format!("{}: {}", program.with_extension("args").display(), e))
+        };
+        match result {
+            None => {
+                println!("test {} ... ok", program.display());
+                summary.passed += 1;
+            },
+            Some(failure) => {
+                println!("test {} ... FAILED", program.display());
+                for line in failure.lines() {
+                    println!("    {}", line);
+                }
+                summary.failed += 1;
+            }
+        }
+    }
+    println!("\n{} passed, {} failed, {} skipped without an .expected file",
+            summary.passed, summary.failed, summary.skipped);
+    return Ok(summary);
+}
+
+/* The contents of the file at `path`, or `None` if there is no such file */
+fn read_optional(path : &Path) -> Result<Option<Vec<u8>>, RustfuckError>
+{
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(RustfuckError::IO(e))
+    }
+}
+
+/* The config and parse options a test runs with, given its `.args` and the runner's config */
+fn test_options(args : &str, config : &Config) -> Result<(Config, ParseOptions), String>
+{
+    let mut config = config.clone();
+    let mut syntax = ParseOptions::default();
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        let (name, value) = match word.find('=') {
+            Some(i) => (&word[..i], Some(&word[i + 1..])),
+            None    => (word, None)
+        };
+        if name == "--debug" {
+            syntax.debug = true;
+            continue;
+        }
+        let value = match value.or_else(|| words.next()) {
+            Some(value) => value,
+            None        => return Err(format!("option '{}' requires a value", name))
+        };
+        let invalid = || format!("invalid value '{}' for '{}'", value, name);
+        match name {
+            "--tape-size" => config.tape_size = match value.parse::<i32>() {
+                Ok(n) if n > 0 => n as usize,
+                _              => return Err(invalid())
+            },
+            "--tape" => config.tape = match value {
+                "fixed"   => Tape::Fixed,
+                "dynamic" => Tape::Dynamic,
+                _         => return Err(invalid())
+            },
+            "--bounds" => config.bounds = match value {
+                "error"     => Bounds::Error,
+                "wrap"      => Bounds::Wrap,
+                "unchecked" => Bounds::Unchecked,
+                _           => return Err(invalid())
+            },
+            "--cell-width" => config.cell_width = match value {
+                "8"  => CellWidth::Bits8,
+                "16" => CellWidth::Bits16,
+                "32" => CellWidth::Bits32,
+                _    => return Err(invalid())
+            },
+            "--io" => config.io = match value {
+                "bytes"   => Io::Bytes,
+                "numeric" => Io::Numeric,
+                _         => return Err(invalid())
+            },
+            "--eof" => config.eof = match value {
+                "zero"      => Eof::Zero,
+                "minus-one" => Eof::MinusOne,
+                "unchanged" => Eof::Unchanged,
+                _           => return Err(invalid())
+            },
+            "--max-steps" => config.max_steps = Some(value.parse::<u64>().map_err(|_| invalid())?),
+            "--timeout"   => config.timeout = match value.parse::<f64>() {
+                Ok(secs) if secs > 0.0 && secs.is_finite() => Some(Duration::from_secs_f64(secs)),
+                _                                          => return Err(invalid())
+            },
+            _ => return Err(format!("unknown option '{}'", name))
+        }
+    }
+    return Ok((config, syntax));
+}
+
+/* Prints the cells around the pointer at each breakpoint into the output, for tests run with `--debug` */
+struct DumpBreakpoints;
+
+impl Hooks for DumpBreakpoints {
+    fn breakpoint(&mut self, offset : usize, state : &ProgramState, output : &mut dyn Write) -> io::Result<()>
+    {
+        return write!(output, "# breakpoint at offset {}, pointer at cell {}\n{}", offset, state.position(), state.tape_window());
+    }
+}
+
+/* The `.b` files under `dir`, sorted so tests always run in the same order */
+fn find_programs(dir : &Path, programs : &mut Vec<PathBuf>) -> io::Result<()>
+{
+    let mut entries = fs::read_dir(dir)?.map(|entry| entry.map(|e| e.path())).collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_programs(&path, programs)?;
+        } else if path.extension().is_some_and(|ext| ext == "b") {
+            programs.push(path);
+        }
+    }
+    Ok(())
+}
+
+/* Why the test failed, or `None` if it passed */
+fn run_test(program : &Path, input : &[u8], expected : &[u8], error : Option<&[u8]>, config : &Config,
+        syntax : &ParseOptions) -> Result<Option<String>, RustfuckError>
+{
+    let src = fs::read(program)?;
+    let code = match ::parse_with(&src, syntax) {
+        Ok(code) => optimizer::optimize_for(code, config),
+        Err(e)   => return Ok(Some(e.to_string()))
+    };
+    let mut state = ProgramState::with_config(config);
+    let mut output = Vec::new();
+    let result = if syntax.debug {
+        interpreter::execute_with(&code, &mut state, &mut &input[..], &mut output, &mut DumpBreakpoints)
+    } else {
+        interpreter::execute(&code, &mut state, &mut &input[..], &mut output)
+    };
+    let expected_error = error.map(|error| String::from_utf8_lossy(error).trim_end().to_owned());
+    match (result, expected_error) {
+        (Err(e), Some(ref expected_error)) if e.to_string() != *expected_error => {
+            return Ok(Some(format!("{}\ninstead of\n{}", e, expected_error)));
+        },
+        (Err(_), Some(_)) => (),
+        (Ok(()), Some(expected_error)) => return Ok(Some(format!("finished instead of stopping with\n{}", expected_error))),
+        (Err(e), None) => {
+            if output == expected {
+                return Ok(Some(format!("{}\nafter printing exactly the expected output", e)));
+            }
+            return Ok(Some(format!("{}\noutput so far:\n{}", e, diff(expected, &output))));
+        },
+        (Ok(()), None) => ()
+    }
+    if output != expected {
+        return Ok(Some(diff(expected, &output)));
+    }
+    return Ok(None);
+}
+
+/*
+ * A line diff from `expected` to `actual`, with lines only in the former
+ * marked `-` and lines only in the latter `+`, each run of changes headed by
+ * the line it starts at.
+ */
+fn diff(expected : &[u8], actual : &[u8]) -> String
+{
+    let expected = String::from_utf8_lossy(expected);
+    let actual = String::from_utf8_lossy(actual);
+    let a : Vec<&str> = expected.split_terminator('\n').collect();
+    let b : Vec<&str> = actual.split_terminator('\n').collect();
+    if a == b {
+        return "(the outputs only differ in a trailing newline)".to_owned();
+    }
+    if (a.len() + 1) * (b.len() + 1) > MAX_DIFF_CELLS {
+        let line = a.iter().zip(b.iter()).position(|(x, y)| x != y).unwrap_or(a.len().min(b.len()));
+        return format!("@@ line {} @@\n-{}\n+{}", line + 1, a.get(line).unwrap_or(&""), b.get(line).unwrap_or(&""));
+    }
+
+    /* longest common subsequence of the lines, from the end */
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    /* each line with its mark and its number in `expected` */
+    let mut lines : Vec<(char, &str, usize)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push((' ', a[i], i));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', a[i], i));
+            i += 1;
+        } else {
+            lines.push(('+', b[j], i));
+            j += 1;
+        }
+    }
+
+    let near_change = |k : usize| {
+        let from = k.saturating_sub(CONTEXT);
+        let to = (k + CONTEXT + 1).min(lines.len());
+        lines[from..to].iter().any(|&(mark, _, _)| mark != ' ')
+    };
+    let mut out = String::new();
+    let mut in_hunk = false;
+    for (k, &(mark, line, number)) in lines.iter().enumerate() {
+        if !near_change(k) {
+            in_hunk = false;
+            continue;
+        }
+        if !in_hunk {
+            out.push_str(&format!("@@ line {} @@\n", number + 1));
+            in_hunk = true;
+        }
+        out.push_str(&format!("{}{}\n", mark, line));
+    }
+    out.pop();
+    return out;
+}
@@ -0,0 +1,127 @@
+/*
+ * Token maps, for the dialects that are brainfuck with its eight commands
+ * spelled differently (trivial brainfuck substitutions), like `moo` for `>`.
+ * Source in one is read by matching the longest token at each byte; bytes
+ * no token starts at are comments, like in brainfuck.
+ */
+
+use Diagnostic;
+use RustfuckError;
+use Span;
+
+/* The commands, in the order of `TokenMap::tokens` */
+const COMMANDS : &[u8; 8] = b"><+-.,[]";
+
+/// How each of the eight brainfuck commands is spelled, see `parse_mapped()`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TokenMap {
+    tokens : [Vec<u8>; 8]
+}
+
+impl TokenMap {
+    /// The map spelling every command as brainfuck does.
+    pub fn new() -> TokenMap
+    {
+        return TokenMap { tokens: COMMANDS.map(|c| vec![c]) };
+    }
+
+    /// Reads a map file into `self`: a `<command>=<token>` a line, like
+    /// `>=moo`, where the token is the rest of the line, without the space
+    /// around it. Blank lines and lines starting with `#` are skipped.
+    /// Commands the file doesn't spell keep the spelling they had.
+    pub fn read(&mut self, text : &[u8]) -> Result<(), RustfuckError>
+    {
+        let mut start = 0;
+        for line in text.split(|&b| b == b'\n') {
+            let offset = start;
+            start += line.len() + 1;
+            let trimmed = String::from_utf8_lossy(line);
+            let trimmed = trimmed.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            self.set_from(trimmed).map_err(|msg| RustfuckError::ParseError(Diagnostic::new(text, offset, &msg)))?;
+        }
+        Ok(())
+    }
+
+    /// Spells a command as `spec` says, `<command>=<token>` like in a map
+    /// file, failing with what is wrong with it.
+    pub fn set_from(&mut self, spec : &str) -> Result<(), String>
+    {
+        let mut chars = spec.chars();
+        let command = chars.next().unwrap_or_default();
+        let token = match chars.as_str().trim_start().strip_prefix('=') {
+            Some(token) => token.trim(),
+            None        => return Err(format!("'{}' is not <command>=<token>", spec))
+        };
+        return self.set(command, token.as_bytes());
+    }
+
+    /// Spells `command` as `token`, failing unless it is one of the eight
+    /// commands and the token isn't empty.
+    pub fn set(&mut self, command : char, token : &[u8]) -> Result<(), String>
+    {
+        let i = match COMMANDS.iter().position(|&c| c as char == command) {
+            Some(i) => i,
+            None    => return Err(format!("'{}' is not a brainfuck command", command))
+        };
+        if token.is_empty() {
+            return Err(format!("the token for '{}' is empty", command));
+        }
+        self.tokens[i] = token.to_vec();
+        Ok(())
+    }
+
+    /// Fails if two commands are spelled the same, so source in the map
+    /// couldn't be read.
+    pub fn check(&self) -> Result<(), String>
+    {
+        for i in 0..COMMANDS.len() {
+            for j in i + 1..COMMANDS.len() {
+                if self.tokens[i] == self.tokens[j] {
+                    return Err(format!("'{}' and '{}' are both spelled '{}'", COMMANDS[i] as char,
+                                       COMMANDS[j] as char, String::from_utf8_lossy(&self.tokens[i])));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// How the map spells the brainfuck `command`.
+    pub fn spelling(&self, command : char) -> String
+    {
+        return COMMANDS.iter()
+                .position(|&c| c as char == command)
+                .map_or_else(String::new, |i| String::from_utf8_lossy(&self.tokens[i]).into_owned());
+    }
+
+    /// The brainfuck commands `src` spells, each with the span of its token.
+    /// Where tokens overlap, the longest one is read.
+    pub fn commands(&self, src : &[u8]) -> Vec<(u8, Span)>
+    {
+        let mut commands = Vec::new();
+        let mut i = 0;
+        while i < src.len() {
+            let longest = (0..COMMANDS.len())
+                    .filter(|&c| src[i..].starts_with(&self.tokens[c]))
+                    .max_by_key(|&c| self.tokens[c].len());
+            match longest {
+                Some(c) => {
+                    let end = i + self.tokens[c].len();
+                    commands.push((COMMANDS[c], Span::new(i, end)));
+                    i = end;
+                },
+                None => i += 1
+            }
+        }
+        return commands;
+    }
+}
+
+impl Default for TokenMap {
+    fn default() -> TokenMap
+    {
+        return TokenMap::new();
+    }
+}
@@ -0,0 +1,60 @@
+use std::io::Write;
+use bytecode;
+use bytecode::Op;
+use diagnostic::LineIndex;
+use interpreter::Hooks;
+use interpreter::ProgramState;
+use Stmt;
+
+/// Writes a line for every instruction the interpreter executes, with where
+/// in the source it came from and the pointer and the value of the cell
+/// under it afterwards, when passed as the hooks to
+/// `interpreter::execute_with()` for the same program it was created for.
+/// The pointer is the cell number as the program sees it. Failing to write
+/// the trace doesn't stop the program.
+pub struct Trace<W : Write> {
+    out       : W,
+    limit     : Option<u64>,
+    lines     : u64,
+    /* per op, the line and column of the source it came from */
+    locations : Vec<(usize, usize)>,
+    /* the first and last source line whose instructions are traced */
+    range     : Option<(usize, usize)>
+}
+
+impl<W : Write> Trace<W> {
+    /// Traces `code`, parsed from `src`, to `out`, stopping after `limit`
+    /// lines if given. With a `range` of source lines, from the first to the
+    /// last, only instructions from those lines are traced.
+    pub fn new(code : &[Stmt], src : &[u8], out : W, limit : Option<u64>, range : Option<(usize, usize)>) -> Trace<W>
+    {
+        let lines = LineIndex::new(src);
+        let locations = bytecode::lower_with_sources(code).1.iter().map(|span| lines.locate(span.start)).collect();
+        return Trace { out, limit, lines: 0, locations, range };
+    }
+}
+
+impl<W : Write> Hooks for Trace<W> {
+    fn after_op(&mut self, pc : usize, op : Op, state : &ProgramState)
+    {
+        let (line, col) = self.locations.get(pc).cloned().unwrap_or((0, 0));
+        if let Some((first, last)) = self.range {
+            if line < first || line > last {
+                return;
+            }
+        }
+        if let Some(limit) = self.limit {
+            if self.lines >= limit {
+                if self.lines == limit {
+                    let _ = writeln!(self.out, "trace limit reached, not tracing any further");
+                    self.lines += 1;
+                }
+                return;
+            }
+        }
+        self.lines += 1;
+        let cell = state.tape.get(state.ptr as usize) as u32;
+        let _ = writeln!(self.out, "{:>8}  {:>9}  {:<24} ptr {:<6} cell {}",
+                pc, format!("{}:{}", line, col), op.to_string(), state.position(), cell);
+    }
+}
@@ -0,0 +1,131 @@
+/*
+ * Differential testing: running a program both in the interpreter and
+ * natively, and finding where they disagree. The interpreter is the
+ * reference, it is the simplest implementation of the semantics in a
+ * `Config`.
+ */
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process;
+use std::process::Stdio;
+use std::thread;
+use bytecode;
+use compiler;
+use compiler::Artifact;
+use compiler::Backend;
+use compiler::BuildOptions;
+use interpreter;
+use interpreter::ProgramState;
+use jit;
+use Config;
+use RustfuckError;
+use Stmt;
+
+/* Bytes of output shown on either side of where two outputs diverge */
+const CONTEXT : usize = 16;
+
+/// Everything a program wrote to stdout, and how it failed, if it did.
+pub struct Outcome {
+    pub output : Vec<u8>,
+    pub error  : Option<String>
+}
+
+/// Runs `code` in the interpreter with `input` as its input.
+pub fn interpret(code : &[Stmt], config : &Config, input : &[u8]) -> Outcome
+{
+    let mut state = ProgramState::with_config(config);
+    let mut output = Vec::new();
+    let result = interpreter::execute(code, &mut state, &mut &input[..], &mut output);
+    return Outcome { output, error: result.err().map(|e| e.to_string()) };
+}
+
+/// Runs `code` through the JIT with `input` as its input.
+pub fn run_jit(code : &[Stmt], config : &Config, input : &[u8]) -> Outcome
+{
+    let mut state = ProgramState::with_config(config);
+    let mut output = Vec::new();
+    let result = jit::execute(&bytecode::lower(code), &mut state, &mut &input[..], &mut output);
+    return Outcome { output, error: result.err().map(|e| e.to_string()) };
+}
+
+/// Compiles `code` with `backend` to a temporary executable and runs it with
+/// `input` on its stdin. Only failing to build or start it is an error.
+pub fn run_compiled(code : &[Stmt], config : &Config, backend : Backend, input : &[u8]) -> Result<Outcome, RustfuckError>
+{
+    let executable = env::temp_dir().join(format!("rustfuck-verify-{}", process::id()));
+    let executable = executable.to_string_lossy().into_owned();
+    compiler::compile(code, config, &executable, backend, &[Artifact::Executable], None, &BuildOptions::default())?;
+    let child = process::Command::new(&executable)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e)    => {
+            let _ = fs::remove_file(&executable);
+            return Err(RustfuckError::IO(e));
+        }
+    };
+    /* fed from another thread, so a program that writes before reading everything can't deadlock */
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let feeder = thread::spawn(move || { let _ = stdin.write_all(&input); });
+    let result = child.wait_with_output();
+    let _ = feeder.join();
+    let _ = fs::remove_file(&executable);
+    let result = result?;
+
+    let error = if result.status.success() {
+        None
+    } else {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        Some(format!("{} ({})", result.status, stderr.trim()))
+    };
+    return Ok(Outcome { output: result.stdout, error });
+}
+
+/// Compares `actual`, the outcome of running a program as `name`, to what
+/// the interpreter did, `expected`. Returns a description of the first
+/// difference, or `None` if they agree. Two programs that both fail agree,
+/// whatever their error messages.
+pub fn compare(expected : &Outcome, actual : &Outcome, name : &str) -> Option<String>
+{
+    return compare_named(expected, "interpreter", actual, name);
+}
+
+/// Compares the outcomes of two runs, `first` and `second`, naming them
+/// `first_name` and `second_name` in the description of the first
+/// difference, like `compare()` does.
+pub fn compare_named(first : &Outcome, first_name : &str, second : &Outcome, second_name : &str) -> Option<String>
+{
+    let (a, b) = (&first.output, &second.output);
+    if let Some(at) = (0..a.len().min(b.len())).find(|&i| a[i] != b[i]).or_else(|| {
+        if a.len() != b.len() { Some(a.len().min(b.len())) } else { None }
+    }) {
+        let line = a[..at].iter().filter(|&&c| c == b'\n').count() + 1;
+        let col = at - a[..at].iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1) + 1;
+        let width = first_name.len().max(second_name.len()) + 1;
+        return Some(format!("the outputs differ at byte {} (line {}, col {}):\n    {:<width$} {}\n    {:<width$} {}",
+                at, line, col, format!("{}:", first_name), excerpt(a, at), format!("{}:", second_name), excerpt(b, at),
+                width = width));
+    }
+    match (&first.error, &second.error) {
+        (Some(e), None) => Some(format!("the {} failed but the {} didn't: {}", first_name, second_name, e)),
+        (None, Some(e)) => Some(format!("the {} failed but the {} didn't: {}", second_name, first_name, e)),
+        _               => None
+    }
+}
+
+/* The output around byte `at`, escaped and quoted, or a note if it ends before */
+fn excerpt(output : &[u8], at : usize) -> String
+{
+    if at >= output.len() {
+        return format!("(ends after {} bytes)", output.len());
+    }
+    let start = at.saturating_sub(CONTEXT);
+    let end = (at + CONTEXT).min(output.len());
+    return format!("\"{}\"", output[start..end].escape_ascii());
+}
@@ -0,0 +1,199 @@
+use Config;
+use config::CellWidth;
+use config::Eof;
+use config::Io;
+use Stmt;
+use std::fmt::Write;
+
+/* Blocks nested deeper than this are indented no further, so deep programs don't make huge files */
+const MAX_INDENT : usize = 32;
+
+const WASM_PAGE_SIZE : usize = 65536;
+
+/*
+ * $p holds the byte address of the current cell, so pointer moves and
+ * offsets are scaled by the cell size.
+ */
+struct CodeGenContext {
+    loopc : u32,
+    bytes : i32,
+    load  : &'static str,
+    store : &'static str,
+    io    : Io,
+    eof   : Eof
+}
+
+impl CodeGenContext {
+    fn new(config : &Config) -> CodeGenContext
+    {
+        let width = config.cell_width;
+        let (load, store) = match width {
+            CellWidth::Bits8  => ("i32.load8_u", "i32.store8"),
+            CellWidth::Bits16 => ("i32.load16_u", "i32.store16"),
+            CellWidth::Bits32 => ("i32.load", "i32.store")
+        };
+        return CodeGenContext { loopc: 0, bytes: width.bytes() as i32, load, store, io: config.io, eof: config.eof };
+    }
+}
+
+fn write_header(wat : &mut String, config : &Config)
+{
+    write!(wat, "(module\n").unwrap();
+    if config.io == Io::Numeric {
+        write!(wat, "  (import \"env\" \"putnum\" (func $putnum (param i32)))\n").unwrap();
+        write!(wat, "  (import \"env\" \"getnum\" (func $getnum (result i64)))\n").unwrap();
+    } else {
+        write!(wat, "  (import \"env\" \"putchar\" (func $putchar (param i32)))\n").unwrap();
+        write!(wat, "  (import \"env\" \"getchar\" (func $getchar (result i32)))\n").unwrap();
+    }
+    write!(wat, "  (memory (export \"memory\") {})\n", (config.tape_size * config.cell_width.bytes()).div_ceil(WASM_PAGE_SIZE)).unwrap();
+    write!(wat, "  (func (export \"run\")\n").unwrap();
+    write!(wat, "    (local $p i32)\n").unwrap();
+    write!(wat, "    (local $c i32)\n").unwrap();
+    if config.io == Io::Numeric {
+        write!(wat, "    (local $n i64)\n").unwrap();
+    }
+}
+
+fn write_footer(wat : &mut String)
+{
+    write!(wat, "  )\n").unwrap();
+    write!(wat, ")\n").unwrap();
+}
+
+fn write_line(wat : &mut String, depth : usize, line : &str)
+{
+    write!(wat, "{}{}\n", "  ".repeat(depth.min(MAX_INDENT) + 2), line).unwrap();
+}
+
+/* formats the address of the cell at `offset` from the pointer */
+fn cell_addr(context : &CodeGenContext, offset : i32) -> String
+{
+    if offset == 0 {
+        return "(local.get $p)".to_owned();
+    }
+    return format!("(i32.add (local.get $p) (i32.const {}))", offset * context.bytes);
+}
+
+fn write_add_at(wat : &mut String, depth : usize, context : &CodeGenContext, offset : i32, value : &str)
+{
+    let addr = cell_addr(context, offset);
+    write_line(wat, depth, &format!("({} {} (i32.add ({} {}) {}))", context.store, addr, context.load, addr, value));
+}
+
+fn write_move(wat : &mut String, depth : usize, context : &CodeGenContext, n : i32)
+{
+    write_line(wat, depth, &format!("(local.set $p {})", cell_addr(context, n)));
+}
+
+/*
+ * `env.getchar` returns -1 on EOF, which is stored as is unless the EOF
+ * policy says otherwise. `env.getnum` returns the number read as an
+ * unsigned 32-bit value, or -1 on EOF.
+ */
+fn write_getc(wat : &mut String, depth : usize, context : &CodeGenContext)
+{
+    if context.io == Io::Bytes && context.eof == Eof::MinusOne {
+        write_line(wat, depth, &format!("({} (local.get $p) (call $getchar))", context.store));
+        return;
+    }
+    let (value, eof) = match context.io {
+        Io::Bytes   => {
+            write_line(wat, depth, "(local.set $c (call $getchar))");
+            ("(local.get $c)", "(i32.eq (local.get $c) (i32.const -1))")
+        },
+        Io::Numeric => {
+            write_line(wat, depth, "(local.set $n (call $getnum))");
+            ("(i32.wrap_i64 (local.get $n))", "(i64.eq (local.get $n) (i64.const -1))")
+        }
+    };
+    match context.eof {
+        Eof::MinusOne  => write_line(wat, depth, &format!("({} (local.get $p) (select (i32.const -1) {} {}))", context.store, value, eof)),
+        Eof::Zero      => write_line(wat, depth, &format!("({} (local.get $p) (select (i32.const 0) {} {}))", context.store, value, eof)),
+        Eof::Unchanged => {
+            write_line(wat, depth, &format!("(if (i32.eqz {})", eof));
+            write_line(wat, depth + 1, &format!("(then ({} (local.get $p) {}))", context.store, value));
+            write_line(wat, depth, ")");
+        }
+    }
+}
+
+fn write_loop_begin(wat : &mut String, depth : usize, context : &mut CodeGenContext) -> u32
+{
+    let loop_num = context.loopc;
+    write_line(wat, depth, &format!("(block $loop_end{}", loop_num));
+    write_line(wat, depth + 1, &format!("(loop $loop_cond{}", loop_num));
+    write_line(wat, depth + 2, &format!("(br_if $loop_end{} (i32.eqz ({} (local.get $p))))", loop_num, context.load));
+    context.loopc += 1;
+    return loop_num;
+}
+
+fn write_loop_end(wat : &mut String, depth : usize, loop_num : u32)
+{
+    write_line(wat, depth + 2, &format!("(br $loop_cond{})", loop_num));
+    write_line(wat, depth + 1, ")");
+    write_line(wat, depth, ")");
+}
+
+fn write_code(wat : &mut String, code : &[Stmt], depth : usize, context : &mut CodeGenContext)
+{
+    for stmt in code {
+        match stmt {
+            Stmt::Move(n, _)      => write_move(wat, depth, context, *n),
+            Stmt::Add(n, _)       => write_add_at(wat, depth, context, 0, &format!("(i32.const {})", n)),
+            Stmt::AddAt(o, n, _)  => write_add_at(wat, depth, context, *o, &format!("(i32.const {})", n)),
+            Stmt::Set(n, _)       => write_line(wat, depth, &format!("({} (local.get $p) (i32.const {}))", context.store, n)),
+            Stmt::MulAdd(o, f, _) => {
+                write_line(wat, depth, &format!("(if ({} (local.get $p))", context.load));
+                write_line(wat, depth + 1, "(then");
+                let value = format!("(i32.mul ({} (local.get $p)) (i32.const {}))", context.load, f);
+                write_add_at(wat, depth + 2, context, *o, &value);
+                write_line(wat, depth + 1, ")");
+                write_line(wat, depth, ")");
+            },
+            Stmt::Scan(n, _)      => {
+                let loop_num = write_loop_begin(wat, depth, context);
+                write_move(wat, depth + 2, context, *n);
+                write_loop_end(wat, depth, loop_num);
+            },
+            Stmt::Input(_)        => write_getc(wat, depth, context),
+            Stmt::Output(_)       => write_line(wat, depth, &match context.io {
+                Io::Bytes   => "(call $putchar (i32.load8_u (local.get $p)))".to_owned(),
+                Io::Numeric => format!("(call $putnum ({} (local.get $p)))", context.load)
+            }),
+            Stmt::Loop(loop_code, _) => {
+                let loop_num = write_loop_begin(wat, depth, context);
+                write_code(wat, loop_code, depth + 2, context);
+                write_loop_end(wat, depth, loop_num);
+            },
+            /* breakpoints are only shown by the interpreter */
+            Stmt::Debug(_)        => (),
+            /* only the interpreter and the llvm backend run them, see `uses_procedures()` */
+            Stmt::Proc(..) | Stmt::Call(_) => panic!("the WebAssembly translation has no pbrain procedures"),
+            /* only the interpreter runs them, see `uses_forks()` */
+            Stmt::Fork(_)         => panic!("the WebAssembly translation has no Brainfork threads"),
+            /* only the interpreter and the llvm backend run it, see `uses_random()` */
+            Stmt::Random(_)       => panic!("the WebAssembly translation has no random bytes")
+        }
+    }
+}
+
+/// Generates a WebAssembly text module exporting `run` (which runs `code`)
+/// and its `memory`. Cell I/O goes through the imported `env.putchar` and
+/// `env.getchar` functions, the latter returning -1 on EOF. The memory holds
+/// at least `config.tape_size` cells of `config.cell_width`; `putchar` is
+/// passed the low byte of a cell. With numeric I/O the imports are instead
+/// `env.putnum`, passed the bits of a cell, and `env.getnum`, returning the
+/// number read as an unsigned 32-bit value in an i64, or -1 on EOF.
+pub fn code_gen(code : &[Stmt], config : &Config) -> String
+{
+    let mut wat : String = String::new();
+
+    let mut context = CodeGenContext::new(config);
+
+    write_header(&mut wat, config);
+    write_code(&mut wat, code, 0, &mut context);
+    write_footer(&mut wat);
+
+    return wat;
+}
@@ -0,0 +1,494 @@
+/*
+ * Template based x86-64 code generation for lowered bytecode, shared by the
+ * JIT and the object file backend. Every op is translated into a fixed
+ * machine code sequence.
+ *
+ * Register usage inside the generated function:
+ *   r12 - base address of the tape
+ *   r13 - pointer (cell index)
+ *
+ * Cells are addressed as [r12 + index * cell width], so the pointer stays a
+ * cell index whatever the configured width.
+ *   r14 - pointer to a context whose first word receives the final pointer,
+ *         and whose second word receives numbers read in numeric I/O mode
+ */
+
+use Config;
+use bytecode::Op;
+use config::Bounds;
+use config::CellWidth;
+use config::Eof;
+use config::Io;
+
+/* status codes returned by the generated function */
+pub const STATUS_OK             : u64 = 0;
+pub const STATUS_OUT_OF_BOUNDS  : u64 = 1;
+pub const STATUS_CALLBACK_ERROR : u64 = 2;
+
+/* returned by an I/O callback that failed */
+pub const CALLBACK_ERROR : i32 = i32::MIN;
+
+/* offset in the context of the number written by numeric input */
+const CTX_NUMBER : u8 = 8;
+
+/*
+ * How `,` and `.` are performed by the generated code. `.` passes the
+ * zero-extended cell. `,` expects a byte, or -1 on EOF, in byte I/O mode;
+ * in numeric I/O mode it expects 1 and the number at CTX_NUMBER in the
+ * context, or anything else on EOF, like `scanf`.
+ */
+pub enum IoCalls {
+    /*
+     * absolute addresses of `extern "C" fn(ctx) -> i32` (input) and `fn(ctx, cell) -> i32`
+     * (output), which return CALLBACK_ERROR on failure
+     */
+    Callbacks { input : u64, output : u64 },
+    /*
+     * calls to libc `getchar`/`putchar`, or `scanf`/`printf` with the format
+     * strings appended to the code in numeric I/O mode, left for the linker
+     * to resolve
+     */
+    Extern
+}
+
+/* generated code plus the positions of the rel32 fields of external calls */
+pub struct Assembly {
+    pub code         : Vec<u8>,
+    pub input_calls  : Vec<usize>,
+    pub output_calls : Vec<usize>
+}
+
+/* a rel32 field at `pos` in the code that should point at op `target` */
+struct Fixup {
+    pos    : usize,
+    target : usize
+}
+
+/* labels shared by all ops, emitted after the program body */
+#[derive(Default)]
+struct Labels {
+    out_of_bounds        : Vec<usize>,
+    offset_out_of_bounds : Vec<usize>,
+    callback_error       : Vec<usize>,
+    input_calls          : Vec<usize>,
+    output_calls         : Vec<usize>,
+    /* rel32 fields of `lea`s of the scanf and printf format strings */
+    input_format         : Vec<usize>,
+    output_format        : Vec<usize>
+}
+
+fn emit_u32(code : &mut Vec<u8>, v : u32)
+{
+    code.extend_from_slice(&v.to_le_bytes());
+}
+
+fn emit_u64(code : &mut Vec<u8>, v : u64)
+{
+    code.extend_from_slice(&v.to_le_bytes());
+}
+
+/* emits `jcc rel32` (or `jmp rel32` when cc is None) with a zero placeholder, returning its position */
+fn emit_jump(code : &mut Vec<u8>, cc : Option<u8>) -> usize
+{
+    match cc {
+        Some(cc) => code.extend_from_slice(&[0x0F, cc]),
+        None     => code.push(0xE9)
+    }
+    emit_u32(code, 0);
+    return code.len() - 4;
+}
+
+fn patch_jump(code : &mut [u8], pos : usize, target : usize)
+{
+    let rel = target as i64 - (pos as i64 + 4);
+    code[pos..pos + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+}
+
+const JB  : u8 = 0x82;
+const JAE : u8 = 0x83;
+const JE  : u8 = 0x84;
+const JNE : u8 = 0x85;
+
+/* the index register of a cell operand */
+#[derive(Clone, Copy)]
+enum Index {
+    Ptr,    // r13
+    Offset  // rcx
+}
+
+/* emits `opcode` with the memory operand [r12 + index * width] and `reg` in the ModRM reg field */
+fn emit_mem(code : &mut Vec<u8>, width : CellWidth, index : Index, opcode : &[u8], reg : u8)
+{
+    let scale = match width {
+        CellWidth::Bits8  => 0x00,
+        CellWidth::Bits16 => 0x40,
+        CellWidth::Bits32 => 0x80
+    };
+    let (rex, sib) = match index {
+        Index::Ptr    => (0x43, 0x2C), // REX.XB, index r13, base r12
+        Index::Offset => (0x41, 0x0C)  // REX.B,  index rcx, base r12
+    };
+    code.push(rex);
+    code.extend_from_slice(opcode);
+    code.extend_from_slice(&[0x04 | (reg << 3), sib | scale]);
+}
+
+/* emits a cell sized instruction, picking the byte or the word/dword form of the opcode */
+fn emit_cell_op(code : &mut Vec<u8>, width : CellWidth, index : Index, byte_op : &[u8], wide_op : &[u8], reg : u8)
+{
+    match width {
+        CellWidth::Bits8  => emit_mem(code, width, index, byte_op, reg),
+        CellWidth::Bits16 => {
+            code.push(0x66); // operand size prefix
+            emit_mem(code, width, index, wide_op, reg);
+        },
+        CellWidth::Bits32 => emit_mem(code, width, index, wide_op, reg)
+    }
+}
+
+/* emits a cell sized immediate */
+fn emit_cell_imm(code : &mut Vec<u8>, width : CellWidth, n : i32)
+{
+    match width {
+        CellWidth::Bits8  => code.push(n as u8),
+        CellWidth::Bits16 => code.extend_from_slice(&(n as u16).to_le_bytes()),
+        CellWidth::Bits32 => emit_u32(code, n as u32)
+    }
+}
+
+/* cmp cell [r12 + r13 * width], 0 */
+fn emit_test_cell(code : &mut Vec<u8>, width : CellWidth)
+{
+    emit_cell_op(code, width, Index::Ptr, &[0x80], &[0x83], 7);
+    code.push(0x00);
+}
+
+/* movzx `reg`, cell [r12 + r13 * width] */
+fn emit_load_cell(code : &mut Vec<u8>, width : CellWidth, reg : u8)
+{
+    match width {
+        CellWidth::Bits8  => emit_mem(code, width, Index::Ptr, &[0x0F, 0xB6], reg),
+        CellWidth::Bits16 => emit_mem(code, width, Index::Ptr, &[0x0F, 0xB7], reg),
+        CellWidth::Bits32 => emit_mem(code, width, Index::Ptr, &[0x8B], reg)
+    }
+}
+
+/*
+ * Reduces a pointer step modulo the tape size when wrapping, so a stepped
+ * pointer is always below twice the tape size and one subtraction wraps it.
+ */
+fn wrap_step(config : &Config, n : i32) -> i32
+{
+    if config.bounds == Bounds::Wrap {
+        return n.rem_euclid(config.tape_size as i32);
+    }
+    return n;
+}
+
+/* applies the bounds policy to r13: jumps to the out of bounds exit unless it is on the tape, or wraps it */
+fn emit_check_ptr(code : &mut Vec<u8>, labels : &mut Labels, config : &Config)
+{
+    if config.bounds == Bounds::Unchecked {
+        return;
+    }
+    code.extend_from_slice(&[0x49, 0x81, 0xFD]); // cmp r13, imm32
+    emit_u32(code, config.tape_size as u32);
+    if config.bounds == Bounds::Wrap {
+        let skip = emit_jump(code, Some(JB));
+        code.extend_from_slice(&[0x49, 0x81, 0xED]); // sub r13, imm32
+        emit_u32(code, config.tape_size as u32);
+        let end = code.len();
+        patch_jump(code, skip, end);
+        return;
+    }
+    labels.out_of_bounds.push(emit_jump(code, Some(JAE)));
+}
+
+/* rcx = r13 + offset, with the bounds policy applied like `emit_check_ptr()` */
+fn emit_offset_ptr(code : &mut Vec<u8>, labels : &mut Labels, config : &Config, offset : i32)
+{
+    code.extend_from_slice(&[0x49, 0x8D, 0x8D]); // lea rcx, [r13 + disp32]
+    emit_u32(code, wrap_step(config, offset) as u32);
+    if config.bounds == Bounds::Unchecked {
+        return;
+    }
+    code.extend_from_slice(&[0x48, 0x81, 0xF9]); // cmp rcx, imm32
+    emit_u32(code, config.tape_size as u32);
+    if config.bounds == Bounds::Wrap {
+        let skip = emit_jump(code, Some(JB));
+        code.extend_from_slice(&[0x48, 0x81, 0xE9]); // sub rcx, imm32
+        emit_u32(code, config.tape_size as u32);
+        let end = code.len();
+        patch_jump(code, skip, end);
+        return;
+    }
+    labels.offset_out_of_bounds.push(emit_jump(code, Some(JAE)));
+}
+
+/* calls `f` with the context in rdi, jumping to the callback error exit if it returns CALLBACK_ERROR */
+fn emit_callback(code : &mut Vec<u8>, labels : &mut Labels, f : u64)
+{
+    code.extend_from_slice(&[0x4C, 0x89, 0xF7]); // mov rdi, r14
+    code.extend_from_slice(&[0x48, 0xB8]);       // mov rax, imm64
+    emit_u64(code, f);
+    code.extend_from_slice(&[0xFF, 0xD0]);       // call rax
+    code.push(0x3D);                             // cmp eax, imm32
+    emit_u32(code, CALLBACK_ERROR as u32);
+    labels.callback_error.push(emit_jump(code, Some(JE)));
+}
+
+/* emits `call rel32` to an external symbol, returning the position of the rel32 */
+fn emit_extern_call(code : &mut Vec<u8>) -> usize
+{
+    code.push(0xE8);
+    emit_u32(code, 0);
+    return code.len() - 4;
+}
+
+/* lea rdi, [rip + format string], returning the position of the rel32 */
+fn emit_format_arg(code : &mut Vec<u8>) -> usize
+{
+    code.extend_from_slice(&[0x48, 0x8D, 0x3D]);
+    emit_u32(code, 0);
+    return code.len() - 4;
+}
+
+fn emit_input(code : &mut Vec<u8>, labels : &mut Labels, io : &IoCalls, config : &Config)
+{
+    match io {
+        IoCalls::Callbacks { input, .. } => emit_callback(code, labels, *input),
+        IoCalls::Extern if config.io == Io::Numeric => {
+            let pos = emit_format_arg(code);
+            labels.input_format.push(pos);
+            code.extend_from_slice(&[0x49, 0x8D, 0x76, CTX_NUMBER]); // lea rsi, [r14 + CTX_NUMBER]
+            code.extend_from_slice(&[0x31, 0xC0]);                   // xor eax, eax (no vector registers)
+            let pos = emit_extern_call(code);
+            labels.input_calls.push(pos);
+        },
+        IoCalls::Extern => {
+            let pos = emit_extern_call(code);
+            labels.input_calls.push(pos);
+        }
+    }
+}
+
+/* stores the input in eax to the current cell, or what `config.eof` says on EOF */
+fn emit_store_input(code : &mut Vec<u8>, config : &Config)
+{
+    let width = config.cell_width;
+    if config.io == Io::Bytes && config.eof == Eof::MinusOne {
+        emit_cell_op(code, width, Index::Ptr, &[0x88], &[0x89], 0); // mov cell [r12 + r13 * width], eax
+        return;
+    }
+    let eof = match config.io {
+        Io::Bytes   => {
+            code.extend_from_slice(&[0x83, 0xF8, 0xFF]); // cmp eax, -1
+            emit_jump(code, Some(JE))
+        },
+        Io::Numeric => {
+            code.extend_from_slice(&[0x83, 0xF8, 0x01]); // cmp eax, 1
+            let eof = emit_jump(code, Some(JNE));
+            code.extend_from_slice(&[0x41, 0x8B, 0x46, CTX_NUMBER]); // mov eax, [r14 + CTX_NUMBER]
+            eof
+        }
+    };
+    emit_cell_op(code, width, Index::Ptr, &[0x88], &[0x89], 0);
+    if config.eof == Eof::Unchanged {
+        let end = code.len();
+        patch_jump(code, eof, end);
+        return;
+    }
+    let done = emit_jump(code, None);
+    let here = code.len();
+    patch_jump(code, eof, here);
+    code.push(0xB8); // mov eax, imm32
+    emit_u32(code, if config.eof == Eof::Zero { 0 } else { u32::MAX });
+    emit_cell_op(code, width, Index::Ptr, &[0x88], &[0x89], 0);
+    let end = code.len();
+    patch_jump(code, done, end);
+}
+
+fn emit_output(code : &mut Vec<u8>, labels : &mut Labels, io : &IoCalls, config : &Config)
+{
+    match io {
+        IoCalls::Callbacks { output, .. } => {
+            emit_load_cell(code, config.cell_width, 6); // movzx esi, cell [r12 + r13 * width]
+            emit_callback(code, labels, *output);
+        },
+        IoCalls::Extern if config.io == Io::Numeric => {
+            emit_load_cell(code, config.cell_width, 6);
+            let pos = emit_format_arg(code);
+            labels.output_format.push(pos);
+            code.extend_from_slice(&[0x31, 0xC0]);      // xor eax, eax
+            let pos = emit_extern_call(code);
+            labels.output_calls.push(pos);
+        },
+        IoCalls::Extern => {
+            emit_load_cell(code, config.cell_width, 7); // movzx edi, cell [r12 + r13 * width]
+            let pos = emit_extern_call(code);
+            labels.output_calls.push(pos);
+        }
+    }
+}
+
+fn emit_op(code : &mut Vec<u8>, labels : &mut Labels, fixups : &mut Vec<Fixup>, io : &IoCalls, config : &Config, idx : usize, op : Op)
+{
+    let width = config.cell_width;
+    match op {
+        /* a fused op is its first op, followed by its second, see `bytecode::fuse()` */
+        Op::Move(n) | Op::MoveJumpIfZero(n, _) | Op::MoveJumpIfNonZero(n, _) => {
+            code.extend_from_slice(&[0x49, 0x81, 0xC5]); // add r13, imm32
+            emit_u32(code, wrap_step(config, n) as u32);
+            emit_check_ptr(code, labels, config);
+        },
+        Op::Add(n) | Op::AddMove(n, _) => {
+            emit_cell_op(code, width, Index::Ptr, &[0x80], &[0x81], 0); // add cell [r12 + r13 * width], imm
+            emit_cell_imm(code, width, n);
+        },
+        Op::Set(n) | Op::SetAdd(n, _) => {
+            emit_cell_op(code, width, Index::Ptr, &[0xC6], &[0xC7], 0); // mov cell [r12 + r13 * width], imm
+            emit_cell_imm(code, width, n);
+        },
+        Op::AddAt(offset, n) => {
+            emit_offset_ptr(code, labels, config, offset);
+            emit_cell_op(code, width, Index::Offset, &[0x80], &[0x81], 0); // add cell [r12 + rcx * width], imm
+            emit_cell_imm(code, width, n);
+        },
+        Op::MulAdd(offset, factor) => {
+            emit_load_cell(code, width, 0);              // movzx eax, cell [r12 + r13 * width]
+            code.extend_from_slice(&[0x85, 0xC0]);       // test eax, eax
+            let skip = emit_jump(code, Some(JE));
+            emit_offset_ptr(code, labels, config, offset);
+            code.extend_from_slice(&[0x69, 0xC0]);       // imul eax, eax, imm32
+            emit_u32(code, factor as u32);
+            emit_cell_op(code, width, Index::Offset, &[0x00], &[0x01], 0); // add cell [r12 + rcx * width], eax
+            let end = code.len();
+            patch_jump(code, skip, end);
+        },
+        Op::Scan(stride) => {
+            let start = code.len();
+            emit_test_cell(code, width);
+            let done = emit_jump(code, Some(JE));
+            code.extend_from_slice(&[0x49, 0x81, 0xC5]); // add r13, imm32
+            emit_u32(code, wrap_step(config, stride) as u32);
+            emit_check_ptr(code, labels, config);
+            let back = emit_jump(code, None);
+            patch_jump(code, back, start);
+            let end = code.len();
+            patch_jump(code, done, end);
+        },
+        Op::Input => {
+            emit_input(code, labels, io, config);
+            emit_store_input(code, config);
+        },
+        Op::Output => {
+            emit_output(code, labels, io, config);
+        },
+        Op::JumpIfZero(offset) => {
+            emit_test_cell(code, width);
+            fixups.push(Fixup { pos: emit_jump(code, Some(JE)), target: idx + offset });
+        },
+        Op::JumpIfNonZero(offset) => {
+            emit_test_cell(code, width);
+            fixups.push(Fixup { pos: emit_jump(code, Some(JNE)), target: idx - offset });
+        },
+        /* breakpoints are only shown by the interpreter */
+        Op::Debug(_) => (),
+        /* only the interpreter and the llvm backend run them, see `uses_procedures()` */
+        Op::Proc(_) | Op::Return | Op::Call => panic!("the x86-64 code generator has no pbrain procedures"),
+        /* only the interpreter runs them, see `uses_forks()` */
+        Op::Fork => panic!("the x86-64 code generator has no Brainfork threads"),
+        /* only the interpreter and the llvm backend run it, see `uses_random()` */
+        Op::Random => panic!("the x86-64 code generator has no random bytes")
+    }
+}
+
+/*
+ * Translates `ops` into a function `extern "C" fn(tape, ctx) -> status`
+ * which runs on a fixed tape of `config.tape_size` cells of
+ * `config.cell_width` with its bounds policy, starting with the pointer at
+ * `start_ptr`, and stores the pointer to the first word of `ctx` before
+ * returning.
+ */
+pub fn assemble(ops : &[Op], start_ptr : u64, config : &Config, io : &IoCalls) -> Assembly
+{
+    let mut code : Vec<u8> = Vec::new();
+    let mut labels = Labels::default();
+    let mut fixups : Vec<Fixup> = Vec::new();
+    let mut op_addrs : Vec<usize> = Vec::with_capacity(ops.len() + 1);
+
+    /* prologue: save callee-saved registers (keeps the stack 16 byte aligned) */
+    code.extend_from_slice(&[0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57]);
+    code.extend_from_slice(&[0x49, 0x89, 0xFC]); // mov r12, rdi
+    code.extend_from_slice(&[0x49, 0x89, 0xF6]); // mov r14, rsi
+    code.extend_from_slice(&[0x49, 0xBD]);       // mov r13, imm64
+    emit_u64(&mut code, start_ptr);
+
+    for (idx, op) in ops.iter().enumerate() {
+        op_addrs.push(code.len());
+        emit_op(&mut code, &mut labels, &mut fixups, io, config, idx, *op);
+    }
+    op_addrs.push(code.len());
+
+    /* exits: set the status in eax, then jump to the epilogue */
+    code.extend_from_slice(&[0xB8]); // mov eax, imm32
+    emit_u32(&mut code, STATUS_OK as u32);
+    let mut to_epilogue = vec![emit_jump(&mut code, None)];
+
+    /* report the offending cell rather than the pointer for offset accesses */
+    let here = code.len();
+    for pos in labels.offset_out_of_bounds {
+        patch_jump(&mut code, pos, here);
+    }
+    code.extend_from_slice(&[0x49, 0x89, 0xCD]); // mov r13, rcx
+    labels.out_of_bounds.push(emit_jump(&mut code, None));
+
+    let exits : Vec<(u64, Vec<usize>)> = vec![
+        (STATUS_OUT_OF_BOUNDS,  labels.out_of_bounds),
+        (STATUS_CALLBACK_ERROR, labels.callback_error)
+    ];
+    for (status, jumps) in exits {
+        let here = code.len();
+        for pos in jumps {
+            patch_jump(&mut code, pos, here);
+        }
+        code.extend_from_slice(&[0xB8]); // mov eax, imm32
+        emit_u32(&mut code, status as u32);
+        to_epilogue.push(emit_jump(&mut code, None));
+    }
+
+    /* epilogue: hand the pointer back and restore registers */
+    let here = code.len();
+    for pos in to_epilogue {
+        patch_jump(&mut code, pos, here);
+    }
+    code.extend_from_slice(&[0x4D, 0x89, 0x2E]); // mov [r14], r13
+    code.extend_from_slice(&[0x41, 0x5F, 0x41, 0x5E, 0x41, 0x5D, 0x41, 0x5C, 0x5B, 0xC3]);
+
+    for fixup in fixups {
+        patch_jump(&mut code, fixup.pos, op_addrs[fixup.target]);
+    }
+
+    /* format strings for scanf and printf */
+    let formats : Vec<(&[u8], Vec<usize>)> = vec![
+        (b"%d\0",   labels.input_format),
+        (b"%u\n\0", labels.output_format)
+    ];
+    for (format, leas) in formats {
+        if leas.is_empty() {
+            continue;
+        }
+        let here = code.len();
+        for pos in leas {
+            patch_jump(&mut code, pos, here);
+        }
+        code.extend_from_slice(format);
+    }
+
+    return Assembly {
+        code,
+        input_calls:  labels.input_calls,
+        output_calls: labels.output_calls
+    };
+}
+
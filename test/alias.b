@@ -0,0 +1,10 @@
+On a tape of sixteen cells that wraps around the cell sixteen to the right
+of the counter is the counter itself so the loop below counts down by two
+and runs twice; it must not be taken for a multiplication loop
+Run it with the bounds policy set to wrap and a tape size of 16
+
+++++ [- >>>>>>>>>>>>>>>> - <<<<<<<<<<<<<<<< > + <]
+Add 63 to the cell right of the counter and print it which makes A
+>> +++++++++ [<+++++++>-] < .
+Newline
+[-] ++++++++++ .
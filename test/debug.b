@@ -0,0 +1,9 @@
+Stops at three breakpoints when run with debug enabled and prints the
+cells around the pointer each time; without it the hashes are comments
+
+Put 1 2 3 in the first three cells and break on the second
++ > ++ > +++ < #
+Move twenty cells right and break there with four in the cell
+>>>>>>>>>>>>>>>>>>>> ++++ #
+Clear it and break again
+[-] #
@@ -0,0 +1,11 @@
+Exercises a tape that grows in both directions; run it with a small tape
+such as rustfuck run with tape size 2 and tape mode dynamic
+
+Grow to the left and print A
+<<<< ++++++++ [>++++++++<-] >+ .
+Fill eight cells to the right with ones then scan back over them and print B
+>>>>>>>>>> + > + > + > + > + > + > + > + [<] > [-] > [-] < ++++++++ [>++++++++<-] >++ .
+Scan right past the end of the tape then print C
+[>] ++++++++ [<++++++++>-] <++ .
+Newline
+[-] ++++++++++ .
@@ -0,0 +1,11 @@
+Reads past the end of input with a cell holding A and prints the cell plus 48
+so with empty input it prints q (unchanged) or 0 (zero) or / (minus one)
+
+Put A in cell one
+++++++++ [>++++++++<-] > +
+Read it at EOF
+,
+Add 48 and print
+< ++++++ [>++++++++<-] > .
+Newline
+[-] ++++++++++ .
@@ -0,0 +1,7 @@
+Adds two numbers; run it with numeric io
+Read two numbers
+, > ,
+Add the second to the first and print the sum
+[-<+>] < .
+Read once more past the end of input and print the cell
+, .
@@ -0,0 +1,8 @@
+Never terminates; run it with max steps or a timeout and the interpreter
+stops it with an error pointing into the loop
+
+Set a cell and loop on it forever while clearing the next one
++
+[
+  > +++ [-] <
+]
@@ -0,0 +1,5 @@
+scan loops should find the next zero cell in either direction
+
+>+>+>+>+>>+++++++[<++++++++>-]<<<<<    cells 1 to 4 are 1 and cell 5 is 56
+[>]<.                                   scan right to cell 6 and print cell 5
+<[<]+++++++[>+++++++<-]>.               scan left to cell 0 and print 50
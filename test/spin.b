@@ -0,0 +1,9 @@
+On a tape of three cells that wraps around every cell is set so the scan
+for a zero cell below never finds one; run it with the bounds policy set to
+wrap a tape size of 3 and max steps and the interpreter stops it with an
+error pointing into the loop instead of hanging
+
+Print A first
+++++++++ [> ++++++++ <-] > + . [-] <
+Set every cell and scan
++ > + > + [>]
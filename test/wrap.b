@@ -0,0 +1,8 @@
+Moves off both ends of the tape; run it with the bounds policy set to wrap
+
+Fill the last cell via cell zero and print A
+< ++++++++ [>++++++++<-] > + .
+Scan left from cell zero around to the last cell and back then print A again
+[<] > .
+Newline
+[-] ++++++++++ .